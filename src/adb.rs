@@ -1,14 +1,21 @@
+use std::io::{self, IsTerminal};
 use std::{path::Path, process::Command};
 
-pub fn list_devices() -> Vec<String> {
+use anyhow::bail;
+use dialoguer::Select;
+use tracing::warn;
+
+use crate::integrity;
+
+pub fn list_devices() -> Result<Vec<String>, String> {
     let output = Command::new("adb")
         .arg("devices")
         .output()
-        .unwrap_or_else(|_| panic!("failed to execute adb"));
+        .map_err(|e| format!("failed to execute adb: {e}"))?;
     let stdout = String::from_utf8_lossy(&output.stdout);
 
     // skip first line "List of devices attached"
-    stdout
+    Ok(stdout
         .lines()
         .skip(1)
         .filter_map(|line| {
@@ -19,26 +26,182 @@ pub fn list_devices() -> Vec<String> {
                 None
             }
         })
-        .collect()
+        .collect())
 }
 
-pub fn shell_run(device: &str, cmd: &str, args: Vec<String>) -> Result<(), String> {
+/// Pick the device to talk to: `--serial` always wins, then the only
+/// connected device, then the configured default among several, and
+/// finally the user's choice when running in a terminal.
+pub fn select_device(serial: Option<&str>, default: Option<&str>) -> anyhow::Result<String> {
+    let devices = list_devices().map_err(anyhow::Error::msg)?;
+
+    if let Some(serial) = serial {
+        if !devices.iter().any(|d| d == serial) {
+            bail!("device {serial} is not connected (connected: {devices:?})");
+        }
+        return Ok(serial.to_string());
+    }
+
+    match devices.as_slice() {
+        [] => bail!("no connected devices"),
+        [device] => Ok(device.clone()),
+        _ => match default {
+            Some(default) if devices.iter().any(|d| d == default) => Ok(default.to_string()),
+            Some(default) => bail!(
+                "default device {default} is not connected, pass --device to pick one of {devices:?}"
+            ),
+            None if io::stdin().is_terminal() && io::stderr().is_terminal() => {
+                let choice = Select::new()
+                    .with_prompt("several devices are connected, pick one")
+                    .items(&devices)
+                    .default(0)
+                    .interact()?;
+                Ok(devices[choice].clone())
+            }
+            None => bail!(
+                "more than one connected device {devices:?}, pass --device or set one with `device use <serial>`"
+            ),
+        },
+    }
+}
+
+/// Run `adb -s <device> <args>`, returning whether it succeeded and its
+/// combined output (adb reports some failures on stdout with status 0).
+fn run_captured(device: &str, args: &[&str]) -> anyhow::Result<(bool, String)> {
+    let output = Command::new("adb")
+        .arg("-s")
+        .arg(device)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("failed to execute adb: {e}"))?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok((output.status.success(), text.trim().to_string()))
+}
+
+fn shell_output(device: &str, cmd: &str) -> anyhow::Result<String> {
+    let (ok, output) = run_captured(device, &["shell", cmd])?;
+    if !ok {
+        bail!("`{cmd}` failed on {device}: {output}");
+    }
+    Ok(output)
+}
+
+fn is_root(device: &str) -> anyhow::Result<bool> {
+    Ok(shell_output(device, "id -u")? == "0")
+}
+
+/// Restart adbd as root, refusing early on production firmware.
+pub fn root(device: &str) -> anyhow::Result<()> {
+    if is_root(device)? {
+        return Ok(());
+    }
+
+    // getprop is absent on some firmwares, only trust an explicit "0"
+    let debuggable = shell_output(device, "getprop ro.debuggable 2>/dev/null").unwrap_or_default();
+    if debuggable == "0" {
+        bail!("{device} runs production firmware (ro.debuggable=0), adbd cannot run as root");
+    }
+
+    let (ok, output) = run_captured(device, &["root"])?;
+    if !ok || output.contains("cannot run as root") {
+        bail!("adb root failed on {device}: {output}");
+    }
+
+    run_captured(device, &["wait-for-device"])?;
+    if !is_root(device)? {
+        bail!("adbd on {device} restarted but is still not root: {output}");
+    }
+    Ok(())
+}
+
+/// Make the root filesystem writable, through `adb remount` where adbd
+/// supports it and a plain remount otherwise.
+pub fn remount(device: &str) -> anyhow::Result<()> {
+    if !is_root(device)? {
+        bail!("remount needs root, run `device root` first");
+    }
+
+    let (ok, output) = run_captured(device, &["remount"])?;
+    if ok && !output.contains("failed") && !output.contains("not supported") {
+        return Ok(());
+    }
+
+    shell_output(device, "mount -o remount,rw /")
+        .map(|_| ())
+        .map_err(|e| anyhow::anyhow!("remount failed on {device}: {e} (adb remount: {output})"))
+}
+
+/// Push a file and compare the sha256 of the remote copy with the local
+/// one, pushing again up to `retries` times on a mismatch.
+pub fn push_verified(
+    device: &str,
+    local_path: &Path,
+    remote_path: &str,
+    retries: u32,
+) -> anyhow::Result<()> {
+    let expected = integrity::sha256(&mut std::fs::File::open(local_path)?)?;
+
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            warn!("retrying push of {local_path:?} ({attempt}/{retries})");
+        }
+
+        if let Err(e) = push(device, local_path, remote_path) {
+            warn!("{e}");
+            continue;
+        }
+
+        let checksum_cmd = format!("sha256sum {}", shell_quote(remote_path));
+        let output = match shell_output(device, &checksum_cmd) {
+            Ok(output) => output,
+            Err(e) => {
+                warn!("{e}");
+                continue;
+            }
+        };
+        let actual = output.split_whitespace().next().unwrap_or_default();
+        if actual == expected {
+            return Ok(());
+        }
+        warn!(
+            "checksum mismatch after pushing {local_path:?} to {remote_path}: {actual} != {expected}"
+        );
+    }
+
+    bail!(
+        "failed to push {local_path:?} to {device} intact after {} attempt(s)",
+        retries + 1
+    )
+}
+
+/// Quote an argument for the device shell, which re-splits the command line.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Run a command on the device and return its exit code, which adb passes
+/// through on devices supporting the shell v2 protocol.
+pub fn shell_exit_code(device: &str, cmd: &str, args: Vec<String>) -> Result<i32, String> {
     let status = Command::new("adb")
         .arg("-s")
         .arg(device)
         .arg("shell")
         .arg(cmd)
-        .args(&args)
+        .args(args.iter().map(|arg| shell_quote(arg)))
         .status()
         .map_err(|e| format!("failed to execute adb shell: {e}"))?;
 
-    if status.success() {
+    Ok(status.code().unwrap_or(-1))
+}
+
+pub fn shell_run(device: &str, cmd: &str, args: Vec<String>) -> Result<(), String> {
+    let code = shell_exit_code(device, cmd, args)?;
+    if code == 0 {
         Ok(())
     } else {
-        Err(format!(
-            "adb pull failed with code {}",
-            status.code().unwrap_or(-1)
-        ))
+        Err(format!("adb shell failed with code {code}"))
     }
 }
 
@@ -50,18 +213,19 @@ pub fn push(device: &str, local_path: &Path, remote_path: &str) -> Result<(), St
         .arg(local_path)
         .arg(remote_path)
         .status()
-        .map_err(|e| format!("failed to execute adb pull: {e}"))?;
+        .map_err(|e| format!("failed to execute adb push: {e}"))?;
 
     if status.success() {
         Ok(())
     } else {
         Err(format!(
-            "adb pull failed with code {}",
+            "adb push failed with code {}",
             status.code().unwrap_or(-1)
         ))
     }
 }
 
+#[allow(dead_code)]
 pub fn pull(device: &str, remote_path: &str, local_path: &Path) -> Result<(), String> {
     let status = Command::new("adb")
         .arg("-s")
@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, anyhow, bail};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::AppSettings;
+use crate::defs::AppFilter;
+use crate::process;
+use crate::strict;
+
+/// Scheme of miniapp deep links, `app://<app id>[/<page>][?<args>]`.
+const DEEP_LINK_SCHEME: &str = "app://";
+
+/// A parsed miniapp deep link.
+#[derive(Clone, Debug)]
+pub struct DeepLink {
+    pub app_id: u64,
+    pub page: Option<String>,
+    /// Raw query string, handed to the page as-is
+    pub query: Option<String>,
+}
+
+/// Warn about (or in strict mode reject) ids that do not look like
+/// miniapp ids, which are 16 digits starting with "80".
+pub fn check_app_id(app_id: u64) -> anyhow::Result<()> {
+    let id = app_id.to_string();
+    if id.len() != 16 || !id.starts_with("80") {
+        strict::warn_or_bail!("app id `{id}` is unusual (expected 16 digits starting with \"80\")");
+    }
+    Ok(())
+}
+
+impl DeepLink {
+    pub fn parse(uri: &str) -> anyhow::Result<Self> {
+        let rest = uri
+            .strip_prefix(DEEP_LINK_SCHEME)
+            .ok_or_else(|| anyhow!("deep link must start with {DEEP_LINK_SCHEME}"))?;
+
+        let (path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (rest, None),
+        };
+        let (id, page) = match path.split_once('/') {
+            Some((id, page)) => (id, Some(page.trim_matches('/'))),
+            None => (path, None),
+        };
+
+        let app_id = id
+            .parse()
+            .map_err(|_| anyhow!("invalid app id `{id}` in deep link"))?;
+
+        Ok(Self {
+            app_id,
+            page: page.filter(|p| !p.is_empty()).map(str::to_string),
+            query: query.filter(|q| !q.is_empty()).map(str::to_string),
+        })
+    }
+
+    /// Page argument for miniapp_cli, with the query attached.
+    fn page_arg(&self) -> Option<String> {
+        match (&self.page, &self.query) {
+            (Some(page), Some(query)) => Some(format!("{page}?{query}")),
+            (None, Some(query)) => Some(format!("?{query}")),
+            (page, None) => page.clone(),
+        }
+    }
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters.
+fn percent_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+/// Start arguments as a query string, the format pages receive them in.
+pub fn encode_query(args: &[(String, String)]) -> String {
+    args.iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Start an app through miniapp_cli, optionally on a given page.
+fn start(app_id: u64, page: Option<&str>) -> anyhow::Result<()> {
+    let app_id = app_id.to_string();
+    let mut args = vec!["start", app_id.as_str()];
+    args.extend(page);
+
+    let status = process::run_with_output("miniapp_cli", &args)?;
+    if !status.success() {
+        bail!("miniapp_cli failed to start app {app_id} ({status})");
+    }
+    Ok(())
+}
+
+pub fn open(link: &DeepLink) -> anyhow::Result<()> {
+    let page = link.page_arg();
+    info!(
+        "opening app {} on {}",
+        link.app_id,
+        page.as_deref().unwrap_or("its start page")
+    );
+    start(link.app_id, page.as_deref())
+}
+
+/* =========================
+ * App data
+ * ========================= */
+
+pub fn data_dir(settings: &AppSettings, app_id: u64) -> PathBuf {
+    settings.data_dir.join(app_id.to_string())
+}
+
+/// Uninstall an app through miniapp_cli. With `keep_data`, its data
+/// directory is moved aside meanwhile and put back afterwards, like
+/// `pm uninstall -k`, so a reinstall finds it.
+pub fn uninstall(app_id: u64, data_dir: &Path, keep_data: bool) -> anyhow::Result<()> {
+    let id = app_id.to_string();
+    let kept = data_dir.with_file_name(format!(".{id}.kept"));
+    let keep = keep_data && data_dir.is_dir();
+    if keep_data && !keep {
+        warn!("app {id} has no data directory at {data_dir:?}, nothing to keep");
+    }
+    if keep {
+        fs::rename(data_dir, &kept)
+            .with_context(|| format!("failed to move {data_dir:?} aside"))?;
+    }
+
+    let result = process::run_with_output("miniapp_cli", &["uninstall", &id]);
+
+    if keep {
+        if data_dir.exists() {
+            fs::remove_dir_all(data_dir)?;
+        }
+        fs::rename(&kept, data_dir).with_context(|| {
+            format!("failed to put data of app {id} back, it is kept at {kept:?}")
+        })?;
+        info!("kept data of app {id} in {data_dir:?}");
+    }
+
+    let status = result?;
+    if !status.success() {
+        bail!("miniapp_cli failed to uninstall app {id} ({status})");
+    }
+    Ok(())
+}
+
+/// Archive an app's data directory as a gzipped tar, rooted at the app id.
+pub fn export_data(app_id: u64, data_dir: &Path, dest: &Path) -> anyhow::Result<()> {
+    if !data_dir.is_dir() {
+        bail!("app {app_id} has no data directory at {data_dir:?}");
+    }
+
+    let file = File::create(dest).with_context(|| format!("failed to create {dest:?}"))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    tar.follow_symlinks(false);
+    tar.append_dir_all(app_id.to_string(), data_dir)
+        .with_context(|| format!("failed to archive {data_dir:?}"))?;
+    tar.into_inner()?.finish()?.sync_all()?;
+    Ok(())
+}
+
+/* =========================
+ * Crashes
+ * ========================= */
+
+/// Extract a miniapp id (16 digits starting with "80") from a path.
+fn find_app_id(path: &Path) -> Option<u64> {
+    path.to_string_lossy()
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|run| run.len() == 16 && run.starts_with("80"))
+        .and_then(|run| run.parse().ok())
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => collect_files(&path, out),
+            Ok(t) if t.is_file() => out.push(path),
+            _ => {}
+        }
+    }
+}
+
+/// Crash files found under `dirs`, grouped by app id, newest first.
+pub fn find_crashes(dirs: &[PathBuf], app_id: Option<u64>) -> BTreeMap<u64, Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir in dirs {
+        collect_files(dir, &mut files);
+    }
+
+    let mut crashes: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        if let Some(id) = find_app_id(&file)
+            && app_id.is_none_or(|wanted| wanted == id)
+        {
+            crashes.entry(id).or_default().push(file);
+        }
+    }
+
+    let modified = |path: &PathBuf| {
+        fs::metadata(path)
+            .and_then(|m| m.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    for files in crashes.values_mut() {
+        files.sort_by_key(|path| std::cmp::Reverse(modified(path)));
+    }
+
+    crashes
+}
+
+pub fn list_crashes(crashes: &BTreeMap<u64, Vec<PathBuf>>) {
+    info!("app crashes:");
+    if crashes.is_empty() {
+        info!("  (no crashes found)");
+    }
+
+    for (id, files) in crashes {
+        info!("{id}: {} crash file(s)", files.len());
+        for file in files {
+            info!("  {file:?}");
+        }
+    }
+}
+
+/// Copy crash files into `<dest>/<app id>/`.
+pub fn export_crashes(crashes: &BTreeMap<u64, Vec<PathBuf>>, dest: &Path) -> anyhow::Result<()> {
+    for (id, files) in crashes {
+        let app_dir = dest.join(id.to_string());
+        fs::create_dir_all(&app_dir)?;
+
+        for file in files {
+            let Some(name) = file.file_name() else {
+                continue;
+            };
+            if let Err(e) = fs::copy(file, app_dir.join(name)) {
+                warn!("failed to export {file:?}: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/* =========================
+ * Installed apps
+ * ========================= */
+
+/// An installed app as reported by miniapp_cli.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct InstalledApp {
+    pub app_id: u64,
+    /// Filter the app was listed under
+    pub kind: AppFilter,
+    pub version: Option<String>,
+    pub name: Option<String>,
+}
+
+/// Parse the output of `miniapp_cli list`, one `<app id> [<version>
+/// [<name>]]` per line. Lines not starting with an app id, such as
+/// headers, are skipped.
+pub fn parse_app_list(output: &str, kind: AppFilter) -> Vec<InstalledApp> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let app_id = fields.next()?.parse().ok()?;
+            let version = fields.next().map(str::to_owned);
+            let name = fields.collect::<Vec<_>>().join(" ");
+            Some(InstalledApp {
+                app_id,
+                kind,
+                version,
+                name: (!name.is_empty()).then_some(name),
+            })
+        })
+        .collect()
+}
+
+/// List installed apps of the given kinds through miniapp_cli.
+pub fn list(filters: &[AppFilter]) -> anyhow::Result<Vec<InstalledApp>> {
+    let mut apps = Vec::new();
+    for filter in filters {
+        let kind = filter.name();
+        // captured rather than echoed so --json keeps stdout clean
+        let output = Command::new("miniapp_cli")
+            .args(["list", kind])
+            .output()
+            .context("failed to run miniapp_cli")?;
+        if !output.status.success() {
+            bail!(
+                "miniapp_cli failed to list {kind} apps ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        apps.extend(parse_app_list(&stdout, *filter));
+    }
+    Ok(apps)
+}
+
+pub fn print_apps(apps: &[InstalledApp]) {
+    if apps.is_empty() {
+        info!("no apps installed");
+        return;
+    }
+
+    info!("{:<18} {:<18} {:<12} {}", "APP", "KIND", "VERSION", "NAME");
+    for app in apps {
+        info!(
+            "{:<18} {:<18} {:<12} {}",
+            app.app_id,
+            app.kind.name(),
+            app.version.as_deref().unwrap_or("-"),
+            app.name.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/* =========================
+ * Resource usage
+ * ========================= */
+
+/// CPU and memory use of a running app, summed over its processes.
+#[derive(Debug, Serialize)]
+pub struct AppUsage {
+    pub app_id: u64,
+    pub pids: Vec<u32>,
+    /// Share of one CPU used over the sample interval
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+}
+
+/// Running processes of miniapps by app id, told apart by the app id the
+/// platform puts in their command lines.
+fn app_processes() -> BTreeMap<u64, Vec<u32>> {
+    let mut processes: BTreeMap<u64, Vec<u32>> = BTreeMap::new();
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return processes;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Some(pid) = entry.file_name().to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        let Ok(cmdline) = fs::read(entry.path().join("cmdline")) else {
+            continue;
+        };
+        let cmdline = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+        if let Some(id) = find_app_id(Path::new(&cmdline)) {
+            processes.entry(id).or_default().push(pid);
+        }
+    }
+    processes
+}
+
+/// User plus system CPU time of a process, in clock ticks.
+fn cpu_ticks(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // the command name may contain spaces, fields resume after its ')'
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+fn rss_bytes(pid: u32) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{pid}/statm")).ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf only reads a system setting
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as u64)
+}
+
+/// Sample the running apps over `interval`, busiest first.
+pub fn sample_usage(interval: Duration) -> Vec<AppUsage> {
+    let before: BTreeMap<u32, u64> = app_processes()
+        .into_values()
+        .flatten()
+        .filter_map(|pid| Some((pid, cpu_ticks(pid)?)))
+        .collect();
+    let started = Instant::now();
+    thread::sleep(interval);
+    let elapsed = started.elapsed().as_secs_f64();
+    // SAFETY: sysconf only reads a system setting
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+
+    let mut usage: Vec<AppUsage> = app_processes()
+        .into_iter()
+        .map(|(app_id, pids)| {
+            // processes started meanwhile count from zero
+            let ticks: u64 = pids
+                .iter()
+                .filter_map(|pid| {
+                    let now = cpu_ticks(*pid)?;
+                    Some(now.saturating_sub(before.get(pid).copied().unwrap_or(0)))
+                })
+                .sum();
+            AppUsage {
+                app_id,
+                cpu_percent: ticks as f64 / ticks_per_sec / elapsed * 100.0,
+                rss_bytes: pids.iter().filter_map(|pid| rss_bytes(*pid)).sum(),
+                pids,
+            }
+        })
+        .collect();
+    usage.sort_by(|a, b| {
+        b.cpu_percent
+            .total_cmp(&a.cpu_percent)
+            .then(b.rss_bytes.cmp(&a.rss_bytes))
+    });
+    usage
+}
+
+pub fn print_usage(usage: &[AppUsage]) {
+    // redraw in place like top when someone is watching
+    if io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+    }
+    if usage.is_empty() {
+        info!("no apps running");
+        return;
+    }
+
+    info!(
+        "{:<18} {:>6} {:>10} {:>5}",
+        "APP", "CPU%", "RSS MiB", "PROCS"
+    );
+    for app in usage {
+        info!(
+            "{:<18} {:>6.1} {:>10.1} {:>5}",
+            app.app_id,
+            app.cpu_percent,
+            app.rss_bytes as f64 / (1024.0 * 1024.0),
+            app.pids.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_app_list_skipping_headers() {
+        let output = "ID VERSION NAME\n8001234567890123 1.2.0 Word Book\n8009876543210987\n\n";
+        let apps = parse_app_list(output, AppFilter::User);
+        assert_eq!(
+            apps,
+            [
+                InstalledApp {
+                    app_id: 8001234567890123,
+                    kind: AppFilter::User,
+                    version: Some("1.2.0".into()),
+                    name: Some("Word Book".into()),
+                },
+                InstalledApp {
+                    app_id: 8009876543210987,
+                    kind: AppFilter::User,
+                    version: None,
+                    name: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn app_list_serializes_as_array() {
+        let apps = parse_app_list(
+            "8001234567890123 1.2.0 Word Book",
+            AppFilter::BuiltinThirdparty,
+        );
+        assert_eq!(
+            serde_json::to_value(&apps).unwrap(),
+            serde_json::json!([{
+                "app_id": 8001234567890123u64,
+                "kind": "builtin-thirdparty",
+                "version": "1.2.0",
+                "name": "Word Book",
+            }])
+        );
+    }
+}
@@ -0,0 +1,464 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::read::GzDecoder;
+use lzma_rust2::XzReader;
+use tar::EntryType;
+use tempfile::tempdir;
+use tracing::info;
+use zip::ZipArchive;
+
+use crate::logging::Heartbeat;
+use crate::process;
+use crate::strict;
+
+/// Bytes read from the start of a file to tell its format.
+const MAGIC_LEN: usize = 262;
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+/// External tool unpacking squashfs images.
+const UNSQUASHFS: &str = "unsquashfs";
+
+/// Caps on what one archive may unpack to, against archive bombs.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub max_entries: u64,
+    pub max_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_entries: 100_000,
+            max_bytes: 2 << 30,
+        }
+    }
+}
+
+/// A way of unpacking one archive format.
+///
+/// Extractors only read their format; every entry goes through a `Guard`,
+/// which enforces the same safety rules for all of them.
+pub trait Extractor {
+    fn name(&self) -> &'static str;
+
+    /// Whether `magic`, the first bytes of a file, start an archive of this format.
+    fn detect(&self, magic: &[u8]) -> bool;
+
+    /// Unpack every entry of `archive` through `guard`.
+    fn unpack(&self, archive: &Path, guard: &mut Guard) -> Result<()>;
+}
+
+/// Formats tried by `extract`, in order.
+const EXTRACTORS: &[&dyn Extractor] = &[&Zip, &Tar, &Squashfs];
+
+/// The extractor for the format of `archive`.
+pub fn detect(archive: &Path) -> Result<&'static dyn Extractor> {
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    File::open(archive)
+        .with_context(|| format!("failed to open {archive:?}"))?
+        .take(MAGIC_LEN as u64)
+        .read_to_end(&mut magic)?;
+
+    EXTRACTORS
+        .iter()
+        .copied()
+        .find(|extractor| extractor.detect(&magic))
+        .ok_or_else(|| {
+            let names: Vec<_> = EXTRACTORS.iter().map(|e| e.name()).collect();
+            anyhow!("{archive:?} is not a known archive, expected one of {names:?}")
+        })
+}
+
+/// Unpack `archive` into `dest`, whatever its format.
+pub fn extract(archive: &Path, dest: &Path, limits: Limits) -> Result<()> {
+    extract_with(detect(archive)?, archive, dest, limits)
+}
+
+/// Unpack `archive` into `dest` with a given extractor.
+pub fn extract_with(
+    extractor: &dyn Extractor,
+    archive: &Path,
+    dest: &Path,
+    limits: Limits,
+) -> Result<()> {
+    info!("extracting {archive:?} ({}) to {dest:?}", extractor.name());
+    fs::create_dir_all(dest)?;
+    let heartbeat = Heartbeat::new(format!("extracting {archive:?}"));
+    let mut guard = Guard::new(dest, limits, heartbeat);
+    extractor
+        .unpack(archive, &mut guard)
+        .with_context(|| format!("failed to extract {archive:?}"))
+}
+
+/* =========================
+ * Safety checks
+ * ========================= */
+
+/// Whether writing `rel` below `dest` would follow a symlink already there.
+pub fn through_symlink(dest: &Path, rel: &Path) -> bool {
+    let mut path = dest.to_path_buf();
+    rel.iter().any(|part| {
+        path.push(part);
+        fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink())
+    })
+}
+
+/// Permission bits to apply to an unpacked entry, without setuid, setgid
+/// and sticky bits.
+pub fn safe_mode(name: &Path, mode: u32) -> Result<u32> {
+    if mode & 0o7000 != 0 {
+        strict::warn_or_bail!(
+            "dropping special permission bits {:o} of {name:?}",
+            mode & 0o7000
+        );
+    }
+    Ok(mode & 0o777)
+}
+
+/// Writes the entries of one archive below its destination, refusing
+/// traversal, writes through symlinks and anything beyond the limits.
+pub struct Guard<'a> {
+    dest: &'a Path,
+    limits: Limits,
+    entries: u64,
+    bytes: u64,
+    heartbeat: Heartbeat,
+}
+
+impl<'a> Guard<'a> {
+    fn new(dest: &'a Path, limits: Limits, heartbeat: Heartbeat) -> Self {
+        Self {
+            dest,
+            limits,
+            entries: 0,
+            bytes: 0,
+            heartbeat,
+        }
+    }
+
+    /// Destination of the entry called `name`, counted against the limits.
+    fn target(&mut self, name: &Path) -> Result<PathBuf> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            bail!("archive has more than {} entries", self.limits.max_entries);
+        }
+        let (entries, bytes) = (self.entries, self.bytes);
+        self.heartbeat
+            .tick(|| format!("{entries} entries, {} MiB so far", bytes >> 20));
+
+        let mut rel = PathBuf::new();
+        for component in name.components() {
+            match component {
+                Component::Normal(part) => rel.push(part),
+                // `./` prefixes, and `./` itself for the root, as tar writes them
+                Component::CurDir => {}
+                _ => bail!("archive entry {name:?} points outside the extraction directory"),
+            }
+        }
+        // an earlier symlink entry must not redirect later ones
+        if through_symlink(self.dest, &rel) {
+            bail!("archive entry {name:?} would be written through a symlink");
+        }
+        Ok(self.dest.join(rel))
+    }
+
+    pub fn dir(&mut self, name: &Path, mode: Option<u32>) -> Result<()> {
+        let path = self.target(name)?;
+        fs::create_dir_all(&path)?;
+        if let Some(mode) = mode {
+            fs::set_permissions(&path, fs::Permissions::from_mode(safe_mode(name, mode)?))?;
+        }
+        Ok(())
+    }
+
+    pub fn file(&mut self, name: &Path, content: &mut dyn Read, mode: Option<u32>) -> Result<()> {
+        let path = self.target(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // count what is actually written, declared sizes can lie
+        let remaining = self.limits.max_bytes - self.bytes;
+        let mut out = File::create(&path)?;
+        let written = io::copy(&mut content.take(remaining + 1), &mut out)?;
+        if written > remaining {
+            bail!(
+                "archive unpacks to more than {} bytes",
+                self.limits.max_bytes
+            );
+        }
+        self.bytes += written;
+
+        if let Some(mode) = mode {
+            fs::set_permissions(&path, fs::Permissions::from_mode(safe_mode(name, mode)?))?;
+        }
+        Ok(())
+    }
+
+    /// Symlinks are kept as they are: they point into the device's root
+    /// once mounted, and `target` keeps them from redirecting extraction.
+    pub fn symlink(&mut self, name: &Path, link: &Path) -> Result<()> {
+        let path = self.target(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        std::os::unix::fs::symlink(link, &path)?;
+        Ok(())
+    }
+
+    /// Record an entry of a kind modules have no use for.
+    pub fn skip(&mut self, name: &Path, kind: &str) -> Result<()> {
+        strict::warn_or_bail!("skipping {kind} archive entry {name:?}");
+        Ok(())
+    }
+}
+
+/* =========================
+ * Formats
+ * ========================= */
+
+pub struct Zip;
+
+impl Extractor for Zip {
+    fn name(&self) -> &'static str {
+        "zip"
+    }
+
+    fn detect(&self, magic: &[u8]) -> bool {
+        magic.starts_with(b"PK\x03\x04") || magic.starts_with(b"PK\x05\x06")
+    }
+
+    fn unpack(&self, archive: &Path, guard: &mut Guard) -> Result<()> {
+        let mut archive = ZipArchive::new(File::open(archive)?)?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = PathBuf::from(file.name());
+            let mode = file.unix_mode();
+
+            if file.is_dir() {
+                guard.dir(&name, mode)?;
+            } else if file.is_symlink() {
+                let mut link = String::new();
+                file.read_to_string(&mut link)?;
+                guard.symlink(&name, Path::new(&link))?;
+            } else {
+                guard.file(&name, &mut file, mode)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tarballs, plain or compressed with gzip, xz or zstd.
+pub struct Tar;
+
+impl Tar {
+    fn decompressed(archive: &Path) -> Result<Box<dyn Read>> {
+        let mut reader = BufReader::new(File::open(archive)?);
+        let magic = reader.fill_buf()?;
+        Ok(if magic.starts_with(GZIP_MAGIC) {
+            Box::new(GzDecoder::new(reader))
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Box::new(zstd::Decoder::with_buffer(reader)?)
+        } else if magic.starts_with(XZ_MAGIC) {
+            Box::new(XzReader::new(reader, true))
+        } else {
+            Box::new(reader)
+        })
+    }
+}
+
+impl Extractor for Tar {
+    fn name(&self) -> &'static str {
+        "tar"
+    }
+
+    fn detect(&self, magic: &[u8]) -> bool {
+        magic.starts_with(GZIP_MAGIC)
+            || magic.starts_with(ZSTD_MAGIC)
+            || magic.starts_with(XZ_MAGIC)
+            || magic.get(257..262) == Some(b"ustar")
+    }
+
+    fn unpack(&self, archive: &Path, guard: &mut Guard) -> Result<()> {
+        let mut archive = tar::Archive::new(Self::decompressed(archive)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.into_owned();
+            let mode = entry.header().mode().ok();
+
+            match entry.header().entry_type() {
+                EntryType::Directory => guard.dir(&name, mode)?,
+                EntryType::Regular | EntryType::Continuous => {
+                    guard.file(&name, &mut entry, mode)?
+                }
+                EntryType::Symlink => {
+                    let link = entry
+                        .link_name()?
+                        .with_context(|| format!("symlink {name:?} has no target"))?
+                        .into_owned();
+                    guard.symlink(&name, &link)?;
+                }
+                EntryType::Link => guard.skip(&name, "hard link")?,
+                // pax and GNU headers describe the entries around them
+                EntryType::XHeader | EntryType::XGlobalHeader => {}
+                EntryType::GNULongName | EntryType::GNULongLink => {}
+                _ => guard.skip(&name, "special file")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Squashfs images, unpacked by `unsquashfs` into scratch space and then
+/// copied through the guard like any other archive.
+pub struct Squashfs;
+
+impl Squashfs {
+    fn copy_tree(root: &Path, dir: &Path, guard: &mut Guard) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let name = path.strip_prefix(root)?;
+            let meta = fs::symlink_metadata(&path)?;
+            let mode = Some(meta.permissions().mode());
+
+            if meta.is_dir() {
+                guard.dir(name, mode)?;
+                Self::copy_tree(root, &path, guard)?;
+            } else if meta.is_symlink() {
+                guard.symlink(name, &fs::read_link(&path)?)?;
+            } else if meta.is_file() {
+                guard.file(name, &mut File::open(&path)?, mode)?;
+            } else {
+                guard.skip(name, "special file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Extractor for Squashfs {
+    fn name(&self) -> &'static str {
+        "squashfs"
+    }
+
+    fn detect(&self, magic: &[u8]) -> bool {
+        magic.starts_with(b"hsqs")
+    }
+
+    fn unpack(&self, archive: &Path, guard: &mut Guard) -> Result<()> {
+        let scratch = tempdir()?;
+        let out = scratch.path().join("root");
+        let out_arg = out.to_string_lossy();
+        let archive_arg = archive.to_string_lossy();
+
+        let status = process::run_with_output(
+            UNSQUASHFS,
+            &["-no-xattrs", "-quiet", "-d", &out_arg, &archive_arg],
+        )
+        .with_context(|| format!("{UNSQUASHFS} is needed for squashfs modules"))?;
+        if !status.success() {
+            bail!("{UNSQUASHFS} failed ({status})");
+        }
+
+        Self::copy_tree(&out, &out, guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn guard(dest: &Path) -> Guard<'_> {
+        Guard::new(dest, Limits::default(), Heartbeat::new("test"))
+    }
+
+    #[test]
+    fn target_keeps_plain_and_dotted_names_inside() {
+        let dest = tempdir().unwrap();
+        let mut guard = guard(dest.path());
+
+        assert_eq!(
+            guard.target(Path::new("system/bin/tool")).unwrap(),
+            dest.path().join("system/bin/tool")
+        );
+        assert_eq!(
+            guard.target(Path::new("./module.prop")).unwrap(),
+            dest.path().join("module.prop")
+        );
+        assert_eq!(guard.target(Path::new("./")).unwrap(), dest.path());
+    }
+
+    #[test]
+    fn target_rejects_traversal() {
+        let dest = tempdir().unwrap();
+        let mut guard = guard(dest.path());
+        for name in [
+            "../escape",
+            "system/../../escape",
+            "/etc/passwd",
+            "system/./../..",
+            "..",
+        ] {
+            assert!(
+                guard.target(Path::new(name)).is_err(),
+                "{name:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn target_rejects_writes_through_earlier_symlinks() {
+        let dest = tempdir().unwrap();
+        let outside = tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), dest.path().join("link")).unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", dest.path().join("file-link")).unwrap();
+        let mut guard = guard(dest.path());
+
+        assert!(guard.target(Path::new("link/payload")).is_err());
+        assert!(guard.target(Path::new("./link/deeper/payload")).is_err());
+        assert!(guard.target(Path::new("file-link")).is_err());
+        assert!(guard.target(Path::new("linked")).is_ok());
+    }
+
+    #[test]
+    fn target_counts_entries_against_the_limit() {
+        let dest = tempdir().unwrap();
+        let limits = Limits {
+            max_entries: 2,
+            ..Limits::default()
+        };
+        let mut guard = Guard::new(dest.path(), limits, Heartbeat::new("test"));
+
+        assert!(guard.target(Path::new("a")).is_ok());
+        assert!(guard.target(Path::new("b")).is_ok());
+        assert!(guard.target(Path::new("c")).is_err());
+    }
+
+    #[test]
+    fn file_stops_at_the_byte_limit() {
+        let dest = tempdir().unwrap();
+        let limits = Limits {
+            max_bytes: 4,
+            ..Limits::default()
+        };
+        let mut guard = Guard::new(dest.path(), limits, Heartbeat::new("test"));
+
+        assert!(
+            guard
+                .file(Path::new("small"), &mut &b"1234"[..], None)
+                .is_ok()
+        );
+        assert!(guard.file(Path::new("big"), &mut &b"5"[..], None).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::clock::{Clock, Timestamp};
+use crate::defs::AUDIT_LOG;
+use crate::state;
+
+/// Who issued a command.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClientIdentity {
+    /// Id of the access token, for commands received over the agent
+    pub token_id: Option<String>,
+    /// Peer address, or `local` for direct invocations
+    pub peer: String,
+}
+
+impl ClientIdentity {
+    /// Identity of a direct invocation, using the ssh peer when available
+    pub fn local() -> Self {
+        let peer = std::env::var("SSH_CLIENT")
+            .ok()
+            .and_then(|c| c.split_whitespace().next().map(str::to_string))
+            .unwrap_or_else(|| "local".to_string());
+
+        Self {
+            token_id: None,
+            peer,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub time: Timestamp,
+    pub client: ClientIdentity,
+    pub command: String,
+    pub args: Vec<String>,
+    pub result: String,
+}
+
+/// Append an entry to the audit log. Failures are logged, never fatal.
+pub fn record(
+    clock: &dyn Clock,
+    client: ClientIdentity,
+    command: &str,
+    args: &[String],
+    result: &anyhow::Result<()>,
+) {
+    let now = Timestamp::read(clock);
+    if now.clock_plausible
+        && let Err(e) = reconcile_entries(&now)
+    {
+        warn!("failed to reconcile audit timestamps: {e}");
+    }
+
+    let entry = AuditEntry {
+        time: now,
+        client,
+        command: command.to_string(),
+        args: args.to_vec(),
+        result: match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {e:#}"),
+        },
+    };
+
+    let write = || -> anyhow::Result<()> {
+        state::append_record(Path::new(AUDIT_LOG), &serde_json::to_string(&entry)?)?;
+        Ok(())
+    };
+
+    if let Err(e) = write() {
+        warn!("failed to write audit entry to {AUDIT_LOG}: {e}");
+    }
+}
+
+/// Read all audit entries, skipping lines that fail to parse.
+pub fn read_entries() -> anyhow::Result<Vec<AuditEntry>> {
+    Ok(state::read_records(Path::new(AUDIT_LOG))?
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Correct entries recorded this boot before the clock was set.
+///
+/// The log is read and rewritten atomically under its lock, so a crash
+/// leaves either the old or the new version in place and no concurrent
+/// entry is lost. Lines that do not parse are kept as they are.
+fn reconcile_entries(now: &Timestamp) -> anyhow::Result<()> {
+    state::update_journal(Path::new(AUDIT_LOG), |records| {
+        let mut changed = false;
+        for record in records.iter_mut() {
+            let Ok(mut entry) = serde_json::from_str::<AuditEntry>(record) else {
+                continue;
+            };
+            if entry.time.reconcile(now)
+                && let Ok(json) = serde_json::to_string(&entry)
+            {
+                *record = json;
+                changed = true;
+            }
+        }
+        changed
+    })?;
+    Ok(())
+}
+
+pub fn list_entries(limit: Option<usize>) -> anyhow::Result<()> {
+    let entries = read_entries()?;
+    let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+
+    info!("audit log:");
+    if entries.is_empty() {
+        info!("  (no entries)");
+    }
+
+    for entry in entries.iter().skip(skip) {
+        let who = match &entry.client.token_id {
+            Some(token) => format!("{}[{token}]", entry.client.peer),
+            None => entry.client.peer.clone(),
+        };
+        // entries stay in append order, which is correct even when the
+        // clock was not; flag the readings that could not be corrected
+        let unsure = if entry.time.clock_plausible { "" } else { "?" };
+        info!(
+            "{}{unsure} {who} `{}` {:?} -> {}",
+            entry.time, entry.command, entry.args, entry.result
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::archive::{self, Limits};
+use crate::clock;
+use crate::defs::{
+    CHANNELS_FILE, CONFIG_FILE, KEYS_DIR, MODULE_DATA_DIR, MODULES_DIR, MODULES_UPDATE_DIR,
+    PROFILES_DIR, REPOS_FILE, SCRIBA_DIR, TRUSTED_AUTHORS_FILE,
+};
+use crate::module;
+
+/// Description stored at the root of a backup archive.
+const MANIFEST: &str = "scriba-backup.json";
+
+/// Files and directories kept besides the modules: the config, the modules'
+/// data and the choices made with `trust`, `repo`, `module channel` and
+/// `module profile`, but no per-boot state, logs or caches.
+const KEPT: &[&str] = &[
+    CONFIG_FILE,
+    MODULE_DATA_DIR,
+    KEYS_DIR,
+    TRUSTED_AUTHORS_FILE,
+    REPOS_FILE,
+    CHANNELS_FILE,
+    PROFILES_DIR,
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(with = "clock::rfc3339_or_unix")]
+    created: u64,
+    /// Version of scriba that wrote the backup
+    scriba: String,
+    modules: Vec<String>,
+}
+
+/// Path of a `KEPT` entry inside the archive, relative to `SCRIBA_DIR`.
+fn archive_path(path: &str) -> &Path {
+    Path::new(path)
+        .strip_prefix(SCRIBA_DIR)
+        .expect("kept paths are below SCRIBA_DIR")
+}
+
+/// The directory a module is backed up from: its pending update when there
+/// is one, as that is what the next boot installs.
+fn module_source(module_id: &str) -> PathBuf {
+    let update_dir = Path::new(MODULES_UPDATE_DIR).join(module_id);
+    if update_dir.is_dir() {
+        update_dir
+    } else {
+        Path::new(MODULES_DIR).join(module_id)
+    }
+}
+
+/// Write every installed module with its flags, the config and the user's
+/// choices into a gzipped tar at `dest`. Returns the modules included.
+pub fn create(dest: &Path) -> anyhow::Result<Vec<String>> {
+    let modules = module::module_ids();
+    let manifest = Manifest {
+        created: clock::unix_now(),
+        scriba: env!("CARGO_PKG_VERSION").to_string(),
+        modules: modules.clone(),
+    };
+
+    let file = File::create(dest).with_context(|| format!("failed to create {dest:?}"))?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    tar.follow_symlinks(false);
+
+    let content = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(manifest.created);
+    tar.append_data(&mut header, MANIFEST, content.as_slice())?;
+
+    for id in &modules {
+        let source = module_source(id);
+        tar.append_dir_all(Path::new("modules").join(id), &source)
+            .with_context(|| format!("failed to archive {source:?}"))?;
+        // flags live in the installed copy, not in a pending update
+        let installed = Path::new(MODULES_DIR).join(id);
+        if source != installed && installed.join("disable.flag").exists() {
+            tar.append_path_with_name(
+                installed.join("disable.flag"),
+                Path::new("modules").join(id).join("disable.flag"),
+            )?;
+        }
+    }
+
+    for path in KEPT {
+        let source = Path::new(path);
+        if source.is_dir() {
+            tar.append_dir_all(archive_path(path), source)?;
+        } else if source.is_file() {
+            tar.append_path_with_name(source, archive_path(path))?;
+        }
+    }
+
+    tar.into_inner()?.finish()?.sync_all()?;
+    Ok(modules)
+}
+
+/// Replace `target` with `source`, both on the same filesystem.
+fn replace(source: &Path, target: &Path) -> anyhow::Result<()> {
+    if fs::symlink_metadata(target).is_ok_and(|meta| meta.is_dir()) {
+        module::delete_dir(target)?;
+    } else if target.exists() {
+        fs::remove_file(target)?;
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(source, target)?;
+    Ok(())
+}
+
+/// Restore a backup made by `create`. Its modules are staged as pending
+/// updates and modules it does not contain are marked for uninstall, so
+/// the module set switches over at the next reboot; the config and the
+/// kept state files are replaced right away.
+pub fn restore(backup: &Path, limits: Limits) -> anyhow::Result<()> {
+    // unpack next to the modules so everything can be moved into place
+    fs::create_dir_all(SCRIBA_DIR)?;
+    let unpacked = tempfile::tempdir_in(SCRIBA_DIR)?;
+    archive::extract(backup, unpacked.path(), limits)?;
+
+    let manifest: Manifest = fs::read_to_string(unpacked.path().join(MANIFEST))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .with_context(|| format!("{backup:?} is not a scriba backup"))?;
+    info!(
+        "restoring backup of {} module(s) made by scriba {} at {}",
+        manifest.modules.len(),
+        manifest.scriba,
+        clock::local(manifest.created)
+    );
+
+    for id in &manifest.modules {
+        module::validate_module_id(id)?;
+        let source = unpacked.path().join("modules").join(id);
+        if !source.join("module.prop").is_file() {
+            bail!("module {id} in {backup:?} has no module.prop");
+        }
+    }
+
+    for id in module::installed_ids() {
+        if !manifest.modules.contains(&id) {
+            fs::write(Path::new(MODULES_DIR).join(&id).join("uninstall.flag"), "")?;
+            info!("module {id} is not in the backup, marked for uninstall");
+        }
+    }
+    for id in module::module_ids() {
+        let update_dir = Path::new(MODULES_UPDATE_DIR).join(&id);
+        if !manifest.modules.contains(&id) && update_dir.is_dir() {
+            module::delete_dir(&update_dir)?;
+        }
+    }
+    for id in &manifest.modules {
+        replace(
+            &unpacked.path().join("modules").join(id),
+            &Path::new(MODULES_UPDATE_DIR).join(id),
+        )?;
+        let uninstall_flag = Path::new(MODULES_DIR).join(id).join("uninstall.flag");
+        if uninstall_flag.exists() {
+            fs::remove_file(uninstall_flag)?;
+        }
+        info!("module {id} staged");
+    }
+
+    for path in KEPT {
+        let source = unpacked.path().join(archive_path(path));
+        if fs::symlink_metadata(&source).is_err() {
+            continue;
+        }
+        if let Err(e) = replace(&source, Path::new(path)) {
+            warn!("failed to restore {path}: {e:#}");
+            continue;
+        }
+        info!("restored {path}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,899 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use tempfile::tempdir;
+use tracing::{error, info, warn};
+
+use crate::changelog;
+use crate::clock::{Clock, Timestamp};
+use crate::config::AppConfig;
+use crate::critical;
+use crate::defs::{
+    ADB_AUTH_FLAG, LAST_BOOT_FILE, MODULES_DIR, MODULES_UPDATE_DIR, PATH_PROFILE_FILE,
+    SAFE_MODE_FLAG, STATE_DIR,
+};
+use crate::logging::Heartbeat;
+use crate::maintenance;
+use crate::metrics;
+use crate::module::{self, ScriptResult};
+use crate::mount;
+use crate::path_env;
+use crate::rollback;
+use crate::state;
+use crate::storage;
+use crate::strict;
+
+/// A staged update that replaces (or adds) an installed module.
+pub struct Promotion {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub from_version: Option<i64>,
+    pub to_version: Option<i64>,
+}
+
+/// Directory-level decisions of boot-complete, taken before anything moves.
+pub struct BootPlan {
+    /// Installed modules flagged for uninstall
+    pub removals: Vec<PathBuf>,
+    pub promotions: Vec<Promotion>,
+    /// Modules are left alone when the safe mode flag is set
+    pub safe_mode: bool,
+}
+
+/// What boot-complete does with a single module.
+pub enum ModuleStep {
+    /// module.prop is missing or fails validation
+    Invalid(anyhow::Error),
+    Disabled,
+    Init {
+        props: HashMap<String, String>,
+        mount: bool,
+        script: bool,
+    },
+}
+
+/// Subdirectories of `dir`, in name order.
+fn module_dirs(dir: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Module directories in `dir` in the order they are mounted.
+fn mount_order(dir: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = module_dirs(dir)?;
+    dirs.sort_by_cached_key(|path| module::mount_order_key(path));
+    Ok(dirs)
+}
+
+pub fn plan() -> Result<BootPlan> {
+    let removals = module_dirs(MODULES_DIR)?
+        .into_iter()
+        .filter(|path| path.join("uninstall.flag").exists())
+        .collect();
+
+    let mut promotions = Vec::new();
+    for source in module_dirs(MODULES_UPDATE_DIR)? {
+        let Some(name) = source.file_name() else {
+            continue;
+        };
+        let target = Path::new(MODULES_DIR).join(name);
+        promotions.push(Promotion {
+            from_version: module::module_version(&target),
+            to_version: module::module_version(&source),
+            source,
+            target,
+        });
+    }
+
+    Ok(BootPlan {
+        removals,
+        promotions,
+        safe_mode: Path::new(SAFE_MODE_FLAG).exists(),
+    })
+}
+
+pub fn module_step(module_dir: &Path) -> ModuleStep {
+    let props = match module::read_module_prop(&module_dir.join("module.prop")) {
+        Ok(props) => props,
+        Err(err) => return ModuleStep::Invalid(err),
+    };
+
+    if module::disabled_this_boot(module_dir) {
+        return ModuleStep::Disabled;
+    }
+
+    ModuleStep::Init {
+        mount: props.get("skip_mount").map(String::as_str) != Some("true"),
+        script: module_dir.join("boot-complete.sh").exists(),
+        props,
+    }
+}
+
+/* =========================
+ * Boot complete
+ * ========================= */
+
+/// Outcome of the last boot-complete run, kept for `status`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BootRecord {
+    pub started: Timestamp,
+    pub duration_ms: u64,
+    /// `ok`, `safe mode`, or the error that stopped boot-complete
+    pub result: String,
+    /// Ids of modules that failed to initialize
+    pub failed_modules: Vec<String>,
+    /// Modules left to the late pass by a stage budget
+    #[serde(default)]
+    pub deferred: Vec<DeferredModule>,
+    /// Outcome of the late pass, `None` until it finished
+    #[serde(default)]
+    pub late_pass: Option<String>,
+    /// Modules whose mount target did not exist yet, left to
+    /// `internal late-mount`
+    #[serde(default)]
+    pub waiting_mounts: Vec<String>,
+    /// Outcome of retrying those mounts, `None` until it finished
+    #[serde(default)]
+    pub late_mount: Option<String>,
+}
+
+/// Boot stage a deferred module still has to go through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeferredStage {
+    /// Not mounted yet; the script runs after it
+    Mount,
+    /// Mounted, boot-complete.sh not run yet
+    Script,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeferredModule {
+    pub id: String,
+    pub stage: DeferredStage,
+}
+
+/// Time spent in a boot stage against its configured budget.
+struct StageBudget {
+    limit: Option<Duration>,
+    spent: Duration,
+}
+
+impl StageBudget {
+    fn new(secs: u64) -> Self {
+        Self {
+            limit: (secs > 0).then(|| Duration::from_secs(secs)),
+            spent: Duration::ZERO,
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.limit.is_some_and(|limit| self.spent >= limit)
+    }
+
+    fn spend<T>(&mut self, clock: &dyn Clock, work: impl FnOnce() -> T) -> T {
+        let start = clock.since_boot();
+        let result = work();
+        self.spent += clock.since_boot().saturating_sub(start);
+        result
+    }
+}
+
+pub fn last_boot() -> Option<BootRecord> {
+    let content = fs::read_to_string(LAST_BOOT_FILE).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save_boot_record(record: &BootRecord) -> Result<()> {
+    fs::create_dir_all(STATE_DIR)?;
+    state::write_atomic(
+        Path::new(LAST_BOOT_FILE),
+        serde_json::to_string_pretty(record)?,
+    )?;
+    Ok(())
+}
+
+/// Parts of boot-complete to leave out, for manual recovery and debugging.
+pub struct BootOptions {
+    pub skip_mount: bool,
+    pub skip_scripts: bool,
+    /// Initialize only these modules; all of them when empty
+    pub only_modules: Vec<String>,
+}
+
+impl BootOptions {
+    fn includes(&self, path: &Path) -> bool {
+        self.only_modules.is_empty() || self.only_modules.contains(&module_id(path))
+    }
+
+    /// What was left out, for the boot record.
+    fn describe(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.skip_mount {
+            parts.push("mounts skipped".to_string());
+        }
+        if self.skip_scripts {
+            parts.push("scripts skipped".to_string());
+        }
+        if !self.only_modules.is_empty() {
+            parts.push(format!("only {}", self.only_modules.join(", ")));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+/// Set while the boot sequence runs, passed to module scripts as `BOOTMODE`.
+static BOOTING: AtomicBool = AtomicBool::new(false);
+
+/// Whether scripts run now are part of the boot sequence rather than a
+/// command run by hand.
+pub fn booting() -> bool {
+    BOOTING.load(Ordering::Relaxed)
+}
+
+pub fn boot_complete(config: &AppConfig, clock: &dyn Clock, options: &BootOptions) -> Result<()> {
+    BOOTING.store(true, Ordering::Relaxed);
+    let started = Timestamp::read(clock);
+    let since_boot = clock.since_boot();
+    let mut failed_modules = Vec::new();
+    let mut deferred = Vec::new();
+    let mut waiting_mounts = Vec::new();
+
+    let result = run_boot_complete(
+        config,
+        clock,
+        options,
+        &mut failed_modules,
+        &mut deferred,
+        &mut waiting_mounts,
+    );
+
+    let mut record = BootRecord {
+        started,
+        duration_ms: clock.since_boot().saturating_sub(since_boot).as_millis() as u64,
+        result: match &result {
+            Ok(true) => match options.describe() {
+                Some(partial) => format!("ok ({partial})"),
+                None => "ok".to_string(),
+            },
+            Ok(false) => "safe mode".to_string(),
+            Err(e) => format!("failed: {e}"),
+        },
+        failed_modules,
+        deferred,
+        late_pass: None,
+        waiting_mounts,
+        late_mount: None,
+    };
+    if let Err(e) = save_boot_record(&record) {
+        warn!("failed to record boot result in {LAST_BOOT_FILE}: {e}");
+    }
+    metrics::write_boot_metrics(&config.metrics, &record);
+
+    if !record.deferred.is_empty() {
+        let failed = late_pass(
+            config,
+            options,
+            &record.deferred,
+            &mut record.failed_modules,
+            &mut record.waiting_mounts,
+        );
+        record.late_pass = Some(match failed {
+            0 => "ok".to_string(),
+            n => format!("{n} module(s) failed"),
+        });
+        if let Err(e) = save_boot_record(&record) {
+            warn!("failed to record late pass result in {LAST_BOOT_FILE}: {e}");
+        }
+        metrics::write_boot_metrics(&config.metrics, &record);
+    }
+
+    if !record.waiting_mounts.is_empty() {
+        retry_mounts(config, !options.skip_scripts, &mut record);
+        if let Err(e) = save_boot_record(&record) {
+            warn!("failed to record late mount result in {LAST_BOOT_FILE}: {e}");
+        }
+        metrics::write_boot_metrics(&config.metrics, &record);
+    }
+
+    if result.as_ref().is_ok_and(|&initialized| initialized)
+        && (!record.deferred.is_empty() || record.late_mount.is_some())
+    {
+        write_path_profile(
+            Some(options),
+            &record.failed_modules,
+            &record.waiting_mounts,
+        );
+    }
+
+    result.map(|_| ())
+}
+
+fn module_id(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into()
+}
+
+/// Write the PATH profile for the modules `options` initializes, or every
+/// module without options, leaving out those that failed or whose mount
+/// target is missing.
+fn write_path_profile(options: Option<&BootOptions>, failed: &[String], waiting: &[String]) {
+    let dirs = path_env::dirs(|path| {
+        let id = module_id(path);
+        options.is_none_or(|options| options.includes(path))
+            && !failed.contains(&id)
+            && !waiting.contains(&id)
+    });
+    if let Err(e) = path_env::write_profile(&dirs) {
+        warn!("failed to write {PATH_PROFILE_FILE}: {e:#}");
+    }
+}
+
+fn record_failure(path: &Path, err: anyhow::Error, failed_modules: &mut Vec<String>) {
+    error!("failed to initialize module {path:?}: {err:#}");
+    failed_modules.push(module_id(path));
+}
+
+/// Record a failed mount stage, or queue the module for `retry_mounts`
+/// when the failure was a path that does not exist yet.
+fn mount_failure(
+    path: &Path,
+    err: anyhow::Error,
+    failed_modules: &mut Vec<String>,
+    waiting_mounts: &mut Vec<String>,
+) {
+    if mount::is_missing_path(&err) {
+        warn!(
+            "module {path:?} mounts onto a path that does not exist yet, retrying later: {err:#}"
+        );
+        waiting_mounts.push(module_id(path));
+    } else {
+        record_failure(path, err, failed_modules);
+    }
+}
+
+/// Returns whether modules were initialized, i.e. safe mode was not set.
+fn run_boot_complete(
+    config: &AppConfig,
+    clock: &dyn Clock,
+    options: &BootOptions,
+    failed_modules: &mut Vec<String>,
+    deferred: &mut Vec<DeferredModule>,
+    waiting_mounts: &mut Vec<String>,
+) -> Result<bool> {
+    info!("executing boot complete logic");
+
+    // 1. Unlock adb shell by creating /tmp/.adb_auth_verified
+    info!("unlocking adb shell");
+    if let Err(e) = fs::File::create(ADB_AUTH_FLAG) {
+        warn!("failed to unlock adb shell by creating {ADB_AUTH_FLAG}: {e}");
+    } else {
+        info!("adb shell unlocked by creating {ADB_AUTH_FLAG}");
+    }
+
+    // a full partition would fail the moves and mounts below
+    if let Err(e) = maintenance::reclaim(&config.maintenance) {
+        warn!("failed to reclaim disk space: {e:#}");
+    }
+
+    let plan = plan()?;
+
+    // 2. Remove uninstall flagged modules
+    info!("removing uninstall flagged modules");
+    for path in &plan.removals {
+        // last chance to clean up state kept outside the module directory
+        if path.join("pre-uninstall.sh").is_file() {
+            info!("executing pre-uninstall.sh in {path:?}");
+            if let Err(e) = module::run_script(path, "pre-uninstall.sh", &config.scripts)
+                .and_then(ScriptResult::check)
+            {
+                warn!("pre-uninstall.sh of {path:?} failed, removing it anyway: {e:#}");
+            }
+        }
+        info!("removing {path:?}");
+        let purge = path.join("purge.flag").exists();
+        if let Err(e) = module::delete_dir(path) {
+            warn!("failed to delete module dir {path:?}: {e}");
+        }
+        if purge && let Err(e) = module::wipe_data(&module_id(path)) {
+            warn!("failed to delete data of {path:?}: {e:#}");
+        }
+        if let Err(e) = critical::restore(&module_id(path)) {
+            warn!("failed to restore critical paths of {path:?}: {e:#}");
+        }
+        if let Err(e) = rollback::remove(&module_id(path)) {
+            warn!("failed to delete kept versions of {path:?}: {e}");
+        }
+    }
+
+    // 3. Move update modules
+    info!("installing update pending modules");
+    for promotion in &plan.promotions {
+        let (path, target) = (&promotion.source, &promotion.target);
+        info!("updating {path:?}");
+        if let (Some(from), Some(to)) = (promotion.from_version, promotion.to_version) {
+            changelog::show_update(path, from, to);
+        }
+        // keep the replaced version for `module rollback`
+        if let Some(from) = promotion.from_version
+            && let Err(e) = rollback::archive(&module_id(target), target, from)
+        {
+            warn!("failed to keep v{from} of {target:?}, it is replaced: {e:#}");
+        }
+        // staged with install --clean
+        if path.join("clean.flag").exists() {
+            if let Err(e) = module::replace_data(&module_id(target)) {
+                warn!("failed to replace data of {target:?}: {e:#}");
+            }
+            let _ = fs::remove_file(path.join("clean.flag"));
+        }
+        if let Err(e) = module::move_dir(path, target) {
+            warn!("failed to move update module {path:?} to {target:?}: {e}");
+        }
+    }
+
+    if plan.safe_mode {
+        warn!("safe mode flag exists, not initializing modules");
+        if let Err(e) = path_env::write_profile(&[]) {
+            warn!("failed to write {PATH_PROFILE_FILE}: {e:#}");
+        }
+        return Ok(false);
+    }
+
+    // 4. Initialize modules: mount all of them, then run their scripts,
+    // leaving whatever does not fit in a stage budget to the late pass
+    info!("initializing modules");
+    let mut mount_budget = StageBudget::new(config.boot.mount_budget_secs);
+    let mut mounted = Vec::new();
+    for installed in &options.only_modules {
+        if !Path::new(MODULES_DIR).join(installed).is_dir() {
+            warn!("module {installed} is not installed, cannot initialize only it");
+        }
+    }
+    // post-mount scripts run with the modules about to be initialized
+    write_path_profile(Some(options), failed_modules, waiting_mounts);
+    let paths = mount_order(MODULES_DIR)?;
+    let mut heartbeat = Heartbeat::new("mounting modules");
+    for (index, path) in paths.iter().cloned().enumerate() {
+        heartbeat.tick(|| format!("{index} of {} module(s) done", paths.len()));
+        if !options.includes(&path) {
+            continue;
+        }
+        if mount_budget.exhausted() {
+            warn!(
+                "mount budget of {}s used up, deferring {path:?} to the late pass",
+                config.boot.mount_budget_secs
+            );
+            deferred.push(DeferredModule {
+                id: module_id(&path),
+                stage: DeferredStage::Mount,
+            });
+            continue;
+        }
+
+        info!("initializing {path:?}");
+        match mount_budget.spend(clock, || mount_stage(&path, config, !options.skip_mount)) {
+            Ok(Some(script)) => mounted.push((path, script)),
+            Ok(None) => {}
+            Err(e) => mount_failure(&path, e, failed_modules, waiting_mounts),
+        }
+    }
+
+    // before the scripts, which run with the same PATH
+    write_path_profile(Some(options), failed_modules, waiting_mounts);
+
+    if options.skip_scripts {
+        info!("--skip-scripts given, not running boot-complete.sh");
+        return Ok(true);
+    }
+
+    let mut script_budget = StageBudget::new(config.boot.script_budget_secs);
+    let mut heartbeat = Heartbeat::new("running module scripts");
+    let total = mounted.len();
+    for (index, (path, script)) in mounted.into_iter().enumerate() {
+        heartbeat.tick(|| format!("{index} of {total} module(s) done"));
+        if script && script_budget.exhausted() {
+            warn!(
+                "script budget of {}s used up, deferring boot-complete.sh of {path:?} to the late pass",
+                config.boot.script_budget_secs
+            );
+            deferred.push(DeferredModule {
+                id: module_id(&path),
+                stage: DeferredStage::Script,
+            });
+            continue;
+        }
+
+        if let Err(e) = script_budget.spend(clock, || script_stage(&path, script, config)) {
+            record_failure(&path, e, failed_modules);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Finish deferred modules once the rest of boot-complete is done. Returns
+/// how many failed.
+fn late_pass(
+    config: &AppConfig,
+    options: &BootOptions,
+    deferred: &[DeferredModule],
+    failed_modules: &mut Vec<String>,
+    waiting_mounts: &mut Vec<String>,
+) -> usize {
+    info!("late pass: finishing {} deferred module(s)", deferred.len());
+    let failed_before = failed_modules.len();
+
+    let mut scripts = Vec::new();
+    for module in deferred {
+        let path = Path::new(MODULES_DIR).join(&module.id);
+        match module.stage {
+            DeferredStage::Mount => {
+                info!("initializing {path:?}");
+                match mount_stage(&path, config, !options.skip_mount) {
+                    Ok(Some(script)) => scripts.push((path, script)),
+                    Ok(None) => {}
+                    Err(e) => mount_failure(&path, e, failed_modules, waiting_mounts),
+                }
+            }
+            DeferredStage::Script => scripts.push((path, true)),
+        }
+    }
+
+    for (path, script) in scripts {
+        if options.skip_scripts {
+            break;
+        }
+        if let Err(e) = script_stage(&path, script, config) {
+            record_failure(&path, e, failed_modules);
+        }
+    }
+
+    failed_modules.len() - failed_before
+}
+
+/// Retry the mounts of `record.waiting_mounts` up to `boot.mount_retries`
+/// times, `boot.mount_retry_delay_secs` apart, then run their scripts.
+/// Modules whose target is still missing after that count as failed but
+/// stay waiting, so `internal late-mount` can try them again.
+fn retry_mounts(config: &AppConfig, run_scripts: bool, record: &mut BootRecord) {
+    let retries = config.boot.mount_retries;
+    let mut waiting = std::mem::take(&mut record.waiting_mounts);
+    // a module that mounts now no longer counts as failed
+    record.failed_modules.retain(|id| !waiting.contains(id));
+    let mut failed = 0;
+
+    for attempt in 1..=retries {
+        if waiting.is_empty() {
+            break;
+        }
+        std::thread::sleep(Duration::from_secs(config.boot.mount_retry_delay_secs));
+        info!(
+            "retrying mounts of {} module(s), attempt {attempt} of {retries}",
+            waiting.len()
+        );
+
+        let mut still_waiting = Vec::new();
+        for id in waiting {
+            let path = Path::new(MODULES_DIR).join(&id);
+            // drop what a partial attempt left mounted
+            if let Err(e) = mount::unmount_module(&id) {
+                warn!("failed to undo earlier mounts of {id}: {e:#}");
+            }
+            match mount_stage(&path, config, true) {
+                Ok(Some(script)) => {
+                    info!("module {id} mounted on retry");
+                    if run_scripts && let Err(e) = script_stage(&path, script, config) {
+                        record_failure(&path, e, &mut record.failed_modules);
+                        failed += 1;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) if mount::is_missing_path(&e) => {
+                    info!("mount target of {id} still missing: {e:#}");
+                    still_waiting.push(id);
+                }
+                Err(e) => {
+                    record_failure(&path, e, &mut record.failed_modules);
+                    failed += 1;
+                }
+            }
+        }
+        waiting = still_waiting;
+    }
+
+    for id in &waiting {
+        error!("mount target of {id} did not appear after {retries} retries");
+        record.failed_modules.push(id.clone());
+    }
+    failed += waiting.len();
+    record.waiting_mounts = waiting;
+
+    record.late_mount = Some(match failed {
+        0 => "ok".to_string(),
+        n => format!("{n} module(s) failed"),
+    });
+}
+
+/// Retry the mounts the last boot-complete left waiting for their targets,
+/// e.g. once the services creating those targets are up.
+pub fn late_mount(config: &AppConfig) -> Result<()> {
+    let Some(mut record) = last_boot() else {
+        bail!("no boot record in {LAST_BOOT_FILE}, boot-complete has not run");
+    };
+    if record.waiting_mounts.is_empty() {
+        info!("no module mounts are waiting for their targets");
+        return Ok(());
+    }
+
+    retry_mounts(config, true, &mut record);
+    // --only-module of the boot is not recorded, so this covers every module
+    write_path_profile(None, &record.failed_modules, &record.waiting_mounts);
+    save_boot_record(&record)?;
+    metrics::write_boot_metrics(&config.metrics, &record);
+
+    match record.late_mount.as_deref() {
+        Some("ok") | None => Ok(()),
+        Some(outcome) => bail!("late mount: {outcome}"),
+    }
+}
+
+pub fn init_module(path: &Path, config: &AppConfig) -> Result<()> {
+    match mount_stage(path, config, true)? {
+        Some(script) => script_stage(path, script, config),
+        None => Ok(()),
+    }
+}
+
+/// Mount every enabled module again without rebooting, undoing what each
+/// had mounted first.
+pub fn mount_all(config: &AppConfig) -> Result<()> {
+    if Path::new(SAFE_MODE_FLAG).exists() {
+        bail!("safe mode flag exists, not mounting modules");
+    }
+
+    let mut modules = Vec::new();
+    for path in mount_order(MODULES_DIR)? {
+        match module_step(&path) {
+            ModuleStep::Init { mount: true, .. } => modules.push((module_id(&path), path)),
+            ModuleStep::Invalid(e) => warn!("skipping {path:?}, invalid properties: {e}"),
+            _ => {}
+        }
+    }
+
+    // everything comes off first, modules can share replicas and overlays
+    for (id, _) in modules.iter().rev() {
+        mount::unmount_module(id)?;
+    }
+
+    let mut failed = Vec::new();
+    for (id, path) in &modules {
+        info!("mounting {id}");
+        if let Err(e) = mount::mount_installed(path, &config.module(id), &config.guard) {
+            error!("failed to mount {id}: {e:#}");
+            failed.push(id.as_str());
+        }
+    }
+
+    if !failed.is_empty() {
+        bail!("failed to mount {failed:?}");
+    }
+    Ok(())
+}
+
+/// Mount a module unless it is disabled or `mount_allowed` is false.
+/// Returns whether it has a boot-complete.sh, or `None` when it is disabled.
+fn mount_stage(path: &Path, config: &AppConfig, mount_allowed: bool) -> Result<Option<bool>> {
+    let (props, mount, script) = match module_step(path) {
+        ModuleStep::Invalid(err) => bail!("invalid properties: {err}"),
+        ModuleStep::Disabled => {
+            warn!("module {path:?} is disabled, not initializing it");
+            return Ok(None);
+        }
+        ModuleStep::Init {
+            props,
+            mount,
+            script,
+        } => (props, mount, script),
+    };
+
+    info!(
+        "module info: {}, {}, {}, {}",
+        props["id"], props["name"], props["description"], props["version"]
+    );
+
+    info!("mounting module {path:?}");
+    if !mount_allowed {
+        info!("--skip-mount given, not mounting module");
+    } else if mount {
+        mount::mount_installed(path, &config.module(&props["id"]), &config.guard)?;
+        if path.join("post-mount.sh").is_file() {
+            info!("executing post-mount.sh in {path:?}");
+            module::run_script(path, "post-mount.sh", &config.scripts)
+                .and_then(ScriptResult::check)
+                .context("post-mount.sh failed")?;
+        }
+    } else {
+        info!("module has skip_mount, not mounting module")
+    }
+
+    Ok(Some(script))
+}
+
+fn script_stage(path: &Path, script: bool, config: &AppConfig) -> Result<()> {
+    info!("executing boot-complete.sh in {path:?}");
+    if script {
+        module::run_script(path, "boot-complete.sh", &config.scripts)
+            .and_then(ScriptResult::check)
+            .context("boot-complete.sh failed")?;
+    } else {
+        strict::warn_or_bail!("boot-complete.sh does not exist");
+    }
+
+    Ok(())
+}
+
+/* =========================
+ * Simulation
+ * ========================= */
+
+/// Print what the next boot-complete would do, without changing anything.
+pub fn simulate(config: &AppConfig) -> Result<()> {
+    let plan = plan()?;
+
+    info!("simulated boot plan (nothing is changed):");
+    info!("  unlock adb shell ({ADB_AUTH_FLAG})");
+
+    for path in &plan.removals {
+        if path.join("pre-uninstall.sh").is_file() {
+            info!("  run pre-uninstall.sh of {path:?}");
+        }
+        info!("  remove {path:?} (uninstall flagged)");
+        if path.join("purge.flag").exists() {
+            info!(
+                "  delete {:?} (purge flagged)",
+                module::data_dir(&module_id(path))
+            );
+        }
+    }
+
+    for promotion in &plan.promotions {
+        let version = |v: Option<i64>| v.map_or("?".to_string(), |v| format!("v{v}"));
+        info!(
+            "  update {:?}: {} -> {}",
+            promotion.target,
+            version(promotion.from_version),
+            version(promotion.to_version)
+        );
+        if promotion.source.join("clean.flag").exists() {
+            info!(
+                "  replace {:?} with the data postinstall.sh prepared (clean flagged)",
+                module::data_dir(&module_id(&promotion.target))
+            );
+        }
+    }
+
+    if plan.safe_mode {
+        warn!("  safe mode flag exists ({SAFE_MODE_FLAG}), modules will not be initialized");
+        return Ok(());
+    }
+
+    // module set as it will be after removals and promotions, keyed by
+    // directory name, pointing at where the contents currently live
+    let mut modules = BTreeMap::new();
+    for path in module_dirs(MODULES_DIR)? {
+        if !plan.removals.contains(&path)
+            && let Some(name) = path.file_name()
+        {
+            modules.insert(name.to_os_string(), path);
+        }
+    }
+    for promotion in &plan.promotions {
+        if let Some(name) = promotion.target.file_name() {
+            modules.insert(name.to_os_string(), promotion.source.clone());
+        }
+    }
+
+    if modules.is_empty() {
+        info!("  no modules to initialize");
+    }
+
+    let mut paths: Vec<&PathBuf> = modules.values().collect();
+    paths.sort_by_cached_key(|path| module::mount_order_key(path));
+    for path in paths {
+        simulate_module(path, config);
+    }
+
+    Ok(())
+}
+
+fn simulate_module(path: &Path, config: &AppConfig) {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (props, mount, script) = match module_step(path) {
+        ModuleStep::Invalid(err) => {
+            warn!("  module {name}: invalid properties ({err}), skipped");
+            return;
+        }
+        ModuleStep::Disabled => {
+            info!("  module {name}: disabled, skipped");
+            return;
+        }
+        ModuleStep::Init {
+            props,
+            mount,
+            script,
+        } => (props, mount, script),
+    };
+
+    info!("  module {name} (v{}):", props["version"]);
+
+    if mount {
+        let module_config = config.module(&props["id"]);
+        if let Some(change) = storage::planned_change(path, module_config.storage) {
+            info!("    storage: {change}");
+        }
+
+        let mounter = mount::mounter(mount::module_mode(&module_config, path));
+
+        let mount_plan = tempdir().map_err(anyhow::Error::from).and_then(|scratch| {
+            let dir = storage::preview(path, scratch.path())?;
+            let language = mount::device_language(module_config.locale.as_deref());
+            let mount_plan = mount::plan_module(&dir, mounter.as_ref(), language.as_deref())?;
+            Ok((dir, mount_plan))
+        });
+
+        match mount_plan {
+            Ok((dir, mount_plan)) => {
+                info!(
+                    "    mount {} path(s) ({}):",
+                    mount_plan.targets.len(),
+                    mounter.name()
+                );
+                for (src, dst) in &mount_plan.targets {
+                    let rel = src.strip_prefix(&dir).unwrap_or(src);
+                    info!("      {dst:?} <- {}", rel.display());
+                }
+                for (src, dst) in &mount_plan.additions {
+                    let rel = src.strip_prefix(&dir).unwrap_or(src);
+                    info!("      {dst:?} (new) <- {}", rel.display());
+                }
+                for path in &mount_plan.hidden {
+                    info!("      {path:?} (hidden)");
+                }
+                for (kind, (count, examples)) in mount_plan.warnings.by_kind() {
+                    warn!("    {count} x {kind}, e.g. {examples:?}");
+                }
+            }
+            Err(err) => warn!("    mount would fail: {err}"),
+        }
+        if path.join("post-mount.sh").is_file() {
+            info!("    run post-mount.sh");
+        }
+    } else {
+        info!("    skip_mount set, not mounting");
+    }
+
+    if let Some(value) = props.get("path_dirs") {
+        info!("    add to PATH: {value}");
+    }
+
+    if script {
+        info!("    run boot-complete.sh");
+    } else {
+        info!("    no boot-complete.sh");
+    }
+}
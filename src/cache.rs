@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use tempfile::{TempDir, tempdir};
+use tracing::{info, warn};
+
+use crate::clock::{self, Clock};
+use crate::config::CacheConfig;
+use crate::ids::IdSource;
+use crate::integrity;
+use crate::logging::Heartbeat;
+use crate::state;
+
+/// A downloaded file, stored once per content hash under `blobs/`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub sha256: String,
+    pub size: u64,
+    /// File name taken from the URL, used when pushing to a device
+    pub name: String,
+    #[serde(with = "clock::rfc3339_or_unix")]
+    pub fetched: u64,
+    #[serde(with = "clock::rfc3339_or_unix")]
+    pub last_used: u64,
+}
+
+/// Cached downloads keyed by URL.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Cache directory of the running test, instead of the user's.
+    static TEST_CACHE_DIR: std::cell::RefCell<Option<PathBuf>> = const { std::cell::RefCell::new(None) };
+}
+
+/// `$XDG_CACHE_HOME/scriba`, falling back to `~/.cache/scriba`.
+pub fn cache_dir() -> PathBuf {
+    #[cfg(test)]
+    if let Some(dir) = TEST_CACHE_DIR.with_borrow(Clone::clone) {
+        return dir;
+    }
+
+    if let Ok(xdg) = std::env::var("XDG_CACHE_HOME") {
+        Path::new(&xdg).join("scriba")
+    } else if let Ok(home) = std::env::var("HOME") {
+        Path::new(&home).join(".cache/scriba")
+    } else {
+        PathBuf::from("scriba-cache")
+    }
+}
+
+fn index_path() -> PathBuf {
+    cache_dir().join("index.json")
+}
+
+fn blob_path(sha256: &str) -> PathBuf {
+    cache_dir().join("blobs").join(sha256)
+}
+
+fn load_index() -> anyhow::Result<Index> {
+    match fs::read_to_string(index_path()) {
+        Ok(content) => Ok(serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("cache index is corrupt ({e}), starting over");
+            Index::default()
+        })),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Index::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_index(index: &Index) -> anyhow::Result<()> {
+    state::write_atomic(&index_path(), serde_json::to_string_pretty(index)?)?;
+    Ok(())
+}
+
+/// Last path segment of a URL, without query or fragment.
+fn url_file_name(url: &str) -> String {
+    url.split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download")
+        .to_string()
+}
+
+pub fn is_url(value: &str) -> bool {
+    value.starts_with("http://") || value.starts_with("https://")
+}
+
+/// Download `url` into `dest`, returning the content sha256 and size.
+/// Progress shows as a bar in a terminal and as heartbeat log lines.
+fn download(url: &str, dest: &Path) -> anyhow::Result<(String, u64)> {
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("failed to download {url}"))?;
+    let body = response.into_body();
+    let total = body.content_length();
+    let mut reader = body.into_reader();
+
+    let bar = match total {
+        Some(total) => ProgressBar::new(total),
+        None => ProgressBar::new_spinner(),
+    };
+    bar.set_style(
+        ProgressStyle::with_template("{bytes}/{total_bytes} {wide_bar} {bytes_per_sec}")
+            .expect("valid progress template"),
+    );
+    let mut heartbeat = Heartbeat::new(format!("downloading {url}"));
+
+    let mut file = fs::File::create(dest)?;
+    let mut size = 0;
+    let sha256 = integrity::sha256_each(&mut reader, |chunk| {
+        file.write_all(chunk)?;
+        size += chunk.len() as u64;
+        bar.inc(chunk.len() as u64);
+        heartbeat.tick(|| match total {
+            Some(total) => format!("{} of {} KiB", size >> 10, total >> 10),
+            None => format!("{} KiB", size >> 10),
+        });
+        Ok(())
+    })?;
+    bar.finish_and_clear();
+
+    Ok((sha256, size))
+}
+
+/// Download `url` for one-off use on the device, bypassing the cache, and
+/// check it against `sha256` when given. The file lives in a temporary
+/// directory removed with the returned handle.
+pub fn download_temp(url: &str, sha256: Option<&str>) -> anyhow::Result<(TempDir, PathBuf)> {
+    let dir = tempdir()?;
+    let path = dir.path().join(url_file_name(url));
+    info!("downloading {url}");
+    let (actual, size) = download(url, &path)?;
+    if let Some(expected) = sha256
+        && !expected.eq_ignore_ascii_case(&actual)
+    {
+        bail!("checksum mismatch for {url}: expected {expected}, got {actual}");
+    }
+    info!("downloaded {size} bytes, sha256 {actual}");
+    Ok((dir, path))
+}
+
+/// Check a local file against an expected sha256.
+pub fn verify_sha256(path: &Path, expected: &str) -> anyhow::Result<()> {
+    let actual = integrity::sha256(&mut fs::File::open(path)?)?;
+    if !expected.eq_ignore_ascii_case(&actual) {
+        bail!("checksum mismatch for {path:?}: expected {expected}, got {actual}");
+    }
+    Ok(())
+}
+
+/// Path of the cached download of `url`, fetching it first when missing.
+///
+/// A known `sha256` is checked both against the cached copy and against
+/// a fresh download.
+pub fn fetch(
+    url: &str,
+    sha256: Option<&str>,
+    config: &CacheConfig,
+    clock: &dyn Clock,
+    ids: &dyn IdSource,
+) -> anyhow::Result<(PathBuf, CacheEntry)> {
+    fs::create_dir_all(cache_dir().join("blobs"))?;
+    let mut index = load_index()?;
+    let now = clock.unix_now();
+
+    if let Some(entry) = index.entries.get_mut(url)
+        && blob_path(&entry.sha256).is_file()
+        && sha256.is_none_or(|expected| expected.eq_ignore_ascii_case(&entry.sha256))
+    {
+        info!("using cached download of {url}");
+        entry.last_used = now;
+        let entry = entry.clone();
+        save_index(&index)?;
+        return Ok((blob_path(&entry.sha256), entry));
+    }
+
+    info!("downloading {url}");
+    let tmp = cache_dir()
+        .join("blobs")
+        .join(format!(".download-{}", ids.next_id()));
+    let (actual, size) = download(url, &tmp).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })?;
+    if let Some(expected) = sha256
+        && !expected.eq_ignore_ascii_case(&actual)
+    {
+        let _ = fs::remove_file(&tmp);
+        bail!("checksum mismatch for {url}: expected {expected}, got {actual}");
+    }
+    fs::rename(&tmp, blob_path(&actual))?;
+
+    let entry = CacheEntry {
+        sha256: actual,
+        size,
+        name: url_file_name(url),
+        fetched: now,
+        last_used: now,
+    };
+    index.entries.insert(url.to_string(), entry.clone());
+    evict(
+        &mut index,
+        config.max_size_mb.saturating_mul(1024 * 1024),
+        url,
+    );
+    save_index(&index)?;
+
+    Ok((blob_path(&entry.sha256), entry))
+}
+
+/// Drop least recently used entries until the blobs fit in `max_size`.
+/// `keep` is never evicted, so a fetch always returns an existing file.
+fn evict(index: &mut Index, max_size: u64, keep: &str) {
+    let blob_size = |index: &Index| -> u64 {
+        let mut seen = std::collections::HashSet::new();
+        index
+            .entries
+            .values()
+            .filter(|e| seen.insert(e.sha256.clone()))
+            .map(|e| e.size)
+            .sum()
+    };
+
+    while blob_size(index) > max_size {
+        let Some(oldest) = index
+            .entries
+            .iter()
+            .filter(|(url, _)| *url != keep)
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(url, _)| url.clone())
+        else {
+            break;
+        };
+
+        let entry = index.entries.remove(&oldest).expect("key was just found");
+        if !index.entries.values().any(|e| e.sha256 == entry.sha256) {
+            let _ = fs::remove_file(blob_path(&entry.sha256));
+        }
+        info!("evicted {oldest} from the download cache");
+    }
+}
+
+/// Drop the least recently used download. Returns the bytes freed, or
+/// `None` when the cache is empty.
+pub fn evict_oldest() -> anyhow::Result<Option<u64>> {
+    let mut index = load_index()?;
+    let Some(oldest) = index
+        .entries
+        .iter()
+        .min_by_key(|(_, e)| e.last_used)
+        .map(|(url, _)| url.clone())
+    else {
+        return Ok(None);
+    };
+
+    let entry = index.entries.remove(&oldest).expect("key was just found");
+    let mut freed = 0;
+    // blobs are shared by URLs with the same content
+    if !index.entries.values().any(|e| e.sha256 == entry.sha256) {
+        fs::remove_file(blob_path(&entry.sha256))?;
+        freed = entry.size;
+    }
+    save_index(&index)?;
+    info!("evicted {oldest} from the download cache");
+    Ok(Some(freed))
+}
+
+pub fn list() -> anyhow::Result<()> {
+    let index = load_index()?;
+
+    info!("download cache ({:?}):", cache_dir());
+    if index.entries.is_empty() {
+        info!("  (empty)");
+    }
+
+    let mut total = 0;
+    for (url, entry) in &index.entries {
+        total += entry.size;
+        info!(
+            "  {url} - {} KiB, sha256 {}, last used {}",
+            entry.size / 1024,
+            entry.sha256.get(..12).unwrap_or(&entry.sha256),
+            clock::local(entry.last_used)
+        );
+    }
+    info!("total: {} KiB", total / 1024);
+
+    Ok(())
+}
+
+/// Remove every cached download.
+pub fn clean() -> anyhow::Result<()> {
+    match fs::remove_dir_all(cache_dir()) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    info!("download cache cleared");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::ids::FixedIds;
+
+    /// 2026-01-02T03:04:05Z
+    const NOW: u64 = 1_767_323_045;
+
+    const CLOCK: FixedClock = FixedClock {
+        unix: NOW,
+        uptime: 0,
+        boot_id: "boot",
+    };
+
+    /// Point the cache of this test thread at a fresh directory.
+    fn test_cache() -> TempDir {
+        let dir = tempdir().unwrap();
+        TEST_CACHE_DIR.set(Some(dir.path().to_path_buf()));
+        dir
+    }
+
+    /// Serve `body` once. Before sending it, wait for `watch` to appear and
+    /// report whether it did.
+    fn serve_once(body: &'static [u8], watch: PathBuf) -> (String, mpsc::Receiver<bool>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/modules/example.zip",
+            listener.local_addr().unwrap()
+        );
+        let (seen_tx, seen_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.flush().unwrap();
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !watch.exists() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            seen_tx.send(watch.exists()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        (url, seen_rx)
+    }
+
+    fn entry(sha256: &str, size: u64, last_used: u64) -> CacheEntry {
+        CacheEntry {
+            sha256: sha256.to_string(),
+            size,
+            name: "example.zip".to_string(),
+            fetched: last_used,
+            last_used,
+        }
+    }
+
+    #[test]
+    fn fetch_downloads_through_a_named_temp_file() {
+        let dir = test_cache();
+        let tmp = dir.path().join("blobs/.download-fixed");
+        let (url, seen) = serve_once(b"module bytes", tmp.clone());
+
+        let (path, fetched) = fetch(
+            &url,
+            None,
+            &CacheConfig::default(),
+            &CLOCK,
+            &FixedIds("fixed"),
+        )
+        .unwrap();
+
+        assert!(seen.recv().unwrap(), "no {tmp:?} while downloading");
+        assert!(!tmp.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"module bytes");
+        assert_eq!(path, blob_path(&fetched.sha256));
+        assert_eq!((fetched.fetched, fetched.last_used), (NOW, NOW));
+        assert_eq!(fetched.name, "example.zip");
+    }
+
+    #[test]
+    fn fetch_removes_the_temp_file_on_checksum_mismatch() {
+        let dir = test_cache();
+        let tmp = dir.path().join("blobs/.download-fixed");
+        let (url, _seen) = serve_once(b"module bytes", tmp.clone());
+
+        let result = fetch(
+            &url,
+            Some(&"0".repeat(64)),
+            &CacheConfig::default(),
+            &CLOCK,
+            &FixedIds("fixed"),
+        );
+
+        assert!(result.is_err());
+        assert!(!tmp.exists());
+        assert!(load_index().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn fetch_uses_the_cached_copy_and_marks_it_used() {
+        let _dir = test_cache();
+        let sha256 = "ab".repeat(32);
+        fs::create_dir_all(blob_path(&sha256).parent().unwrap()).unwrap();
+        fs::write(blob_path(&sha256), "cached").unwrap();
+        let url = "http://127.0.0.1:9/example.zip";
+        let mut index = Index::default();
+        index.entries.insert(url.to_string(), entry(&sha256, 6, 1));
+        save_index(&index).unwrap();
+
+        // checksums may be given in either case
+        let (path, fetched) = fetch(
+            url,
+            Some(&sha256.to_ascii_uppercase()),
+            &CacheConfig::default(),
+            &CLOCK,
+            &FixedIds("unused"),
+        )
+        .unwrap();
+
+        assert_eq!(path, blob_path(&sha256));
+        assert_eq!(fetched.last_used, NOW);
+        assert_eq!(load_index().unwrap().entries[url].last_used, NOW);
+    }
+
+    #[test]
+    fn evict_drops_least_recently_used_first() {
+        let _dir = test_cache();
+        let mut index = Index::default();
+        for (url, sha256, last_used) in [("a", "aa", 30), ("b", "bb", 10), ("c", "cc", 20)] {
+            fs::create_dir_all(blob_path(sha256).parent().unwrap()).unwrap();
+            fs::write(blob_path(sha256), "blob").unwrap();
+            index
+                .entries
+                .insert(url.to_string(), entry(sha256, 100, last_used));
+        }
+
+        evict(&mut index, 150, "a");
+
+        assert_eq!(index.entries.keys().collect::<Vec<_>>(), ["a"]);
+        assert!(blob_path("aa").exists());
+        assert!(!blob_path("bb").exists());
+        assert!(!blob_path("cc").exists());
+    }
+
+    #[test]
+    fn evict_keeps_the_fetched_entry_and_shared_blobs() {
+        let _dir = test_cache();
+        let mut index = Index::default();
+        fs::create_dir_all(blob_path("aa").parent().unwrap()).unwrap();
+        fs::write(blob_path("aa"), "blob").unwrap();
+        // same content under two URLs counts once
+        index.entries.insert("old".to_string(), entry("aa", 100, 1));
+        index.entries.insert("new".to_string(), entry("aa", 100, 2));
+        index
+            .entries
+            .insert("kept".to_string(), entry("kk", 100, 0));
+
+        evict(&mut index, 100, "kept");
+
+        assert_eq!(index.entries.keys().collect::<Vec<_>>(), ["kept"]);
+        assert!(!blob_path("aa").exists());
+
+        let mut index = Index::default();
+        index.entries.insert("old".to_string(), entry("aa", 100, 1));
+        index.entries.insert("new".to_string(), entry("aa", 100, 2));
+        evict(&mut index, 100, "new");
+        assert_eq!(index.entries.len(), 2, "shared blob already fits");
+    }
+}
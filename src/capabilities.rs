@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::clock::Timestamp;
+use crate::defs::{CAPABILITIES_FILE, RUN_STATE_DIR, SCRIBA_DIR};
+use crate::mount::{self, FuseOverlay};
+use crate::state;
+use crate::status;
+
+/// Interpreters whose presence is probed, the programs of the default
+/// `scripts.interpreters`.
+const PROBED_SHELLS: &[&str] = &["/bin/sh", "/bin/ash", "/bin/bash", "/usr/bin/env"];
+
+/// What the running kernel and firmware offer, probed once per boot.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub probed: Timestamp,
+    pub kernel: String,
+    pub overlayfs: bool,
+    /// The fuse filesystem and /dev/fuse
+    pub fuse: bool,
+    pub fuse_overlay_helper: Option<PathBuf>,
+    /// fsopen(2) and friends, Linux 5.2+
+    pub new_mount_api: bool,
+    /// Probed interpreters that exist
+    pub shells: Vec<String>,
+    pub busybox: Option<PathBuf>,
+    pub busybox_applets: Vec<String>,
+    /// Free bytes on the scriba partition when probed
+    pub free_bytes: Option<u64>,
+    /// `enforcing` or `permissive`, `None` without SELinux
+    pub selinux: Option<String>,
+    /// Active Linux security modules, e.g. capability,landlock
+    pub lsms: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn landlock(&self) -> bool {
+        self.lsms.iter().any(|lsm| lsm == "landlock")
+    }
+
+    /// Whether `program` is a probed interpreter this device lacks.
+    pub fn lacks_shell(&self, program: &str) -> bool {
+        PROBED_SHELLS.contains(&program) && !self.shells.iter().any(|shell| shell == program)
+    }
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_string())
+}
+
+fn new_mount_api() -> bool {
+    // SAFETY: fsopen only returns a descriptor, which is closed right away
+    let fd = unsafe { libc::syscall(libc::SYS_fsopen, c"tmpfs".as_ptr(), 0) };
+    if fd >= 0 {
+        unsafe { libc::close(fd as i32) };
+        return true;
+    }
+    // EPERM and the like still mean the syscall exists
+    std::io::Error::last_os_error().raw_os_error() != Some(libc::ENOSYS)
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+fn busybox_applets(busybox: &Path) -> Vec<String> {
+    Command::new(busybox)
+        .arg("--list")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Probe the device now.
+pub fn probe() -> Capabilities {
+    let busybox = find_in_path("busybox");
+    Capabilities {
+        probed: Timestamp::now(),
+        kernel: read_trimmed("/proc/sys/kernel/osrelease").unwrap_or_default(),
+        overlayfs: mount::kernel_supports("overlay"),
+        fuse: mount::kernel_supports("fuse") && Path::new("/dev/fuse").exists(),
+        fuse_overlay_helper: FuseOverlay::helper(),
+        new_mount_api: new_mount_api(),
+        shells: PROBED_SHELLS
+            .iter()
+            .filter(|shell| Path::new(shell).is_file())
+            .map(|shell| shell.to_string())
+            .collect(),
+        busybox_applets: busybox.as_deref().map(busybox_applets).unwrap_or_default(),
+        busybox,
+        free_bytes: status::filesystem_space(SCRIBA_DIR).map(|(_, free)| free),
+        selinux: read_trimmed("/sys/fs/selinux/enforce").map(|enforce| {
+            if enforce == "1" {
+                "enforcing".to_string()
+            } else {
+                "permissive".to_string()
+            }
+        }),
+        lsms: read_trimmed("/sys/kernel/security/lsm")
+            .map(|list| {
+                list.split(',')
+                    .filter(|lsm| !lsm.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+fn save(capabilities: &Capabilities) {
+    let written = fs::create_dir_all(RUN_STATE_DIR).and_then(|()| {
+        let json = serde_json::to_vec_pretty(capabilities)?;
+        state::write_atomic(Path::new(CAPABILITIES_FILE), json)
+    });
+    if let Err(e) = written {
+        warn!("failed to cache capabilities in {CAPABILITIES_FILE}: {e}");
+    }
+}
+
+/// Probe again and replace the cached result.
+pub fn refresh() -> Capabilities {
+    let capabilities = probe();
+    save(&capabilities);
+    capabilities
+}
+
+/// Capabilities cached this boot, probing them the first time.
+pub fn get() -> &'static Capabilities {
+    static CAPABILITIES: OnceLock<Capabilities> = OnceLock::new();
+    CAPABILITIES.get_or_init(|| {
+        fs::read_to_string(CAPABILITIES_FILE)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_else(refresh)
+    })
+}
+
+pub fn print(capabilities: &Capabilities) {
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    info!(
+        "kernel {} (probed at {})",
+        capabilities.kernel, capabilities.probed
+    );
+    info!("overlayfs: {}", yes_no(capabilities.overlayfs));
+    match &capabilities.fuse_overlay_helper {
+        Some(helper) => info!(
+            "fuse: {}, fuse-overlayfs: {}",
+            yes_no(capabilities.fuse),
+            helper.display()
+        ),
+        None => info!("fuse: {}, fuse-overlayfs: no", yes_no(capabilities.fuse)),
+    }
+    info!("new mount api: {}", yes_no(capabilities.new_mount_api));
+    if capabilities.shells.is_empty() {
+        warn!("shells: none of {PROBED_SHELLS:?}");
+    } else {
+        info!("shells: {}", capabilities.shells.join(", "));
+    }
+    match &capabilities.busybox {
+        Some(busybox) => info!(
+            "busybox: {} ({} applets)",
+            busybox.display(),
+            capabilities.busybox_applets.len()
+        ),
+        None => info!("busybox: no"),
+    }
+    if let Some(free) = capabilities.free_bytes {
+        info!("free space on {SCRIBA_DIR}: {} MiB", free >> 20);
+    }
+    info!(
+        "selinux: {}",
+        capabilities.selinux.as_deref().unwrap_or("no")
+    );
+    info!(
+        "security modules: {} (landlock: {})",
+        if capabilities.lsms.is_empty() {
+            "unknown".to_string()
+        } else {
+            capabilities.lsms.join(",")
+        },
+        yes_no(capabilities.landlock())
+    );
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use std::path::Path;
+
+use tracing::info;
+
+pub const CHANGELOG_FILE: &str = "CHANGELOG.md";
+
+/// A heading and the lines below it, up to the next heading.
+pub struct Section {
+    pub title: String,
+    /// First number in the heading, matched against module versions
+    pub version: Option<i64>,
+    pub lines: Vec<String>,
+}
+
+pub fn parse(content: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+
+    for line in content.lines() {
+        if let Some(title) = line.strip_prefix('#') {
+            let title = title.trim_start_matches('#').trim().to_string();
+            let version = title
+                .split(|c: char| !c.is_ascii_digit())
+                .find(|run| !run.is_empty())
+                .and_then(|run| run.parse().ok());
+            sections.push(Section {
+                title,
+                version,
+                lines: Vec::new(),
+            });
+        } else if let Some(section) = sections.last_mut() {
+            section.lines.push(line.to_string());
+        } else {
+            // text before the first heading
+            sections.push(Section {
+                title: String::new(),
+                version: None,
+                lines: vec![line.to_string()],
+            });
+        }
+    }
+
+    sections
+}
+
+/// Strip the markdown that reads badly as plain text.
+fn render_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    let text = match trimmed
+        .strip_prefix("- ")
+        .or_else(|| trimmed.strip_prefix("* "))
+    {
+        Some(item) => format!("• {item}"),
+        None => trimmed.to_string(),
+    };
+
+    format!("{indent}{}", text.replace("**", "").replace('`', ""))
+}
+
+pub fn print_sections<'a>(sections: impl IntoIterator<Item = &'a Section>) {
+    for section in sections {
+        if !section.title.is_empty() {
+            info!("{}", section.title);
+        }
+        for line in &section.lines {
+            if !line.trim().is_empty() {
+                info!("  {}", render_line(line));
+            }
+        }
+    }
+}
+
+pub fn read(module_dir: &Path) -> Option<Vec<Section>> {
+    fs::read_to_string(module_dir.join(CHANGELOG_FILE))
+        .ok()
+        .map(|content| parse(&content))
+}
+
+/// Print what changed between the installed version and a staged update.
+pub fn show_update(update_dir: &Path, from_version: i64, to_version: i64) {
+    let Some(sections) = read(update_dir) else {
+        return;
+    };
+
+    let relevant: Vec<_> = sections
+        .iter()
+        .filter(|s| {
+            s.version
+                .is_some_and(|v| v > from_version && v <= to_version)
+        })
+        .collect();
+    if relevant.is_empty() {
+        return;
+    }
+
+    info!("changes from v{from_version} to v{to_version}:");
+    print_sections(relevant);
+}
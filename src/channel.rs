@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::bail;
+use tracing::{info, warn};
+
+use crate::defs::{CHANNELS_FILE, MODULES_DIR};
+use crate::module;
+use crate::repo;
+use crate::state;
+
+/// Channel of modules nobody picked one for.
+pub const DEFAULT_CHANNEL: &str = "stable";
+
+/// Longest accepted channel name.
+const MAX_CHANNEL_LEN: usize = 32;
+
+pub fn validate(channel: &str) -> anyhow::Result<()> {
+    if channel.is_empty() || channel.len() > MAX_CHANNEL_LEN {
+        bail!("channel `{channel}` must be 1 to {MAX_CHANNEL_LEN} characters");
+    }
+    if !channel
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        bail!("channel `{channel}` may only contain a-z, 0-9 and '-'");
+    }
+    Ok(())
+}
+
+/// Chosen channels by module id; modules on the default channel are left out.
+fn load() -> anyhow::Result<BTreeMap<String, String>> {
+    match fs::read_to_string(CHANNELS_FILE) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save(channels: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    state::write_atomic(
+        Path::new(CHANNELS_FILE),
+        serde_json::to_string_pretty(channels)?,
+    )?;
+    Ok(())
+}
+
+/// Channel updates of `module_id` come from.
+pub fn of(module_id: &str) -> String {
+    load()
+        .ok()
+        .and_then(|mut channels| channels.remove(module_id))
+        .unwrap_or_else(|| DEFAULT_CHANNEL.to_string())
+}
+
+/// Follow `channel` for `module_id` from now on. Returns the previous one.
+pub fn set(module_id: &str, channel: &str) -> anyhow::Result<String> {
+    validate(channel)?;
+    let mut channels = load()?;
+    let previous = if channel == DEFAULT_CHANNEL {
+        channels.remove(module_id)
+    } else {
+        channels.insert(module_id.to_string(), channel.to_string())
+    };
+    save(&channels)?;
+    Ok(previous.unwrap_or_else(|| DEFAULT_CHANNEL.to_string()))
+}
+
+/// Warn when the newest release repositories offer on `channel` is older
+/// than the installed version of `module_id`.
+pub fn warn_downgrade(module_id: &str, channel: &str) -> anyhow::Result<()> {
+    let Some(installed) = module::module_version(&Path::new(MODULES_DIR).join(module_id)) else {
+        return Ok(());
+    };
+    let Some(offered) = repo::offered(module_id, channel)? else {
+        return Ok(());
+    };
+    if offered.version < installed {
+        warn!(
+            "{module_id} v{installed} is installed but the {channel} channel offers v{}, installing from it would be a downgrade",
+            offered.version
+        );
+    }
+    Ok(())
+}
+
+pub fn list() -> anyhow::Result<()> {
+    let channels = load()?;
+    if channels.is_empty() {
+        info!("every module follows the {DEFAULT_CHANNEL} channel");
+    }
+    for (id, channel) in &channels {
+        info!("{id}: {channel}");
+    }
+    Ok(())
+}
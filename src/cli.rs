@@ -1,13 +1,19 @@
+use clap::Command;
 use clap::builder::Styles;
 use clap::builder::styling::AnsiColor;
 use clap::builder::styling::Effects;
-use clap::{Parser, Subcommand, crate_description, crate_name, crate_version};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::{CommandFactory, Parser, Subcommand, crate_description, crate_name, crate_version};
 use clap_complete::Shell;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use tracing::warn;
 
+use crate::app::DeepLink;
+use crate::config;
 use crate::defs::AppFilter;
 use crate::defs::Environment;
+use crate::module;
 
 #[derive(Parser)]
 #[command(name = crate_name!(),
@@ -23,6 +29,28 @@ pub struct Cli {
     #[arg(long, global = true, value_enum)]
     pub force_env: Option<Environment>,
 
+    /// Serial of the adb device to run on (host only, overrides default_device)
+    #[arg(long, short = 's', visible_alias = "device", global = true)]
+    pub serial: Option<String>,
+
+    /// Write logs to this file (overrides $SCRIBA_LOG_DIR)
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Fail instead of warning about skipped files, missing boot scripts,
+    /// unusual app ids and defaulted module properties
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Print machine-readable JSON on stdout instead of log lines, for
+    /// `status`, `env`, `query`, `module list`, `module info`, `module grep`,
+    /// `module check-updates`, `module verify`, `module lint`,
+    /// `module conflicts`, `module why-disabled`, `module install --dry-run`,
+    /// `module uninstall --dry-run`, `mount list`, `repo search`, `app list`,
+    /// `app top` and `smoke-test`; logs go to stderr
+    #[arg(long, global = true)]
+    pub json: bool,
+
     #[command(subcommand)]
     pub command: Option<TopLevel>,
 }
@@ -41,6 +69,104 @@ pub enum TopLevel {
         command: ModuleCommand,
     },
 
+    /// Read and change configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Choose the adb device commands are forwarded to (host only)
+    Device {
+        #[command(subcommand)]
+        command: DeviceCommand,
+    },
+
+    /// Manage downloads cached on the host (host only)
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Helpers that run on the host (host only)
+    Host {
+        #[command(subcommand)]
+        command: HostCommand,
+    },
+
+    /// Inspect module mounts
+    Mount {
+        #[command(subcommand)]
+        command: MountCommand,
+    },
+
+    /// Find and install modules from online repositories
+    Repo {
+        #[command(subcommand)]
+        command: RepoCommand,
+    },
+
+    /// Manage trusted module author keys
+    Trust {
+        #[command(subcommand)]
+        command: TrustCommand,
+    },
+
+    /// Review the command audit trail
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+
+    /// Save or restore all modules, their flags and the configuration
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommand,
+    },
+
+    /// Install this binary on the device and set up boot integration
+    InstallSelf,
+
+    /// Remove the installed binary and boot integration
+    UninstallSelf {
+        /// Also delete modules, config and logs
+        #[arg(long)]
+        purge: bool,
+    },
+
+    /// Emergency mode: disable and unmount all modules, drop pending
+    /// updates and set safe mode
+    Recover,
+
+    /// Overview of modules, the last boot and storage on the device
+    Status,
+
+    /// What the device supports: overlayfs, fuse, the new mount API, shells,
+    /// busybox applets, free space and security modules, probed once per boot
+    Env {
+        /// Probe again instead of showing this boot's cached result
+        #[arg(long)]
+        refresh: bool,
+    },
+
+    /// Run a read-only SQL query over a snapshot of the device state
+    ///
+    /// Tables: modules (id, name, version, author, description, enabled,
+    /// update_pending, uninstall_pending, mounted, trust), module_props (module_id,
+    /// key, value), scripts (module_id, script, exit_code, duration_ms,
+    /// finished), mounts (mount_point, module_id), audit (time, peer,
+    /// token_id, command, args, result) and app_crashes (app_id, path).
+    Query {
+        /// SQL statement, e.g. "select id, version from modules where enabled = 1"
+        sql: String,
+    },
+
+    /// Show what the next boot will do, without changing anything
+    SimulateBoot,
+
+    /// Install, mount, check and remove two bundled example modules, to
+    /// validate the device setup end to end
+    SmokeTest,
+
     /// Internal commands
     Internal {
         #[command(subcommand)]
@@ -54,6 +180,167 @@ pub enum TopLevel {
     },
 }
 
+impl TopLevel {
+    /// Whether the command works on local files only, and so runs the same
+    /// on host and device without touching scriba's device directories
+    pub fn is_local(&self) -> bool {
+        // archives and directories on the host are linted where they are
+        if let TopLevel::Module {
+            command: ModuleCommand::Lint { target },
+        } = self
+        {
+            return Path::new(target).exists();
+        }
+        matches!(
+            self,
+            TopLevel::Module {
+                command: ModuleCommand::Pack { .. }
+                    | ModuleCommand::Create { .. }
+                    | ModuleCommand::Diff { .. }
+                    | ModuleCommand::Sign { .. }
+            } | TopLevel::Config { .. }
+                | TopLevel::Device { .. }
+                | TopLevel::Cache { .. }
+                | TopLevel::Host { .. }
+                | TopLevel::Completion { .. }
+        )
+    }
+}
+
+/// Global options that only make sense on the host and are not forwarded.
+const HOST_ONLY_OPTIONS: &[&str] = &["--serial", "--device", "--force-env", "--log-file"];
+
+/// Short forms of host-only options, which may carry their value attached.
+const HOST_ONLY_SHORT: &[&str] = &["-s"];
+
+/// Arguments to re-run on the device, without host-only options.
+pub fn forwarded_args() -> Vec<String> {
+    strip_host_only(std::env::args().skip(1))
+}
+
+/// Spellings of every option that takes a value, across all subcommands.
+fn value_options(command: &Command, options: &mut Vec<String>) {
+    for arg in command.get_arguments() {
+        if arg.is_positional() || !arg.get_action().takes_values() {
+            continue;
+        }
+        options.extend(
+            arg.get_long_and_visible_aliases()
+                .into_iter()
+                .flatten()
+                .map(|long| format!("--{long}")),
+        );
+        options.extend(arg.get_short().map(|short| format!("-{short}")));
+    }
+    for subcommand in command.get_subcommands() {
+        value_options(subcommand, options);
+    }
+}
+
+/// `args` without host-only options and their values. The value of any
+/// other option is kept even if it looks like `-s<serial>`, and so is
+/// everything after `--`.
+fn strip_host_only(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut takes_value = Vec::new();
+    value_options(&Cli::command(), &mut takes_value);
+
+    let mut forwarded = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            forwarded.push(arg);
+            forwarded.extend(iter);
+            break;
+        }
+        if HOST_ONLY_OPTIONS.contains(&arg.as_str()) || HOST_ONLY_SHORT.contains(&arg.as_str()) {
+            iter.next();
+            continue;
+        }
+        if HOST_ONLY_OPTIONS
+            .iter()
+            .any(|option| arg.starts_with(&format!("{option}=")))
+            || HOST_ONLY_SHORT.iter().any(|option| arg.starts_with(option))
+        {
+            continue;
+        }
+        if takes_value.contains(&arg)
+            && let Some(value) = iter.next()
+        {
+            forwarded.push(arg);
+            forwarded.push(value);
+            continue;
+        }
+        forwarded.push(arg);
+    }
+    forwarded
+}
+
+/* =========================
+ * Device commands
+ * ========================= */
+
+#[derive(Subcommand)]
+pub enum DeviceCommand {
+    /// List connected devices
+    List,
+
+    /// Use this device when several are connected
+    Use {
+        /// adb serial of the device
+        serial: String,
+    },
+
+    /// Restart adbd as root (engineering firmware only)
+    Root,
+
+    /// Remount the root filesystem read-write (requires root)
+    Remount,
+
+    /// Set the device clock, which is often years off after a battery
+    /// pull; uses the host clock unless --ntp is given
+    SetTime {
+        /// Use the time of this host (the default)
+        #[arg(long, conflicts_with = "ntp")]
+        from_host: bool,
+
+        /// Ask this NTP server (`host` or `host:port`) from the host instead
+        #[arg(long)]
+        ntp: Option<String>,
+    },
+}
+
+/* =========================
+ * Cache commands
+ * ========================= */
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List cached downloads
+    List,
+
+    /// Delete all cached downloads
+    Clean,
+}
+
+#[derive(Subcommand)]
+pub enum HostCommand {
+    /// Serve a directory over HTTP on the LAN, printing a `module install`
+    /// command per file, for devices that are easier to reach over Wi-Fi
+    /// than adb
+    ServeDir {
+        /// Directory to serve
+        dir: PathBuf,
+
+        /// Port to listen on, 0 picks a free one
+        #[arg(short, long, default_value_t = 8000)]
+        port: u16,
+
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0")]
+        bind: IpAddr,
+    },
+}
+
 /* =========================
  * Internal commands
  * ========================= */
@@ -61,7 +348,208 @@ pub enum TopLevel {
 #[derive(Subcommand)]
 pub enum InternalCommand {
     /// Execute boot complete logic
-    BootComplete,
+    BootComplete {
+        /// Initialize modules without mounting their payloads
+        #[arg(long)]
+        skip_mount: bool,
+
+        /// Initialize modules without running their boot-complete.sh
+        #[arg(long)]
+        skip_scripts: bool,
+
+        /// Initialize only this module, repeatable
+        #[arg(long = "only-module", value_name = "ID", value_parser = parse_module_id)]
+        only_modules: Vec<String>,
+    },
+
+    /// Re-apply the mounts of all enabled modules without rebooting
+    MountAll,
+
+    /// Delete rotated logs, cached downloads and archived module versions
+    /// when free space is below maintenance.min_free_mb, as boot-complete
+    /// does first
+    Maintenance,
+
+    /// Retry the mounts the last boot-complete left waiting for targets
+    /// that did not exist yet
+    LateMount,
+
+    /// Set the system clock, used by `device set-time`
+    SetTime {
+        /// Seconds since the unix epoch
+        unix: u64,
+    },
+
+    /// Exercise prop parsing, extraction, state IO and a mount in a private
+    /// namespace; `install-self` refuses binaries that fail it
+    Selftest,
+
+    /// Deliver an event to module event handlers (events/<name>.sh)
+    Event {
+        /// Event name, e.g. screen-unlocked
+        name: String,
+
+        /// Event data passed to handlers as SCRIBA_EVENT_<KEY>
+        #[arg(long, value_parser = parse_key_value)]
+        data: Vec<(String, String)>,
+    },
+}
+
+/* =========================
+ * Config commands
+ * ========================= */
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// List settable keys with their accepted values
+    List,
+
+    /// Print the value of a key
+    Get {
+        /// Dotted key, e.g. log.levels.module
+        key: String,
+    },
+
+    /// Set the value of a key
+    Set {
+        /// Dotted key, e.g. log.levels.module
+        #[arg(value_parser = parse_config_key)]
+        key: String,
+
+        /// New value
+        value: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ChannelCommand {
+    /// Follow a channel, e.g. `beta`; `stable` is the default
+    Set {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Channel name
+        channel: String,
+    },
+
+    /// List modules that follow a channel other than stable
+    List,
+}
+
+/* =========================
+ * Repo commands
+ * ========================= */
+
+#[derive(Subcommand)]
+pub enum RepoCommand {
+    /// Add a repository and fetch its index
+    Add {
+        /// http(s) URL of a JSON index listing modules by id, name,
+        /// version, url, sha256 and optionally channel and deltas, each a
+        /// `from_version`, `url` and `sha256`
+        url: String,
+    },
+
+    /// Forget a repository
+    Remove {
+        /// URL the repository was added with
+        url: String,
+    },
+
+    /// List added repositories
+    List,
+
+    /// Fetch the index of every repository again
+    Update,
+
+    /// Search the fetched indexes by module id or name
+    Search {
+        /// Text to look for, ignoring case
+        term: String,
+    },
+
+    /// Install or update a module from the newest version offered, through
+    /// a delta from the installed version when the index lists one
+    Install {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Start from an empty data directory, replacing the current one
+        /// when the update takes effect
+        #[arg(long)]
+        clean: bool,
+
+        /// Install it even without a valid signature
+        #[arg(long)]
+        allow_unsigned: bool,
+
+        /// Answer an install prompt of the module, repeatable
+        #[arg(long = "answer", value_parser = parse_key_value)]
+        answers: Vec<(String, String)>,
+    },
+}
+
+/* =========================
+ * Audit commands
+ * ========================= */
+
+#[derive(Subcommand)]
+pub enum TrustCommand {
+    /// Trust modules declaring this author key
+    Add {
+        /// Key fingerprint (hex, ':' separators allowed)
+        fingerprint: String,
+
+        /// Name to show for the author
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Stop trusting an author key
+    Remove {
+        /// Key fingerprint
+        fingerprint: String,
+    },
+
+    /// List trusted author keys
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum MountCommand {
+    /// List mounted paths with the module owning each, checked against the
+    /// mount table
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum AuditCommand {
+    /// List recorded command executions
+    List {
+        /// Show only the most recent entries
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupCommand {
+    /// Write a .tar.gz of every module, the config, trusted keys,
+    /// repositories, channels and profiles
+    Create {
+        /// Archive to write, on the device when forwarded
+        /// (default: `scriba-backup-<time>.tar.gz` in the current directory)
+        output: Option<PathBuf>,
+    },
+
+    /// Restore a backup: its modules replace the installed ones after
+    /// reboot, the config and state files right away
+    Restore {
+        /// Backup archive
+        file: String,
+    },
 }
 
 /* =========================
@@ -81,6 +569,22 @@ pub enum AppCommand {
         /// ID of application
         #[arg(value_parser = parse_app_id)]
         app_id: u64,
+
+        /// Keep the app's data directory, so reinstalling it restores its state
+        #[arg(long)]
+        keep_data: bool,
+    },
+
+    /// Save an application's data directory as a .tar.gz
+    ExportData {
+        /// ID of application
+        #[arg(value_parser = parse_app_id)]
+        app_id: u64,
+
+        /// Archive to write, on the device when forwarded
+        /// (default: `<app id>-data-<time>.tar.gz` in the current directory)
+        #[arg(short, long)]
+        output: Option<String>,
     },
 
     /// Start an application
@@ -92,6 +596,39 @@ pub enum AppCommand {
         /// Initial page to open
         #[arg(long)]
         page: Option<String>,
+
+        /// Start argument passed to the page, repeatable
+        #[arg(long = "arg", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
+    },
+
+    /// Open a deep link such as `app://<app id>/<page>?arg=1`
+    Open {
+        /// Deep link to open
+        #[arg(value_parser = parse_deep_link)]
+        link: DeepLink,
+    },
+
+    /// Show crash dumps of applications
+    Crashes {
+        /// Only show crashes of this application
+        #[arg(value_parser = parse_app_id)]
+        app_id: Option<u64>,
+
+        /// Copy the crash files into this directory, grouped by app
+        #[arg(long)]
+        export: Option<String>,
+    },
+
+    /// Show CPU and memory use of running applications, refreshed like top
+    Top {
+        /// Seconds between refreshes
+        #[arg(short, long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Stop after this many refreshes (default: until interrupted)
+        #[arg(short = 'n', long)]
+        iterations: Option<u64>,
     },
 
     /// List installed applications
@@ -115,12 +652,118 @@ pub enum AppCommand {
 pub enum ModuleCommand {
     /// Install or update a module
     Install {
-        /// Path to module archive
+        /// Path or http(s) URL of a module archive (zip, tar, tar.gz,
+        /// tar.xz, tar.zst or squashfs) or delta package
         path: String,
 
-        /// Clear module data before install/update
+        /// Expected sha256 of the archive, checked before extracting
+        #[arg(long, value_parser = parse_sha256)]
+        sha256: Option<String>,
+
+        /// Start from an empty data directory, replacing the current one
+        /// when the update takes effect
         #[arg(long)]
         clean: bool,
+
+        /// Install modules without a valid signature from a key in
+        /// /userdisk/scriba/keys/
+        #[arg(long)]
+        allow_unsigned: bool,
+
+        /// Answer an install prompt of the module, repeatable; prompts
+        /// left unanswered are asked in a terminal or take their default
+        #[arg(long = "answer", value_parser = parse_key_value)]
+        answers: Vec<(String, String)>,
+
+        /// Stage the module disabled with `--enable=false`, to enable it
+        /// later with `module enable`
+        #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+        enable: bool,
+
+        /// Extract and validate the module, then print the destination,
+        /// overridden paths and scripts without installing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Set internally for archives resolved through a repository or an
+        /// update feed, which `trust.require_trusted` applies to
+        #[arg(skip)]
+        from_repo: bool,
+    },
+
+    /// Stage the version an update replaced, taking effect after reboot;
+    /// the last two replaced versions are kept
+    Rollback {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Kept version to go back to (default: the newest one older than
+        /// the installed version)
+        #[arg(long)]
+        to: Option<i64>,
+    },
+
+    /// Cancel a pending update, keeping the installed version
+    CancelUpdate {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Mount an installed module now, or print an equivalent shell script
+    Mount {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Emit a standalone sh script of the mount commands instead of
+        /// mounting, e.g. to run from a recovery shell
+        #[arg(long)]
+        emit_script: bool,
+
+        /// Write the script to this file instead of stdout
+        #[arg(short, long, requires = "emit_script")]
+        output: Option<String>,
+    },
+
+    /// Undo the mounts of a module until the next boot or remount
+    Unmount {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Undo and re-apply the mounts of a module, e.g. after editing its files
+    Remount {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Enable a disabled module
+    Enable {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Enable and start it for the current boot only; it is disabled
+        /// again after the next reboot
+        #[arg(long)]
+        until_reboot: bool,
+    },
+
+    /// Disable a module from the next boot on
+    Disable {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Save and switch between sets of enabled modules
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
     },
 
     /// Uninstall a module
@@ -128,29 +771,397 @@ pub enum ModuleCommand {
         /// Module identifier
         #[arg(value_parser = parse_module_id)]
         module_id: String,
+
+        /// Also delete the module's data directory when it is removed
+        #[arg(long)]
+        purge: bool,
+
+        /// Print what would happen without changing anything or running
+        /// uninstall.sh
+        #[arg(long)]
+        dry_run: bool,
     },
 
-    /// List installed modules
+    /// List installed modules in mount order, the highest priority first
     List,
+
+    /// Show details of a module, including the last run of each script
+    Info {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// List paths on / that more than one enabled module provides, with
+    /// the module whose file is visible
+    Conflicts,
+
+    /// Choose the release channel repository installs and update checks
+    /// use for a module
+    Channel {
+        #[command(subcommand)]
+        command: ChannelCommand,
+    },
+
+    /// Explain why a module is not active: disabled, pending uninstall,
+    /// invalid, failed at boot, waiting for its mount target or safe mode
+    WhyDisabled {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Re-hash the files of an installed module and report changes since
+    /// it was installed
+    Verify {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Check a module archive, source directory or installed module for
+    /// problems: module.prop, scripts, payload paths and archive hygiene
+    Lint {
+        /// Module archive, module source directory or installed module id
+        target: String,
+    },
+
+    /// Find which modules ship files whose path contains a pattern, e.g.
+    /// `module grep bin/busybox`; prints `id: path` per match
+    Grep {
+        /// Substring to look for
+        pattern: String,
+
+        /// Match regardless of case
+        #[arg(short, long)]
+        ignore_case: bool,
+
+        /// Also search inside text files, printing `id: path:line: text`
+        #[arg(short, long)]
+        content: bool,
+
+        /// Skip files larger than this many bytes when searching contents
+        #[arg(long, default_value_t = 1 << 20, requires = "content")]
+        max_size: u64,
+    },
+
+    /// Compare installed modules against the manifest their `updateJson`
+    /// points to, a JSON object with `version`, `zipUrl` and optionally
+    /// `sha256` and `deltas` as in a repository index
+    CheckUpdates {
+        /// Only check this module
+        #[arg(value_parser = parse_module_id)]
+        module_id: Option<String>,
+
+        /// Download newer versions and stage them as pending updates
+        #[arg(long)]
+        apply: bool,
+
+        /// Stage updates even without a valid signature
+        #[arg(long, requires = "apply")]
+        allow_unsigned: bool,
+    },
+
+    /// Show the changelog of a module
+    Changelog {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+    },
+
+    /// Build a delta package between two versions of a module
+    Diff {
+        /// Source directory of the currently installed version
+        old: String,
+
+        /// Source directory of the new version
+        new: String,
+
+        /// Output delta package path
+        #[arg(short, long)]
+        output: String,
+    },
+
+    /// Sign a module source directory, writing its `signature` file
+    Sign {
+        /// Module source directory (containing module.prop)
+        dir: String,
+
+        /// Hex ed25519 secret key file, created when missing; put the
+        /// printed public key into /userdisk/scriba/keys/<name>.pub on
+        /// devices that should accept the module
+        #[arg(short, long)]
+        key: PathBuf,
+    },
+
+    /// Zip an installed module into an archive that installs on another
+    /// device, without its flags and install-time state
+    Export {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Archive to write, on the device when forwarded
+        /// (default: `<id>-<version>.zip` in the current directory)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Generate a skeleton module source directory, asking for what is not
+    /// given when running in a terminal
+    Create {
+        /// Module identifier
+        #[arg(value_parser = parse_module_id)]
+        module_id: String,
+
+        /// Output directory, or archive with --zip (default: ./<module_id>,
+        /// or ./<module_id>.zip)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Write a module archive instead of a directory
+        #[arg(long)]
+        zip: bool,
+
+        #[arg(long)]
+        name: Option<String>,
+
+        #[arg(long)]
+        description: Option<String>,
+
+        #[arg(long)]
+        version: Option<i64>,
+
+        #[arg(long)]
+        author: Option<String>,
+    },
+
+    /// Build a module archive from a source directory
+    Pack {
+        /// Module source directory (containing module.prop)
+        dir: String,
+
+        /// Output archive path (default: <dir name>.zip)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Produce byte-identical archives for identical sources
+        /// (fixed timestamps, sorted entries, normalized permissions)
+        #[arg(long)]
+        reproducible: bool,
+
+        /// Sign the directory with this secret key before packing, like
+        /// `module sign`
+        #[arg(short, long)]
+        key: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileCommand {
+    /// Save which installed modules are enabled
+    Save {
+        /// Profile name, e.g. daily
+        #[arg(value_parser = parse_profile_name)]
+        name: String,
+    },
+
+    /// Enable and disable modules to match a saved profile
+    Apply {
+        /// Profile name
+        #[arg(value_parser = parse_profile_name)]
+        name: String,
+    },
+
+    /// List saved profiles
+    List,
+}
+
+/// Attach values only known at runtime (installed modules, concrete config
+/// keys, added repositories) as possible values, so generated completion
+/// scripts offer them
+pub fn with_runtime_values(
+    cmd: Command,
+    module_ids: &[String],
+    config_keys: &[String],
+    repo_urls: &[String],
+) -> Command {
+    let module_ids = PossibleValuesParser::new(module_ids.iter().map(PossibleValue::new));
+    let config_keys = PossibleValuesParser::new(config_keys.iter().map(PossibleValue::new));
+    let repo_urls = PossibleValuesParser::new(repo_urls.iter().map(PossibleValue::new));
+
+    // completion cannot tell which key was typed, so offer the values of
+    // every key with a fixed set
+    let mut config_values: Vec<&str> = Vec::new();
+    for entry in config::SCHEMA {
+        let values: &[&str] = match entry.kind {
+            config::ValueKind::Enum(allowed) => allowed,
+            config::ValueKind::Bool => &["true", "false"],
+            _ => &[],
+        };
+        for value in values {
+            if !config_values.contains(value) {
+                config_values.push(value);
+            }
+        }
+    }
+    let config_values = PossibleValuesParser::new(config_values);
+
+    cmd.mut_subcommand("module", |module| {
+        let with_id: Vec<String> = module
+            .get_subcommands()
+            .filter(|s| s.get_arguments().any(|a| a.get_id() == "module_id"))
+            .map(|s| s.get_name().to_string())
+            .collect();
+
+        with_id.iter().fold(module, |module, name| {
+            module.mut_subcommand(name, |s| {
+                s.mut_arg("module_id", |a| a.value_parser(module_ids.clone()))
+            })
+        })
+    })
+    .mut_subcommand("config", |c| {
+        c.mut_subcommand("get", |s| {
+            s.mut_arg("key", |a| a.value_parser(config_keys.clone()))
+        })
+        .mut_subcommand("set", |s| {
+            s.mut_arg("key", |a| a.value_parser(config_keys))
+                .mut_arg("value", |a| a.value_parser(config_values))
+        })
+    })
+    .mut_subcommand("repo", |r| {
+        r.mut_subcommand("remove", |s| {
+            s.mut_arg("url", |a| a.value_parser(repo_urls))
+        })
+    })
+}
+
+/// Space-separated subcommand path of the current invocation, e.g. `module install`
+pub fn command_path() -> String {
+    let Ok(matches) = Cli::command().try_get_matches_from(std::env::args_os()) else {
+        return String::new();
+    };
+
+    let mut parts = Vec::new();
+    let mut current = &matches;
+    while let Some((name, sub)) = current.subcommand() {
+        parts.push(name);
+        current = sub;
+    }
+
+    parts.join(" ")
 }
 
 fn parse_app_id(value: &str) -> Result<u64, String> {
-    let id = u64::from_str(value).map_err(|_| "app id must be an integer".to_string())?;
+    u64::from_str(value).map_err(|_| "app id must be an integer".to_string())
+}
 
-    if value.len() != 16 || !value.starts_with("80") {
-        warn!(
-            "warning: app id `{}` is unusual (expected 16 digits starting with \"80\")",
-            value
-        );
+fn parse_deep_link(value: &str) -> Result<DeepLink, String> {
+    DeepLink::parse(value).map_err(|e| e.to_string())
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| "expected key=value".to_string())?;
+
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err("key must contain only letters, numbers, or underscore".to_string());
     }
 
-    Ok(id)
+    Ok((key.to_string(), value.to_string()))
 }
 
-fn parse_module_id(value: &str) -> Result<String, String> {
-    if value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+fn parse_config_key(value: &str) -> Result<String, String> {
+    match config::find_key(value) {
+        Some(_) => Ok(value.to_string()),
+        None => Err(format!(
+            "unknown config key, expected one of: {}",
+            config::SCHEMA
+                .iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn parse_profile_name(value: &str) -> Result<String, String> {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
         Ok(value.to_string())
     } else {
-        Err("module id must contain only letters, numbers, or underscore".to_string())
+        Err("profile name must contain only letters, numbers, '-' or '_'".to_string())
+    }
+}
+
+fn parse_sha256(value: &str) -> Result<String, String> {
+    if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(value.to_ascii_lowercase())
+    } else {
+        Err("expected 64 hex digits".to_string())
+    }
+}
+
+fn parse_module_id(value: &str) -> Result<String, String> {
+    module::validate_module_id(value).map_err(|e| e.to_string())?;
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(args: &[&str]) -> Vec<String> {
+        strip_host_only(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn strip_host_only_drops_serial_in_every_form() {
+        assert_eq!(strip(&["-s", "abc", "module", "list"]), ["module", "list"]);
+        assert_eq!(strip(&["-sabc", "module", "list"]), ["module", "list"]);
+        assert_eq!(
+            strip(&["--serial=abc", "--log-file", "x.log", "status"]),
+            ["status"]
+        );
+        assert_eq!(
+            strip(&["module", "list", "--device", "abc", "--json"]),
+            ["module", "list", "--json"]
+        );
+    }
+
+    #[test]
+    fn strip_host_only_keeps_option_values_that_look_like_serials() {
+        assert_eq!(
+            strip(&["-s", "abc", "app", "run", "8001", "--page", "-settings"]),
+            ["app", "run", "8001", "--page", "-settings"]
+        );
+        assert_eq!(
+            strip(&[
+                "module",
+                "mount",
+                "m",
+                "--emit-script",
+                "-o",
+                "-sfoo.sh",
+                "-sabc"
+            ]),
+            ["module", "mount", "m", "--emit-script", "-o", "-sfoo.sh"]
+        );
+    }
+
+    #[test]
+    fn strip_host_only_keeps_everything_after_double_dash() {
+        assert_eq!(
+            strip(&["-s", "abc", "repo", "search", "--", "-sfoo"]),
+            ["repo", "search", "--", "-sfoo"]
+        );
+        assert_eq!(
+            strip(&["config", "set", "default_device", "--", "--serial"]),
+            ["config", "set", "default_device", "--", "--serial"]
+        );
     }
 }
@@ -0,0 +1,324 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, bail};
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Anything before 2024-01-01 means the clock has not been set since a
+/// battery pull; these devices boot at 1970 or at the firmware build date.
+const MIN_PLAUSIBLE_UNIX: u64 = 1_704_067_200;
+
+/// Where time comes from. Time-dependent logic takes a `&dyn Clock` so it
+/// can be driven by a fixed clock instead of the system one.
+pub trait Clock {
+    /// Wall clock seconds since the unix epoch (0 if before it).
+    fn unix_now(&self) -> u64;
+
+    /// Time since boot, counting suspend; unaffected by clock changes.
+    fn since_boot(&self) -> Duration;
+
+    /// Kernel boot id, distinguishing timestamps taken in different boots.
+    fn boot_id(&self) -> String;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn unix_now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn since_boot(&self) -> Duration {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: clock_gettime only writes into the provided timespec
+        let ret = unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) };
+        if ret == 0 {
+            Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn boot_id(&self) -> String {
+        fs::read_to_string("/proc/sys/kernel/random/boot_id")
+            .map(|id| id.trim().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Clock stopped at one reading, for tests.
+#[cfg(test)]
+pub struct FixedClock {
+    pub unix: u64,
+    pub uptime: u64,
+    pub boot_id: &'static str,
+}
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn unix_now(&self) -> u64 {
+        self.unix
+    }
+
+    fn since_boot(&self) -> Duration {
+        Duration::from_secs(self.uptime)
+    }
+
+    fn boot_id(&self) -> String {
+        self.boot_id.to_string()
+    }
+}
+
+/// Wall clock seconds since the unix epoch, from the system clock.
+pub fn unix_now() -> u64 {
+    SystemClock.unix_now()
+}
+
+pub fn is_plausible(unix: u64) -> bool {
+    unix >= MIN_PLAUSIBLE_UNIX
+}
+
+fn utc(unix: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp(unix as i64, 0).unwrap_or_default()
+}
+
+/// RFC3339 in UTC with an explicit offset, e.g. `2026-01-02T03:04:05+00:00`.
+/// Used for everything that is stored, so host and device agree.
+pub fn rfc3339(unix: u64) -> String {
+    utc(unix).to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// Compact UTC form for file names, e.g. `20260102T030405+0000`; RFC3339's
+/// colons are rejected by FAT storage and Windows hosts the logs end up on.
+pub fn file_stamp(unix: u64) -> String {
+    utc(unix).format("%Y%m%dT%H%M%S%z").to_string()
+}
+
+/// Local time of this machine for display, with its offset.
+pub fn local(unix: u64) -> String {
+    utc(unix)
+        .with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S %:z")
+        .to_string()
+}
+
+/// Set the system clock, which needs root.
+pub fn set_system_time(unix: u64) -> io::Result<()> {
+    let ts = libc::timespec {
+        tv_sec: unix as libc::time_t,
+        tv_nsec: 0,
+    };
+    // SAFETY: clock_settime only reads the provided timespec
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &ts) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Seconds between the NTP epoch (1900) and the unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Time reported by the (S)NTP server at `server`, `host` or `host:port`.
+pub fn ntp_time(server: &str) -> anyhow::Result<u64> {
+    let address = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{server}:123")
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    socket
+        .connect(&address)
+        .with_context(|| format!("cannot reach NTP server {address}"))?;
+
+    // leap indicator 0, version 4, client mode
+    let mut packet = [0u8; 48];
+    packet[0] = 0x23;
+    socket.send(&packet)?;
+    let len = socket
+        .recv(&mut packet)
+        .with_context(|| format!("no answer from NTP server {address}"))?;
+    if len < 48 {
+        bail!("short answer from NTP server {address}");
+    }
+
+    // transmit timestamp, seconds part
+    let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    if seconds < NTP_UNIX_OFFSET {
+        bail!("NTP server {address} sent an invalid time");
+    }
+    Ok(seconds - NTP_UNIX_OFFSET)
+}
+
+/// Stored timestamps are RFC3339 strings; older state has unix seconds.
+pub mod rfc3339_or_unix {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Unix(u64),
+        Rfc3339(String),
+    }
+
+    pub fn serialize<S: Serializer>(unix: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::rfc3339(*unix))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match Stored::deserialize(deserializer)? {
+            Stored::Unix(unix) => Ok(unix),
+            Stored::Rfc3339(text) => chrono::DateTime::parse_from_rfc3339(&text)
+                .map(|time| time.timestamp().max(0) as u64)
+                .map_err(D::Error::custom),
+        }
+    }
+}
+
+/// A wall clock reading together with a monotonic position within the boot,
+/// so it can be corrected later if the wall clock was wrong at the time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Timestamp {
+    /// Seconds since the unix epoch, as read (or later reconciled);
+    /// stored as RFC3339 UTC
+    #[serde(with = "rfc3339_or_unix")]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub boot_id: String,
+    /// Seconds since boot when the reading was taken
+    #[serde(default)]
+    pub uptime: u64,
+    #[serde(default = "default_plausible")]
+    pub clock_plausible: bool,
+}
+
+fn default_plausible() -> bool {
+    true
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Self::read(&SystemClock)
+    }
+
+    pub fn read(clock: &dyn Clock) -> Self {
+        let timestamp = clock.unix_now();
+        Self {
+            timestamp,
+            boot_id: clock.boot_id(),
+            uptime: clock.since_boot().as_secs(),
+            clock_plausible: is_plausible(timestamp),
+        }
+    }
+
+    /// Fix an implausible reading from the current boot using the distance
+    /// in uptime to `now`. Returns whether the timestamp changed.
+    pub fn reconcile(&mut self, now: &Timestamp) -> bool {
+        if self.clock_plausible
+            || !now.clock_plausible
+            || self.boot_id.is_empty()
+            || self.boot_id != now.boot_id
+        {
+            return false;
+        }
+
+        let elapsed = now.uptime.saturating_sub(self.uptime);
+        self.timestamp = now.timestamp.saturating_sub(elapsed);
+        self.clock_plausible = true;
+        true
+    }
+}
+
+/// Local time of this machine, for display.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&local(self.timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOT: &str = "boot-a";
+    /// 2026-01-02T03:04:05Z
+    const SET: u64 = 1_767_323_045;
+
+    fn reading(timestamp: u64, boot_id: &str, uptime: u64) -> Timestamp {
+        Timestamp {
+            timestamp,
+            boot_id: boot_id.to_string(),
+            uptime,
+            clock_plausible: is_plausible(timestamp),
+        }
+    }
+
+    #[test]
+    fn read_takes_all_parts_from_the_clock() {
+        let clock = FixedClock {
+            unix: SET,
+            uptime: 42,
+            boot_id: BOOT,
+        };
+        let time = Timestamp::read(&clock);
+
+        assert_eq!(time.timestamp, SET);
+        assert_eq!(time.uptime, 42);
+        assert_eq!(time.boot_id, BOOT);
+        assert!(time.clock_plausible);
+        assert!(!Timestamp::read(&FixedClock { unix: 60, ..clock }).clock_plausible);
+    }
+
+    #[test]
+    fn reconcile_corrects_by_uptime_within_a_boot() {
+        let mut early = reading(60, BOOT, 10);
+        let now = reading(SET, BOOT, 100);
+
+        assert!(early.reconcile(&now));
+        assert_eq!(early.timestamp, SET - 90);
+        assert!(early.clock_plausible);
+        // already corrected
+        assert!(!early.reconcile(&now));
+    }
+
+    #[test]
+    fn reconcile_leaves_other_boots_alone() {
+        let mut early = reading(60, "boot-b", 10);
+        assert!(!early.reconcile(&reading(SET, BOOT, 100)));
+        assert_eq!(early.timestamp, 60);
+
+        let mut unknown = reading(60, "", 10);
+        assert!(!unknown.reconcile(&reading(SET, "", 100)));
+    }
+
+    #[test]
+    fn reconcile_needs_a_plausible_now() {
+        let mut early = reading(60, BOOT, 10);
+        assert!(!early.reconcile(&reading(120, BOOT, 100)));
+        assert!(!early.clock_plausible);
+    }
+
+    #[test]
+    fn reconcile_keeps_plausible_readings() {
+        let mut time = reading(SET - 1000, BOOT, 10);
+        assert!(!time.reconcile(&reading(SET, BOOT, 100)));
+        assert_eq!(time.timestamp, SET - 1000);
+    }
+
+    #[test]
+    fn stamps_are_utc() {
+        assert_eq!(rfc3339(SET), "2026-01-02T03:04:05+00:00");
+        assert_eq!(file_stamp(SET), "20260102T030405+0000");
+    }
+}
@@ -1,22 +1,535 @@
 use std::{
+    collections::HashMap,
     fs, io,
     path::{Path, PathBuf},
 };
 
+use anyhow::{anyhow, bail};
 use config::{Config, File};
 use serde::Deserialize;
+use toml_edit::{DocumentMut, Item, Table, value};
 
-use crate::defs::{CONFIG_FILE, Environment};
+use crate::archive::Limits;
+use crate::defs::{
+    APP_DATA_DIR, CONFIG_FILE, CRASH_DIRS, CRITICAL_PATHS, DEFAULT_INTERPRETERS, Environment,
+};
+use crate::state;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// Serial of the adb device to use when several are connected
+    pub default_device: Option<String>,
+    /// Treat warnings about questionable input as errors, like `--strict`
+    pub strict: bool,
+    pub log: LogConfig,
+    pub app: AppSettings,
+    pub transfer: TransferConfig,
+    pub cache: CacheConfig,
+    pub archive: ArchiveConfig,
+    pub scripts: ScriptConfig,
+    pub trust: TrustConfig,
+    pub conflicts: ConflictConfig,
+    pub boot: BootConfig,
+    pub maintenance: MaintenanceConfig,
+    pub metrics: MetricsConfig,
+    pub guard: GuardConfig,
+    /// Device language picking the `locale/` trees of modules, e.g. zh_CN;
+    /// unset uses $LC_ALL, $LC_MESSAGES or $LANG
+    pub locale: Option<String>,
+    /// Per-module settings, keyed by module id
+    pub modules: HashMap<String, ModuleConfig>,
+}
+
+impl AppConfig {
+    /// Settings for a module, falling back to defaults when unconfigured
+    pub fn module(&self, id: &str) -> ModuleConfig {
+        let mut module = self.modules.get(id).cloned().unwrap_or_default();
+        module.locale = module.locale.or_else(|| self.locale.clone());
+        module
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Per-subsystem level overrides, e.g. `module = "debug"`
+    pub levels: HashMap<String, String>,
+    /// Seconds records are buffered before being appended to the log file,
+    /// 0 to write each one as it comes
+    pub flush_interval_secs: u64,
+    /// Log to tmpfs as records come and persist to the log file only every
+    /// `persist_interval_secs`
+    pub tmpfs: bool,
+    pub persist_interval_secs: u64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            levels: HashMap::new(),
+            flush_interval_secs: 5,
+            tmpfs: false,
+            persist_interval_secs: 300,
+        }
+    }
+}
 
 #[derive(Debug, Deserialize)]
-pub struct AppConfig {}
+#[serde(default)]
+pub struct AppSettings {
+    /// Directories scanned for miniapp crash dumps and tombstones
+    pub crash_dirs: Vec<PathBuf>,
+    /// Directory holding the data directory of each app, named by app id
+    pub data_dir: PathBuf,
+}
 
-impl Default for AppConfig {
+impl Default for AppSettings {
     fn default() -> Self {
-        Self {}
+        Self {
+            crash_dirs: CRASH_DIRS.iter().map(PathBuf::from).collect(),
+            data_dir: PathBuf::from(APP_DATA_DIR),
+        }
     }
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TransferConfig {
+    /// How often a push whose checksum does not match is retried
+    pub push_retries: u32,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self { push_retries: 3 }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Size the host download cache is trimmed to, least recently used first
+    pub max_size_mb: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { max_size_mb: 512 }
+    }
+}
+
+/// Caps on what a module archive, delta or backup may unpack to.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    pub max_entries: u64,
+    pub max_size_mb: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            max_entries: limits.max_entries,
+            max_size_mb: limits.max_bytes >> 20,
+        }
+    }
+}
+
+impl ArchiveConfig {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_entries: self.max_entries,
+            max_bytes: self.max_size_mb << 20,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TrustConfig {
+    /// Refuse modules whose `author_key` is not trusted when installing
+    /// from a repository or applying updates; local archives and URLs
+    /// given to `module install` are not checked.
+    pub require_trusted: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ConflictConfig {
+    /// What installing a module that provides a path another enabled
+    /// module provides too does
+    pub policy: ConflictPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Refuse the install
+    Error,
+    /// Install, warning about every shared path
+    #[default]
+    Warn,
+    /// Install, the module mounted last wins without a warning
+    Priority,
+}
+
+/// Time budgets of the boot-complete stages, in seconds; 0 means unlimited.
+/// Modules that do not fit are deferred to a late pass once the rest is up.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BootConfig {
+    /// Total time for mounting modules
+    pub mount_budget_secs: u64,
+    /// Total time for running boot-complete.sh scripts
+    pub script_budget_secs: u64,
+    /// How often a mount whose target does not exist yet is retried
+    /// before the module counts as failed
+    pub mount_retries: u32,
+    /// Wait before each retry of such a mount
+    pub mount_retry_delay_secs: u64,
+}
+
+impl Default for BootConfig {
+    fn default() -> Self {
+        Self {
+            mount_budget_secs: 0,
+            script_budget_secs: 0,
+            mount_retries: 3,
+            mount_retry_delay_secs: 10,
+        }
+    }
+}
+
+/// Paths whose originals are backed up before a module first overrides
+/// them, e.g. what a headless device needs to stay reachable.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct GuardConfig {
+    /// Globs of absolute paths; `*` matches within a path component and
+    /// `**` any number of components
+    pub critical_paths: Vec<String>,
+}
+
+impl Default for GuardConfig {
+    fn default() -> Self {
+        Self {
+            critical_paths: CRITICAL_PATHS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Free space on /userdisk below which rotated logs, cached downloads
+    /// and archived module versions are deleted; 0 turns reclaiming off
+    pub min_free_mb: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self { min_free_mb: 32 }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// node_exporter textfile collector directory to write
+    /// `boot_metrics.prom` into after boot-complete; unset disables it
+    pub textfile_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ScriptConfig {
+    /// Interpreters modules may pick with `interpreter=` or a shebang;
+    /// scripts without either always run under `sh`
+    pub interpreters: Vec<String>,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self {
+            interpreters: DEFAULT_INTERPRETERS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ModuleConfig {
+    /// How the module payload is kept on `/userdisk`
+    pub storage: StorageMode,
+    /// How the module payload is made visible on `/`; unset uses the
+    /// module's `mount=` prop, then bind files
+    pub mount: Option<MountMode>,
+    /// Language of the module's `locale/` tree to mount, overriding `locale`
+    pub locale: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageMode {
+    /// `system/` stays an extracted tree
+    #[default]
+    Plain,
+    /// `system/` is kept as a zstd zip and staged into tmpfs at boot
+    Compressed,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MountMode {
+    /// Bind every file over its existing counterpart
+    #[default]
+    Files,
+    /// Bind whole directories, replacing their contents
+    Dirs,
+    /// Read-only overlayfs with the module on top
+    Overlay,
+    /// Like `overlay`, through the fuse-overlayfs helper
+    Fuse,
+    /// Copy files over their targets, for writable locations
+    Copy,
+}
+
+/// Names of the mount modes, as used in config and `module.prop`.
+pub const MOUNT_MODES: &[&str] = &["files", "dirs", "overlay", "fuse", "copy"];
+
+impl MountMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "files" => Some(Self::Files),
+            "dirs" => Some(Self::Dirs),
+            "overlay" => Some(Self::Overlay),
+            "fuse" => Some(Self::Fuse),
+            "copy" => Some(Self::Copy),
+            _ => None,
+        }
+    }
+}
+
+/* =========================
+ * Schema
+ * ========================= */
+
+pub const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "off"];
+
+/// Values a config key accepts.
+pub enum ValueKind {
+    Enum(&'static [&'static str]),
+    /// Any non-empty string
+    String,
+    /// A non-negative integer
+    UInt,
+    Bool,
+}
+
+/// A settable config key. `<...>` segments match any single key segment.
+pub struct ConfigKey {
+    pub path: &'static str,
+    pub help: &'static str,
+    pub kind: ValueKind,
+}
+
+/// Every key `config set` accepts; also drives completion of key names.
+pub const SCHEMA: &[ConfigKey] = &[
+    ConfigKey {
+        path: "default_device",
+        help: "adb serial used when several devices are connected",
+        kind: ValueKind::String,
+    },
+    ConfigKey {
+        path: "strict",
+        help: "fail instead of warning about questionable input",
+        kind: ValueKind::Bool,
+    },
+    ConfigKey {
+        path: "app.data_dir",
+        help: "directory holding the data directories of apps",
+        kind: ValueKind::String,
+    },
+    ConfigKey {
+        path: "trust.require_trusted",
+        help: "only install repository modules and updates whose author key is trusted",
+        kind: ValueKind::Bool,
+    },
+    ConfigKey {
+        path: "conflicts.policy",
+        help: "what installing a module that provides the same path as another does",
+        kind: ValueKind::Enum(&["error", "warn", "priority"]),
+    },
+    ConfigKey {
+        path: "transfer.push_retries",
+        help: "retries of adb pushes that fail checksum verification",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "cache.max_size_mb",
+        help: "size limit of the host download cache in MiB",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "archive.max_entries",
+        help: "most entries a module archive, delta or backup may unpack",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "archive.max_size_mb",
+        help: "most MiB a module archive, delta or backup may unpack to",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "boot.mount_budget_secs",
+        help: "seconds boot-complete may spend mounting before deferring modules (0 = no limit)",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "boot.script_budget_secs",
+        help: "seconds boot-complete may spend in scripts before deferring modules (0 = no limit)",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "boot.mount_retries",
+        help: "retries of mounts whose target does not exist yet",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "boot.mount_retry_delay_secs",
+        help: "seconds to wait before each retry of a mount whose target is missing",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "maintenance.min_free_mb",
+        help: "free MiB on /userdisk below which old logs, downloads and module versions are deleted (0 = never)",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "metrics.textfile_dir",
+        help: "node_exporter textfile collector directory for boot metrics",
+        kind: ValueKind::String,
+    },
+    ConfigKey {
+        path: "locale",
+        help: "device language selecting module locale/ trees, e.g. zh_CN",
+        kind: ValueKind::String,
+    },
+    ConfigKey {
+        path: "log.flush_interval_secs",
+        help: "seconds log records are buffered before being written to the log file (0 = write each)",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "log.tmpfs",
+        help: "log to /tmp/scriba/run/logs as records come, persisting to flash every log.persist_interval_secs",
+        kind: ValueKind::Bool,
+    },
+    ConfigKey {
+        path: "log.persist_interval_secs",
+        help: "seconds between writes of the tmpfs log to flash with log.tmpfs (0 = write each)",
+        kind: ValueKind::UInt,
+    },
+    ConfigKey {
+        path: "log.levels.<subsystem>",
+        help: "log level of one subsystem",
+        kind: ValueKind::Enum(LOG_LEVELS),
+    },
+    ConfigKey {
+        path: "modules.<id>.storage",
+        help: "how a module payload is stored on /userdisk",
+        kind: ValueKind::Enum(&["plain", "compressed"]),
+    },
+    ConfigKey {
+        path: "modules.<id>.mount",
+        help: "how a module payload is mounted",
+        kind: ValueKind::Enum(MOUNT_MODES),
+    },
+    ConfigKey {
+        path: "modules.<id>.locale",
+        help: "language of a module's locale/ tree to mount, overriding locale",
+        kind: ValueKind::String,
+    },
+];
+
+impl ConfigKey {
+    fn matches(&self, key: &str) -> bool {
+        let pattern: Vec<_> = self.path.split('.').collect();
+        let parts: Vec<_> = key.split('.').collect();
+
+        pattern.len() == parts.len()
+            && pattern
+                .iter()
+                .zip(&parts)
+                .all(|(p, k)| !k.is_empty() && (p == k || (p.starts_with('<') && p.ends_with('>'))))
+    }
+
+    pub fn validate(&self, key: &str, val: &str) -> anyhow::Result<()> {
+        match self.kind {
+            ValueKind::Enum(allowed) if !allowed.contains(&val) => {
+                bail!("invalid value '{val}' for {key}, expected one of: {allowed:?}")
+            }
+            ValueKind::Enum(_) => Ok(()),
+            ValueKind::String if val.trim().is_empty() => bail!("{key} cannot be empty"),
+            ValueKind::String => Ok(()),
+            ValueKind::UInt if val.parse::<u32>().is_err() => {
+                bail!("invalid value '{val}' for {key}, expected a non-negative integer")
+            }
+            ValueKind::UInt => Ok(()),
+            ValueKind::Bool if val.parse::<bool>().is_err() => {
+                bail!("invalid value '{val}' for {key}, expected true or false")
+            }
+            ValueKind::Bool => Ok(()),
+        }
+    }
+
+    /// Human readable description of the accepted values
+    pub fn describe_values(&self) -> String {
+        match self.kind {
+            ValueKind::Enum(allowed) => allowed.join("|"),
+            ValueKind::String => "<string>".to_string(),
+            ValueKind::UInt => "<number>".to_string(),
+            ValueKind::Bool => "true|false".to_string(),
+        }
+    }
+
+    /// Concrete keys for this entry, filling placeholders from `fill`.
+    pub fn expand(&self, fill: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+        let mut keys = vec![String::new()];
+        for segment in self.path.split('.') {
+            let options = if segment.starts_with('<') {
+                fill(segment)
+            } else {
+                vec![segment.to_string()]
+            };
+
+            keys = keys
+                .iter()
+                .flat_map(|prefix| {
+                    options.iter().map(move |option| {
+                        if prefix.is_empty() {
+                            option.clone()
+                        } else {
+                            format!("{prefix}.{option}")
+                        }
+                    })
+                })
+                .collect();
+        }
+        keys
+    }
+}
+
+pub fn find_key(key: &str) -> Option<&'static ConfigKey> {
+    SCHEMA.iter().find(|entry| entry.matches(key))
+}
+
+/* =========================
+ * File access
+ * ========================= */
+
 fn config_path(environment: Environment) -> PathBuf {
     match environment {
         Environment::Device => PathBuf::from(CONFIG_FILE),
@@ -49,16 +562,74 @@ fn ensure_config_file(path: &Path) -> io::Result<()> {
     Ok(())
 }
 
-pub fn load_config(environment: Environment) -> AppConfig {
+pub fn load_config(environment: Environment) -> anyhow::Result<AppConfig> {
     let path = config_path(environment);
 
     ensure_config_file(&path)
-        .unwrap_or_else(|e| panic!("failed to prepare config file {:?}: {e}", path));
+        .map_err(|e| anyhow!("failed to prepare config file {path:?}: {e}"))?;
 
     let config = Config::builder()
-        .add_source(File::from(path))
+        .add_source(File::from(path.clone()))
         .build()
-        .expect("failed to load config");
+        .map_err(|e| anyhow!("failed to load config file {path:?}: {e}"))?;
 
-    config.try_deserialize::<AppConfig>().unwrap_or_default()
+    config
+        .try_deserialize::<AppConfig>()
+        .map_err(|e| anyhow!("invalid config file {path:?}: {e}"))
+}
+
+fn read_document(path: &Path) -> anyhow::Result<DocumentMut> {
+    ensure_config_file(path)?;
+    Ok(fs::read_to_string(path)?.parse::<DocumentMut>()?)
+}
+
+/// Look up a dotted key in the config file.
+pub fn get_value(environment: Environment, key: &str) -> anyhow::Result<Option<String>> {
+    let doc = read_document(&config_path(environment))?;
+
+    let mut item = doc.as_item();
+    for part in key.split('.') {
+        match item.get(part) {
+            Some(next) => item = next,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(item
+        .as_value()
+        .map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string)))
+}
+
+/// Validate `val` against the schema and write it to the config file,
+/// keeping existing comments and formatting.
+pub fn set_value(environment: Environment, key: &str, val: &str) -> anyhow::Result<()> {
+    let entry = find_key(key).ok_or_else(|| anyhow!("unknown config key '{key}'"))?;
+    entry.validate(key, val)?;
+
+    let path = config_path(environment);
+    let mut doc = read_document(&path)?;
+
+    let parts: Vec<_> = key.split('.').collect();
+    let (last, parents) = parts.split_last().expect("split never yields nothing");
+
+    let mut table = doc.as_table_mut();
+    for part in parents {
+        table = table
+            .entry(part)
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("'{part}' in {key} is not a table"))?;
+    }
+    table[last] = match entry.kind {
+        ValueKind::UInt => value(val.parse::<i64>()?),
+        ValueKind::Bool => value(val.parse::<bool>()?),
+        _ => value(val),
+    };
+
+    state::write_atomic(&path, doc.to_string())?;
+    Ok(())
 }
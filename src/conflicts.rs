@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::ConflictPolicy;
+use crate::defs::{MODULES_DIR, MODULES_UPDATE_DIR};
+use crate::module;
+
+/// A path on `/` provided by more than one module.
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub path: PathBuf,
+    /// Modules providing it in mount order; the last one is visible
+    pub modules: Vec<String>,
+}
+
+impl Conflict {
+    pub fn winner(&self) -> &str {
+        self.modules.last().map_or("?", String::as_str)
+    }
+}
+
+/// Whether the module in `dir` has its payload mounted at all.
+fn mounts_payload(dir: &Path) -> bool {
+    module::parse_prop_file(&dir.join("module.prop"))
+        .is_ok_and(|props| props.get("skip_mount").map(String::as_str) != Some("true"))
+}
+
+/// Enabled, mounting modules in mount order, each with the directory its
+/// payload comes from at the next boot: the staged update if there is one.
+fn mounted_modules() -> Vec<(String, PathBuf)> {
+    let mut modules: Vec<_> = module::installed_ids()
+        .into_iter()
+        .filter(|id| module::is_enabled(id))
+        .map(|id| {
+            let update_dir = Path::new(MODULES_UPDATE_DIR).join(&id);
+            let dir = if update_dir.is_dir() {
+                update_dir
+            } else {
+                Path::new(MODULES_DIR).join(&id)
+            };
+            (id, dir)
+        })
+        .filter(|(_, dir)| mounts_payload(dir))
+        .collect();
+    modules.sort_by_cached_key(|(_, dir)| module::mount_order_key(dir));
+    modules
+}
+
+/// Paths provided by more than one of `modules`, which must be in mount
+/// order.
+fn find(modules: &[(String, PathBuf)]) -> Result<Vec<Conflict>> {
+    let mut providers: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+    for (id, dir) in modules {
+        for target in module::payload_targets(dir)? {
+            providers.entry(target).or_default().push(id.clone());
+        }
+    }
+
+    Ok(providers
+        .into_iter()
+        .filter(|(_, modules)| modules.len() > 1)
+        .map(|(path, modules)| Conflict { path, modules })
+        .collect())
+}
+
+/// Conflicts between all enabled modules as they will be mounted next boot.
+pub fn all() -> Result<Vec<Conflict>> {
+    find(&mounted_modules())
+}
+
+/// Conflicts a module staged in `module_dir` would have with the enabled
+/// modules, as an install or update of `module_id`.
+pub fn with_module(module_id: &str, module_dir: &Path) -> Result<Vec<Conflict>> {
+    if !mounts_payload(module_dir) {
+        return Ok(Vec::new());
+    }
+
+    let mut modules = mounted_modules();
+    modules.retain(|(id, _)| id != module_id);
+    modules.push((module_id.to_string(), module_dir.to_path_buf()));
+    modules.sort_by_cached_key(|(_, dir)| module::mount_order_key(dir));
+
+    let conflicts = find(&modules)?;
+    Ok(conflicts
+        .into_iter()
+        .filter(|conflict| conflict.modules.iter().any(|id| id == module_id))
+        .collect())
+}
+
+/// Apply `policy` to the conflicts of a module about to be installed.
+pub fn check(module_id: &str, conflicts: &[Conflict], policy: ConflictPolicy) -> Result<()> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    match policy {
+        ConflictPolicy::Error => {
+            for conflict in conflicts {
+                warn!(
+                    "{}: {}",
+                    conflict.path.display(),
+                    conflict.modules.join(", ")
+                );
+            }
+            bail!(
+                "module {module_id} provides {} path(s) other modules provide too (conflicts.policy = error)",
+                conflicts.len()
+            );
+        }
+        ConflictPolicy::Warn => {
+            for conflict in conflicts {
+                warn!(
+                    "{} is provided by {}, {} wins by mount order",
+                    conflict.path.display(),
+                    conflict.modules.join(", "),
+                    conflict.winner()
+                );
+            }
+        }
+        ConflictPolicy::Priority => {
+            for conflict in conflicts {
+                info!(
+                    "{}: {} wins over {}",
+                    conflict.path.display(),
+                    conflict.winner(),
+                    conflict.modules[..conflict.modules.len() - 1].join(", ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn print(conflicts: &[Conflict]) {
+    if conflicts.is_empty() {
+        info!("no enabled modules provide the same path");
+    }
+    for conflict in conflicts {
+        warn!(
+            "{}: {} (visible: {})",
+            conflict.path.display(),
+            conflict.modules.join(", "),
+            conflict.winner()
+        );
+    }
+}
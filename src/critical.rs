@@ -0,0 +1,250 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::defs::MODULES_BACKUP_DIR;
+use crate::integrity;
+use crate::module;
+use crate::mount::MountPlan;
+use crate::state;
+
+/// What the guard of a module saved before its first mount.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Guard {
+    /// Backend that placed the module's files; only `copy` changes `/`
+    mounter: String,
+    originals: Vec<Original>,
+}
+
+/// A critical path a module's payload covers.
+#[derive(Debug, Serialize, Deserialize)]
+struct Original {
+    target: PathBuf,
+    /// Digest of the original, `None` when the module adds the path
+    digest: Option<String>,
+}
+
+/// Guard of a module, kept next to its previous versions.
+fn guard_dir(module_id: &str) -> PathBuf {
+    Path::new(MODULES_BACKUP_DIR)
+        .join(module_id)
+        .join("critical")
+}
+
+fn manifest_path(module_id: &str) -> PathBuf {
+    guard_dir(module_id).join("guard.json")
+}
+
+/// Where the original of `target` is kept, mirroring its path.
+fn backup_path(module_id: &str, target: &Path) -> PathBuf {
+    let rel: PathBuf = target
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    guard_dir(module_id).join("files").join(rel)
+}
+
+fn load(module_id: &str) -> anyhow::Result<Option<Guard>> {
+    match fs::read_to_string(manifest_path(module_id)) {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the components of `path` match `pattern`, where `*` matches
+/// within one component and `**` any number of them.
+fn glob_match(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            glob_match(&pattern[1..], path) || (!path.is_empty() && glob_match(pattern, &path[1..]))
+        }
+        (Some(p), Some(c)) => {
+            wildcard_match(p.as_bytes(), c.as_bytes()) && glob_match(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(c)) => p == c && wildcard_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Whether `path` matches one of the critical path globs.
+pub fn is_critical(path: &Path, globs: &[String]) -> bool {
+    let path = path.to_string_lossy();
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    globs.iter().any(|glob| {
+        let pattern: Vec<&str> = glob.split('/').filter(|c| !c.is_empty()).collect();
+        glob_match(&pattern, &components)
+    })
+}
+
+/// Copy a file, symlink or directory, preserving permissions.
+fn copy_entry(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let meta = fs::symlink_metadata(src)?;
+    if meta.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+    } else if meta.is_dir() {
+        module::copy_dir(src, dst)?;
+    } else {
+        fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+
+fn remove_entry(path: &Path) -> anyhow::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => module::delete_dir(path),
+        Ok(_) => Ok(fs::remove_file(path)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Backend to record for a mount with `mounter`. Once `copy` has written
+/// over `/` only `restore` undoes it, whatever mounts the module later.
+fn recorded_mounter<'a>(recorded: &'a str, mounter: &'a str) -> &'a str {
+    if recorded == "copy" {
+        recorded
+    } else {
+        mounter
+    }
+}
+
+/// Back up the originals of the critical paths `plan` covers before the
+/// module is mounted with `mounter` for the first time. Paths backed up
+/// before are left alone, as `/` may already hold the module's copies.
+pub fn protect(
+    module_id: &str,
+    plan: &MountPlan,
+    mounter: &str,
+    globs: &[String],
+) -> anyhow::Result<()> {
+    let mut guard = load(module_id)?.unwrap_or_default();
+    let touched = plan
+        .targets
+        .iter()
+        .chain(&plan.additions)
+        .map(|(_, dst)| dst)
+        .chain(&plan.hidden);
+
+    let mut saved = 0;
+    for target in touched {
+        if !is_critical(target, globs) || guard.originals.iter().any(|o| o.target == *target) {
+            continue;
+        }
+        let digest = if fs::symlink_metadata(target).is_ok() {
+            let backup = backup_path(module_id, target);
+            remove_entry(&backup)?;
+            copy_entry(target, &backup)
+                .with_context(|| format!("failed to back up critical path {target:?}"))?;
+            let digest = integrity::entry_digest(target)?;
+            if integrity::entry_digest(&backup)? != digest {
+                bail!("backup of critical path {target:?} does not match the original");
+            }
+            Some(digest)
+        } else {
+            None
+        };
+        guard.originals.push(Original {
+            target: target.clone(),
+            digest,
+        });
+        saved += 1;
+    }
+
+    let mounter = recorded_mounter(&guard.mounter, mounter).to_string();
+    // nothing critical is covered, or nothing changed since the last mount
+    if guard.originals.is_empty() || (saved == 0 && guard.mounter == mounter) {
+        return Ok(());
+    }
+    guard.mounter = mounter;
+    fs::create_dir_all(guard_dir(module_id))?;
+    state::write_atomic(
+        &manifest_path(module_id),
+        serde_json::to_string_pretty(&guard)?,
+    )?;
+    if saved > 0 {
+        info!("backed up {saved} critical path(s) {module_id} overrides");
+    }
+    Ok(())
+}
+
+/// Check the backups of a disabled or removed module and put the originals
+/// back where its `copy` mounts overwrote them, verifying each one. Other
+/// backends leave `/` alone, so their originals return with the unmount
+/// and the backups are only checked. Returns the paths restored.
+pub fn restore(module_id: &str) -> anyhow::Result<usize> {
+    let Some(guard) = load(module_id)? else {
+        return Ok(0);
+    };
+
+    for original in &guard.originals {
+        let Some(digest) = &original.digest else {
+            continue;
+        };
+        let backup = backup_path(module_id, &original.target);
+        if integrity::entry_digest(&backup).ok().as_ref() != Some(digest) {
+            bail!(
+                "backup of critical path {:?} in {backup:?} is missing or corrupt",
+                original.target
+            );
+        }
+    }
+    if guard.mounter != "copy" {
+        info!(
+            "{} critical path backup(s) of {module_id} verified",
+            guard.originals.len()
+        );
+        return Ok(0);
+    }
+
+    for original in &guard.originals {
+        remove_entry(&original.target)?;
+        let Some(digest) = &original.digest else {
+            continue;
+        };
+        copy_entry(&backup_path(module_id, &original.target), &original.target)?;
+        if integrity::entry_digest(&original.target)? != *digest {
+            bail!("restored {:?} does not match its original", original.target);
+        }
+        info!("restored original {:?}", original.target);
+    }
+
+    // the next mount is a first mount again
+    if let Err(e) = module::delete_dir(&guard_dir(module_id)) {
+        warn!("failed to delete critical path backups of {module_id}: {e:#}");
+    }
+    Ok(guard.originals.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_mounter_keeps_copy_until_restore() {
+        assert_eq!(recorded_mounter("", "overlay"), "overlay");
+        assert_eq!(recorded_mounter("overlay", "copy"), "copy");
+        assert_eq!(recorded_mounter("copy", "overlay"), "copy");
+        assert_eq!(recorded_mounter("copy", "files"), "copy");
+    }
+}
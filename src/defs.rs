@@ -1,6 +1,7 @@
 use std::fs;
 
 use clap::ValueEnum;
+use serde::Serialize;
 
 #[derive(Clone, Copy, Debug, ValueEnum, PartialEq, Eq)]
 pub enum Environment {
@@ -24,15 +25,110 @@ impl Environment {
     }
 }
 
-#[derive(Clone, Debug, ValueEnum)]
+#[derive(Clone, Copy, Debug, ValueEnum, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
 pub enum AppFilter {
     User,
     Builtin,
     BuiltinThirdparty,
 }
 
+impl AppFilter {
+    /// Name miniapp_cli knows the kind of app by.
+    pub fn name(self) -> &'static str {
+        match self {
+            AppFilter::User => "user",
+            AppFilter::Builtin => "builtin",
+            AppFilter::BuiltinThirdparty => "builtin-thirdparty",
+        }
+    }
+}
+
+pub const SCRIBA_DIR: &str = "/userdisk/scriba/";
 pub const CONFIG_FILE: &str = "/userdisk/scriba/config.toml";
 pub const LOGS_DIR: &str = "/userdisk/scriba/logs/";
+pub const LOG_DIR_ENV: &str = "SCRIBA_LOG_DIR";
+/// Top-level payload directories of a module and the root each maps onto.
+pub const PAYLOAD_ROOTS: &[(&str, &str)] =
+    &[("system", "/"), ("vendor", "/vendor"), ("opt", "/opt")];
+/// Per-language payload trees of a module, `locale/<lang>/`, each mapped
+/// onto `/`; only the one matching the device language is mounted.
+pub const LOCALE_DIR: &str = "locale";
 pub const BIN_DIR: &str = "/userdisk/scriba/bin/";
+/// Shell script putting the `path_dirs` of enabled modules on `PATH`,
+/// regenerated at boot-complete and sourced through `PROFILE_HOOK`.
+pub const PATH_PROFILE_FILE: &str = "/userdisk/scriba/path.sh";
+/// Static fuse-overlayfs build shipped alongside scriba, looked up in `BIN_DIR`.
+pub const FUSE_OVERLAY_HELPER: &str = "fuse-overlayfs";
 pub const MODULES_DIR: &str = "/userdisk/scriba/modules/";
 pub const MODULES_UPDATE_DIR: &str = "/userdisk/scriba/modules_update/";
+/// Versions replaced by an update, `<id>/<version>/`, for `module rollback`.
+pub const MODULES_BACKUP_DIR: &str = "/userdisk/scriba/modules_backup/";
+/// Data of each module, `<id>/`, kept across updates and, unless purged,
+/// uninstalls; `MODDATA` in module scripts.
+pub const MODULE_DATA_DIR: &str = "/userdisk/scriba/module_data/";
+pub const STAGING_DIR: &str = "/tmp/scriba/staging/";
+/// Per-boot state on tmpfs, gone after a reboot.
+pub const RUN_STATE_DIR: &str = "/tmp/scriba/run/";
+/// Capabilities probed this boot, see `scriba env`.
+pub const CAPABILITIES_FILE: &str = "/tmp/scriba/run/capabilities.json";
+/// Where the host pushes local files referenced by forwarded commands.
+pub const UPLOAD_DIR: &str = "/tmp/scriba/upload/";
+pub const STATE_DIR: &str = "/userdisk/scriba/state/";
+pub const AUDIT_LOG: &str = "/userdisk/scriba/state/audit.jsonl";
+/// Result and duration of the last boot-complete run.
+pub const LAST_BOOT_FILE: &str = "/userdisk/scriba/state/last_boot.json";
+/// Last run of each module script, `<id>/<script>.json`.
+pub const SCRIPT_RESULTS_DIR: &str = "/userdisk/scriba/state/scripts/";
+/// Author key fingerprints trusted with `trust add`.
+pub const TRUSTED_AUTHORS_FILE: &str = "/userdisk/scriba/state/trusted_authors.json";
+/// Mounts made for each module, for `mount list` and `module unmount`.
+pub const MOUNTS_FILE: &str = "/userdisk/scriba/state/mounts.json";
+/// Last classified mount failure of each module that failed to mount.
+pub const MOUNT_FAILURES_FILE: &str = "/userdisk/scriba/state/mount_failures.json";
+/// Module repositories added with `repo add` and their last fetched indexes.
+pub const REPOS_FILE: &str = "/userdisk/scriba/state/repos.json";
+/// Release channel chosen per module with `module channel set`.
+pub const CHANNELS_FILE: &str = "/userdisk/scriba/state/channels.json";
+/// Public keys module signatures are checked against, `<name>.pub` in hex.
+pub const KEYS_DIR: &str = "/userdisk/scriba/keys/";
+/// Signature file of a module, the signer's public key and an ed25519
+/// signature over the module contents.
+pub const SIGNATURE_FILE: &str = "signature";
+/// Per-file sha256 manifest written into a module on install.
+pub const HASHES_FILE: &str = "module.sha256";
+pub const PROFILES_DIR: &str = "/userdisk/scriba/state/profiles/";
+pub const SAFE_MODE_FLAG: &str = "/userdisk/Favorite/safe_mode.flag";
+pub const ADB_AUTH_FLAG: &str = "/tmp/.adb_auth_verified";
+/// Init script that runs boot-complete, installed by `install-self`.
+pub const INIT_HOOK: &str = "/etc/init.d/S99scriba";
+/// Login shell snippet sourcing `PATH_PROFILE_FILE`, installed by
+/// `install-self`.
+pub const PROFILE_HOOK: &str = "/etc/profile.d/scriba.sh";
+
+/// Default `guard.critical_paths`: boot scripts and network configuration.
+pub const CRITICAL_PATHS: &[&str] = &[
+    "/etc/init.d/**",
+    "/etc/inittab",
+    "/etc/rc*.d/**",
+    "/etc/network/**",
+    "/etc/wpa_supplicant*",
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/ssh/**",
+];
+
+/// Script interpreters allowed unless `scripts.interpreters` says otherwise.
+pub const DEFAULT_INTERPRETERS: &[&str] = &[
+    "/bin/sh",
+    "/bin/ash",
+    "/bin/bash",
+    "/usr/bin/env sh",
+    "/usr/bin/env bash",
+];
+
+/// Where miniapps keep their data, one directory per app id.
+pub const APP_DATA_DIR: &str = "/userdisk/data/miniapp/";
+
+/// Where the platform leaves crash dumps and tombstones by default.
+pub const CRASH_DIRS: &[&str] = &["/userdisk/crash/", "/userdisk/log/crash/", "/tmp/crash/"];
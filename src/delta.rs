@@ -0,0 +1,358 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use tempfile::tempdir;
+use tracing::info;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::archive::{self, Extractor, Limits, Zip};
+use crate::defs::MODULES_DIR;
+use crate::integrity;
+use crate::module;
+use crate::storage;
+
+/// Name of the manifest that marks an archive as a delta package.
+const MANIFEST: &str = "delta.json";
+/// zstd level used for patches; deltas are built once and applied many times.
+const PATCH_LEVEL: i32 = 19;
+
+/// A delta package turns one installed module version into the next.
+///
+/// Files absent from the manifest are carried over from the installed copy.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeltaManifest {
+    pub id: String,
+    pub from_version: i32,
+    pub to_version: i32,
+    pub entries: Vec<DeltaEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum DeltaEntry {
+    /// zstd patch against the installed file, stored at `patches/<path>`
+    Patch {
+        path: String,
+        sha256: String,
+        mode: u32,
+    },
+    /// Whole file, stored at `files/<path>`
+    Add {
+        path: String,
+        sha256: String,
+        mode: u32,
+    },
+    /// File dropped by the new version
+    Remove { path: String },
+}
+
+/// Smallest zstd window covering `size` bytes, within zstd's limits.
+fn window_log(size: usize) -> u32 {
+    (usize::BITS - size.max(1).leading_zeros()).clamp(10, 31)
+}
+
+fn encode_patch(old: &[u8], new: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = zstd::stream::write::Encoder::with_ref_prefix(Vec::new(), PATCH_LEVEL, old)?;
+    encoder.window_log(window_log(old.len().max(new.len())))?;
+    encoder.long_distance_matching(true)?;
+    encoder.write_all(new)?;
+    Ok(encoder.finish()?)
+}
+
+fn decode_patch(old: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(patch, old)?;
+    decoder.window_log_max(31)?;
+    let mut out = Vec::new();
+    decoder.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Regular files of a module tree as relative paths, ignoring state flags.
+fn module_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let mut entries = Vec::new();
+    module::collect_entries(dir, dir, &mut entries)?;
+
+    Ok(entries
+        .into_iter()
+        .filter(|rel| !is_flag(rel))
+        .filter(|rel| {
+            fs::symlink_metadata(dir.join(rel))
+                .map(|m| m.is_file())
+                .unwrap_or(false)
+        })
+        .collect())
+}
+
+fn is_flag(rel: &Path) -> bool {
+    rel.components().count() == 1 && rel.extension().is_some_and(|ext| ext == "flag")
+}
+
+fn prop_id_version(dir: &Path) -> Result<(String, i32)> {
+    let props = module::parse_prop_file(&dir.join("module.prop"))
+        .with_context(|| format!("failed to read module.prop in {dir:?}"))?;
+    let id = props
+        .get("id")
+        .ok_or_else(|| anyhow!("module.prop in {dir:?} missing id"))?
+        .clone();
+    let version = props
+        .get("version")
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| anyhow!("module.prop in {dir:?} has no valid version"))?;
+    Ok((id, version))
+}
+
+/// Whether the archive at `path` is a delta package rather than a full module.
+pub fn is_delta(path: &Path) -> Result<bool> {
+    // deltas are zips; other archive formats are plain modules
+    if archive::detect(path)?.name() != Zip.name() {
+        return Ok(false);
+    }
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    Ok(archive.by_name(MANIFEST).is_ok())
+}
+
+/// Build a delta package turning module source `old_dir` into `new_dir`.
+pub fn create_delta(old_dir: &Path, new_dir: &Path, output: &Path) -> Result<DeltaManifest> {
+    let (old_id, from_version) = prop_id_version(old_dir)?;
+    let (id, to_version) = prop_id_version(new_dir)?;
+    if old_id != id {
+        bail!("module ids differ: '{old_id}' vs '{id}'");
+    }
+
+    let old_files = module_files(old_dir)?;
+    let new_files = module_files(new_dir)?;
+
+    let mut zip = ZipWriter::new(File::create(output)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut entries = Vec::new();
+
+    for rel in &new_files {
+        let path = rel.to_string_lossy().to_string();
+        let new_path = new_dir.join(rel);
+        let new = fs::read(&new_path)?;
+        let mode = fs::metadata(&new_path)?.permissions().mode() & 0o7777;
+        let sha256 = integrity::sha256(&mut new.as_slice())?;
+
+        if old_files.contains(rel) {
+            let old_path = old_dir.join(rel);
+            let old = fs::read(&old_path)?;
+            let old_mode = fs::metadata(&old_path)?.permissions().mode() & 0o7777;
+            if old == new && old_mode == mode {
+                continue;
+            }
+
+            // patches are already compressed, store them as-is
+            let patch = encode_patch(&old, &new)?;
+            if patch.len() < new.len() {
+                zip.start_file(format!("patches/{path}"), stored)?;
+                zip.write_all(&patch)?;
+                entries.push(DeltaEntry::Patch { path, sha256, mode });
+                continue;
+            }
+        }
+
+        zip.start_file(format!("files/{path}"), options)?;
+        zip.write_all(&new)?;
+        entries.push(DeltaEntry::Add { path, sha256, mode });
+    }
+
+    for rel in old_files.difference(&new_files) {
+        entries.push(DeltaEntry::Remove {
+            path: rel.to_string_lossy().to_string(),
+        });
+    }
+
+    let manifest = DeltaManifest {
+        id,
+        from_version,
+        to_version,
+        entries,
+    };
+    zip.start_file(MANIFEST, options)?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    zip.finish()?;
+
+    Ok(manifest)
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str, max_bytes: u64) -> Result<Vec<u8>> {
+    let file = archive
+        .by_name(name)
+        .with_context(|| format!("delta package is missing {name}"))?;
+    // declared sizes can lie, count what is read
+    let mut data = Vec::new();
+    file.take(max_bytes + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > max_bytes {
+        bail!("delta entry {name} unpacks to more than {max_bytes} bytes");
+    }
+    Ok(data)
+}
+
+/// Reject manifest paths that could escape the module directory.
+fn safe_relative(path: &str) -> Result<&Path> {
+    let rel = Path::new(path);
+    if rel.as_os_str().is_empty()
+        || rel.is_absolute()
+        || rel
+            .components()
+            .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        bail!("delta entry has unsafe path '{path}'");
+    }
+    Ok(rel)
+}
+
+/// Apply a delta package against the installed module it targets.
+///
+/// Returns a temp directory holding the complete new module, named after
+/// the module id, ready to be staged like an extracted archive.
+pub fn apply_delta(delta_path: &Path, limits: Limits) -> Result<PathBuf> {
+    let mut archive = ZipArchive::new(File::open(delta_path)?)?;
+    let manifest: DeltaManifest =
+        serde_json::from_slice(&read_entry(&mut archive, MANIFEST, limits.max_bytes)?)
+            .context("invalid delta manifest")?;
+    if manifest.entries.len() as u64 > limits.max_entries {
+        bail!("delta package has more than {} entries", limits.max_entries);
+    }
+    module::validate_module_id(&manifest.id)?;
+
+    let installed_dir = Path::new(MODULES_DIR).join(&manifest.id);
+    if !installed_dir.is_dir() {
+        bail!(
+            "delta package targets module {} which is not installed",
+            manifest.id
+        );
+    }
+
+    let (_, installed_version) = prop_id_version(&installed_dir)?;
+    if installed_version != manifest.from_version {
+        bail!(
+            "delta package applies to {} v{}, but v{} is installed",
+            manifest.id,
+            manifest.from_version,
+            installed_version
+        );
+    }
+
+    info!(
+        "applying delta {} v{} -> v{}",
+        manifest.id, manifest.from_version, manifest.to_version
+    );
+
+    let target_dir = tempdir()?.keep().join(&manifest.id);
+    module::copy_dir(&installed_dir, &target_dir)?;
+    // deltas are made between source trees, so patch a plain `system/`; the
+    // configured storage mode applies again when the update is mounted
+    if target_dir.join(storage::COMPRESSED_PAYLOAD).is_file() {
+        storage::decompress(&target_dir)?;
+    }
+
+    // state flags and the hash manifest belong to the installed copy, not
+    // the update, and would break its signature
+    for entry in fs::read_dir(&target_dir)? {
+        let entry = entry?;
+        if integrity::is_bookkeeping(Path::new(&entry.file_name())) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    let mut remaining = limits.max_bytes;
+    for entry in &manifest.entries {
+        match entry {
+            DeltaEntry::Patch { path, sha256, mode } | DeltaEntry::Add { path, sha256, mode } => {
+                let rel = safe_relative(path)?;
+                // the installed copy may hold symlinks into the device's root
+                if archive::through_symlink(&target_dir, rel) {
+                    bail!("delta entry {path} would be written through a symlink");
+                }
+                let dst = target_dir.join(rel);
+                let data = match entry {
+                    DeltaEntry::Patch { .. } => {
+                        let old = fs::read(&dst)
+                            .with_context(|| format!("installed copy is missing {path}"))?;
+                        let patch =
+                            read_entry(&mut archive, &format!("patches/{path}"), remaining)?;
+                        decode_patch(&old, &patch)
+                            .with_context(|| format!("failed to patch {path}"))?
+                    }
+                    _ => read_entry(&mut archive, &format!("files/{path}"), remaining)?,
+                };
+                if data.len() as u64 > remaining {
+                    bail!(
+                        "delta package unpacks to more than {} bytes",
+                        limits.max_bytes
+                    );
+                }
+                remaining -= data.len() as u64;
+
+                if integrity::sha256(&mut data.as_slice())? != *sha256 {
+                    bail!("checksum mismatch for {path} after applying delta");
+                }
+
+                if let Some(parent) = dst.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&dst, &data)?;
+                let mode = archive::safe_mode(rel, *mode)?;
+                fs::set_permissions(&dst, fs::Permissions::from_mode(mode))?;
+            }
+
+            DeltaEntry::Remove { path } => {
+                let rel = safe_relative(path)?;
+                if archive::through_symlink(&target_dir, rel.parent().unwrap_or(rel)) {
+                    bail!("delta entry {path} would be removed through a symlink");
+                }
+                let dst = target_dir.join(rel);
+                if dst.exists() {
+                    fs::remove_file(&dst)?;
+                }
+            }
+        }
+    }
+
+    let (_, version) = prop_id_version(&target_dir)?;
+    if version != manifest.to_version {
+        bail!(
+            "delta result has version {version}, expected {}",
+            manifest.to_version
+        );
+    }
+
+    Ok(target_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_relative_accepts_plain_paths() {
+        for path in ["module.prop", "system/bin/tool", "a b/c"] {
+            assert_eq!(safe_relative(path).unwrap(), Path::new(path));
+        }
+    }
+
+    #[test]
+    fn safe_relative_rejects_escapes() {
+        for path in [
+            "",
+            ".",
+            "./module.prop",
+            "..",
+            "../module",
+            "system/../../etc",
+            "/etc/passwd",
+            "//etc/passwd",
+        ] {
+            assert!(safe_relative(path).is_err(), "{path:?} was accepted");
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::boot::{self, DeferredStage, ModuleStep};
+use crate::defs::{
+    LAST_BOOT_FILE, MODULES_DIR, MODULES_UPDATE_DIR, MOUNT_FAILURES_FILE, SAFE_MODE_FLAG,
+};
+use crate::logging;
+use crate::module;
+use crate::mount;
+
+/// Module log lines quoted as evidence.
+const LOG_EXCERPT_LINES: usize = 5;
+
+/// One reason a module is not active, with what shows it.
+#[derive(Debug, Serialize)]
+pub struct Reason {
+    pub reason: String,
+    /// Flag paths, state entries and log lines backing the reason
+    pub evidence: Vec<String>,
+}
+
+/// Why a module is or is not active.
+#[derive(Debug, Serialize)]
+pub struct Diagnosis {
+    pub id: String,
+    /// Whether any of its payload is mounted right now
+    pub mounted: bool,
+    pub reasons: Vec<Reason>,
+}
+
+/// Last warning and error lines of a module's detail log.
+fn log_excerpt(module_id: &str) -> Vec<String> {
+    let path = logging::module_log_path(module_id);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+
+    let lines: Vec<String> = content
+        .lines()
+        .filter(|line| line.contains(" WARN ") || line.contains("ERROR "))
+        .map(|line| format!("{}: {line}", path.display()))
+        .collect();
+    let skip = lines.len().saturating_sub(LOG_EXCERPT_LINES);
+    lines.into_iter().skip(skip).collect()
+}
+
+/// Look through every state source that can keep a module from being
+/// active: safe mode, pending uninstall, `disable.flag`, module.prop
+/// validation, the last boot record and the module's recorded script runs.
+pub fn why_disabled(module_id: &str) -> Result<Diagnosis> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
+    let update_dir = Path::new(MODULES_UPDATE_DIR).join(module_id);
+    let mut reasons = Vec::new();
+
+    if !module_dir.is_dir() {
+        if !update_dir.is_dir() {
+            bail!("module {module_id} is not installed");
+        }
+        reasons.push(Reason {
+            reason: "installed but not active until the next reboot".to_string(),
+            evidence: vec![update_dir.display().to_string()],
+        });
+        return Ok(Diagnosis {
+            id: module_id.to_string(),
+            mounted: false,
+            reasons,
+        });
+    }
+
+    if Path::new(SAFE_MODE_FLAG).exists() {
+        reasons.push(Reason {
+            reason: "safe mode is on, no module is initialized at boot".to_string(),
+            evidence: vec![SAFE_MODE_FLAG.to_string()],
+        });
+    }
+
+    let uninstall_flag = module_dir.join("uninstall.flag");
+    if uninstall_flag.exists() {
+        reasons.push(Reason {
+            reason: "pending uninstall, it will be removed at the next reboot".to_string(),
+            evidence: vec![uninstall_flag.display().to_string()],
+        });
+    }
+
+    match boot::module_step(&module_dir) {
+        ModuleStep::Invalid(e) => reasons.push(Reason {
+            reason: "module.prop fails validation, boot skips it".to_string(),
+            evidence: vec![
+                module_dir.join("module.prop").display().to_string(),
+                format!("{e:#}"),
+            ],
+        }),
+        ModuleStep::Disabled => reasons.push(Reason {
+            reason: "disabled".to_string(),
+            evidence: vec![module_dir.join("disable.flag").display().to_string()],
+        }),
+        ModuleStep::Init { mount: false, .. } => reasons.push(Reason {
+            reason: "skip_mount is set, its payload is never mounted".to_string(),
+            evidence: vec![format!(
+                "{}: skip_mount=true",
+                module_dir.join("module.prop").display()
+            )],
+        }),
+        ModuleStep::Init { .. } => {}
+    }
+
+    if let Some(record) = boot::last_boot() {
+        let entry = |what: &str| format!("{LAST_BOOT_FILE}: {what} (boot at {})", record.started);
+        if record.failed_modules.iter().any(|id| id == module_id) {
+            let mut evidence = vec![entry("failed_modules")];
+            evidence.extend(log_excerpt(module_id));
+            reasons.push(Reason {
+                reason: "failed to initialize at the last boot".to_string(),
+                evidence,
+            });
+        }
+        if record.waiting_mounts.iter().any(|id| id == module_id) {
+            let outcome = record.late_mount.as_deref().unwrap_or("not retried yet");
+            reasons.push(Reason {
+                reason: "mount target was missing at the last boot".to_string(),
+                evidence: vec![entry("waiting_mounts"), format!("late mount: {outcome}")],
+            });
+        }
+        if let Some(deferred) = record.deferred.iter().find(|m| m.id == module_id) {
+            let outcome = record.late_pass.as_deref().unwrap_or("not finished");
+            reasons.push(Reason {
+                reason: match deferred.stage {
+                    DeferredStage::Mount => "deferred by a boot stage budget before mounting",
+                    DeferredStage::Script => "deferred by a boot stage budget before its script",
+                }
+                .to_string(),
+                evidence: vec![entry("deferred"), format!("late pass: {outcome}")],
+            });
+        }
+    }
+
+    if let Some(failure) = mount::mount_failures().remove(module_id) {
+        reasons.push(Reason {
+            reason: format!("its last mount failed ({})", failure.kind.as_str()),
+            evidence: vec![
+                format!(
+                    "{MOUNT_FAILURES_FILE}: {} time(s) in a row, last at {}",
+                    failure.count, failure.at
+                ),
+                failure.error,
+            ],
+        });
+    }
+
+    for result in module::script_results(module_id) {
+        if !result.success() {
+            let mut evidence = vec![format!(
+                "{} exited with {:?} at {}",
+                result.script, result.exit_code, result.finished
+            )];
+            evidence.extend(
+                result
+                    .output
+                    .iter()
+                    .rev()
+                    .take(LOG_EXCERPT_LINES)
+                    .rev()
+                    .cloned(),
+            );
+            reasons.push(Reason {
+                reason: format!("{} failed on its last run", result.script),
+                evidence,
+            });
+        }
+    }
+
+    let mounted = module::is_mounted(module_id);
+    if update_dir.is_dir() {
+        reasons.push(Reason {
+            reason: "an update is staged and replaces it at the next reboot".to_string(),
+            evidence: vec![update_dir.display().to_string()],
+        });
+    }
+
+    Ok(Diagnosis {
+        id: module_id.to_string(),
+        mounted,
+        reasons,
+    })
+}
+
+pub fn print(diagnosis: &Diagnosis) {
+    if diagnosis.reasons.is_empty() {
+        if diagnosis.mounted {
+            info!("module {} is active", diagnosis.id);
+        } else {
+            info!(
+                "module {} is enabled and nothing recorded keeps it from being active, it mounts at the next boot",
+                diagnosis.id
+            );
+        }
+        return;
+    }
+
+    for reason in &diagnosis.reasons {
+        warn!("{}: {}", diagnosis.id, reason.reason);
+        for evidence in &reason.evidence {
+            info!("  {evidence}");
+        }
+    }
+    info!(
+        "payload {}",
+        if diagnosis.mounted {
+            "is mounted right now"
+        } else {
+            "is not mounted"
+        }
+    );
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use tracing::info;
+
+use crate::defs::{MODULES_DIR, MODULES_UPDATE_DIR};
+use crate::module;
+
+/// A module script a command would run, and when.
+#[derive(Debug, Serialize)]
+pub struct PlannedScript {
+    pub script: String,
+    pub when: &'static str,
+}
+
+/// What `module install` or `module uninstall` would do.
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub id: String,
+    /// Version the module would be at, `None` for an uninstall
+    pub version: Option<i64>,
+    /// Version installed now, if any
+    pub installed: Option<i64>,
+    /// What would change on disk
+    pub action: String,
+    pub destination: Option<PathBuf>,
+    /// Paths on / the module's payload would cover (install) or stop
+    /// covering (uninstall)
+    pub paths: Vec<PathBuf>,
+    pub scripts: Vec<PlannedScript>,
+}
+
+fn script(dir: &Path, name: &str, when: &'static str) -> Option<PlannedScript> {
+    dir.join(name).is_file().then(|| PlannedScript {
+        script: name.to_string(),
+        when,
+    })
+}
+
+/// Plan installing the module extracted and validated in `staged`.
+pub fn install(staged: &Path, prop: &HashMap<String, String>, clean: bool) -> Result<Plan> {
+    let id = prop.get("id").cloned().unwrap_or_default();
+    let destination = Path::new(MODULES_UPDATE_DIR).join(&id);
+    let installed = module::module_version(&Path::new(MODULES_DIR).join(&id));
+
+    let mut action = match (destination.is_dir(), installed) {
+        (true, _) => "replace the pending update, takes effect after reboot",
+        (false, Some(_)) => "stage an update, takes effect after reboot",
+        (false, None) => "stage a new module, takes effect after reboot",
+    }
+    .to_string();
+    if clean && module::data_dir(&id).exists() {
+        action += &format!(
+            ", replacing {} with fresh data when it takes effect",
+            module::data_dir(&id).display()
+        );
+    }
+
+    let postinstall = if !staged.join("postinstall.sh").exists() {
+        "install.sh"
+    } else {
+        "postinstall.sh"
+    };
+    let scripts = [
+        script(staged, "preinstall.sh", "before staging"),
+        script(staged, postinstall, "after staging"),
+        script(staged, "post-mount.sh", "after mounting at every boot"),
+        script(staged, "boot-complete.sh", "at every boot"),
+        script(staged, "uninstall.sh", "on uninstall"),
+        script(
+            staged,
+            "pre-uninstall.sh",
+            "before removal at the next boot",
+        ),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    let paths = if prop.get("skip_mount").map(String::as_str) == Some("true") {
+        Vec::new()
+    } else {
+        module::payload_targets(staged)?
+    };
+
+    Ok(Plan {
+        version: prop.get("version").and_then(|v| v.parse().ok()),
+        installed,
+        action,
+        destination: Some(destination),
+        paths,
+        scripts,
+        id,
+    })
+}
+
+/// Plan `module uninstall` of `module_id`, which toggles the uninstall of
+/// an installed module or drops a pending one.
+pub fn uninstall(module_id: &str, purge: bool) -> Result<Plan> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
+    let update_dir = Path::new(MODULES_UPDATE_DIR).join(module_id);
+    let installed = module::module_version(&module_dir);
+
+    let mut plan = Plan {
+        id: module_id.to_string(),
+        version: None,
+        installed,
+        action: String::new(),
+        destination: None,
+        paths: Vec::new(),
+        scripts: Vec::new(),
+    };
+    let data = module::data_dir(module_id);
+    if update_dir.exists() {
+        plan.action = format!("remove the pending update in {}", update_dir.display());
+        if purge && !module_dir.exists() && data.exists() {
+            plan.action += &format!(" and delete {}", data.display());
+        }
+    } else if module_dir.join("uninstall.flag").exists() {
+        plan.action = "unmark it for uninstall".to_string();
+    } else if module_dir.exists() {
+        plan.action = "mark it for uninstall, removed at the next reboot".to_string();
+        if purge && data.exists() {
+            plan.action += &format!(" with {}", data.display());
+        }
+        plan.paths = module::payload_targets(&module_dir)?;
+        plan.scripts
+            .extend(script(&module_dir, "uninstall.sh", "now"));
+        plan.scripts.extend(script(
+            &module_dir,
+            "pre-uninstall.sh",
+            "before removal at the next boot",
+        ));
+    } else {
+        bail!("module {module_id} is not installed or being updated");
+    }
+    Ok(plan)
+}
+
+pub fn print(plan: &Plan) {
+    let version = |v: Option<i64>| v.map_or_else(|| "?".to_string(), |v| format!("v{v}"));
+    match (plan.version, plan.installed) {
+        (Some(_), Some(_)) => info!(
+            "dry run: {} {} -> {}",
+            plan.id,
+            version(plan.installed),
+            version(plan.version)
+        ),
+        (Some(_), None) => info!("dry run: {} {}", plan.id, version(plan.version)),
+        (None, _) => info!("dry run: {} {}", plan.id, version(plan.installed)),
+    }
+    info!("  would {}", plan.action);
+    if let Some(destination) = &plan.destination {
+        info!("  destination: {}", destination.display());
+    }
+
+    if plan.scripts.is_empty() {
+        info!("  no scripts");
+    }
+    for script in &plan.scripts {
+        info!("  runs {} {}", script.script, script.when);
+    }
+
+    let verb = if plan.version.is_some() {
+        "overrides"
+    } else {
+        "releases"
+    };
+    info!("  {verb} {} path(s):", plan.paths.len());
+    for path in &plan.paths {
+        info!("    {}", path.display());
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::bail;
+use tracing::{info, warn};
+
+use crate::config::ScriptConfig;
+use crate::defs::MODULES_DIR;
+use crate::module::{self, ScriptResult};
+
+/// Deliver an event to every active module shipping `events/<name>.sh`.
+///
+/// Handlers get `SCRIBA_EVENT=<name>` and one `SCRIBA_EVENT_<KEY>` variable
+/// per data pair. A failing handler is logged and does not stop the others.
+pub fn dispatch(
+    name: &str,
+    data: &[(String, String)],
+    scripts: &ScriptConfig,
+) -> anyhow::Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        bail!("event name must contain only letters, numbers, '-' or '_'");
+    }
+
+    let mut envs = vec![("SCRIBA_EVENT".to_string(), name.to_string())];
+    envs.extend(data.iter().map(|(key, value)| {
+        (
+            format!(
+                "SCRIBA_EVENT_{}",
+                key.to_ascii_uppercase().replace('-', "_")
+            ),
+            value.clone(),
+        )
+    }));
+
+    let script = format!("events/{name}.sh");
+    let mut entries: Vec<_> = fs::read_dir(MODULES_DIR)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+
+    let mut handled = 0;
+    for path in entries {
+        if !is_active(&path) || !path.join(&script).is_file() {
+            continue;
+        }
+
+        info!("delivering event {name} to {path:?}");
+        handled += 1;
+        if let Err(e) = module::run_script_with_env(&path, &script, &envs, scripts)
+            .and_then(ScriptResult::check)
+        {
+            warn!("event handler {script} of {path:?} failed: {e}");
+        }
+    }
+
+    info!("event {name} delivered to {handled} module(s)");
+    Ok(())
+}
+
+/// Disabled and uninstall-flagged modules do not receive events.
+fn is_active(module_dir: &Path) -> bool {
+    module_dir.is_dir()
+        && !module::disabled_this_boot(module_dir)
+        && !module_dir.join("uninstall.flag").exists()
+}
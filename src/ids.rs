@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of names for temp files and markers, so code creating them can
+/// be run with predictable names.
+pub trait IdSource {
+    /// A name component not handed out before by this source.
+    fn next_id(&self) -> String;
+}
+
+/// Ids unique across concurrently running scriba processes:
+/// `<pid>-<counter>`.
+pub struct ProcessIds;
+
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+impl IdSource for ProcessIds {
+    fn next_id(&self) -> String {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{n}", std::process::id())
+    }
+}
+
+/// Hands out the same id every time, for tests.
+#[cfg(test)]
+pub struct FixedIds(pub &'static str);
+
+#[cfg(test)]
+impl IdSource for FixedIds {
+    fn next_id(&self) -> String {
+        self.0.to_string()
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use zip::ZipArchive;
+
+use crate::defs::{HASHES_FILE, MODULES_DIR};
+use crate::module;
+use crate::state;
+use crate::storage::COMPRESSED_PAYLOAD;
+
+/// Hex sha256 of everything `reader` yields.
+pub fn sha256(reader: &mut impl Read) -> io::Result<String> {
+    sha256_each(reader, |_| Ok(()))
+}
+
+/// Hex sha256 of everything `reader` yields, handing each chunk to `each`
+/// as it is read.
+pub fn sha256_each(
+    reader: &mut impl Read,
+    mut each: impl FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        each(&buf[..n])?;
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Digest of every file and symlink below `dir`, by relative path: the
+/// file's sha256, or `-> <target>` for a symlink.
+pub fn tree_digests(dir: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let mut entries = Vec::new();
+    module::collect_entries(dir, dir, &mut entries)?;
+
+    let mut digests = BTreeMap::new();
+    for rel in entries {
+        let path = dir.join(&rel);
+        let meta = fs::symlink_metadata(&path)?;
+        if meta.is_file() {
+            digests.insert(rel, sha256(&mut fs::File::open(&path)?)?);
+        } else if meta.is_symlink() {
+            digests.insert(
+                rel,
+                format!("-> {}", fs::read_link(&path)?.to_string_lossy()),
+            );
+        }
+    }
+    Ok(digests)
+}
+
+/// Digest of one entry: a file's sha256, `-> <target>` for a symlink, or
+/// for a directory the sha256 of its `tree_digests`.
+pub fn entry_digest(path: &Path) -> Result<String> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_symlink() {
+        return Ok(format!("-> {}", fs::read_link(path)?.to_string_lossy()));
+    }
+    if meta.is_file() {
+        return Ok(sha256(&mut fs::File::open(path)?)?);
+    }
+
+    let mut listing = String::new();
+    for (rel, digest) in tree_digests(path)? {
+        listing.push_str(&format!("{} {digest}\n", rel.to_string_lossy()));
+    }
+    Ok(sha256(&mut listing.as_bytes())?)
+}
+
+/// Digests of the `system/` tree kept in a compressed payload, by the
+/// path the files had in the module.
+fn payload_digests(payload: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let mut archive = ZipArchive::new(fs::File::open(payload)?)?;
+    let mut digests = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let rel = Path::new("system").join(file.name());
+        if file.is_dir() {
+            continue;
+        } else if file.is_symlink() {
+            let mut link = String::new();
+            file.read_to_string(&mut link)?;
+            digests.insert(rel, format!("-> {link}"));
+        } else {
+            digests.insert(rel, sha256(&mut file)?);
+        }
+    }
+    Ok(digests)
+}
+
+/// Whether a path holds scriba's own bookkeeping rather than module
+/// contents: top-level state flags and the hash manifest.
+pub fn is_bookkeeping(rel: &Path) -> bool {
+    rel.components().count() == 1
+        && (rel == Path::new(HASHES_FILE) || rel.extension().is_some_and(|ext| ext == "flag"))
+}
+
+/// Current digests of a module's contents, looking into a compressed
+/// payload instead of hashing the archive itself.
+fn module_digests(module_dir: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let mut digests = tree_digests(module_dir)?;
+    digests.retain(|rel, _| !is_bookkeeping(rel));
+
+    let payload = module_dir.join(COMPRESSED_PAYLOAD);
+    if payload.is_file() {
+        digests.remove(Path::new(COMPRESSED_PAYLOAD));
+        digests.extend(payload_digests(&payload)?);
+    }
+    Ok(digests)
+}
+
+/// Record the digests of a freshly installed module in its `HASHES_FILE`,
+/// as `<digest>  <path>` lines like `sha256sum` prints.
+pub fn write_manifest(module_dir: &Path) -> Result<usize> {
+    let digests = module_digests(module_dir)?;
+    let manifest: String = digests
+        .iter()
+        .map(|(rel, digest)| format!("{digest}  {}\n", rel.to_string_lossy()))
+        .collect();
+    state::write_atomic(&module_dir.join(HASHES_FILE), manifest)?;
+    Ok(digests.len())
+}
+
+fn read_manifest(path: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let mut digests = BTreeMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let Some((digest, rel)) = line.split_once("  ") else {
+            bail!("malformed line in {path:?}: {line}");
+        };
+        digests.insert(PathBuf::from(rel), digest.to_string());
+    }
+    Ok(digests)
+}
+
+/// Differences between an installed module and its hash manifest.
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub id: String,
+    /// Entries listed in the manifest
+    pub checked: usize,
+    /// Entries whose contents changed
+    pub modified: Vec<PathBuf>,
+    /// Entries listed in the manifest but gone
+    pub missing: Vec<PathBuf>,
+    /// Entries not listed in the manifest
+    pub added: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    pub fn intact(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.added.is_empty()
+    }
+}
+
+/// Re-hash an installed module and compare it with its manifest.
+pub fn verify(module_id: &str) -> Result<VerifyReport> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
+    if !module_dir.is_dir() {
+        bail!("module {module_id} is not installed");
+    }
+    let manifest_path = module_dir.join(HASHES_FILE);
+    if !manifest_path.is_file() {
+        bail!("module {module_id} has no {HASHES_FILE}, reinstall it to record one");
+    }
+
+    let expected = read_manifest(&manifest_path)?;
+    let mut actual = module_digests(&module_dir)?;
+
+    let mut report = VerifyReport {
+        id: module_id.to_string(),
+        checked: expected.len(),
+        modified: Vec::new(),
+        missing: Vec::new(),
+        added: Vec::new(),
+    };
+    for (rel, digest) in expected {
+        match actual.remove(&rel) {
+            Some(current) if current == digest => {}
+            Some(_) => report.modified.push(rel),
+            None => report.missing.push(rel),
+        }
+    }
+    report.added = actual.into_keys().collect();
+    Ok(report)
+}
+
+pub fn print_report(report: &VerifyReport) {
+    for path in &report.modified {
+        warn!("modified: {}", path.display());
+    }
+    for path in &report.missing {
+        warn!("missing: {}", path.display());
+    }
+    for path in &report.added {
+        warn!("added: {}", path.display());
+    }
+
+    if report.intact() {
+        info!("module {}: {} file(s) intact", report.id, report.checked);
+    } else {
+        warn!(
+            "module {}: {} modified, {} missing, {} added of {} file(s)",
+            report.id,
+            report.modified.len(),
+            report.missing.len(),
+            report.added.len(),
+            report.checked
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_each_hashes_and_hands_over_every_chunk() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let mut seen = Vec::new();
+        let digest = sha256_each(&mut data.as_slice(), |chunk| {
+            seen.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(seen, data);
+        assert_eq!(digest, hex::encode(Sha256::digest(&data)));
+        assert_eq!(
+            sha256(&mut "abc".as_bytes()).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+use tempfile::tempdir;
+use tracing::{error, info, warn};
+use zip::ZipArchive;
+
+use crate::archive::{self, Limits};
+use crate::cache;
+use crate::config::MountMode;
+use crate::critical;
+use crate::defs::{CRITICAL_PATHS, DEFAULT_INTERPRETERS, LOCALE_DIR, MODULES_DIR, PAYLOAD_ROOTS};
+use crate::integrity;
+use crate::module;
+use crate::path_env;
+use crate::storage;
+use crate::trust;
+
+/// Props every module.prop needs.
+const REQUIRED_PROPS: &[&str] = &["id", "name", "description", "version"];
+
+/// Paths a payload must not cover: kernel and runtime filesystems, and the
+/// partition scriba itself lives on.
+const FORBIDDEN_TARGETS: &[&str] = &["/proc", "/sys", "/dev", "/run", "/tmp", "/userdisk"];
+
+/// Directories whose files are expected to be executable.
+const EXECUTABLE_DIRS: &[&str] = &["bin", "sbin", "libexec"];
+
+/// Files packing tools leave behind that do not belong in a module.
+const JUNK_NAMES: &[&str] = &["__MACOSX", ".DS_Store", "Thumbs.db", ".git"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem `module lint` found.
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    /// Which group of checks found it: prop, scripts, payload or archive
+    pub check: &'static str,
+    /// Relative to the module root, or an archive entry name
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LintReport {
+    pub target: String,
+    /// Module id from module.prop, when it could be read
+    pub id: Option<String>,
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    fn add(
+        &mut self,
+        severity: Severity,
+        check: &'static str,
+        path: Option<&Path>,
+        message: impl Into<String>,
+    ) {
+        self.findings.push(Finding {
+            severity,
+            check,
+            path: path.map(Path::to_path_buf),
+            message: message.into(),
+        });
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == severity)
+            .count()
+    }
+}
+
+/// Where the module being linted comes from, which decides how strict the
+/// directory name and bookkeeping checks are.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Installed,
+    Source,
+    Archive,
+}
+
+/// Check a module archive, a module source directory or an installed
+/// module against what scriba expects of modules.
+pub fn lint(target: &str) -> Result<LintReport> {
+    let mut report = LintReport {
+        target: target.to_string(),
+        id: None,
+        findings: Vec::new(),
+    };
+
+    let path = Path::new(target);
+    if path.is_file() {
+        if ZipArchive::new(File::open(path)?).is_ok() {
+            zip_hygiene(path, &mut report)?;
+        }
+        let scratch = tempdir()?;
+        let dir = scratch.path().join("module");
+        if let Err(e) = archive::extract(path, &dir, Limits::default()) {
+            report.add(Severity::Error, "archive", None, format!("{e:#}"));
+            return Ok(report);
+        }
+        lint_dir(&dir, Origin::Archive, &mut report)?;
+    } else if path.is_dir() {
+        lint_dir(path, Origin::Source, &mut report)?;
+    } else {
+        module::validate_module_id(target)?;
+        let dir = Path::new(MODULES_DIR).join(target);
+        if !dir.is_dir() {
+            bail!("{target} is neither a module archive or directory nor an installed module");
+        }
+        lint_dir(&dir, Origin::Installed, &mut report)?;
+    }
+    Ok(report)
+}
+
+/// Entry names of a zip that extraction would refuse or that do not belong
+/// in a module.
+fn zip_hygiene(path: &Path, report: &mut LintReport) -> Result<()> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut seen = HashSet::new();
+    for i in 0..archive.len() {
+        let name = archive.by_index(i)?.name().to_string();
+        let entry = Path::new(&name);
+        if name.contains('\\') {
+            report.add(
+                Severity::Warning,
+                "archive",
+                Some(entry),
+                "uses backslashes, which are not path separators on the device",
+            );
+        }
+        if entry.is_absolute() || entry.components().any(|c| c == Component::ParentDir) {
+            report.add(
+                Severity::Error,
+                "archive",
+                Some(entry),
+                "points outside the extraction directory",
+            );
+        }
+        if !seen.insert(name.trim_end_matches('/').to_string()) {
+            report.add(
+                Severity::Error,
+                "archive",
+                Some(entry),
+                "appears more than once",
+            );
+        }
+    }
+    Ok(())
+}
+
+fn lint_dir(dir: &Path, origin: Origin, report: &mut LintReport) -> Result<()> {
+    let mut entries = Vec::new();
+    module::collect_entries(dir, dir, &mut entries)?;
+    if origin != Origin::Installed {
+        for rel in &entries {
+            let junk = rel
+                .components()
+                .any(|c| JUNK_NAMES.contains(&c.as_os_str().to_string_lossy().as_ref()));
+            if junk {
+                report.add(
+                    Severity::Warning,
+                    "archive",
+                    Some(rel),
+                    "leftover of the packing machine, not part of the module",
+                );
+            } else if integrity::is_bookkeeping(rel) {
+                report.add(
+                    Severity::Warning,
+                    "archive",
+                    Some(rel),
+                    "install-time state of an installed module, not part of a module",
+                );
+            }
+        }
+    }
+
+    if !lint_prop(dir, origin, &entries, report)? {
+        return Ok(());
+    }
+    lint_scripts(dir, report)?;
+
+    let scratch = tempdir()?;
+    let payload = storage::preview(dir, scratch.path())?;
+    lint_payload(&payload, report)
+}
+
+/// Check module.prop. Returns whether there is one to go on with.
+fn lint_prop(
+    dir: &Path,
+    origin: Origin,
+    entries: &[PathBuf],
+    report: &mut LintReport,
+) -> Result<bool> {
+    let prop_path = dir.join("module.prop");
+    if !prop_path.is_file() {
+        let nested = entries
+            .iter()
+            .find(|rel| rel.components().count() == 2 && rel.ends_with("module.prop"));
+        let message = match nested.and_then(|rel| rel.parent()) {
+            Some(parent) => format!(
+                "module.prop is in {}/ instead of the module root, pack the contents of that directory",
+                parent.display()
+            ),
+            None => "no module.prop at the module root".to_string(),
+        };
+        report.add(Severity::Error, "prop", None, message);
+        return Ok(false);
+    }
+
+    let rel = Some(Path::new("module.prop"));
+    let props = match module::parse_prop_file(&prop_path) {
+        Ok(props) => props,
+        Err(e) => {
+            report.add(Severity::Error, "prop", rel, format!("{e:#}"));
+            return Ok(false);
+        }
+    };
+
+    for key in REQUIRED_PROPS {
+        if props.get(*key).is_none_or(|value| value.is_empty()) {
+            report.add(
+                Severity::Error,
+                "prop",
+                rel,
+                format!("required property {key} is missing or empty"),
+            );
+        }
+    }
+    if let Some(version) = props.get("version")
+        && version.parse::<i32>().is_err()
+    {
+        report.add(
+            Severity::Error,
+            "prop",
+            rel,
+            format!("version `{version}` is not an integer"),
+        );
+    }
+
+    if let Some(id) = props.get("id").filter(|id| !id.is_empty()) {
+        report.id = Some(id.clone());
+        if let Err(e) = module::validate_module_id(id) {
+            report.add(Severity::Error, "prop", rel, format!("{e:#}"));
+        }
+        let dir_name = dir.file_name().map(|n| n.to_string_lossy().to_string());
+        match origin {
+            Origin::Installed if dir_name.as_deref() != Some(id) => report.add(
+                Severity::Error,
+                "prop",
+                rel,
+                format!("id {id} does not match the module directory name"),
+            ),
+            Origin::Source if dir_name.as_deref() != Some(id) => report.add(
+                Severity::Warning,
+                "prop",
+                rel,
+                format!(
+                    "id {id} does not match the directory name {}, installed modules live in a directory named after their id",
+                    dir_name.unwrap_or_default()
+                ),
+            ),
+            _ => {}
+        }
+    }
+
+    match props.get("skip_mount").map(|v| v.to_lowercase()) {
+        None => report.add(
+            Severity::Warning,
+            "prop",
+            rel,
+            "skip_mount is not set, it defaults to false and is an error with --strict",
+        ),
+        Some(value) if value != "true" && value != "false" => report.add(
+            Severity::Error,
+            "prop",
+            rel,
+            "skip_mount must be true or false",
+        ),
+        Some(_) => {}
+    }
+    if let Some(mode) = props.get("mount")
+        && MountMode::from_name(mode).is_none()
+    {
+        report.add(
+            Severity::Error,
+            "prop",
+            rel,
+            format!("mount `{mode}` is not a known mount mode"),
+        );
+    }
+    if let Some(priority) = props.get("priority")
+        && priority.parse::<i32>().is_err()
+    {
+        report.add(
+            Severity::Error,
+            "prop",
+            rel,
+            format!("priority `{priority}` is not an integer"),
+        );
+    }
+    if let Some(value) = props.get("path_dirs") {
+        match path_env::parse(value) {
+            Ok(path_dirs) => {
+                for path_dir in path_dirs
+                    .iter()
+                    .filter(|path_dir| !dir.join(path_dir).is_dir())
+                {
+                    report.add(
+                        Severity::Warning,
+                        "prop",
+                        rel,
+                        format!(
+                            "path_dirs entry {} is not a directory, it is left off PATH",
+                            path_dir.display()
+                        ),
+                    );
+                }
+            }
+            Err(e) => report.add(Severity::Error, "prop", rel, format!("{e:#}")),
+        }
+    }
+    if let Some(key) = props.get("author_key")
+        && let Err(e) = trust::normalize_fingerprint(key)
+    {
+        report.add(
+            Severity::Error,
+            "prop",
+            rel,
+            format!("author_key is invalid: {e}"),
+        );
+    }
+    if let Some(url) = props.get("updateJson")
+        && !cache::is_url(url)
+    {
+        report.add(
+            Severity::Error,
+            "prop",
+            rel,
+            "updateJson must be an http(s) URL",
+        );
+    }
+    Ok(true)
+}
+
+/// Check the shell scripts at the module root: shebang, executable bit,
+/// line endings and `sh -n` syntax.
+fn lint_scripts(dir: &Path, report: &mut LintReport) -> Result<()> {
+    let declared = module::parse_prop_file(&dir.join("module.prop"))?
+        .remove("interpreter")
+        .filter(|interpreter| !interpreter.is_empty());
+
+    let mut scripts: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "sh"))
+        .collect();
+    scripts.sort();
+
+    for path in scripts {
+        let rel = PathBuf::from(path.file_name().unwrap_or_default());
+        let rel = Some(rel.as_path());
+        let interpreter = match (&declared, module::shebang(&path)?) {
+            (Some(declared), _) => Some(declared.clone()),
+            (None, Some(shebang)) => Some(shebang),
+            (None, None) => {
+                report.add(
+                    Severity::Warning,
+                    "scripts",
+                    rel,
+                    "has no shebang and runs with sh",
+                );
+                None
+            }
+        };
+        if let Some(interpreter) = &interpreter {
+            let normalized = interpreter.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !DEFAULT_INTERPRETERS.contains(&normalized.as_str()) {
+                report.add(
+                    Severity::Warning,
+                    "scripts",
+                    rel,
+                    format!(
+                        "interpreter `{normalized}` is refused unless devices add it to scripts.interpreters"
+                    ),
+                );
+            }
+        }
+
+        if fs::metadata(&path)?.permissions().mode() & 0o111 == 0 {
+            report.add(Severity::Warning, "scripts", rel, "is not executable");
+        }
+        let content = fs::read(&path)?;
+        if content.windows(2).any(|pair| pair == b"\r\n") {
+            report.add(
+                Severity::Error,
+                "scripts",
+                rel,
+                "has CRLF line endings, the shell reads the \\r as part of each command",
+            );
+        }
+
+        // only sh scripts can be checked with sh
+        let is_sh = interpreter.as_deref().is_none_or(|interpreter| {
+            interpreter.ends_with("/sh") || interpreter.ends_with("/ash")
+        });
+        if !is_sh {
+            continue;
+        }
+        match Command::new("sh").arg("-n").arg(&path).output() {
+            Ok(output) if !output.status.success() => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                report.add(
+                    Severity::Error,
+                    "scripts",
+                    rel,
+                    format!("syntax error: {}", stderr.trim()),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => warn!("cannot check the syntax of {path:?} without sh: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Check what the payload roots place on `/`.
+fn lint_payload(dir: &Path, report: &mut LintReport) -> Result<()> {
+    let mut roots: Vec<(PathBuf, PathBuf)> = PAYLOAD_ROOTS
+        .iter()
+        .map(|(payload, root)| (PathBuf::from(payload), PathBuf::from(root)))
+        .collect();
+    if let Ok(languages) = fs::read_dir(dir.join(LOCALE_DIR)) {
+        for language in languages.filter_map(|entry| entry.ok()) {
+            roots.push((
+                Path::new(LOCALE_DIR).join(language.file_name()),
+                PathBuf::from("/"),
+            ));
+        }
+    }
+
+    let critical_paths: Vec<String> = CRITICAL_PATHS.iter().map(|s| s.to_string()).collect();
+    for (payload, root) in roots {
+        let src_root = dir.join(&payload);
+        if !src_root.is_dir() {
+            continue;
+        }
+        let mut entries = Vec::new();
+        module::collect_entries(&src_root, &src_root, &mut entries)?;
+        for rel in entries {
+            let meta = fs::symlink_metadata(src_root.join(&rel))?;
+            let target = root.join(&rel);
+            let path = payload.join(&rel);
+            let path = Some(path.as_path());
+
+            let file_type = meta.file_type();
+            if file_type.is_block_device()
+                || file_type.is_char_device()
+                || file_type.is_fifo()
+                || file_type.is_socket()
+            {
+                report.add(
+                    Severity::Error,
+                    "payload",
+                    path,
+                    "is a device node, fifo or socket, which cannot be mounted",
+                );
+            }
+            if let Some(forbidden) = FORBIDDEN_TARGETS
+                .iter()
+                .find(|forbidden| target.starts_with(forbidden))
+            {
+                report.add(
+                    Severity::Error,
+                    "payload",
+                    path,
+                    format!("covers {forbidden}, which modules may not override"),
+                );
+            }
+            if meta.is_file() && critical::is_critical(&target, &critical_paths) {
+                report.add(
+                    Severity::Warning,
+                    "payload",
+                    path,
+                    format!(
+                        "overrides {}, which boot or network access depend on",
+                        target.display()
+                    ),
+                );
+            }
+            if !meta.is_file() {
+                continue;
+            }
+
+            let mode = meta.permissions().mode();
+            if mode & 0o6000 != 0 {
+                report.add(Severity::Warning, "payload", path, "is setuid or setgid");
+            }
+            let in_bin_dir = rel
+                .parent()
+                .and_then(|parent| parent.file_name())
+                .is_some_and(|name| EXECUTABLE_DIRS.contains(&name.to_string_lossy().as_ref()));
+            if in_bin_dir && mode & 0o111 == 0 {
+                report.add(
+                    Severity::Warning,
+                    "payload",
+                    path,
+                    "is in a bin directory but not executable",
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn print_report(report: &LintReport) {
+    for finding in &report.findings {
+        let location = finding
+            .path
+            .as_ref()
+            .map(|path| format!("{}: ", path.display()))
+            .unwrap_or_default();
+        match finding.severity {
+            Severity::Error => error!("[{}] {location}{}", finding.check, finding.message),
+            Severity::Warning => warn!("[{}] {location}{}", finding.check, finding.message),
+        }
+    }
+    info!(
+        "{}: {} error(s), {} warning(s)",
+        report.id.as_deref().unwrap_or(&report.target),
+        report.errors(),
+        report.warnings()
+    );
+}
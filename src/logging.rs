@@ -1,48 +1,683 @@
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::io::{self, Write};
+use std::panic::{self, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError, TryLockError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use tracing::{Level, error, info, warn};
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::time::{ChronoLocal, ChronoUtc};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::defs::LOGS_DIR;
+use crate::clock::{self, Clock, SystemClock};
+use crate::config::LogConfig;
+use crate::defs::{LOG_DIR_ENV, LOGS_DIR, RUN_STATE_DIR};
 
-pub fn init_logging() -> () {
-    // 1. Prepare the directory
-    if !Path::new(LOGS_DIR).exists() {
-        fs::create_dir_all(LOGS_DIR).expect("failed to create logs directory");
+/// Subsystems whose level can be tuned under `[log.levels]`.
+pub const SUBSYSTEMS: &[&str] = &[
+    "adb",
+    "app",
+    "archive",
+    "audit",
+    "backup",
+    "boot",
+    "cache",
+    "capabilities",
+    "changelog",
+    "channel",
+    "clock",
+    "config",
+    "conflicts",
+    "critical",
+    "delta",
+    "diagnose",
+    "dry_run",
+    "events",
+    "integrity",
+    "lint",
+    "logging",
+    "maintenance",
+    "metrics",
+    "module",
+    "mount",
+    "path_env",
+    "process",
+    "profile",
+    "prompt",
+    "recover",
+    "repo",
+    "rollback",
+    "scaffold",
+    "selftest",
+    "serve",
+    "setup",
+    "signing",
+    "smoke",
+    "status",
+    "storage",
+    "trust",
+    "update",
+];
+
+/// How long to wait before trying to reopen an unavailable log file.
+const REOPEN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A log file is rotated at startup once it grows past this size.
+const ROTATE_SIZE: u64 = 1024 * 1024;
+
+/// Rotated log files kept next to the current one.
+const ROTATED_KEEP: usize = 5;
+
+/// Timestamps written to log files: UTC with an explicit offset, so host
+/// and device logs line up whatever their timezones.
+const FILE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f%:z";
+
+/// Timestamps on the console, in local time.
+const CONSOLE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f %:z";
+
+/// Log directory: `$SCRIBA_LOG_DIR`, or the default logs directory.
+fn log_dir() -> PathBuf {
+    std::env::var_os(LOG_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(LOGS_DIR))
+}
+
+/// Resolve the log file path: `--log-file` wins, then `$SCRIBA_LOG_DIR`,
+/// then the default logs directory.
+fn log_file_path(log_file: Option<&Path>) -> PathBuf {
+    match log_file {
+        Some(path) => path.to_path_buf(),
+        None => log_dir().join("latest.log"),
+    }
+}
+
+/// Move an oversized log aside as `<name>-<UTC stamp>.log` and drop the
+/// oldest rotated files beyond `ROTATED_KEEP`.
+fn rotate(path: &Path, clock: &dyn Clock) -> io::Result<()> {
+    if fs::metadata(path).map_or(true, |meta| meta.len() < ROTATE_SIZE) {
+        return Ok(());
+    }
+
+    let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) else {
+        return Ok(());
+    };
+    let stem = stem.to_string_lossy();
+    let stamp = clock::file_stamp(clock.unix_now());
+    fs::rename(path, dir.join(format!("{stem}-{stamp}.log")))?;
+
+    let rotated = rotated_in(dir, &stem);
+    let excess = rotated.len().saturating_sub(ROTATED_KEEP);
+    for old in &rotated[..excess] {
+        fs::remove_file(old)?;
+    }
+
+    Ok(())
+}
+
+/// Files `rotate` moved aside from `<dir>/<stem>.log`, oldest first; the
+/// UTC stamps in the names sort chronologically.
+fn rotated_in(dir: &Path, stem: &str) -> Vec<PathBuf> {
+    let prefix = format!("{stem}-");
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".log"))
+        })
+        .collect();
+    rotated.sort();
+    rotated
+}
+
+/// Log files moved aside by `rotate` from the default log, oldest first.
+/// Crash reports and other logs in the directory are not included.
+pub fn rotated_logs() -> Vec<PathBuf> {
+    let log = log_file_path(None);
+    let stem = log.file_stem().unwrap_or_default().to_string_lossy();
+    rotated_in(&log_dir(), &stem)
+}
+
+fn open_log_file(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Log file writer that tolerates a missing destination.
+///
+/// While the file cannot be opened (e.g. `/userdisk` is not mounted yet),
+/// records are dropped and the open is retried every `REOPEN_INTERVAL`,
+/// so the file layer attaches itself once the directory appears.
+struct LazyFile {
+    path: PathBuf,
+    file: Option<fs::File>,
+    last_attempt: Instant,
+}
+
+impl LazyFile {
+    fn new(path: PathBuf, file: Option<fs::File>) -> Self {
+        Self {
+            path,
+            file,
+            last_attempt: Instant::now(),
+        }
+    }
+
+    fn file(&mut self) -> Option<&mut fs::File> {
+        if self.file.is_none() && self.last_attempt.elapsed() >= REOPEN_INTERVAL {
+            self.last_attempt = Instant::now();
+            self.file = open_log_file(&self.path).ok();
+        }
+
+        self.file.as_mut()
+    }
+}
+
+impl Write for LazyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(file) = self.file() else {
+            return Ok(buf.len());
+        };
+
+        if let Err(e) = file.write_all(buf) {
+            // the file went away (e.g. storage unmounted), reopen later
+            self.file = None;
+            return Err(e);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Buffered records are written out early once they reach this size.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Log file writer that keeps records in memory and appends them in one
+/// go every flush interval, so the flash sees a few large writes instead
+/// of one per line. With a mirror, each record also goes straight to a
+/// file on tmpfs that can be followed live.
+struct BufferedLog {
+    file: LazyFile,
+    mirror: Option<LazyFile>,
+    pending: Vec<u8>,
+    /// Write each record as it comes instead of buffering
+    immediate: bool,
+}
+
+impl BufferedLog {
+    fn write_out(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let result = self.file.write_all(&self.pending);
+        self.pending.clear();
+        result.and_then(|()| self.file.flush())
+    }
+}
+
+/// The file log, set up once by `init_logging`.
+static FILE_LOG: OnceLock<Mutex<BufferedLog>> = OnceLock::new();
+
+fn file_log() -> Option<MutexGuard<'static, BufferedLog>> {
+    let log = FILE_LOG.get()?;
+    // a panicking thread may hold the lock itself, so it must not wait for
+    // it; its records are dropped instead
+    if thread::panicking() {
+        return match log.try_lock() {
+            Ok(log) => Some(log),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        };
+    }
+    Some(log.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Writer the file layer formats each record into.
+struct FileLogWriter;
+
+impl Write for FileLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(mut log) = file_log() else {
+            return Ok(buf.len());
+        };
+
+        if let Some(mirror) = log.mirror.as_mut() {
+            let _ = mirror.write_all(buf);
+        }
+        log.pending.extend_from_slice(buf);
+        if log.immediate || log.pending.len() >= MAX_PENDING {
+            log.write_out()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // written out by the flush thread, `flush` and at exit
+        Ok(())
+    }
+}
+
+/// Write out buffered log records now. Called before exiting and when a
+/// panic is about to take the process down.
+pub fn flush() {
+    if let Some(mut log) = file_log() {
+        let _ = log.write_out();
+    }
+}
+
+/// Write `crash-<UTC stamp>.log` to the log directory with the panic, a
+/// backtrace, the version and the command line, so a crash nobody was
+/// watching, e.g. during boot-complete, leaves something to go on.
+fn write_crash_report(info: &PanicHookInfo) -> io::Result<PathBuf> {
+    let now = clock::unix_now();
+    let path = log_dir().join(format!("crash-{}.log", clock::file_stamp(now)));
+    let args: Vec<String> = std::env::args().collect();
+    let report = format!(
+        "scriba {} crashed at {}\ncommand: {}\nthread: {}\n{info}\n\nbacktrace:\n{}\n",
+        env!("CARGO_PKG_VERSION"),
+        clock::rfc3339(now),
+        args.join(" "),
+        thread::current().name().unwrap_or("unnamed"),
+        Backtrace::force_capture(),
+    );
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// Path of a module's detail log, `<log dir>/modules/<id>.log`.
+pub fn module_log_path(module_id: &str) -> PathBuf {
+    log_dir().join("modules").join(format!("{module_id}.log"))
+}
+
+/// Detail log for a single module, see `module_log_path`.
+///
+/// Receives verbose per-path output that would otherwise flood `latest.log`.
+pub struct ModuleLog {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl ModuleLog {
+    pub fn open(module_id: &str) -> Self {
+        let path = module_log_path(module_id);
+        let file = open_log_file(&path)
+            .inspect_err(|e| warn!("failed to open module log {path:?}: {e}"))
+            .ok();
+
+        Self { path, file }
     }
 
-    // 2. Setup the specific file path: "logs/latest.log"
-    let file_path = format!("{}/latest.log", LOGS_DIR);
-    let file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file_path)
-        .expect("failed to open log file");
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, level: Level, message: &str) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+
+        let timestamp = clock::rfc3339(clock::unix_now());
+        if writeln!(file, "{timestamp} {level:>5} {message}").is_err() {
+            self.file = None;
+        }
+    }
+}
+
+/// Time between heartbeat lines of a long operation.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Periodic progress lines for long operations, so someone tailing
+/// `latest.log` can tell slow from hung. Operations that loop call `tick`;
+/// blocking waits use `Heartbeat::background`.
+pub struct Heartbeat {
+    what: String,
+    started: Instant,
+    last: Instant,
+}
+
+impl Heartbeat {
+    pub fn new(what: impl Into<String>) -> Self {
+        let now = Instant::now();
+        Self {
+            what: what.into(),
+            started: now,
+            last: now,
+        }
+    }
+
+    /// Log `progress` once an interval has passed since the last line.
+    pub fn tick(&mut self, progress: impl FnOnce() -> String) {
+        if self.last.elapsed() < HEARTBEAT_INTERVAL {
+            return;
+        }
+        self.last = Instant::now();
+        info!(
+            "still {}: {}, {}s elapsed",
+            self.what,
+            progress(),
+            self.started.elapsed().as_secs()
+        );
+    }
 
-    // 3. Create a non-blocking writer (crucial for performance)
-    let (non_blocking, guard) = tracing_appender::non_blocking(file);
+    /// Log from a background thread until the returned guard is dropped.
+    pub fn background(what: impl Into<String>) -> HeartbeatGuard {
+        let what = what.into();
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let started = Instant::now();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(HEARTBEAT_INTERVAL) {
+                info!("still {what}, {}s elapsed", started.elapsed().as_secs());
+            }
+        });
 
-    // 4. Define the File Layer (No ANSI colors, usually specific format)
+        HeartbeatGuard {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Stops a background heartbeat when dropped.
+pub struct HeartbeatGuard {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for HeartbeatGuard {
+    fn drop(&mut self) {
+        // disconnecting the channel ends the thread's wait
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Build the level filter from the INFO default, the configured per-subsystem
+/// levels, and finally `RUST_LOG`, so the environment wins on conflicts.
+///
+/// Returns the directives that failed to parse alongside the filter.
+fn build_filter(levels: &HashMap<String, String>) -> (EnvFilter, Vec<String>) {
+    let mut filter = EnvFilter::new("info");
+    let mut invalid = Vec::new();
+
+    let configured = levels
+        .iter()
+        .map(|(subsystem, level)| format!("{}::{subsystem}={level}", env!("CARGO_CRATE_NAME")));
+    let env = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+    let from_env = env
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(str::to_string);
+
+    for directive in configured.chain(from_env) {
+        match directive.parse() {
+            Ok(parsed) => filter = filter.add_directive(parsed),
+            Err(_) => invalid.push(directive),
+        }
+    }
+
+    (filter, invalid)
+}
+
+/// With `json_output`, console logs go to stderr so stdout only carries
+/// the JSON.
+pub fn init_logging(
+    log_file: Option<&Path>,
+    json_output: bool,
+    config: &LogConfig,
+) -> anyhow::Result<()> {
+    // 1. Open the log file, remembering the error instead of aborting
+    let file_path = log_file_path(log_file);
+    let rotate_error = rotate(&file_path, &SystemClock).err();
+    let (file, open_error) = match open_log_file(&file_path) {
+        Ok(file) => (Some(file), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    // 2. With log.tmpfs, mirror records to tmpfs and persist them rarely
+    let mirror = config.tmpfs.then(|| {
+        let name = file_path.file_name().unwrap_or("latest.log".as_ref());
+        let mirror_path = Path::new(RUN_STATE_DIR).join("logs").join(name);
+        let _ = rotate(&mirror_path, &SystemClock);
+        let file = open_log_file(&mirror_path).ok();
+        LazyFile::new(mirror_path, file)
+    });
+    let interval = Duration::from_secs(if config.tmpfs {
+        config.persist_interval_secs
+    } else {
+        config.flush_interval_secs
+    });
+    let buffered = BufferedLog {
+        file: LazyFile::new(file_path.clone(), file),
+        mirror,
+        pending: Vec::new(),
+        immediate: interval.is_zero(),
+    };
+    let _ = FILE_LOG.set(Mutex::new(buffered));
+    if !interval.is_zero() {
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+                flush();
+            }
+        });
+    }
+
+    // a panic exits without returning through main, flush on the way out
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // the report does not depend on the log, which may be what broke
+        let report = write_crash_report(info);
+        error!("{info}");
+        match report {
+            Ok(path) => error!("crash report written to {path:?}"),
+            Err(e) => error!("failed to write crash report: {e}"),
+        }
+        flush();
+        default_hook(info);
+    }));
+
+    // 3. Define the File Layer (No ANSI colors, usually specific format)
     let file_layer = tracing_subscriber::fmt::layer()
-        .with_writer(non_blocking)
+        .with_writer(|| FileLogWriter)
+        .with_timer(ChronoUtc::new(FILE_TIME_FORMAT.to_string()))
         .with_ansi(false);
 
-    // 5. Define the Console Layer (With colors)
+    // 4. Define the Console Layer (With colors)
     let console_layer = tracing_subscriber::fmt::layer()
-        .with_writer(std::io::stdout)
+        .with_writer(move || -> Box<dyn Write> {
+            if json_output {
+                Box::new(io::stderr())
+            } else {
+                Box::new(io::stdout())
+            }
+        })
+        .with_timer(ChronoLocal::new(CONSOLE_TIME_FORMAT.to_string()))
         .with_ansi(true);
 
-    // 6. Define the filter (Read RUST_LOG env var, fallback to INFO)
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    // 5. Define the filter (INFO, then config [log.levels], then RUST_LOG)
+    let (filter, invalid_directives) = build_filter(&config.levels);
 
-    // 7. Register the subscriber
+    // 6. Register the subscriber
     tracing_subscriber::registry()
         .with(filter)
         .with(console_layer)
         .with(file_layer)
-        .init();
+        .try_init()?;
+
+    if let Some(e) = rotate_error {
+        warn!("failed to rotate log file {file_path:?}: {e}");
+    }
+
+    if let Some(e) = open_error {
+        warn!(
+            "failed to open log file {file_path:?}: {e}, logging to console only until it becomes available"
+        );
+    }
+
+    for directive in invalid_directives {
+        warn!("ignoring invalid log directive `{directive}`");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::clock::FixedClock;
+
+    /// 2026-01-02T03:04:05Z
+    const NOW: u64 = 1_767_323_045;
+
+    const CLOCK: FixedClock = FixedClock {
+        unix: NOW,
+        uptime: 0,
+        boot_id: "boot",
+    };
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
 
-    std::mem::forget(guard);
+    #[test]
+    fn rotated_in_skips_crash_reports_and_other_logs() {
+        let dir = tempdir().unwrap();
+        for name in [
+            "latest.log",
+            "latest-20260102T030405+0000.log",
+            "latest-20251231T000000+0000.log",
+            "crash-20250101T000000+0000.log",
+            "boot-20250101T000000+0000.log",
+            "latest-20250101T000000+0000.txt",
+        ] {
+            fs::write(dir.path().join(name), "log").unwrap();
+        }
+
+        assert_eq!(
+            rotated_in(dir.path(), "latest"),
+            [
+                dir.path().join("latest-20251231T000000+0000.log"),
+                dir.path().join("latest-20260102T030405+0000.log"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rotate_leaves_small_logs_alone() {
+        let dir = tempdir().unwrap();
+        let log = dir.path().join("latest.log");
+        fs::write(&log, "short").unwrap();
+
+        rotate(&log, &CLOCK).unwrap();
+        assert_eq!(names(dir.path()), ["latest.log"]);
+    }
+
+    #[test]
+    fn rotate_moves_aside_with_a_utc_stamp() {
+        let dir = tempdir().unwrap();
+        let log = dir.path().join("latest.log");
+        fs::write(&log, vec![b'x'; ROTATE_SIZE as usize]).unwrap();
+
+        rotate(&log, &CLOCK).unwrap();
+        assert_eq!(names(dir.path()), ["latest-20260102T030405+0000.log"]);
+    }
+
+    #[test]
+    fn rotate_prunes_the_oldest_of_its_own_logs() {
+        let dir = tempdir().unwrap();
+        let log = dir.path().join("latest.log");
+        fs::write(&log, vec![b'x'; ROTATE_SIZE as usize]).unwrap();
+        for day in 1..=ROTATED_KEEP {
+            fs::write(
+                dir.path()
+                    .join(format!("latest-202512{day:02}T000000+0000.log")),
+                "old",
+            )
+            .unwrap();
+        }
+        let unrelated = [
+            "boot-20250101T000000+0000.log",
+            "crash-20250101T000000+0000.log",
+        ];
+        for name in unrelated {
+            fs::write(dir.path().join(name), "other").unwrap();
+        }
+
+        rotate(&log, &CLOCK).unwrap();
+        let names = names(dir.path());
+        assert!(!names.contains(&"latest-20251201T000000+0000.log".to_string()));
+        assert!(names.contains(&"latest-20260102T030405+0000.log".to_string()));
+        assert_eq!(
+            names.iter().filter(|n| n.starts_with("latest-")).count(),
+            ROTATED_KEEP
+        );
+        for name in unrelated {
+            assert!(names.contains(&name.to_string()), "{name} was pruned");
+        }
+    }
+
+    #[test]
+    fn file_log_does_not_wait_for_its_own_lock_while_panicking() {
+        let scratch = tempdir().unwrap();
+        let path = scratch.path().join("latest.log");
+        let _ = FILE_LOG.set(Mutex::new(BufferedLog {
+            file: LazyFile::new(path.clone(), open_log_file(&path).ok()),
+            mirror: None,
+            pending: Vec::new(),
+            immediate: true,
+        }));
+
+        /// Logs while its thread unwinds with the file log still locked.
+        struct LogOnUnwind;
+        impl Drop for LogOnUnwind {
+            fn drop(&mut self) {
+                let _ = FileLogWriter.write(b"during unwind\n");
+                flush();
+            }
+        }
+
+        let panicked = thread::spawn(|| {
+            let _held = file_log();
+            let _log = LogOnUnwind;
+            panic!("while holding the file log");
+        })
+        .join();
+
+        assert!(panicked.is_err());
+        // poisoned by the panic, but still usable
+        FileLogWriter.write_all(b"after\n").unwrap();
+    }
 }
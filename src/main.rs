@@ -1,13 +1,58 @@
+mod adb;
+mod app;
+mod archive;
+mod audit;
+mod backup;
+mod boot;
+mod cache;
+mod capabilities;
+mod changelog;
+mod channel;
 mod cli;
+mod clock;
 mod config;
+mod conflicts;
+mod critical;
 mod defs;
+mod delta;
+mod diagnose;
+mod dry_run;
+mod events;
+mod ids;
+mod integrity;
+mod lint;
 mod logging;
+mod maintenance;
+mod metrics;
 mod module;
+mod mount;
+mod path_env;
 mod process;
+mod profile;
+mod prompt;
+mod query;
+mod recover;
+mod repo;
+mod rollback;
+mod scaffold;
+mod selftest;
+mod serve;
+mod setup;
+mod signing;
+mod smoke;
+mod state;
+mod status;
+mod storage;
+mod strict;
+mod trust;
+mod update;
 
 use std::fs;
 use std::io;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::CommandFactory;
 use clap::Parser;
@@ -16,101 +61,414 @@ use tracing::error;
 use tracing::info;
 use tracing::warn;
 
+use crate::app::DeepLink;
+use crate::audit::ClientIdentity;
 use crate::cli::AppCommand;
+use crate::cli::AuditCommand;
+use crate::cli::BackupCommand;
+use crate::cli::CacheCommand;
+use crate::cli::ChannelCommand;
 use crate::cli::Cli;
+use crate::cli::ConfigCommand;
+use crate::cli::DeviceCommand;
+use crate::cli::HostCommand;
 use crate::cli::InternalCommand;
 use crate::cli::ModuleCommand;
+use crate::cli::MountCommand;
+use crate::cli::ProfileCommand;
+use crate::cli::RepoCommand;
 use crate::cli::TopLevel;
+use crate::cli::TrustCommand;
+use crate::clock::SystemClock;
+use crate::config::AppConfig;
 use crate::defs::BIN_DIR;
 use crate::defs::Environment;
-use crate::defs::LOGS_DIR;
 use crate::defs::MODULES_DIR;
 use crate::defs::MODULES_UPDATE_DIR;
+use crate::defs::STATE_DIR;
+use crate::defs::UPLOAD_DIR;
+use crate::ids::ProcessIds;
 
 /* =========================
  * Main
  * ========================= */
 
 fn main() -> anyhow::Result<()> {
-    logging::init_logging();
+    let result = run_main();
+    logging::flush();
+    result
+}
 
+fn run_main() -> anyhow::Result<()> {
     let cli = Cli::parse();
     let environment = cli.force_env.unwrap_or_else(Environment::detect);
+    // a broken config must not keep boot-complete from running
+    let config = match config::load_config(environment) {
+        Ok(config) => config,
+        Err(e) if cli.strict => return Err(e),
+        Err(e) => {
+            eprintln!("{e:#}, using defaults");
+            AppConfig::default()
+        }
+    };
+
+    if let Err(e) = logging::init_logging(cli.log_file.as_deref(), cli.json, &config.log) {
+        eprintln!("failed to initialize logging: {e}");
+    }
+
+    if cli.strict || config.strict {
+        strict::enable();
+    }
+
+    let now = clock::unix_now();
+    if !clock::is_plausible(now) {
+        warn!("system clock looks unset ({now}), timestamps may be wrong until it is synced");
+    }
+
+    if cli.command.as_ref().is_some_and(TopLevel::is_local) {
+        return run(
+            cli.command,
+            environment,
+            cli.serial.as_deref(),
+            cli.json,
+            &config,
+        );
+    }
 
-    // Host forwarding via adb if exactly one device
+    // Host: forward the command to the device over adb
     if environment == Environment::Host {
-        // let devices = adb::list_devices();
-        // if devices.len() == 1 {
-        //     let device = &devices[0];
-
-        //     // Reconstruct the CLI args to pass to adb shell
-        //     // skip argv[0], keep the rest
-        //     let args: Vec<String> = std::env::args().skip(1).collect();
-
-        //     let status = adb::shell_run(device, crate_name!(), args);
-        //     if let Err(err) = status {
-        //         error!(err);
-        //         return Err(anyhow!("failed to execute adb shell: {err}"));
-        //     }
-        // } else {
-        //     error!("no or more than one connected devices");
-        //     return Err(anyhow!("no or more than one connected devices"));
-        // }
-
-        // return Ok(());
-
-        error!("not supported");
+        let device = adb::select_device(cli.serial.as_deref(), config.default_device.as_deref())?;
+        info!("forwarding to device {device}");
+        let mut args = cli::forwarded_args();
+        // strict mode from the host config applies to the device run too
+        if strict::enabled() && !cli.strict {
+            args.insert(0, "--strict".to_string());
+        }
+
+        // commands naming a local or downloadable package get it pushed
+        // and repointed; downloads go through the host cache
+        let package = match &cli.command {
+            Some(TopLevel::Module {
+                command: ModuleCommand::Install { path, sha256, .. },
+            }) if Path::new(path).is_file() || cache::is_url(path) => {
+                Some((path.clone(), sha256.clone()))
+            }
+            Some(TopLevel::App {
+                command: AppCommand::Install { path },
+            }) if Path::new(path).is_file() || cache::is_url(path) => Some((path.clone(), None)),
+            Some(TopLevel::Backup {
+                command: BackupCommand::Restore { file },
+            }) if Path::new(file).is_file() => Some((file.clone(), None)),
+            _ => None,
+        };
+        if let Some((package, sha256)) = package {
+            let (local_path, name) = if cache::is_url(&package) {
+                let (blob, entry) = cache::fetch(
+                    &package,
+                    sha256.as_deref(),
+                    &config.cache,
+                    &SystemClock,
+                    &ProcessIds,
+                )?;
+                (blob, entry.name)
+            } else {
+                let name = Path::new(&package)
+                    .file_name()
+                    .ok_or_else(|| anyhow::anyhow!("cannot get file name of {package}"))?
+                    .to_string_lossy()
+                    .into_owned();
+                (PathBuf::from(&package), name)
+            };
+            let remote_path = format!("{UPLOAD_DIR}{name}");
+            adb::shell_run(
+                &device,
+                "mkdir",
+                vec!["-p".to_string(), UPLOAD_DIR.to_string()],
+            )
+            .map_err(anyhow::Error::msg)?;
+            info!("pushing {local_path:?} to {remote_path}");
+            adb::push_verified(
+                &device,
+                &local_path,
+                &remote_path,
+                config.transfer.push_retries,
+            )?;
+            for arg in args.iter_mut().filter(|arg| **arg == package) {
+                *arg = remote_path.clone();
+            }
+        }
+
+        let binary = setup::installed_binary();
+        let code = adb::shell_exit_code(&device, &binary.to_string_lossy(), args)
+            .map_err(|err| anyhow::anyhow!("failed to execute adb shell: {err}"))?;
+        // the device already reported its error, just pass the status on
+        if code != 0 {
+            logging::flush();
+            std::process::exit(code);
+        }
         return Ok(());
     }
 
     fs::create_dir_all(Path::new(BIN_DIR))?;
-    fs::create_dir_all(Path::new(LOGS_DIR))?;
     fs::create_dir_all(Path::new(MODULES_DIR))?;
     fs::create_dir_all(Path::new(MODULES_UPDATE_DIR))?;
+    fs::create_dir_all(Path::new(STATE_DIR))?;
+
+    let command = cli.command;
+    let audited = matches!(
+        command,
+        Some(
+            TopLevel::App { .. }
+                | TopLevel::Module { .. }
+                | TopLevel::Trust { .. }
+                | TopLevel::Repo { .. }
+                | TopLevel::Backup { .. }
+                | TopLevel::Internal { .. }
+                | TopLevel::Recover
+        )
+    );
 
-    let _config = config::load_config(environment);
+    let result = run(
+        command,
+        environment,
+        cli.serial.as_deref(),
+        cli.json,
+        &config,
+    );
+
+    if audited {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        audit::record(
+            &SystemClock,
+            ClientIdentity::local(),
+            &cli::command_path(),
+            &args,
+            &result,
+        );
+    }
 
-    match cli.command {
+    result
+}
+
+/* =========================
+ * Commands
+ * ========================= */
+
+fn run(
+    command: Option<TopLevel>,
+    environment: Environment,
+    serial: Option<&str>,
+    json: bool,
+    config: &AppConfig,
+) -> anyhow::Result<()> {
+    match command {
         Some(TopLevel::App { command }) => match command {
             AppCommand::Install { path } => {
                 info!("installing app from {path}");
                 process::run_with_output("miniapp_cli", &["install", &path])?;
             }
 
-            AppCommand::Uninstall { app_id } => {
+            AppCommand::Uninstall { app_id, keep_data } => {
+                app::check_app_id(app_id)?;
                 info!("uninstalling app {app_id}");
-                process::run_with_output("miniapp_cli", &["uninstall", &app_id.to_string()])?;
+                app::uninstall(app_id, &app::data_dir(&config.app, app_id), keep_data)?;
             }
 
-            AppCommand::Run { app_id, page } => {
-                info!("running app {app_id}");
-                if let Some(page) = page {
-                    process::run_with_output(
-                        "miniapp_cli",
-                        &["start", &app_id.to_string(), &page.to_string()],
-                    )?;
-                } else {
-                    process::run_with_output("miniapp_cli", &["start", &app_id.to_string()])?;
+            AppCommand::ExportData { app_id, output } => {
+                app::check_app_id(app_id)?;
+                let output = output.map_or_else(
+                    || {
+                        PathBuf::from(format!(
+                            "{app_id}-data-{}.tar.gz",
+                            clock::file_stamp(clock::unix_now())
+                        ))
+                    },
+                    PathBuf::from,
+                );
+                app::export_data(app_id, &app::data_dir(&config.app, app_id), &output)?;
+                info!("data of app {app_id} exported to {output:?}");
+            }
+
+            AppCommand::Run { app_id, page, args } => {
+                app::check_app_id(app_id)?;
+                app::open(&DeepLink {
+                    app_id,
+                    page,
+                    query: (!args.is_empty()).then(|| app::encode_query(&args)),
+                })?;
+            }
+
+            AppCommand::Open { link } => {
+                app::check_app_id(link.app_id)?;
+                app::open(&link)?;
+            }
+
+            AppCommand::Crashes { app_id, export } => {
+                if let Some(app_id) = app_id {
+                    app::check_app_id(app_id)?;
+                }
+                let crashes = app::find_crashes(&config.app.crash_dirs, app_id);
+                app::list_crashes(&crashes);
+
+                if let Some(export) = export {
+                    app::export_crashes(&crashes, Path::new(&export))?;
+                    info!("crash files exported to {export}");
+                }
+            }
+
+            AppCommand::Top {
+                interval,
+                iterations,
+            } => {
+                let interval = Duration::from_secs(interval);
+                let mut refreshes = 0;
+                while iterations.is_none_or(|n| refreshes < n) {
+                    let usage = app::sample_usage(interval);
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&usage)?);
+                    } else {
+                        app::print_usage(&usage);
+                    }
+                    refreshes += 1;
                 }
             }
 
             AppCommand::List { filter } => {
-                info!("listing apps with filters: {filter:?}");
-                error!("unimplemented")
+                let apps = app::list(&filter)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&apps)?);
+                } else {
+                    app::print_apps(&apps);
+                }
             }
         },
 
         Some(TopLevel::Module { command }) => match command {
-            ModuleCommand::Install { path, clean } => {
+            ModuleCommand::Install {
+                path,
+                sha256,
+                clean,
+                allow_unsigned,
+                answers,
+                enable,
+                dry_run,
+                from_repo,
+            } => {
                 info!("installing module from {path} (clean={clean})");
 
-                // extract module & read id
-                let temp_dir = module::unzip_module(Path::new(&path))?;
+                // the download lives until the module is extracted
+                let (_download, path) = if cache::is_url(&path) {
+                    let (dir, file) = cache::download_temp(&path, sha256.as_deref())?;
+                    (Some(dir), file.to_string_lossy().into_owned())
+                } else {
+                    if let Some(expected) = &sha256 {
+                        cache::verify_sha256(Path::new(&path), expected)?;
+                    }
+                    (None, path)
+                };
+
+                // extract module (or rebuild it from a delta) & read id
+                let temp_dir = if delta::is_delta(Path::new(&path))? {
+                    delta::apply_delta(Path::new(&path), config.archive.limits())?
+                } else {
+                    module::extract_module(Path::new(&path), config.archive.limits())?
+                };
                 info!("extracting module to {temp_dir:?}");
-                let prop = module::read_module_prop(&temp_dir.join("module.prop"))?;
-                let module_id = prop
-                    .get("id")
-                    .ok_or_else(|| anyhow::anyhow!("module.prop missing id"))?;
+
+                match signing::verify(&temp_dir)? {
+                    signing::Verification::Valid(name) => info!("module is signed by {name}"),
+                    signing::Verification::Unsigned if allow_unsigned => {
+                        warn!("module is not signed, installing anyway");
+                    }
+                    signing::Verification::Invalid(reason) if allow_unsigned => {
+                        warn!("{reason}, installing anyway");
+                    }
+                    signing::Verification::Unsigned => {
+                        module::delete_dir(&temp_dir)?;
+                        anyhow::bail!("module is not signed, pass --allow-unsigned to install it");
+                    }
+                    signing::Verification::Invalid(reason) => {
+                        module::delete_dir(&temp_dir)?;
+                        anyhow::bail!("{reason}, pass --allow-unsigned to install it anyway");
+                    }
+                }
+
+                let prop = match module::read_module_prop(&temp_dir.join("module.prop")) {
+                    Ok(prop) => prop,
+                    Err(e) => {
+                        module::delete_dir(&temp_dir)?;
+                        return Err(e);
+                    }
+                };
+                let Some(module_id) = prop.get("id") else {
+                    module::delete_dir(&temp_dir)?;
+                    anyhow::bail!("module.prop missing id");
+                };
+
+                let collisions = module::case_collisions(module_id);
+                if !collisions.is_empty() {
+                    module::delete_dir(&temp_dir)?;
+                    anyhow::bail!(
+                        "module {module_id} differs only in case from {collisions:?}, uninstall that first"
+                    );
+                }
+
+                if from_repo
+                    && config.trust.require_trusted
+                    && trust::status(&prop)? != trust::TrustStatus::Trusted
+                {
+                    module::delete_dir(&temp_dir)?;
+                    anyhow::bail!(
+                        "module {module_id} is not from a trusted author (trust.require_trusted is set)"
+                    );
+                }
+
+                let conflicts = conflicts::with_module(module_id, &temp_dir)
+                    .and_then(|found| conflicts::check(module_id, &found, config.conflicts.policy));
+                if let Err(e) = conflicts {
+                    module::delete_dir(&temp_dir)?;
+                    return Err(e);
+                }
+
+                if dry_run {
+                    let plan = dry_run::install(&temp_dir, &prop, clean);
+                    module::delete_dir(&temp_dir)?;
+                    let plan = plan?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&plan)?);
+                    } else {
+                        dry_run::print(&plan);
+                    }
+                    return Ok(());
+                }
+
+                let answers = match prompt::answers(&temp_dir, &answers) {
+                    Ok(answers) => answers,
+                    Err(e) => {
+                        module::delete_dir(&temp_dir)?;
+                        return Err(e);
+                    }
+                };
+
+                // validate from the staged copy, before anything is replaced
+                let mut envs = module::install_env("preinstall", &temp_dir, &prop);
+                envs.extend(answers.iter().cloned());
+                if let Err(e) =
+                    module::run_install_phase(&temp_dir, "preinstall.sh", &envs, &config.scripts)
+                {
+                    module::delete_dir(&temp_dir)?;
+                    anyhow::bail!("preinstall.sh aborted installation of {module_id}: {e}");
+                }
+
+                // postinstall.sh starts from empty data, which replaces the
+                // installed version's data only when the update is promoted
+                let fresh_data = module::fresh_data_dir(module_id);
+                if fresh_data.exists() {
+                    module::delete_dir(&fresh_data)?;
+                }
+                if clean {
+                    fs::create_dir_all(&fresh_data)?;
+                }
 
                 // if module already exists in update dir, delete it
                 let target_dir = Path::new(MODULES_UPDATE_DIR).join(module_id);
@@ -120,16 +478,183 @@ fn main() -> anyhow::Result<()> {
                 }
 
                 // move module to update dir
-                let target_dir = std::path::Path::new(MODULES_UPDATE_DIR).join(module_id);
                 info!("moving module from temp dir to {target_dir:?}");
                 module::move_dir(&temp_dir, &target_dir)?;
-                info!("running install.sh");
-                module::run_script(&target_dir, "install.sh")?;
+
+                // install.sh is the old name of the post-staging hook
+                let postinstall = if !target_dir.join("postinstall.sh").exists()
+                    && target_dir.join("install.sh").exists()
+                {
+                    warn!("install.sh is deprecated, rename it to postinstall.sh");
+                    "install.sh"
+                } else {
+                    "postinstall.sh"
+                };
+                let mut envs = module::install_env("postinstall", &target_dir, &prop);
+                envs.extend(answers);
+                if clean {
+                    envs.push((
+                        "MODDATA".to_string(),
+                        fresh_data.to_string_lossy().to_string(),
+                    ));
+                }
+                if let Err(e) =
+                    module::run_install_phase(&target_dir, postinstall, &envs, &config.scripts)
+                {
+                    module::delete_dir(&target_dir)?;
+                    if clean {
+                        module::delete_dir(&fresh_data)?;
+                    }
+                    anyhow::bail!("{postinstall} failed, update of {module_id} discarded: {e}");
+                }
+
+                if clean {
+                    fs::write(target_dir.join("clean.flag"), "")?;
+                    info!("data of {module_id} is replaced when the update takes effect");
+                }
+
+                if !enable {
+                    fs::write(target_dir.join("disable.flag"), "")?;
+                    info!("module {module_id} staged disabled, enable it with `module enable`");
+                }
+
+                let hashed = integrity::write_manifest(&target_dir)?;
+                info!("recorded hashes of {hashed} file(s)");
 
                 info!("module {module_id} installed to update dir");
             }
 
-            ModuleCommand::Uninstall { module_id } => {
+            ModuleCommand::Rollback { module_id, to } => {
+                let version = rollback::rollback(&module_id, to)?;
+                info!("v{version} of {module_id} staged, takes effect after reboot");
+            }
+
+            ModuleCommand::CancelUpdate { module_id } => {
+                let update_dir = Path::new(MODULES_UPDATE_DIR).join(&module_id);
+                if !update_dir.exists() {
+                    anyhow::bail!("module {module_id} has no pending update");
+                }
+
+                module::delete_dir(&update_dir)?;
+                let fresh_data = module::fresh_data_dir(&module_id);
+                if fresh_data.exists() {
+                    module::delete_dir(&fresh_data)?;
+                }
+                if Path::new(MODULES_DIR).join(&module_id).exists() {
+                    info!("pending update of {module_id} cancelled, installed version is kept");
+                } else {
+                    info!("pending install of {module_id} cancelled, module was not installed");
+                }
+            }
+
+            ModuleCommand::Mount {
+                module_id,
+                emit_script,
+                output,
+            } => {
+                let module_dir = Path::new(MODULES_DIR).join(&module_id);
+                if !module_dir.is_dir() {
+                    anyhow::bail!("module {module_id} is not installed");
+                }
+
+                let module_config = config.module(&module_id);
+                let mounter = mount::mounter(mount::module_mode(&module_config, &module_dir));
+                if emit_script {
+                    let language = mount::device_language(module_config.locale.as_deref());
+                    let script = mount::emit_script(
+                        &module_id,
+                        &module_dir,
+                        mounter.as_ref(),
+                        language.as_deref(),
+                    )?;
+                    match output {
+                        Some(output) => {
+                            fs::write(&output, script)?;
+                            fs::set_permissions(&output, fs::Permissions::from_mode(0o755))?;
+                            info!("mount script written to {output}");
+                        }
+                        None => print!("{script}"),
+                    }
+                } else {
+                    mount::mount_installed(&module_dir, &module_config, &config.guard)?;
+                    info!("module {module_id} mounted");
+                }
+            }
+
+            ModuleCommand::Unmount { module_id } => {
+                let undone = mount::unmount_module(&module_id)?;
+                info!("{undone} mount(s) of {module_id} undone");
+            }
+
+            ModuleCommand::Remount { module_id } => {
+                let module_dir = Path::new(MODULES_DIR).join(&module_id);
+                if !module_dir.is_dir() {
+                    anyhow::bail!("module {module_id} is not installed");
+                }
+
+                let undone = mount::unmount_module(&module_id)?;
+                info!("{undone} mount(s) of {module_id} undone");
+                mount::mount_installed(&module_dir, &config.module(&module_id), &config.guard)?;
+                info!("module {module_id} remounted");
+            }
+
+            ModuleCommand::Enable {
+                module_id,
+                until_reboot,
+            } => {
+                if until_reboot {
+                    if !module::enable_until_reboot(&module_id)? {
+                        info!("module {module_id} is already enabled");
+                        return Ok(());
+                    }
+                    info!("module {module_id} enabled until reboot, initializing it now");
+                    boot::init_module(&Path::new(MODULES_DIR).join(&module_id), config)?;
+                } else if module::set_enabled(&module_id, true)? {
+                    info!("module {module_id} enabled, takes effect after reboot");
+                } else {
+                    info!("module {module_id} is already enabled");
+                }
+            }
+
+            ModuleCommand::Disable { module_id } => {
+                if module::set_enabled(&module_id, false)? {
+                    info!("module {module_id} disabled, takes effect after reboot");
+                } else {
+                    info!("module {module_id} is already disabled");
+                }
+                let restored = critical::restore(&module_id)?;
+                if restored > 0 {
+                    info!("{restored} critical path(s) {module_id} had copied over restored");
+                }
+            }
+
+            ModuleCommand::Profile { command } => match command {
+                ProfileCommand::Save { name } => {
+                    let profile = profile::save(&name)?;
+                    info!(
+                        "profile {name} saved ({} enabled, {} disabled)",
+                        profile.enabled.len(),
+                        profile.disabled.len()
+                    );
+                }
+                ProfileCommand::Apply { name } => profile::apply(&name)?,
+                ProfileCommand::List => profile::list()?,
+            },
+
+            ModuleCommand::Uninstall {
+                module_id,
+                purge,
+                dry_run,
+            } => {
+                if dry_run {
+                    let plan = dry_run::uninstall(&module_id, purge)?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&plan)?);
+                    } else {
+                        dry_run::print(&plan);
+                    }
+                    return Ok(());
+                }
                 info!("uninstalling module {module_id}");
 
                 let module_dir = std::path::Path::new(MODULES_DIR).join(&module_id);
@@ -139,6 +664,9 @@ fn main() -> anyhow::Result<()> {
                 if update_dir.exists() {
                     module::delete_dir(&update_dir)?;
                     info!("module {module_id} removed from update dir");
+                    if purge && !module_dir.exists() {
+                        module::wipe_data(&module_id)?;
+                    }
                     return Ok(());
                 }
 
@@ -147,10 +675,16 @@ fn main() -> anyhow::Result<()> {
                     // unflag uninstall
                     if fs::read_to_string(module_dir.join("uninstall.flag")).is_ok() {
                         fs::remove_file(module_dir.join("uninstall.flag"))?;
+                        let _ = fs::remove_file(module_dir.join("purge.flag"));
                         info!("module {module_id} unmarked for uninstall");
                     } else {
                         // flag uninstall
-                        module::run_script(&module_dir, "uninstall.sh")?;
+                        module::run_script(&module_dir, "uninstall.sh", &config.scripts)?
+                            .check()?;
+                        // the data goes with the module at the next boot
+                        if purge {
+                            fs::write(module_dir.join("purge.flag"), "")?;
+                        }
                         fs::write(module_dir.join("uninstall.flag"), "")?;
                         info!("module {module_id} marked for uninstall");
                     }
@@ -159,120 +693,672 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
+            ModuleCommand::Create {
+                module_id,
+                output,
+                zip,
+                name,
+                description,
+                version,
+                author,
+            } => {
+                let output = output.unwrap_or_else(|| {
+                    let output = PathBuf::from(&module_id);
+                    if zip {
+                        output.with_extension("zip")
+                    } else {
+                        output
+                    }
+                });
+                let skeleton = scaffold::Skeleton {
+                    id: module_id.clone(),
+                    name,
+                    description,
+                    version,
+                    author,
+                };
+                scaffold::create(&skeleton, &output, zip)?;
+                info!("module {module_id} created in {output:?}");
+            }
+
+            ModuleCommand::Pack {
+                dir,
+                output,
+                reproducible,
+                key,
+            } => {
+                let dir = Path::new(&dir);
+                let output = match output {
+                    Some(output) => PathBuf::from(output),
+                    None => {
+                        let name = dir
+                            .canonicalize()?
+                            .file_name()
+                            .ok_or_else(|| anyhow::anyhow!("cannot get directory name"))?
+                            .to_owned();
+                        PathBuf::from(name).with_extension("zip")
+                    }
+                };
+
+                info!("packing module {dir:?} into {output:?} (reproducible={reproducible})");
+                module::pack_module(dir, &output, reproducible, key.as_deref())?;
+                info!("module packed to {output:?}");
+            }
+
+            ModuleCommand::Export { module_id, output } => {
+                let output = output.unwrap_or_else(|| {
+                    let version = module::module_version(&Path::new(MODULES_DIR).join(&module_id));
+                    match version {
+                        Some(version) => PathBuf::from(format!("{module_id}-{version}.zip")),
+                        None => PathBuf::from(format!("{module_id}.zip")),
+                    }
+                });
+                module::export_module(&module_id, &output)?;
+                info!("module {module_id} exported to {output:?}");
+            }
+
+            ModuleCommand::CheckUpdates {
+                module_id,
+                apply,
+                allow_unsigned,
+            } => {
+                let checks = update::check(module_id.as_deref())?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&checks)?);
+                } else {
+                    update::print_checks(&checks);
+                }
+                if !apply {
+                    return Ok(());
+                }
+
+                let mut failed = 0;
+                for check in checks.into_iter().filter(update::UpdateCheck::available) {
+                    let (Some(url), Some(latest)) = (check.url, check.latest) else {
+                        continue;
+                    };
+                    info!("updating {} to v{latest} from {url}", check.id);
+                    let result = repo::install_preferring_delta(
+                        check.delta,
+                        url,
+                        check.sha256,
+                        |path, sha256| {
+                            run(
+                                Some(TopLevel::Module {
+                                    command: ModuleCommand::Install {
+                                        path,
+                                        sha256,
+                                        clean: false,
+                                        allow_unsigned,
+                                        answers: Vec::new(),
+                                        enable: true,
+                                        dry_run: false,
+                                        from_repo: true,
+                                    },
+                                }),
+                                environment,
+                                serial,
+                                json,
+                                config,
+                            )
+                        },
+                    );
+                    if let Err(e) = result {
+                        error!("update of {} failed: {e:#}", check.id);
+                        failed += 1;
+                    }
+                }
+                if failed > 0 {
+                    anyhow::bail!("{failed} update(s) failed");
+                }
+            }
+
+            ModuleCommand::Sign { dir, key } => {
+                let public = signing::sign(Path::new(&dir), &key)?;
+                info!("module {dir} signed, public key {public}");
+            }
+
+            ModuleCommand::Changelog { module_id } => {
+                // a pending update carries the newest changelog
+                let update_dir = Path::new(MODULES_UPDATE_DIR).join(&module_id);
+                let module_dir = Path::new(MODULES_DIR).join(&module_id);
+                let dir = if update_dir.is_dir() {
+                    update_dir
+                } else if module_dir.is_dir() {
+                    module_dir
+                } else {
+                    anyhow::bail!("module {module_id} is not installed or being updated");
+                };
+
+                match changelog::read(&dir) {
+                    Some(sections) => changelog::print_sections(&sections),
+                    None => info!("module {module_id} has no {}", changelog::CHANGELOG_FILE),
+                }
+            }
+
+            ModuleCommand::Diff { old, new, output } => {
+                info!("building delta from {old} to {new}");
+                let manifest =
+                    delta::create_delta(Path::new(&old), Path::new(&new), Path::new(&output))?;
+                info!(
+                    "delta {} v{} -> v{} written to {output} ({} entries)",
+                    manifest.id,
+                    manifest.from_version,
+                    manifest.to_version,
+                    manifest.entries.len()
+                );
+            }
+
             ModuleCommand::List => {
-                module::list_modules(MODULES_DIR, "installed modules:");
-                module::list_modules(MODULES_UPDATE_DIR, "pending update modules:");
+                if json {
+                    let listing = module::ModuleListing {
+                        installed: module::list(MODULES_DIR)?,
+                        pending_update: module::list(MODULES_UPDATE_DIR)?,
+                    };
+                    println!("{}", serde_json::to_string_pretty(&listing)?);
+                } else {
+                    module::list_modules(MODULES_DIR, "installed modules:");
+                    module::list_modules(MODULES_UPDATE_DIR, "pending update modules:");
+                }
+            }
+
+            ModuleCommand::Grep {
+                pattern,
+                ignore_case,
+                content,
+                max_size,
+            } => {
+                let options = module::GrepOptions {
+                    ignore_case,
+                    content,
+                    max_size,
+                };
+                let matches = module::grep(&pattern, &options)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&matches)?);
+                } else if matches.is_empty() {
+                    info!("no module file matches {pattern:?}");
+                } else {
+                    module::print_grep(&matches)?;
+                }
+            }
+
+            ModuleCommand::Conflicts => {
+                let conflicts = conflicts::all()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&conflicts)?);
+                } else {
+                    conflicts::print(&conflicts);
+                }
+            }
+
+            ModuleCommand::Channel { command } => match command {
+                ChannelCommand::Set { module_id, channel } => {
+                    let previous = channel::set(&module_id, &channel)?;
+                    if previous == channel {
+                        info!("{module_id} already follows the {channel} channel");
+                    } else {
+                        info!(
+                            "{module_id} now follows the {channel} channel instead of {previous}"
+                        );
+                        channel::warn_downgrade(&module_id, &channel)?;
+                    }
+                }
+                ChannelCommand::List => channel::list()?,
+            },
+
+            ModuleCommand::WhyDisabled { module_id } => {
+                let diagnosis = diagnose::why_disabled(&module_id)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&diagnosis)?);
+                } else {
+                    diagnose::print(&diagnosis);
+                }
+            }
+
+            ModuleCommand::Lint { target } => {
+                let report = lint::lint(&target)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    lint::print_report(&report);
+                }
+                if report.errors() > 0 {
+                    anyhow::bail!("{target} has {} lint error(s)", report.errors());
+                }
+            }
+
+            ModuleCommand::Verify { module_id } => {
+                let report = integrity::verify(&module_id)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    integrity::print_report(&report);
+                }
+                if !report.intact() {
+                    anyhow::bail!("module {module_id} changed since it was installed");
+                }
+            }
+
+            ModuleCommand::Info { module_id } => {
+                let info = module::info(&module_id)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&info)?);
+                } else {
+                    module::print_info(&info);
+                }
             }
         },
 
         Some(TopLevel::Internal { command }) => match command {
-            InternalCommand::BootComplete => {
-                info!("executing boot complete logic");
+            InternalCommand::BootComplete {
+                skip_mount,
+                skip_scripts,
+                only_modules,
+            } => {
+                let options = boot::BootOptions {
+                    skip_mount,
+                    skip_scripts,
+                    only_modules,
+                };
+                boot::boot_complete(config, &SystemClock, &options)?;
+            }
 
-                // 1. Unlock adb shell by creating /tmp/.adb_auth_verified
-                info!("unlocking adb shell");
-                let adb_auth_path = "/tmp/.adb_auth_verified";
-                if let Err(e) = fs::File::create(adb_auth_path) {
-                    warn!(
-                        "failed to unlock adb shell by creating {}: {}",
-                        adb_auth_path, e
-                    );
+            InternalCommand::MountAll => {
+                boot::mount_all(config)?;
+                info!("modules mounted");
+            }
+
+            InternalCommand::Maintenance => {
+                let reclaimed = maintenance::reclaim(&config.maintenance)?;
+                if reclaimed == 0 {
+                    info!("nothing reclaimed");
+                }
+            }
+
+            InternalCommand::LateMount => {
+                boot::late_mount(config)?;
+            }
+
+            InternalCommand::Selftest => {
+                let checks = selftest::run()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&checks)?);
                 } else {
-                    info!("adb shell unlocked by creating {}", adb_auth_path);
-                }
-
-                // 2. Remove uninstall flagged modules
-                info!("removing uninstall flagged modules");
-                let entries = std::fs::read_dir(MODULES_DIR).unwrap();
-                for entry in entries {
-                    let entry = entry.unwrap();
-                    let path = entry.path();
-                    if path.join("uninstall.flag").exists() {
-                        info!("removing {path:?}");
-                        if let Err(e) = module::delete_dir(&path) {
-                            warn!("failed to delete module dir {path:?}: {e}");
-                        }
-                    }
+                    selftest::print(&checks);
+                }
+                let failed = checks.iter().filter(|check| !check.passed).count();
+                if failed > 0 {
+                    anyhow::bail!("{failed} selftest check(s) failed");
                 }
+                info!("selftest passed");
+            }
 
-                // 3. Move update modules
-                info!("installing update pending modules");
-                let entries = std::fs::read_dir(MODULES_UPDATE_DIR).unwrap();
-                for entry in entries {
-                    let entry = entry.unwrap();
-                    let path = entry.path();
-                    info!("updating {path:?}");
-                    let target = std::path::Path::new(MODULES_DIR).join(path.file_name().unwrap());
-                    if let Err(e) = module::move_dir(&path, &target) {
-                        warn!("failed to move update module {path:?} to {target:?}: {e}");
-                    }
+            InternalCommand::SetTime { unix } => {
+                let before = clock::unix_now();
+                clock::set_system_time(unix)
+                    .map_err(|e| anyhow::anyhow!("failed to set the clock: {e}"))?;
+                info!(
+                    "clock set to {} (was {})",
+                    clock::rfc3339(unix),
+                    clock::rfc3339(before)
+                );
+                // keep it across reboots where there is a hardware clock
+                match process::run_with_output("hwclock", &["-w"]) {
+                    Ok(status) if status.success() => info!("hardware clock updated"),
+                    _ => warn!(
+                        "could not update the hardware clock, the time is lost at the next power loss"
+                    ),
                 }
+            }
 
-                if Path::new("/userdisk/Favorite/safe_mode.flag").exists() {
-                    warn!("safe mode flag exists, not initializing modules");
-                    return Ok(());
+            InternalCommand::Event { name, data } => {
+                info!("dispatching event {name}");
+                events::dispatch(&name, &data, &config.scripts)?;
+            }
+        },
+
+        Some(TopLevel::InstallSelf) => {
+            setup::install_self()?;
+        }
+
+        Some(TopLevel::UninstallSelf { purge }) => {
+            setup::uninstall_self(purge)?;
+        }
+
+        Some(TopLevel::Recover) => {
+            recover::recover()?;
+        }
+
+        Some(TopLevel::Status) => {
+            let status = status::collect();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                status::print(&status);
+            }
+        }
+
+        Some(TopLevel::Env { refresh }) => {
+            let capabilities = if refresh {
+                &capabilities::refresh()
+            } else {
+                capabilities::get()
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(capabilities)?);
+            } else {
+                capabilities::print(capabilities);
+            }
+        }
+
+        Some(TopLevel::Query { sql }) => {
+            query::run(config, &sql, json)?;
+        }
+
+        Some(TopLevel::SimulateBoot) => {
+            boot::simulate(config)?;
+        }
+
+        Some(TopLevel::SmokeTest) => {
+            let install = |archive: &Path| {
+                run(
+                    Some(TopLevel::Module {
+                        command: ModuleCommand::Install {
+                            path: archive.to_string_lossy().into_owned(),
+                            sha256: None,
+                            clean: false,
+                            allow_unsigned: true,
+                            answers: Vec::new(),
+                            enable: true,
+                            dry_run: false,
+                            from_repo: false,
+                        },
+                    }),
+                    environment,
+                    serial,
+                    false,
+                    config,
+                )
+            };
+            let checks = smoke::run(config, &install)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&checks)?);
+            } else {
+                selftest::print(&checks);
+            }
+            let failed = checks.iter().filter(|check| !check.passed).count();
+            if failed > 0 {
+                anyhow::bail!("{failed} smoke test step(s) failed");
+            }
+            info!("smoke test passed, modules install, mount and run on this device");
+        }
+
+        Some(TopLevel::Device { command }) => match command {
+            DeviceCommand::List => {
+                let devices = adb::list_devices().map_err(anyhow::Error::msg)?;
+                info!("connected devices:");
+                if devices.is_empty() {
+                    info!("  (none)");
                 }
+                for device in devices {
+                    let default = config.default_device.as_deref() == Some(device.as_str());
+                    info!("  {device}{}", if default { " (default)" } else { "" });
+                }
+            }
 
-                // 4. Initialize modules
-                info!("initializing modules");
-                for entry in std::fs::read_dir(MODULES_DIR).unwrap() {
-                    let entry = entry?;
-                    let path = entry.path();
-                    info!("initializing {path:?}");
+            DeviceCommand::Use { serial } => {
+                config::set_value(environment, "default_device", &serial)?;
+                if !adb::list_devices().is_ok_and(|devices| devices.contains(&serial)) {
+                    warn!("device {serial} is not connected right now");
+                }
+                info!("default device set to {serial}");
+            }
 
-                    // read props
-                    let props = module::read_module_prop(&path.join("module.prop"));
-                    if let Err(err) = props {
-                        error!("module {path:?} has invalid properties: {err}, skipping");
-                        continue;
-                    }
-                    let props = props.unwrap();
+            DeviceCommand::Root => {
+                let device = adb::select_device(serial, config.default_device.as_deref())?;
+                adb::root(&device)?;
+                info!("adbd on {device} is running as root");
+            }
 
-                    info!(
-                        "module info: {}, {}, {}, {}",
-                        props["id"], props["name"], props["description"], props["version"]
+            DeviceCommand::Remount => {
+                let device = adb::select_device(serial, config.default_device.as_deref())?;
+                adb::remount(&device)?;
+                info!("root filesystem of {device} is writable until the next reboot");
+            }
+
+            DeviceCommand::SetTime { ntp, .. } => {
+                let device = adb::select_device(serial, config.default_device.as_deref())?;
+                let unix = match &ntp {
+                    Some(server) => clock::ntp_time(server)?,
+                    None => clock::unix_now(),
+                };
+                info!(
+                    "setting the clock of {device} to {} from {}",
+                    clock::local(unix),
+                    ntp.as_deref().unwrap_or("this host")
+                );
+                let binary = setup::installed_binary();
+                adb::shell_run(
+                    &device,
+                    &binary.to_string_lossy(),
+                    vec![
+                        "internal".to_string(),
+                        "set-time".to_string(),
+                        unix.to_string(),
+                    ],
+                )
+                .map_err(anyhow::Error::msg)?;
+            }
+        },
+
+        Some(TopLevel::Cache { command }) => match command {
+            CacheCommand::List => {
+                cache::list()?;
+            }
+
+            CacheCommand::Clean => {
+                cache::clean()?;
+            }
+        },
+
+        Some(TopLevel::Host { command }) => match command {
+            HostCommand::ServeDir { dir, port, bind } => {
+                serve::serve_dir(&dir, bind, port)?;
+            }
+        },
+
+        Some(TopLevel::Repo { command }) => match command {
+            RepoCommand::Add { url } => {
+                if repo::add(&url)? {
+                    info!("repository {url} added");
+                } else {
+                    info!("repository {url} was already added, index refreshed");
+                }
+            }
+
+            RepoCommand::Remove { url } => {
+                if repo::remove(&url)? {
+                    info!("repository {url} removed");
+                } else {
+                    info!("repository {url} was not added");
+                }
+            }
+
+            RepoCommand::List => {
+                repo::list()?;
+            }
+
+            RepoCommand::Update => {
+                repo::update()?;
+            }
+
+            RepoCommand::Search { term } => {
+                let matches = repo::search(&term)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&matches)?);
+                } else {
+                    repo::print_search(&matches);
+                }
+            }
+
+            RepoCommand::Install {
+                module_id,
+                clean,
+                allow_unsigned,
+                answers,
+            } => {
+                let entry = repo::find(&module_id)?;
+                let installed = module::module_version(&Path::new(MODULES_DIR).join(&module_id));
+                if let Some(installed) = installed
+                    && installed > entry.version
+                {
+                    warn!(
+                        "v{installed} is installed, v{} of the {} channel is a downgrade",
+                        entry.version, entry.channel
                     );
+                }
+                info!(
+                    "installing {module_id} v{} from {}",
+                    entry.version, entry.url
+                );
+                let delta = entry.delta_from(installed).cloned();
+                return repo::install_preferring_delta(
+                    delta,
+                    entry.url,
+                    Some(entry.sha256),
+                    |path, sha256| {
+                        run(
+                            Some(TopLevel::Module {
+                                command: ModuleCommand::Install {
+                                    path,
+                                    sha256,
+                                    clean,
+                                    allow_unsigned,
+                                    answers: answers.clone(),
+                                    enable: true,
+                                    dry_run: false,
+                                    from_repo: true,
+                                },
+                            }),
+                            environment,
+                            serial,
+                            json,
+                            config,
+                        )
+                    },
+                );
+            }
+        },
 
-                    // disable
-                    if path.join("disable.flag").exists() {
-                        warn!("module {path:?} is disabled, not initializing it");
-                        continue;
-                    }
+        Some(TopLevel::Trust { command }) => match command {
+            TrustCommand::Add { fingerprint, name } => {
+                if trust::add(&fingerprint, name)? {
+                    info!("author key {fingerprint} is now trusted");
+                } else {
+                    info!("author key {fingerprint} is already trusted");
+                }
+            }
 
-                    // mount
-                    info!("mounting module {path:?}");
-                    if props
-                        .get("skip_mount")
-                        .map(|s| s.as_str())
-                        .unwrap_or("false")
-                        != "true"
-                    {
-                        if let Err(err) = module::mount_module(&path) {
-                            warn!("failed to mount module: {err}");
-                            continue;
-                        }
-                    } else {
-                        info!("module has skip_mount, not mounting module")
-                    }
+            TrustCommand::Remove { fingerprint } => {
+                if trust::remove(&fingerprint)? {
+                    info!("author key {fingerprint} is no longer trusted");
+                } else {
+                    info!("author key {fingerprint} was not trusted");
+                }
+            }
 
-                    // execute boot-complete.sh
-                    info!("executing boot-complete.sh in {path:?}");
-                    if path.join("boot-complete.sh").exists() {
-                        if let Err(e) = module::run_script(&path, "boot-complete.sh") {
-                            warn!("failed to run boot-complete.sh for {path:?}: {e}");
-                            continue;
-                        }
+            TrustCommand::List => {
+                trust::list()?;
+            }
+        },
+
+        Some(TopLevel::Mount { command }) => match command {
+            MountCommand::List => {
+                let mounts = mount::list_mounts()?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&mounts)?);
+                    return Ok(());
+                }
+                if mounts.is_empty() {
+                    info!("no module mounts");
+                }
+                for entry in &mounts {
+                    let owner = entry.module_id.as_deref().unwrap_or("(unrecorded)");
+                    if entry.gone {
+                        warn!("{owner}: {} (no longer mounted)", entry.target.display());
                     } else {
-                        warn!("boot-complete.sh does not exist")
+                        info!("{owner}: {}", entry.target.display());
                     }
                 }
+            }
+        },
+
+        Some(TopLevel::Backup { command }) => match command {
+            BackupCommand::Create { output } => {
+                let output = output.unwrap_or_else(|| {
+                    PathBuf::from(format!(
+                        "scriba-backup-{}.tar.gz",
+                        clock::file_stamp(clock::unix_now())
+                    ))
+                });
+                let modules = backup::create(&output)?;
+                info!("{} module(s) backed up to {output:?}", modules.len());
+            }
 
-                // let _ = fs::write("/userdisk/Favorite/safe_mode.flag", "");
+            BackupCommand::Restore { file } => {
+                backup::restore(Path::new(&file), config.archive.limits())?;
+                info!("backup restored, reboot to switch to its modules");
+            }
+        },
+
+        Some(TopLevel::Audit { command }) => match command {
+            AuditCommand::List { limit } => {
+                audit::list_entries(limit)?;
+            }
+        },
+
+        Some(TopLevel::Config { command }) => match command {
+            ConfigCommand::List => {
+                info!("config keys:");
+                for entry in config::SCHEMA {
+                    info!(
+                        "  {} - {} ({})",
+                        entry.path,
+                        entry.help,
+                        entry.describe_values()
+                    );
+                }
+            }
+
+            ConfigCommand::Get { key } => match config::get_value(environment, &key)? {
+                Some(value) => info!("{key} = {value}"),
+                None => info!("{key} is not set"),
+            },
+
+            ConfigCommand::Set { key, value } => {
+                config::set_value(environment, &key, &value)?;
+                info!("{key} set to {value}");
             }
         },
 
         Some(TopLevel::Completion { shell }) => {
-            let mut cmd = Cli::command();
+            let module_ids = module::module_ids();
+            let config_keys: Vec<String> = config::SCHEMA
+                .iter()
+                .flat_map(|entry| {
+                    entry.expand(|placeholder| match placeholder {
+                        "<subsystem>" => {
+                            logging::SUBSYSTEMS.iter().map(|s| s.to_string()).collect()
+                        }
+                        "<id>" => module_ids.clone(),
+                        _ => Vec::new(),
+                    })
+                })
+                .collect();
+
+            let mut cmd =
+                cli::with_runtime_values(Cli::command(), &module_ids, &config_keys, &repo::urls());
             let bin_name = cmd.get_name().to_string();
             generate(shell, &mut cmd, bin_name, &mut io::stdout());
         }
@@ -0,0 +1,101 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::cache;
+use crate::config::MaintenanceConfig;
+use crate::defs::SCRIBA_DIR;
+use crate::logging;
+use crate::rollback;
+use crate::status;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Free space left on the filesystem holding scriba's directories.
+fn free_bytes() -> Option<u64> {
+    status::filesystem_space(SCRIBA_DIR).map(|(_, free)| free)
+}
+
+/// Whether `path` is on the filesystem holding scriba's directories.
+fn on_scriba_filesystem(path: &Path) -> bool {
+    match (fs::metadata(path), fs::metadata(SCRIBA_DIR)) {
+        (Ok(path), Ok(scriba)) => path.dev() == scriba.dev(),
+        _ => false,
+    }
+}
+
+/// When free space is below `maintenance.min_free_mb`, delete rotated logs
+/// (oldest first), then cached downloads (least recently used first), then
+/// the oldest archived version of each module, until it is back above.
+/// Returns the bytes reclaimed.
+///
+/// The download cache is the host-side XDG cache; installs on the device
+/// download to tmpfs instead, so that tier only runs where the cache shares
+/// the filesystem. scriba keeps no trash, removals delete right away.
+pub fn reclaim(config: &MaintenanceConfig) -> Result<u64> {
+    let threshold = config.min_free_mb * MIB;
+    let Some(free) = free_bytes() else {
+        return Ok(0);
+    };
+    if threshold == 0 || free >= threshold {
+        return Ok(0);
+    }
+    warn!(
+        "only {} MiB free, below maintenance.min_free_mb = {}, reclaiming space",
+        free / MIB,
+        config.min_free_mb
+    );
+
+    let enough = || free_bytes().is_none_or(|free| free >= threshold);
+    let mut reclaimed = 0;
+
+    for log in logging::rotated_logs() {
+        if enough() {
+            break;
+        }
+        let size = fs::metadata(&log).map_or(0, |meta| meta.len());
+        match fs::remove_file(&log) {
+            Ok(()) => {
+                info!("removed rotated log {log:?} ({} KiB)", size / 1024);
+                reclaimed += size;
+            }
+            Err(e) => warn!("failed to remove rotated log {log:?}: {e}"),
+        }
+    }
+
+    if on_scriba_filesystem(&cache::cache_dir()) {
+        while !enough() {
+            match cache::evict_oldest()? {
+                Some(freed) => reclaimed += freed,
+                None => break,
+            }
+        }
+    }
+
+    // rollback copies go last, they are the only way back from a bad update
+    while !enough() {
+        match rollback::evict_oldest()? {
+            Some(freed) => reclaimed += freed,
+            None => break,
+        }
+    }
+
+    let free = free_bytes().unwrap_or_default();
+    if enough() {
+        info!(
+            "reclaimed {} KiB, {} MiB free now",
+            reclaimed / 1024,
+            free / MIB
+        );
+    } else {
+        warn!(
+            "reclaimed {} KiB but only {} MiB are free, nothing else is safe to delete",
+            reclaimed / 1024,
+            free / MIB
+        );
+    }
+    Ok(reclaimed)
+}
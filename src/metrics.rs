@@ -0,0 +1,145 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use tracing::{info, warn};
+
+use crate::boot::BootRecord;
+use crate::config::MetricsConfig;
+use crate::module;
+use crate::mount::{self, FailureKind};
+use crate::state;
+
+/// File written into the textfile collector directory.
+const BOOT_METRICS_FILE: &str = "boot_metrics.prom";
+
+/// Label values may not contain raw backslashes, quotes or newlines.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn metric(out: &mut String, name: &str, help: &str, samples: &[(String, f64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+/// Metrics of a boot-complete run in the Prometheus text format.
+pub fn render(record: &BootRecord) -> String {
+    let installed = module::installed_ids();
+    let enabled = installed.iter().filter(|id| module::is_enabled(id)).count();
+    let flag = |set: bool| if set { 1.0 } else { 0.0 };
+    let plain = |value: f64| vec![(String::new(), value)];
+
+    let mut out = String::new();
+    metric(
+        &mut out,
+        "scriba_boot_complete_timestamp_seconds",
+        "When the last boot-complete run started, in unix seconds.",
+        &plain(record.started.timestamp as f64),
+    );
+    metric(
+        &mut out,
+        "scriba_boot_complete_duration_seconds",
+        "How long the last boot-complete run took, without the late pass.",
+        &plain(record.duration_ms as f64 / 1000.0),
+    );
+    metric(
+        &mut out,
+        "scriba_boot_complete_success",
+        "Whether the last boot-complete run finished without error.",
+        &plain(flag(!record.result.starts_with("failed"))),
+    );
+    metric(
+        &mut out,
+        "scriba_boot_safe_mode",
+        "Whether modules were skipped because of the safe mode flag.",
+        &plain(flag(record.result == "safe mode")),
+    );
+    metric(
+        &mut out,
+        "scriba_modules",
+        "Installed modules by state.",
+        &[
+            ("{state=\"enabled\"}".to_string(), enabled as f64),
+            (
+                "{state=\"disabled\"}".to_string(),
+                (installed.len() - enabled) as f64,
+            ),
+        ],
+    );
+    metric(
+        &mut out,
+        "scriba_boot_failed_modules",
+        "Modules that failed to initialize in the last boot-complete run.",
+        &plain(record.failed_modules.len() as f64),
+    );
+    metric(
+        &mut out,
+        "scriba_boot_module_failed",
+        "Set for each module that failed to initialize.",
+        &record
+            .failed_modules
+            .iter()
+            .map(|id| (format!("{{module=\"{}\"}}", escape_label(id)), 1.0))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        &mut out,
+        "scriba_boot_deferred_modules",
+        "Modules left to the late pass by a boot stage budget.",
+        &plain(record.deferred.len() as f64),
+    );
+
+    let failures = mount::mount_failures();
+    metric(
+        &mut out,
+        "scriba_mount_failures",
+        "Modules whose last mount attempt failed, by failure kind.",
+        &FailureKind::ALL
+            .iter()
+            .map(|kind| {
+                let count = failures.values().filter(|f| f.kind == *kind).count();
+                (format!("{{kind=\"{}\"}}", kind.as_str()), count as f64)
+            })
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        &mut out,
+        "scriba_module_mount_failed",
+        "Failed mount attempts in a row, for each module that failed to mount.",
+        &failures
+            .iter()
+            .map(|(id, failure)| {
+                (
+                    format!(
+                        "{{module=\"{}\",kind=\"{}\"}}",
+                        escape_label(id),
+                        failure.kind.as_str()
+                    ),
+                    failure.count as f64,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    out
+}
+
+/// Write the boot metrics for a node_exporter textfile collector, if a
+/// directory is configured. Failures are logged, never fatal.
+pub fn write_boot_metrics(config: &MetricsConfig, record: &BootRecord) {
+    let Some(dir) = &config.textfile_dir else {
+        return;
+    };
+
+    // atomic replacement keeps the collector from reading a partial file
+    let path = Path::new(dir).join(BOOT_METRICS_FILE);
+    match state::write_atomic(&path, render(record)) {
+        Ok(()) => info!("boot metrics written to {path:?}"),
+        Err(e) => warn!("failed to write boot metrics to {path:?}: {e}"),
+    }
+}
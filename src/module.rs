@@ -1,29 +1,77 @@
 use anyhow::bail;
-use anyhow::{Context, Result, anyhow};
-use libc::{MS_BIND, mount};
-use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
 use std::fs::create_dir_all;
 use std::fs::rename;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
-use std::{ffi::CString, os::unix::ffi::OsStrExt};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
 use tempfile::tempdir;
 use tracing::info;
 use tracing::warn;
-use zip::ZipArchive;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
 
+use crate::archive::{self, Limits};
+use crate::boot;
+use crate::cache;
+use crate::capabilities;
+use crate::clock::Timestamp;
+use crate::config::{MOUNT_MODES, MountMode, ScriptConfig};
+use crate::defs::{
+    MODULE_DATA_DIR, MODULES_DIR, MODULES_UPDATE_DIR, PAYLOAD_ROOTS, RUN_STATE_DIR,
+    SCRIPT_RESULTS_DIR,
+};
+use crate::integrity;
+use crate::lint;
+use crate::logging::Heartbeat;
+use crate::mount::{self, MountFailure};
+use crate::path_env;
 use crate::process;
+use crate::prompt;
+use crate::signing;
+use crate::state;
+use crate::storage;
+use crate::strict;
+use crate::trust::{self, TrustStatus};
 
-pub fn read_module_prop(path: &std::path::Path) -> anyhow::Result<HashMap<String, String>> {
-    let content = fs::read_to_string(path)?;
+/// Largest accepted prop file; real ones are a few hundred bytes.
+const MAX_PROP_FILE_SIZE: u64 = 64 * 1024;
+
+/// How long `preinstall.sh` and `postinstall.sh` may run.
+const INSTALL_SCRIPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Parse `key=value` lines without any validation.
+pub fn parse_prop_file(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_PROP_FILE_SIZE {
+        bail!("{path:?} is {size} bytes, more than the {MAX_PROP_FILE_SIZE} allowed");
+    }
+
+    let content = fs::read_to_string(path).map_err(|e| anyhow!("cannot read {path:?}: {e}"))?;
     let mut map = HashMap::new();
     for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
         if let Some((k, v)) = line.split_once('=') {
             map.insert(k.trim().to_string(), v.trim().to_string());
         }
     }
+    Ok(map)
+}
+
+pub fn read_module_prop(path: &std::path::Path) -> anyhow::Result<HashMap<String, String>> {
+    let mut map = parse_prop_file(path)?;
 
     validate_prop(&map, "id", PropType::String)?;
     validate_prop(&map, "name", PropType::String)?;
@@ -36,11 +84,39 @@ pub fn read_module_prop(path: &std::path::Path) -> anyhow::Result<HashMap<String
             "true" | "false" => {}
             _ => return Err(anyhow!("property skip_mount must be 'true' or 'false'")),
         }
+    } else if strict::enabled() {
+        bail!("property skip_mount is not set (it would default to false)");
     } else {
         map.insert("skip_mount".to_string(), "false".to_string());
     }
 
-    let id = map.get("id").unwrap();
+    if map.contains_key("priority") {
+        validate_prop(&map, "priority", PropType::Int)?;
+    }
+
+    if let Some(value) = map.get("path_dirs") {
+        path_env::parse(value).map_err(|e| anyhow!("property path_dirs is invalid: {e}"))?;
+    }
+
+    if let Some(mode) = map.get("mount")
+        && MountMode::from_name(mode).is_none()
+    {
+        bail!("property mount must be one of {MOUNT_MODES:?}");
+    }
+
+    if let Some(key) = map.get("author_key") {
+        trust::normalize_fingerprint(key)
+            .map_err(|e| anyhow!("property author_key is invalid: {e}"))?;
+    }
+
+    if let Some(url) = map.get("updateJson")
+        && !cache::is_url(url)
+    {
+        bail!("property updateJson must be an http(s) URL");
+    }
+
+    let id = &map["id"];
+    validate_module_id(id)?;
     let dir_name = path
         .parent()
         .and_then(|p| p.file_name())
@@ -57,6 +133,39 @@ pub fn read_module_prop(path: &std::path::Path) -> anyhow::Result<HashMap<String
     Ok(map)
 }
 
+/// Longest accepted module id; ids end up in paths, log names and env vars.
+const MAX_MODULE_ID_LEN: usize = 64;
+
+/// Names used (or set aside) for scriba's own directories next to modules.
+const RESERVED_MODULE_IDS: &[&str] = &[
+    "bin",
+    "logs",
+    "modules",
+    "quarantine",
+    "scriba",
+    "staging",
+    "state",
+    "trash",
+    "update",
+];
+
+/// Check an id for charset, length and collisions with reserved names.
+pub fn validate_module_id(id: &str) -> anyhow::Result<()> {
+    if id.is_empty() {
+        bail!("module id cannot be empty");
+    }
+    if id.len() > MAX_MODULE_ID_LEN {
+        bail!("module id '{id}' is longer than {MAX_MODULE_ID_LEN} characters");
+    }
+    if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!("module id '{id}' must contain only letters, numbers, or underscore");
+    }
+    if RESERVED_MODULE_IDS.contains(&id.to_ascii_lowercase().as_str()) {
+        bail!("module id '{id}' is reserved");
+    }
+    Ok(())
+}
+
 #[derive(Clone, Copy)]
 #[allow(dead_code)]
 enum PropType {
@@ -92,24 +201,349 @@ fn validate_prop(
     Ok(())
 }
 
-pub fn run_script(module_dir: &std::path::Path, script: &str) -> anyhow::Result<()> {
-    let script_path = module_dir.join(script);
-    if script_path.exists() {
-        let status = process::run_with_output("sh", &[script_path.to_str().unwrap()])?;
-        if !status.success() {
-            bail!(
-                "script {} failed with exit code {:?}",
-                script,
-                status.code()
-            );
+/// Output lines kept in a `ScriptResult`.
+const SCRIPT_OUTPUT_LINES: usize = 20;
+
+/// How a module script run went, kept per module and script in the state
+/// store so failures can be explained later.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScriptResult {
+    pub script: String,
+    /// `None` when the script was killed by a signal
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub finished: Timestamp,
+    /// Last lines of stdout followed by the last lines of stderr
+    pub output: Vec<String>,
+}
+
+impl ScriptResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// The result itself, or an error carrying its last output line.
+    pub fn check(self) -> anyhow::Result<Self> {
+        if self.success() {
+            return Ok(self);
         }
-    } else {
-        bail!("script {script} does not exist")
+
+        let last = self
+            .output
+            .last()
+            .map(String::as_str)
+            .unwrap_or("no output");
+        bail!(
+            "script {} failed with exit code {:?}: {last}",
+            self.script,
+            self.exit_code
+        )
+    }
+}
+
+fn output_tail(stdout: &[u8], stderr: &[u8]) -> Vec<String> {
+    let tail = |bytes: &[u8]| {
+        let text = String::from_utf8_lossy(bytes);
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let skip = lines.len().saturating_sub(SCRIPT_OUTPUT_LINES);
+        lines.into_iter().skip(skip).collect::<Vec<_>>()
+    };
+
+    let mut lines = tail(stdout);
+    lines.extend(tail(stderr));
+    let skip = lines.len().saturating_sub(SCRIPT_OUTPUT_LINES);
+    lines.split_off(skip)
+}
+
+fn script_results_dir(module_id: &str) -> PathBuf {
+    Path::new(SCRIPT_RESULTS_DIR).join(module_id)
+}
+
+fn save_script_result(module_dir: &Path, result: &ScriptResult) -> Result<()> {
+    let module_id = module_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow!("cannot get module id of {module_dir:?}"))?;
+    let dir = script_results_dir(module_id);
+    create_dir_all(&dir)?;
+
+    let name = format!("{}.json", result.script.replace('/', "_"));
+    state::write_atomic(&dir.join(name), serde_json::to_string_pretty(result)?)?;
+    Ok(())
+}
+
+/// Last recorded result of every script of a module, by script name.
+pub fn script_results(module_id: &str) -> Vec<ScriptResult> {
+    let mut results: Vec<ScriptResult> = fs::read_dir(script_results_dir(module_id))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| serde_json::from_str(&content).ok())
+        .collect();
+    results.sort_by(|a, b| a.script.cmp(&b.script));
+    results
+}
+
+fn utf8_path(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("path {path:?} is not valid UTF-8"))
+}
+
+/// Longest shebang line looked at.
+const MAX_SHEBANG_LEN: usize = 256;
+
+/// Interpreter named by a script's `#!` line, if it has one.
+pub fn shebang(script_path: &Path) -> Result<Option<String>> {
+    let mut head = Vec::with_capacity(MAX_SHEBANG_LEN);
+    File::open(script_path)?
+        .take(MAX_SHEBANG_LEN as u64)
+        .read_to_end(&mut head)?;
+
+    // a leading install prompt looks like a shebang but is not one
+    if head.starts_with(prompt::PROMPT_MARKER.as_bytes()) {
+        return Ok(None);
+    }
+    let Some(rest) = head.strip_prefix(b"#!") else {
+        return Ok(None);
+    };
+    let line = rest.split(|&b| b == b'\n').next().unwrap_or_default();
+    Ok(Some(String::from_utf8_lossy(line).trim().to_string()))
+}
+
+/// Program and arguments that run `script_path`: the module's
+/// `interpreter=` prop, else the script itself when it has a shebang, else
+/// `sh`. Declared interpreters must be in `scripts.interpreters`.
+fn script_command(
+    module_dir: &Path,
+    script_path: &Path,
+    scripts: &ScriptConfig,
+) -> Result<(String, Vec<String>)> {
+    let path = utf8_path(script_path)?.to_string();
+    let declared = parse_prop_file(&module_dir.join("module.prop"))
+        .ok()
+        .and_then(|props| props.get("interpreter").cloned())
+        .filter(|interpreter| !interpreter.is_empty());
+    let from_shebang = declared.is_none();
+
+    let Some(interpreter) = declared.or(shebang(script_path)?) else {
+        return Ok(("sh".to_string(), vec![path]));
+    };
+
+    let normalized = interpreter.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !scripts.interpreters.contains(&normalized) {
+        bail!("interpreter `{normalized}` is not allowed, add it to scripts.interpreters");
+    }
+    let program = normalized
+        .split(' ')
+        .next()
+        .expect("interpreter is not empty");
+    if capabilities::get().lacks_shell(program) {
+        bail!("interpreter `{program}` is not present on this device, see `scriba env`");
+    }
+
+    if from_shebang {
+        let mode = fs::metadata(script_path)?.permissions().mode();
+        if mode & 0o111 != 0o111 {
+            fs::set_permissions(script_path, fs::Permissions::from_mode(mode | 0o111))?;
+        }
+        return Ok((path, Vec::new()));
+    }
+
+    let mut words = normalized.split(' ').map(str::to_string);
+    let program = words.next().expect("interpreter is not empty");
+    let mut args: Vec<String> = words.collect();
+    args.push(path);
+    Ok((program, args))
+}
+
+/// scriba's version as one number, `major * 10000 + minor * 100 + patch`,
+/// for scripts comparing versions with `-ge`.
+fn version_code() -> u32 {
+    [
+        env!("CARGO_PKG_VERSION_MAJOR"),
+        env!("CARGO_PKG_VERSION_MINOR"),
+        env!("CARGO_PKG_VERSION_PATCH"),
+    ]
+    .iter()
+    .fold(0, |code, part| {
+        code * 100 + part.parse::<u32>().unwrap_or(0)
+    })
+}
+
+/// Persistent data directory of a module, see `MODULE_DATA_DIR`.
+pub fn data_dir(module_id: &str) -> PathBuf {
+    Path::new(MODULE_DATA_DIR).join(module_id)
+}
+
+/// Delete a module's data directory, for `install --clean` and
+/// `uninstall --purge`.
+pub fn wipe_data(module_id: &str) -> anyhow::Result<()> {
+    let dir = data_dir(module_id);
+    if dir.exists() {
+        delete_dir(&dir)?;
+        info!("data of module {module_id} wiped");
+    }
+    Ok(())
+}
+
+/// Empty data directory `install --clean` gives postinstall.sh. It
+/// replaces the module's data when the update is promoted at boot, so the
+/// running version keeps its data until then.
+pub fn fresh_data_dir(module_id: &str) -> PathBuf {
+    Path::new(MODULE_DATA_DIR).join(format!(".{module_id}.fresh"))
+}
+
+/// Replace a module's data with its `fresh_data_dir`, for an update staged
+/// with `install --clean`.
+pub fn replace_data(module_id: &str) -> anyhow::Result<()> {
+    wipe_data(module_id)?;
+    let fresh = fresh_data_dir(module_id);
+    if fresh.exists() {
+        fs::rename(&fresh, data_dir(module_id))?;
+    }
+    Ok(())
+}
+
+/// Environment every module script runs with, ahead of any variables
+/// particular to the script. Creates the module's data directory.
+pub fn script_env(module_dir: &Path) -> Vec<(String, String)> {
+    let id = module_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let data = data_dir(&id);
+    if let Err(e) = create_dir_all(&data) {
+        warn!("failed to create data directory {data:?}: {e}");
+    }
+    let mut envs = vec![
+        (
+            "MODDIR".to_string(),
+            module_dir.to_string_lossy().to_string(),
+        ),
+        ("MODDATA".to_string(), data.to_string_lossy().to_string()),
+        ("SCRIBA_MODULE_ID".to_string(), id),
+        (
+            "SCRIBA_VER".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        ("SCRIBA_VER_CODE".to_string(), version_code().to_string()),
+        ("BOOTMODE".to_string(), boot::booting().to_string()),
+    ];
+
+    // what sourcing the generated profile script gives
+    let path = path_env::profile_path();
+    if !path.is_empty() {
+        let current = std::env::var("PATH").unwrap_or_default();
+        envs.push(("PATH".to_string(), path_env::prepend(&path, &current)));
+    }
+    envs
+}
+
+pub fn run_script(
+    module_dir: &std::path::Path,
+    script: &str,
+    scripts: &ScriptConfig,
+) -> anyhow::Result<ScriptResult> {
+    run_script_with_env(module_dir, script, &[], scripts)
+}
+
+/// Environment passed to install phase scripts.
+pub fn install_env(
+    phase: &str,
+    module_dir: &Path,
+    props: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    let id = props.get("id").cloned().unwrap_or_default();
+    let mut envs = vec![
+        ("SCRIBA_PHASE".to_string(), phase.to_string()),
+        ("SCRIBA_MODULE_ID".to_string(), id.clone()),
+        (
+            "SCRIBA_MODULE_PATH".to_string(),
+            module_dir.to_string_lossy().to_string(),
+        ),
+        (
+            "SCRIBA_MODULE_VERSION".to_string(),
+            props.get("version").cloned().unwrap_or_default(),
+        ),
+    ];
+
+    if let Some(installed) = module_version(&Path::new(MODULES_DIR).join(&id)) {
+        envs.push((
+            "SCRIBA_INSTALLED_VERSION".to_string(),
+            installed.to_string(),
+        ));
+    }
+
+    envs
+}
+
+/// Run an optional install phase script under `INSTALL_SCRIPT_TIMEOUT`.
+/// A missing script counts as success.
+pub fn run_install_phase(
+    module_dir: &Path,
+    script: &str,
+    envs: &[(String, String)],
+    scripts: &ScriptConfig,
+) -> anyhow::Result<()> {
+    let script_path = module_dir.join(script);
+    if !script_path.exists() {
+        return Ok(());
+    }
+
+    info!("running {script}");
+    let (program, args) = script_command(module_dir, &script_path, scripts)?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let envs = [script_env(module_dir), envs.to_vec()].concat();
+    let _heartbeat = Heartbeat::background(format!("running {script}"));
+    let status = process::run_with_timeout(&program, &args, &envs, INSTALL_SCRIPT_TIMEOUT)?;
+    if !status.success() {
+        bail!(
+            "script {} failed with exit code {:?}",
+            script,
+            status.code()
+        );
     }
 
     Ok(())
 }
 
+/// Run a module script with extra environment variables and record the
+/// result. Only failing to start the script is an error; see
+/// `ScriptResult::check` for treating a non-zero exit as one.
+pub fn run_script_with_env(
+    module_dir: &Path,
+    script: &str,
+    envs: &[(String, String)],
+    scripts: &ScriptConfig,
+) -> anyhow::Result<ScriptResult> {
+    let script_path = module_dir.join(script);
+    if !script_path.exists() {
+        bail!("script {script} does not exist")
+    }
+
+    let (program, args) = script_command(module_dir, &script_path, scripts)?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let envs = [script_env(module_dir), envs.to_vec()].concat();
+    let started = Instant::now();
+    let heartbeat = Heartbeat::background(format!("running {script} of {module_dir:?}"));
+    let output = process::run_echoed(&program, &args, &envs)?;
+    drop(heartbeat);
+    let result = ScriptResult {
+        script: script.to_string(),
+        exit_code: output.status.code(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        finished: Timestamp::now(),
+        output: output_tail(&output.stdout, &output.stderr),
+    };
+
+    if let Err(e) = save_script_result(module_dir, &result) {
+        warn!("failed to record result of {script}: {e}");
+    }
+
+    Ok(result)
+}
+
 pub fn delete_dir(path: &std::path::Path) -> anyhow::Result<()> {
     info!("deleting dir {path:?}");
     if path.exists() {
@@ -129,133 +563,428 @@ pub fn move_dir(src: &std::path::Path, dst: &std::path::Path) -> anyhow::Result<
     Ok(())
 }
 
-pub fn unzip_module(zip_path: &Path) -> anyhow::Result<PathBuf> {
-    let file = File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file)?;
-    let tmp_dir = tempdir()?;
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i)?;
-        let outpath = tmp_dir.path().join(file.mangled_name());
+/// Recursively copy a directory, preserving permissions and symlinks.
+pub fn copy_dir(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        let meta = fs::symlink_metadata(&src_path)?;
 
-        if file.name().ends_with('/') {
-            std::fs::create_dir_all(&outpath)?;
+        if meta.is_dir() {
+            copy_dir(&src_path, &dst_path)?;
+        } else if meta.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&src_path)?, &dst_path)?;
         } else {
-            if let Some(parent) = outpath.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            std::io::copy(&mut file, &mut std::fs::File::create(&outpath)?)?;
+            fs::copy(&src_path, &dst_path)?;
         }
     }
-    Ok(tmp_dir.keep())
+    Ok(())
 }
 
-fn bind_mount_file(src: &Path, dst: &Path) -> Result<()> {
-    info!("mounting {src:?} on {dst:?}");
+/// Extract a module archive (zip, tarball or squashfs image) into a temp
+/// directory named after the module id, so it validates like an installed
+/// module.
+pub fn extract_module(archive_path: &Path, limits: Limits) -> anyhow::Result<PathBuf> {
+    let tmp_dir = tempdir()?.keep();
+    let extract_dir = tmp_dir.join("module");
+    archive::extract(archive_path, &extract_dir, limits)?;
 
-    let src_c = CString::new(src.as_os_str().as_bytes()).context("invalid src path")?;
-    let dst_c = CString::new(dst.as_os_str().as_bytes()).context("invalid dst path")?;
+    let props = parse_prop_file(&extract_dir.join("module.prop"))
+        .map_err(|e| anyhow!("failed to read module.prop from archive: {e}"))?;
+    let id = props
+        .get("id")
+        .ok_or_else(|| anyhow!("module.prop in archive has no id"))?;
+    validate_module_id(id)?;
 
-    let ret = unsafe {
-        mount(
-            src_c.as_ptr(),
-            dst_c.as_ptr(),
-            std::ptr::null(),
-            MS_BIND,
-            std::ptr::null(),
-        )
-    };
+    let module_dir = tmp_dir.join(id);
+    rename(&extract_dir, &module_dir)?;
+    Ok(module_dir)
+}
 
-    if ret != 0 {
-        return Err(anyhow!(
-            "bind mount failed: {} -> {} ({})",
-            src.display(),
-            dst.display(),
-            std::io::Error::last_os_error()
-        ));
+/// Collect all entries below `current` as paths relative to `base`.
+pub fn collect_entries(base: &Path, current: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(current)? {
+        let path = entry?.path();
+        out.push(path.strip_prefix(base)?.to_path_buf());
+
+        if fs::symlink_metadata(&path)?.is_dir() {
+            collect_entries(base, &path, out)?;
+        }
     }
 
     Ok(())
 }
 
-fn walk_and_bind_files(base_system_dir: &Path, current_dir: &Path) -> Result<()> {
-    for entry in fs::read_dir(current_dir)? {
-        let entry = entry?;
-        let src_path = entry.path();
-        let meta = fs::symlink_metadata(&src_path)?;
-
-        let rel = src_path
-            .strip_prefix(base_system_dir)
-            .context("strip prefix failed")?;
-        let dst_path = Path::new("/").join(rel);
+/// Zip the contents of `src_dir` (entries at the archive root).
+///
+/// With `reproducible`, entries are sorted, timestamps fixed to the zip epoch
+/// and permissions normalized to 0644/0755, so identical sources produce
+/// byte-identical archives.
+pub fn zip_dir(
+    src_dir: &Path,
+    output: &Path,
+    method: CompressionMethod,
+    reproducible: bool,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    collect_entries(src_dir, src_dir, &mut entries)?;
+    if reproducible {
+        entries.sort();
+    }
 
-        if meta.is_dir() {
-            // If the directory does not exist on /, prune the subtree
-            if !dst_path.exists() {
-                warn!(
-                    "directory {:?} does not exist on /, skipping subtree",
-                    dst_path
-                );
-                continue;
-            }
+    // never pack the archive into itself
+    let output_abs = std::path::absolute(output)?;
 
-            // Recurse, but DO NOT bind the directory itself
-            walk_and_bind_files(base_system_dir, &src_path)?;
+    let mut zip = ZipWriter::new(File::create(output)?);
+    for rel in entries {
+        let path = src_dir.join(&rel);
+        if std::path::absolute(&path)? == output_abs {
             continue;
         }
 
-        if meta.is_file() {
-            // Target file must already exist on readonly root
-            if !dst_path.exists() {
-                warn!("file {:?} does not exist on /, skipping", dst_path);
-                continue;
-            }
+        let meta = fs::symlink_metadata(&path)?;
+        let name = rel.to_string_lossy();
+        let mode = meta.permissions().mode() & 0o7777;
 
-            bind_mount_file(&src_path, &dst_path)?;
-            continue;
+        let mut options = SimpleFileOptions::default().compression_method(method);
+        if reproducible {
+            let mode = if meta.is_dir() || mode & 0o111 != 0 {
+                0o755
+            } else {
+                0o644
+            };
+            options = options
+                .last_modified_time(DateTime::default())
+                .unix_permissions(mode);
+        } else {
+            options = options.unix_permissions(mode);
         }
 
-        // Skip symlinks, devices, sockets, fifos, etc.
-        warn!("skipping unsupported entry {:?}", src_path);
+        if meta.is_dir() {
+            zip.add_directory(name, options)?;
+        } else if meta.is_file() {
+            zip.start_file(name, options)?;
+            std::io::copy(&mut File::open(&path)?, &mut zip)?;
+        } else if meta.is_symlink() {
+            let target = fs::read_link(&path)?;
+            zip.add_symlink(name, target.to_string_lossy(), options)?;
+        } else {
+            strict::warn_or_bail!("skipping unsupported entry {path:?}");
+        }
     }
+    zip.finish()?;
 
     Ok(())
 }
 
-pub fn mount_module(module_dir: &Path) -> Result<()> {
+/// Zip a module source directory with its contents at the archive root,
+/// refusing layouts and modules `module lint` finds errors in. With `key`,
+/// the directory is signed first.
+pub fn pack_module(
+    src_dir: &Path,
+    output: &Path,
+    reproducible: bool,
+    key: Option<&Path>,
+) -> Result<()> {
+    let prop_path = src_dir.join("module.prop");
+    if !prop_path.is_file() {
+        // the usual mistake: zipping the folder instead of its contents
+        let nested = fs::read_dir(src_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| path.join("module.prop").is_file());
+        match nested {
+            Some(nested) => {
+                bail!("{src_dir:?} has its module.prop in {nested:?}, pack that directory instead")
+            }
+            None => bail!("{src_dir:?} does not contain a module.prop"),
+        }
+    }
+
+    let report = lint::lint(&src_dir.to_string_lossy())?;
+    if !report.findings.is_empty() {
+        lint::print_report(&report);
+    }
+    if report.errors() > 0 {
+        bail!(
+            "{src_dir:?} has {} lint error(s), not packing it",
+            report.errors()
+        );
+    }
+
+    if let Some(key) = key {
+        let public = signing::sign(src_dir, key)?;
+        info!("signed {src_dir:?}, public key {public}");
+    }
+    zip_dir(src_dir, output, CompressionMethod::Deflated, reproducible)
+}
+
+/// Zip an installed module back into an installable archive, leaving out
+/// flags and the hash manifest and expanding a compressed payload.
+pub fn export_module(module_id: &str, output: &Path) -> Result<()> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
     if !module_dir.is_dir() {
-        bail!("module dir does not exist");
+        bail!("module {module_id} is not installed");
     }
 
-    let system_dir = module_dir.join("system");
-    if !system_dir.is_dir() {
-        bail!("system dir does not exist or is invalid");
+    let scratch = tempdir()?;
+    let copy = scratch.path().join(module_id);
+    copy_dir(&module_dir, &copy)?;
+    for entry in fs::read_dir(&copy)? {
+        let entry = entry?;
+        if integrity::is_bookkeeping(Path::new(&entry.file_name())) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    if copy.join(storage::COMPRESSED_PAYLOAD).is_file() {
+        storage::decompress(&copy)?;
     }
 
-    walk_and_bind_files(&system_dir, &system_dir)?;
-    Ok(())
+    zip_dir(&copy, output, CompressionMethod::Deflated, false)
+}
+
+/// Version from a module's `module.prop`, without further validation.
+pub fn module_version(module_dir: &Path) -> Option<i64> {
+    parse_prop_file(&module_dir.join("module.prop"))
+        .ok()?
+        .get("version")?
+        .parse()
+        .ok()
+}
+
+/// Mount priority from a module's `module.prop`, 0 when unset or invalid.
+pub fn mount_priority(module_dir: &Path) -> i32 {
+    parse_prop_file(&module_dir.join("module.prop"))
+        .ok()
+        .and_then(|props| props.get("priority")?.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Sort key putting module directories in mount order: descending
+/// `priority`, then by id. Where modules provide the same path, the one
+/// mounted last stays visible.
+pub fn mount_order_key(module_dir: &Path) -> (Reverse<i32>, OsString) {
+    (
+        Reverse(mount_priority(module_dir)),
+        module_dir.file_name().unwrap_or_default().to_os_string(),
+    )
+}
+
+/// Ids of installed and pending modules, sorted and deduplicated.
+pub fn module_ids() -> Vec<String> {
+    let mut ids: Vec<String> = [MODULES_DIR, MODULES_UPDATE_DIR]
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Existing module ids, installed or pending, that differ from `id` only in
+/// case. Such modules would collide on case-insensitive transfers and in
+/// mount targets, so installs must not create them.
+pub fn case_collisions(id: &str) -> Vec<String> {
+    module_ids()
+        .into_iter()
+        .filter(|other| other != id && other.eq_ignore_ascii_case(id))
+        .collect()
+}
+
+/// Groups of installed or pending module ids that only differ in case.
+pub fn all_case_collisions() -> Vec<Vec<String>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for id in module_ids() {
+        groups.entry(id.to_ascii_lowercase()).or_default().push(id);
+    }
+    groups.into_values().filter(|ids| ids.len() > 1).collect()
+}
+
+/// Ids of installed modules, sorted.
+pub fn installed_ids() -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(MODULES_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Whether an installed module will be initialized at boot.
+pub fn is_enabled(module_id: &str) -> bool {
+    !Path::new(MODULES_DIR)
+        .join(module_id)
+        .join("disable.flag")
+        .exists()
+}
+
+/// Volatile flag that enables a disabled module for the current boot only.
+fn until_reboot_flag(module_id: &str) -> PathBuf {
+    Path::new(RUN_STATE_DIR).join("enabled").join(module_id)
+}
+
+/// Whether a module is disabled for this boot: it has a `disable.flag` and
+/// was not enabled until reboot.
+pub fn disabled_this_boot(module_dir: &Path) -> bool {
+    let id = module_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("");
+    module_dir.join("disable.flag").exists() && !until_reboot_flag(id).exists()
+}
+
+/// Enable a disabled module until the next reboot, leaving its persistent
+/// state alone. Returns whether anything changed.
+pub fn enable_until_reboot(module_id: &str) -> Result<bool> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
+    if !module_dir.is_dir() {
+        bail!("module {module_id} is not installed");
+    }
+    if !disabled_this_boot(&module_dir) {
+        return Ok(false);
+    }
+
+    let flag = until_reboot_flag(module_id);
+    create_dir_all(flag.parent().expect("flag has a parent"))?;
+    fs::write(flag, "")?;
+    Ok(true)
+}
+
+/// Drop every until-reboot enablement.
+pub fn clear_until_reboot() -> Result<()> {
+    delete_dir(&Path::new(RUN_STATE_DIR).join("enabled"))
+}
+
+/// Enable or disable an installed or pending module through its
+/// `disable.flag`. Disabling also ends an until-reboot enablement. Returns
+/// whether anything changed.
+pub fn set_enabled(module_id: &str, enabled: bool) -> Result<bool> {
+    // a pending update replaces the installed copy at the next boot, so
+    // it carries the flag too
+    let dirs: Vec<PathBuf> = [MODULES_DIR, MODULES_UPDATE_DIR]
+        .iter()
+        .map(|dir| Path::new(dir).join(module_id))
+        .filter(|dir| dir.is_dir())
+        .collect();
+    if dirs.is_empty() {
+        bail!("module {module_id} is not installed");
+    }
+
+    let mut changed = false;
+    for dir in dirs {
+        let flag = dir.join("disable.flag");
+        match (enabled, flag.exists()) {
+            (true, true) => fs::remove_file(&flag)?,
+            (false, false) => fs::write(&flag, "")?,
+            _ => continue,
+        }
+        changed = true;
+    }
+
+    let until_reboot = until_reboot_flag(module_id);
+    if !enabled && until_reboot.exists() {
+        fs::remove_file(until_reboot)?;
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// One module of `module list --json`.
+#[derive(Serialize)]
+pub struct ModuleSummary {
+    pub id: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// Whether it is enabled, or for a pending module whether it lands
+    /// enabled
+    pub enabled: bool,
+    /// `priority` from module.prop; the list is in mount order
+    pub priority: i32,
+}
+
+#[derive(Serialize)]
+pub struct ModuleListing {
+    pub installed: Vec<ModuleSummary>,
+    pub pending_update: Vec<ModuleSummary>,
+}
+
+/// Module directories in `dir`, in mount order.
+fn dirs_in_mount_order(dir: &str) -> io::Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    dirs.sort_by_cached_key(|dir| mount_order_key(dir));
+    Ok(dirs)
+}
+
+/// Modules with a valid module.prop in `dir`, in mount order.
+pub fn list(dir: &str) -> Result<Vec<ModuleSummary>> {
+    let mut modules = Vec::new();
+    for path in dirs_in_mount_order(dir)? {
+        let Ok(mut props) = read_module_prop(&path.join("module.prop")) else {
+            continue;
+        };
+        let Some(id) = props.remove("id") else {
+            continue;
+        };
+
+        modules.push(ModuleSummary {
+            enabled: !path.join("disable.flag").exists(),
+            priority: props
+                .get("priority")
+                .and_then(|priority| priority.parse().ok())
+                .unwrap_or(0),
+            id,
+            name: props.remove("name"),
+            version: props.remove("version"),
+            description: props.remove("description"),
+        });
+    }
+
+    Ok(modules)
 }
 
 pub fn list_modules(dir: &str, label: &str) {
     info!("{label}");
-    match fs::read_dir(dir) {
-        Ok(entries) => {
-            let mut found = false;
-            for entry in entries.filter_map(|entry| entry.ok()) {
-                let prop_path = entry.path().join("module.prop");
-                if prop_path.exists() {
-                    if let Ok(m) = read_module_prop(&prop_path) {
-                        info!(
-                            "{} - {} v{} ({})",
-                            m.get("id").unwrap_or(&"?".to_string()),
-                            m.get("name").unwrap_or(&"?".to_string()),
-                            m.get("version").unwrap_or(&"?".to_string()),
-                            m.get("description").unwrap_or(&"".to_string())
-                        );
-                        found = true;
-                    }
+    match dirs_in_mount_order(dir) {
+        Ok(paths) => {
+            let mut found = 0;
+            for path in paths {
+                let prop_path = path.join("module.prop");
+                if prop_path.exists()
+                    && let Ok(m) = read_module_prop(&prop_path)
+                {
+                    let disabled = if path.join("disable.flag").exists() {
+                        " [disabled]"
+                    } else {
+                        ""
+                    };
+                    let priority = match m.get("priority") {
+                        Some(priority) if priority != "0" => format!(" [priority {priority}]"),
+                        _ => String::new(),
+                    };
+                    found += 1;
+                    info!(
+                        "{found}. {} - {} v{} ({}){priority}{disabled}",
+                        m.get("id").unwrap_or(&"?".to_string()),
+                        m.get("name").unwrap_or(&"?".to_string()),
+                        m.get("version").unwrap_or(&"?".to_string()),
+                        m.get("description").unwrap_or(&"".to_string())
+                    );
                 }
             }
-            if !found {
+            if found == 0 {
                 info!("  (no modules found)");
             }
         }
@@ -264,3 +993,420 @@ pub fn list_modules(dir: &str, label: &str) {
         }
     }
 }
+
+/// Details of one module for `module info`.
+#[derive(Serialize)]
+pub struct ModuleInfo {
+    pub props: BTreeMap<String, String>,
+    pub enabled: bool,
+    pub update_pending: bool,
+    pub uninstall_pending: bool,
+    /// Whether any of its payload is mounted right now
+    pub mounted: bool,
+    /// Why mounting it failed, until it mounts again
+    pub mount_failure: Option<MountFailure>,
+    /// Files its payload places on the root filesystem
+    pub overrides: Vec<PathBuf>,
+    pub trust: TrustStatus,
+    pub scripts: Vec<ScriptResult>,
+}
+
+/// Whether any of a module's payload is mounted right now.
+pub fn is_mounted(module_id: &str) -> bool {
+    mount::mounts_of(module_id).is_ok_and(|mounts| !mounts.is_empty())
+}
+
+/// Target paths of the files in a module's payload roots, e.g.
+/// `system/bin/foo` becomes `/bin/foo`. Compressed payloads are read from
+/// their archive.
+pub fn payload_targets(module_dir: &Path) -> Result<Vec<PathBuf>> {
+    let scratch = tempdir()?;
+    let dir = storage::preview(module_dir, scratch.path())?;
+
+    let mut targets = Vec::new();
+    for (payload, root) in PAYLOAD_ROOTS {
+        let src_root = dir.join(payload);
+        if !src_root.is_dir() {
+            continue;
+        }
+
+        let mut entries = Vec::new();
+        collect_entries(&src_root, &src_root, &mut entries)?;
+        targets.extend(
+            entries
+                .into_iter()
+                .filter(|rel| {
+                    fs::symlink_metadata(src_root.join(rel)).is_ok_and(|meta| !meta.is_dir())
+                })
+                .map(|rel| Path::new(root).join(rel)),
+        );
+    }
+
+    targets.sort();
+    Ok(targets)
+}
+
+pub fn info(module_id: &str) -> Result<ModuleInfo> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
+    if !module_dir.is_dir() {
+        bail!("module {module_id} is not installed");
+    }
+
+    let props = parse_prop_file(&module_dir.join("module.prop"))?;
+    Ok(ModuleInfo {
+        trust: trust::status(&props)?,
+        props: props.into_iter().collect(),
+        enabled: is_enabled(module_id),
+        update_pending: Path::new(MODULES_UPDATE_DIR).join(module_id).is_dir(),
+        uninstall_pending: module_dir.join("uninstall.flag").exists(),
+        mounted: is_mounted(module_id),
+        mount_failure: mount::mount_failures().remove(module_id),
+        overrides: payload_targets(&module_dir)?,
+        scripts: script_results(module_id),
+    })
+}
+
+pub fn print_info(info: &ModuleInfo) {
+    let prop = |key: &str| info.props.get(key).map_or("?", String::as_str);
+    info!("{} - {} v{}", prop("id"), prop("name"), prop("version"));
+    info!("  {}", prop("description"));
+    let trust = match info.trust {
+        TrustStatus::Trusted => "trusted",
+        TrustStatus::Untrusted => "untrusted",
+        TrustStatus::Unsigned => "no author key",
+    };
+    match info.props.get("author_key") {
+        Some(key) => info!("  author: {} ({key}, {trust})", prop("author")),
+        None => info!("  author: {} ({trust})", prop("author")),
+    }
+    info!(
+        "  enabled: {}, update pending: {}, uninstall pending: {}, mounted: {}",
+        info.enabled, info.update_pending, info.uninstall_pending, info.mounted
+    );
+    if let Some(failure) = &info.mount_failure {
+        warn!(
+            "  mount failed ({}, {} time(s) in a row, last at {}): {}",
+            failure.kind.as_str(),
+            failure.count,
+            failure.at,
+            failure.error
+        );
+    }
+
+    info!("  module.prop:");
+    for (key, value) in &info.props {
+        info!("    {key}={value}");
+    }
+
+    info!("  overrides {} file(s):", info.overrides.len());
+    for target in &info.overrides {
+        info!("    {}", target.display());
+    }
+
+    if info.scripts.is_empty() {
+        info!("  no script runs recorded");
+    }
+    for result in &info.scripts {
+        let outcome = match result.exit_code {
+            Some(0) => "ok".to_string(),
+            Some(code) => format!("exit code {code}"),
+            None => "killed".to_string(),
+        };
+        info!(
+            "  {}: {outcome} after {} ms, at {}",
+            result.script, result.duration_ms, result.finished
+        );
+        if !result.success() {
+            for line in &result.output {
+                warn!("    | {line}");
+            }
+        }
+    }
+}
+
+/// What `module grep` looks at.
+pub struct GrepOptions {
+    pub ignore_case: bool,
+    /// Also search the contents of text files
+    pub content: bool,
+    /// Files larger than this are not searched for content
+    pub max_size: u64,
+}
+
+/// A module file whose path, or one of whose lines, matches `module grep`.
+#[derive(Serialize)]
+pub struct GrepMatch {
+    pub module_id: String,
+    pub pending_update: bool,
+    /// Relative to the module directory
+    pub path: PathBuf,
+    /// Number and text of the matching line, for content matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<(usize, String)>,
+}
+
+/// Search the files of installed and pending modules for `pattern`, a plain
+/// substring. Compressed payloads are searched inside their archive.
+pub fn grep(pattern: &str, options: &GrepOptions) -> Result<Vec<GrepMatch>> {
+    let fold = |text: &str| {
+        if options.ignore_case {
+            text.to_lowercase()
+        } else {
+            text.to_string()
+        }
+    };
+    let needle = fold(pattern);
+
+    let mut matches = Vec::new();
+    for (dir, pending_update) in [(MODULES_DIR, false), (MODULES_UPDATE_DIR, true)] {
+        let mut module_dirs: Vec<PathBuf> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        module_dirs.sort();
+
+        for module_dir in module_dirs {
+            let module_id = module_dir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            let mut files = Vec::new();
+            collect_entries(&module_dir, &module_dir, &mut files)?;
+            let mut sources: Vec<_> = files
+                .into_iter()
+                .map(|rel| (module_dir.join(&rel), rel))
+                .collect();
+
+            // a compressed payload is searched as the `system/` it stands for
+            let scratch = tempdir()?;
+            let preview = storage::preview(&module_dir, scratch.path())?;
+            if preview != module_dir {
+                let mut files = Vec::new();
+                collect_entries(&preview, &preview.join("system"), &mut files)?;
+                sources.extend(files.into_iter().map(|rel| (preview.join(&rel), rel)));
+            }
+            sources.sort_by(|a, b| a.1.cmp(&b.1));
+
+            for (full, rel) in sources {
+                let found = |line| GrepMatch {
+                    module_id: module_id.clone(),
+                    pending_update,
+                    path: rel.clone(),
+                    line,
+                };
+                if fold(&rel.to_string_lossy()).contains(&needle) {
+                    matches.push(found(None));
+                }
+
+                if !options.content {
+                    continue;
+                }
+                let Ok(meta) = fs::symlink_metadata(&full) else {
+                    continue;
+                };
+                if !meta.is_file() || meta.len() > options.max_size {
+                    continue;
+                }
+                let content = fs::read(&full)?;
+                // binary files would only produce noise
+                if content.contains(&0) {
+                    continue;
+                }
+                for (number, line) in String::from_utf8_lossy(&content).lines().enumerate() {
+                    if fold(line).contains(&needle) {
+                        matches.push(found(Some((number + 1, line.to_string()))));
+                    }
+                }
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// Print grep matches one per line, `id: path` or `id: path:line: text`.
+pub fn print_grep(matches: &[GrepMatch]) -> Result<()> {
+    let mut out = io::stdout().lock();
+    for found in matches {
+        let pending = if found.pending_update {
+            " (pending update)"
+        } else {
+            ""
+        };
+        match &found.line {
+            Some((number, text)) => writeln!(
+                out,
+                "{}{pending}: {}:{number}: {text}",
+                found.module_id,
+                found.path.display()
+            )?,
+            None => writeln!(
+                out,
+                "{}{pending}: {}",
+                found.module_id,
+                found.path.display()
+            )?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str =
+        "id=example\nname=Example\ndescription=An example\nversion=3\nskip_mount=false\n";
+
+    /// Write `content` as the module.prop of a module directory `dir_name`.
+    fn prop_file(scratch: &Path, dir_name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let dir = scratch.join(dir_name);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("module.prop");
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn read_error(content: &str) -> String {
+        let scratch = tempdir().unwrap();
+        let path = prop_file(scratch.path(), "example", content);
+        format!("{:#}", read_module_prop(&path).unwrap_err())
+    }
+
+    #[test]
+    fn parse_prop_file_tolerates_odd_lines() {
+        let scratch = tempdir().unwrap();
+        let path = prop_file(
+            scratch.path(),
+            "example",
+            "# comment=ignored\r\n  id = example \r\nno separator\n\n=empty key\nurl=https://x/?a=b\nid=again\n",
+        );
+        let props = parse_prop_file(&path).unwrap();
+
+        assert_eq!(props["id"], "again");
+        assert_eq!(props["url"], "https://x/?a=b");
+        assert_eq!(props[""], "empty key");
+        assert!(!props.contains_key("# comment"));
+        assert_eq!(props.len(), 3);
+    }
+
+    #[test]
+    fn parse_prop_file_rejects_unreadable_input() {
+        let scratch = tempdir().unwrap();
+        let binary = prop_file(scratch.path(), "binary", [b'i', b'd', b'=', 0xff, 0xfe]);
+        assert!(parse_prop_file(&binary).is_err());
+
+        let huge = prop_file(
+            scratch.path(),
+            "huge",
+            vec![b'#'; MAX_PROP_FILE_SIZE as usize + 1],
+        );
+        assert!(parse_prop_file(&huge).is_err());
+
+        assert!(parse_prop_file(&scratch.path().join("missing/module.prop")).is_err());
+    }
+
+    #[test]
+    fn read_module_prop_accepts_a_valid_prop() {
+        let scratch = tempdir().unwrap();
+        let without_skip = VALID.replace("skip_mount=false\n", "priority=-2\npath_dirs=bin\n");
+        let path = prop_file(scratch.path(), "example", without_skip);
+        let props = read_module_prop(&path).unwrap();
+
+        assert_eq!(props["version"], "3");
+        assert_eq!(props["skip_mount"], "false");
+    }
+
+    #[test]
+    fn read_module_prop_rejects_malformed_props() {
+        let cases = [
+            (
+                VALID.replace("id=example\n", ""),
+                "missing required property: id",
+            ),
+            (
+                VALID.replace("name=Example", "name=  "),
+                "name cannot be empty",
+            ),
+            (
+                VALID.replace("version=3", "version=3.1"),
+                "version must be a valid integer",
+            ),
+            (
+                VALID.replace("version=3", "version=99999999999"),
+                "version must be a valid integer",
+            ),
+            (
+                VALID.replace("skip_mount=false", "skip_mount=yes"),
+                "skip_mount",
+            ),
+            (format!("{VALID}mount=sideways\n"), "property mount"),
+            (
+                format!("{VALID}priority=high\n"),
+                "priority must be a valid integer",
+            ),
+            (format!("{VALID}path_dirs=../bin\n"), "path_dirs is invalid"),
+            (format!("{VALID}path_dirs=/bin\n"), "path_dirs is invalid"),
+            (format!("{VALID}path_dirs=bin'x\n"), "path_dirs is invalid"),
+            (
+                format!("{VALID}updateJson=file:///etc/passwd\n"),
+                "updateJson",
+            ),
+            (format!("{VALID}author_key=nothex\n"), "author_key"),
+        ];
+        for (content, expected) in cases {
+            let error = read_error(&content);
+            assert!(error.contains(expected), "{content:?} gave `{error}`");
+        }
+    }
+
+    #[test]
+    fn read_module_prop_rejects_bad_ids() {
+        for id in ["../escape", "with space", "dash-ed", "", "Modules", "ünï"] {
+            assert!(validate_module_id(id).is_err(), "id {id:?} was accepted");
+        }
+        assert!(validate_module_id("Example_2").is_ok());
+        let long = "a".repeat(MAX_MODULE_ID_LEN + 1);
+        assert!(validate_module_id(&long).is_err());
+        assert!(validate_module_id(&"a".repeat(MAX_MODULE_ID_LEN)).is_ok());
+    }
+
+    #[test]
+    fn read_module_prop_needs_the_id_to_match_its_directory() {
+        let scratch = tempdir().unwrap();
+        let path = prop_file(scratch.path(), "other", VALID);
+        let error = format!("{:#}", read_module_prop(&path).unwrap_err());
+        assert!(error.contains("does not match directory name"), "{error}");
+    }
+
+    #[test]
+    fn mount_order_puts_the_highest_priority_first() {
+        let scratch = tempdir().unwrap();
+        let mut dirs = Vec::new();
+        for (id, priority) in [
+            ("b_high", "5"),
+            ("a_low", "-1"),
+            ("d_plain", ""),
+            ("c_plain", ""),
+        ] {
+            let prop = if priority.is_empty() {
+                VALID.to_string()
+            } else {
+                format!("{VALID}priority={priority}\n")
+            };
+            dirs.push(
+                prop_file(scratch.path(), id, prop)
+                    .parent()
+                    .unwrap()
+                    .to_path_buf(),
+            );
+        }
+        dirs.sort_by_cached_key(|dir| mount_order_key(dir));
+
+        let order: Vec<_> = dirs.iter().map(|dir| dir.file_name().unwrap()).collect();
+        assert_eq!(order, ["b_high", "c_plain", "d_plain", "a_low"]);
+    }
+}
@@ -0,0 +1,1363 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow, bail};
+use libc::{MS_BIND, MS_RDONLY, MS_REC, c_ulong};
+use serde::{Deserialize, Serialize};
+use tracing::{Level, info, warn};
+
+use crate::capabilities;
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::config::{GuardConfig, ModuleConfig, MountMode};
+use crate::critical;
+use crate::defs::{
+    BIN_DIR, FUSE_OVERLAY_HELPER, LOCALE_DIR, MODULES_DIR, MOUNT_FAILURES_FILE, MOUNTS_FILE,
+    PAYLOAD_ROOTS, RUN_STATE_DIR,
+};
+use crate::integrity;
+use crate::logging::{Heartbeat, ModuleLog};
+use crate::module;
+use crate::process;
+use crate::state;
+use crate::storage::{self, COMPRESSED_PAYLOAD};
+use crate::strict;
+
+/// Number of example paths kept per warning kind for the summary.
+const WARNING_EXAMPLES: usize = 3;
+/// Kernel log lines attached to a failed mount.
+const KERNEL_LOG_LINES: usize = 20;
+/// File marking a module directory that replaces its target as a whole.
+const REPLACE_MARKER: &str = ".replace";
+/// Prefix of the zero-byte files hiding the entry named by the rest.
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// Locale tree mounted when none matches the device language.
+const LOCALE_FALLBACK: &str = "default";
+/// Variables the device language is read from, in order of precedence.
+const LOCALE_ENV: &[&str] = &["LC_ALL", "LC_MESSAGES", "LANG"];
+
+/// Repeated mount warnings, summarized once per kind after a module is
+/// mounted. Every occurrence still goes to the module's own log.
+#[derive(Default)]
+pub struct MountWarnings {
+    entries: Vec<(&'static str, PathBuf)>,
+}
+
+impl MountWarnings {
+    fn add(&mut self, kind: &'static str, path: &Path) {
+        self.entries.push((kind, path.to_path_buf()));
+    }
+
+    /// Occurrence count and a few example paths per warning kind.
+    pub fn by_kind(&self) -> BTreeMap<&'static str, (usize, Vec<&Path>)> {
+        let mut kinds: BTreeMap<_, (usize, Vec<&Path>)> = BTreeMap::new();
+        for (kind, path) in &self.entries {
+            let (count, examples) = kinds.entry(*kind).or_default();
+            *count += 1;
+            if examples.len() < WARNING_EXAMPLES {
+                examples.push(path);
+            }
+        }
+        kinds
+    }
+
+    fn report(&self, module_id: &str, log: &mut ModuleLog) {
+        for (kind, path) in &self.entries {
+            log.write(Level::WARN, &format!("{kind}: {path:?}"));
+        }
+
+        for (kind, (count, examples)) in self.by_kind() {
+            warn!(
+                "module {module_id}: {count} x {kind}, e.g. {examples:?} (details in {:?})",
+                log.path()
+            );
+        }
+    }
+}
+
+/// What a mounter would do for a module, worked out without touching `/`.
+#[derive(Default)]
+pub struct MountPlan {
+    /// `(source in module, target on /)` pairs
+    pub targets: Vec<(PathBuf, PathBuf)>,
+    /// `(source in module, target on /)` pairs for files and directories
+    /// absent from `/`, made to appear by `magic_mount`
+    pub additions: Vec<(PathBuf, PathBuf)>,
+    /// Paths on `/` that whiteouts hide
+    pub hidden: Vec<PathBuf>,
+    pub warnings: MountWarnings,
+}
+
+/// A way of making a module's `system/` tree visible on `/`.
+///
+/// Planning is separate from applying so the plan can be previewed, and so
+/// a fake mounter can record what would have been mounted.
+pub trait Mounter {
+    fn name(&self) -> &'static str;
+
+    /// Whether the running kernel and firmware support this backend.
+    fn available(&self) -> bool {
+        true
+    }
+
+    /// Add the mounts for payload tree `src_root`, mapped onto `dst_root`,
+    /// to `plan` without changing anything.
+    fn plan(&self, src_root: &Path, dst_root: &Path, plan: &mut MountPlan) -> Result<()>;
+
+    /// Carry out a plan produced by `plan`.
+    fn apply(&self, plan: &MountPlan) -> Result<()>;
+
+    /// Shell command equivalent to `apply` for one planned target.
+    fn shell_command(&self, src: &Path, dst: &Path) -> String;
+}
+
+/// Backends tried, in order, when the configured one is unavailable.
+const FALLBACKS: &[MountMode] = &[MountMode::Overlay, MountMode::Fuse, MountMode::Files];
+
+fn backend(mode: MountMode) -> Box<dyn Mounter> {
+    match mode {
+        MountMode::Files => Box::new(BindFiles),
+        MountMode::Dirs => Box::new(BindDirs),
+        MountMode::Overlay => Box::new(Overlay),
+        MountMode::Fuse => Box::new(FuseOverlay),
+        MountMode::Copy => Box::new(Copy),
+    }
+}
+
+/// Mount mode of a module: the configured one, else its `mount=` prop.
+pub fn module_mode(module_config: &ModuleConfig, module_dir: &Path) -> MountMode {
+    module_config.mount.unwrap_or_else(|| {
+        module::parse_prop_file(&module_dir.join("module.prop"))
+            .ok()
+            .and_then(|props| props.get("mount").and_then(|m| MountMode::from_name(m)))
+            .unwrap_or_default()
+    })
+}
+
+/// The backend for `mode`, or the first available fallback.
+pub fn mounter(mode: MountMode) -> Box<dyn Mounter> {
+    let preferred = backend(mode);
+    if preferred.available() {
+        return preferred;
+    }
+
+    for &fallback in FALLBACKS {
+        let candidate = backend(fallback);
+        if candidate.available() {
+            warn!(
+                "{} mounts are not available, using {} instead",
+                preferred.name(),
+                candidate.name()
+            );
+            return candidate;
+        }
+    }
+
+    preferred
+}
+
+/* =========================
+ * Backends
+ * ========================= */
+
+/// Bind every file over its existing counterpart on the read-only root.
+pub struct BindFiles;
+
+impl Mounter for BindFiles {
+    fn name(&self) -> &'static str {
+        "bind files"
+    }
+
+    fn plan(&self, src_root: &Path, dst_root: &Path, plan: &mut MountPlan) -> Result<()> {
+        walk_files(src_root, dst_root, src_root, plan)
+    }
+
+    fn apply(&self, plan: &MountPlan) -> Result<()> {
+        // replicas first, so overrides inside them land on the replica
+        magic_mount(plan)?;
+        for (src, dst) in &plan.targets {
+            bind_mount(src, dst)?;
+        }
+        Ok(())
+    }
+
+    fn shell_command(&self, src: &Path, dst: &Path) -> String {
+        format!("mount --bind {} {}", quote(src), quote(dst))
+    }
+}
+
+/// Bind whole directories; the module has to carry their complete contents.
+pub struct BindDirs;
+
+impl Mounter for BindDirs {
+    fn name(&self) -> &'static str {
+        "bind directories"
+    }
+
+    fn plan(&self, src_root: &Path, dst_root: &Path, plan: &mut MountPlan) -> Result<()> {
+        walk_dirs(src_root, dst_root, src_root, plan)
+    }
+
+    fn apply(&self, plan: &MountPlan) -> Result<()> {
+        for (src, dst) in &plan.targets {
+            bind_mount(src, dst)?;
+        }
+        Ok(())
+    }
+
+    fn shell_command(&self, src: &Path, dst: &Path) -> String {
+        format!("mount --bind {} {}", quote(src), quote(dst))
+    }
+}
+
+/// Read-only overlayfs per directory, the module layered above the original.
+pub struct Overlay;
+
+impl Mounter for Overlay {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn available(&self) -> bool {
+        capabilities::get().overlayfs
+    }
+
+    fn plan(&self, src_root: &Path, dst_root: &Path, plan: &mut MountPlan) -> Result<()> {
+        walk_dirs(src_root, dst_root, src_root, plan)
+    }
+
+    fn apply(&self, plan: &MountPlan) -> Result<()> {
+        for (src, dst) in &plan.targets {
+            info!("overlaying {src:?} on {dst:?}");
+            // overlays cannot nest more than two deep, so a directory that
+            // another module already overlays gets one overlay of both
+            let lower = match scriba_overlay_lowerdir(dst)? {
+                Some(lower) => {
+                    info!("{dst:?} is overlaid already, stacking {src:?} on its layers");
+                    unmount(dst)?;
+                    lower
+                }
+                None => dst.display().to_string(),
+            };
+            let data = format!("lowerdir={}:{lower}", src.display());
+            sys_mount(
+                Path::new("overlay"),
+                dst,
+                Some("overlay"),
+                MS_RDONLY,
+                Some(&data),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn shell_command(&self, src: &Path, dst: &Path) -> String {
+        let options = format!("ro,lowerdir={}:{}", src.display(), dst.display());
+        format!(
+            "mount -t overlay overlay -o {} {}",
+            quote(Path::new(&options)),
+            quote(dst)
+        )
+    }
+}
+
+/// Overlay through the userspace fuse-overlayfs helper, for kernels
+/// built without overlayfs.
+pub struct FuseOverlay;
+
+impl FuseOverlay {
+    /// The bundled helper, else one on `PATH`.
+    pub fn helper() -> Option<PathBuf> {
+        let bundled = Path::new(BIN_DIR).join(FUSE_OVERLAY_HELPER);
+        if bundled.is_file() {
+            return Some(bundled);
+        }
+
+        std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(FUSE_OVERLAY_HELPER))
+                .find(|path| path.is_file())
+        })
+    }
+}
+
+impl Mounter for FuseOverlay {
+    fn name(&self) -> &'static str {
+        "fuse overlay"
+    }
+
+    fn available(&self) -> bool {
+        let capabilities = capabilities::get();
+        capabilities.fuse && capabilities.fuse_overlay_helper.is_some()
+    }
+
+    fn plan(&self, src_root: &Path, dst_root: &Path, plan: &mut MountPlan) -> Result<()> {
+        walk_dirs(src_root, dst_root, src_root, plan)
+    }
+
+    fn apply(&self, plan: &MountPlan) -> Result<()> {
+        let helper = Self::helper()
+            .ok_or_else(|| anyhow!("{FUSE_OVERLAY_HELPER} not found in {BIN_DIR} or PATH"))?;
+        let helper = helper.to_string_lossy();
+
+        for (src, dst) in &plan.targets {
+            info!("overlaying {src:?} on {dst:?} with {helper}");
+            let options = format!("ro,lowerdir={}:{}", src.display(), dst.display());
+            let dst = dst.to_string_lossy();
+            let status = process::run_with_output(&helper, &["-o", &options, &dst])?;
+            if !status.success() {
+                bail!("{helper} failed for {dst} ({status})");
+            }
+        }
+        Ok(())
+    }
+
+    fn shell_command(&self, src: &Path, dst: &Path) -> String {
+        let helper = Self::helper().unwrap_or_else(|| Path::new(BIN_DIR).join(FUSE_OVERLAY_HELPER));
+        let options = format!("ro,lowerdir={}:{}", src.display(), dst.display());
+        format!(
+            "{} -o {} {}",
+            quote(&helper),
+            quote(Path::new(&options)),
+            quote(dst)
+        )
+    }
+}
+
+/// Copy files over their targets, for locations that are writable anyway.
+pub struct Copy;
+
+impl Mounter for Copy {
+    fn name(&self) -> &'static str {
+        "copy"
+    }
+
+    fn plan(&self, src_root: &Path, dst_root: &Path, plan: &mut MountPlan) -> Result<()> {
+        walk_files(src_root, dst_root, src_root, plan)
+    }
+
+    fn apply(&self, plan: &MountPlan) -> Result<()> {
+        for path in &plan.hidden {
+            info!("removing {path:?}");
+            remove_entry(path)?;
+        }
+        for (src, dst) in &plan.targets {
+            info!("copying {src:?} to {dst:?}");
+            if src.is_dir() {
+                module::delete_dir(dst)?;
+                module::copy_dir(src, dst)?;
+            } else {
+                fs::copy(src, dst).with_context(|| format!("failed to copy {src:?} to {dst:?}"))?;
+            }
+        }
+        for (src, dst) in &plan.additions {
+            info!("adding {src:?} as {dst:?}");
+            if src.is_dir() {
+                module::copy_dir(src, dst)?;
+            } else {
+                fs::copy(src, dst).with_context(|| format!("failed to copy {src:?} to {dst:?}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn shell_command(&self, src: &Path, dst: &Path) -> String {
+        if src.is_dir() {
+            return format!("rm -rf {1} && cp -a {0} {1}", quote(src), quote(dst));
+        }
+        format!("cp {} {}", quote(src), quote(dst))
+    }
+}
+
+/* =========================
+ * Planning
+ * ========================= */
+
+/// Entries of `dir` in name order, with their target path below `dst_root`.
+fn entries(
+    src_root: &Path,
+    dst_root: &Path,
+    dir: &Path,
+) -> Result<Vec<(PathBuf, PathBuf, fs::Metadata)>> {
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let src_path = entry.path();
+            let meta = fs::symlink_metadata(&src_path)?;
+            let rel = src_path
+                .strip_prefix(src_root)
+                .context("strip prefix failed")?;
+            let dst_path = dst_root.join(rel);
+            Ok((src_path, dst_path, meta))
+        })
+        .collect()
+}
+
+fn walk_files(
+    src_root: &Path,
+    dst_root: &Path,
+    current_dir: &Path,
+    plan: &mut MountPlan,
+) -> Result<()> {
+    for (src_path, dst_path, meta) in entries(src_root, dst_root, current_dir)? {
+        let name = src_path.file_name().unwrap_or_default().to_string_lossy();
+        if meta.is_file() && name == REPLACE_MARKER {
+            plan.warnings.add(
+                "replace marker directly under a payload root, ignoring",
+                &src_path,
+            );
+            continue;
+        }
+        if meta.is_file()
+            && meta.len() == 0
+            && let Some(hidden) = name.strip_prefix(WHITEOUT_PREFIX)
+        {
+            let target = dst_path.with_file_name(hidden);
+            if dst_path.parent() == Some(Path::new("/")) {
+                plan.warnings
+                    .add("cannot hide entries of / itself, skipping", &target);
+            } else if fs::symlink_metadata(&target).is_err() {
+                plan.warnings
+                    .add("whiteout for an entry absent from /, skipping", &target);
+            } else {
+                plan.hidden.push(target);
+            }
+            continue;
+        }
+
+        if (meta.is_dir() || meta.is_file()) && !dst_path.exists() {
+            // the whole entry is new, its parent gets replicated to hold it
+            if dst_path.parent() == Some(Path::new("/")) {
+                plan.warnings
+                    .add("cannot add entries to / itself, skipping", &dst_path);
+            } else {
+                plan.additions.push((src_path, dst_path));
+            }
+            continue;
+        }
+
+        if meta.is_dir() {
+            if src_path.join(REPLACE_MARKER).is_file() {
+                // the module's directory stands in for the stock one
+                plan.targets.push((src_path, dst_path));
+                continue;
+            }
+
+            // Recurse, but DO NOT bind the directory itself
+            walk_files(src_root, dst_root, &src_path, plan)?;
+            continue;
+        }
+
+        if meta.is_file() {
+            plan.targets.push((src_path, dst_path));
+            continue;
+        }
+
+        // Skip symlinks, devices, sockets, fifos, etc.
+        plan.warnings.add("skipping unsupported entry", &src_path);
+    }
+
+    Ok(())
+}
+
+/// Pick the shallowest directories that hold files; everything below a
+/// picked directory is covered by mounting it.
+fn walk_dirs(
+    src_root: &Path,
+    dst_root: &Path,
+    current_dir: &Path,
+    plan: &mut MountPlan,
+) -> Result<()> {
+    for (src_path, dst_path, meta) in entries(src_root, dst_root, current_dir)? {
+        if !meta.is_dir() {
+            if current_dir == src_root {
+                plan.warnings
+                    .add("file directly under a payload root, skipping", &src_path);
+            }
+            continue;
+        }
+
+        if !dst_path.is_dir() {
+            plan.warnings
+                .add("directory does not exist on /, skipping subtree", &dst_path);
+            continue;
+        }
+
+        let holds_files = fs::read_dir(&src_path)?
+            .filter_map(|e| e.ok())
+            .any(|e| e.file_type().is_ok_and(|t| !t.is_dir()));
+        if holds_files {
+            plan.targets.push((src_path, dst_path));
+        } else {
+            walk_dirs(src_root, dst_root, &src_path, plan)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Language of the device: `configured`, else the first set locale variable.
+pub fn device_language(configured: Option<&str>) -> Option<String> {
+    configured.map(str::to_string).or_else(|| {
+        LOCALE_ENV
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+    })
+}
+
+/// `zh-CN` and `zh_cn.UTF-8@latin` alike become `zh_cn`.
+fn normalize_language(language: &str) -> String {
+    let language = language.split(['.', '@']).next().unwrap_or(language);
+    language.replace('-', "_").to_ascii_lowercase()
+}
+
+/// The `locale/<lang>/` tree of a module matching `language`: the full
+/// language, then its language part, then the fallback tree.
+fn locale_tree(locale_dir: &Path, language: Option<&str>) -> Result<Option<PathBuf>> {
+    let mut wanted = Vec::new();
+    if let Some(language) = language.map(normalize_language)
+        && !matches!(language.as_str(), "" | "c" | "posix")
+    {
+        if let Some((base, _)) = language.split_once('_') {
+            wanted.push(language.clone());
+            wanted.push(base.to_string());
+        } else {
+            wanted.push(language);
+        }
+    }
+    wanted.push(LOCALE_FALLBACK.to_string());
+
+    let mut trees = Vec::new();
+    for entry in fs::read_dir(locale_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            trees.push(entry.path());
+        }
+    }
+    Ok(wanted.iter().find_map(|want| {
+        trees
+            .iter()
+            .find(|tree| {
+                normalize_language(&tree.file_name().unwrap_or_default().to_string_lossy()) == *want
+            })
+            .cloned()
+    }))
+}
+
+/// Plan the mounts for every payload root (`system/`, `vendor/`, ...) of
+/// `module_dir`, and for its locale tree matching `language`. Roots missing
+/// on this firmware are skipped with a warning.
+pub fn plan_module(
+    module_dir: &Path,
+    mounter: &dyn Mounter,
+    language: Option<&str>,
+) -> Result<MountPlan> {
+    if !module_dir.is_dir() {
+        bail!("module dir does not exist");
+    }
+
+    let mut plan = MountPlan::default();
+    let mut found = false;
+    for (dir, root) in PAYLOAD_ROOTS {
+        let src_root = module_dir.join(dir);
+        if !src_root.is_dir() {
+            continue;
+        }
+        found = true;
+
+        let dst_root = Path::new(root);
+        if !dst_root.is_dir() {
+            plan.warnings.add(
+                "payload root does not exist on this firmware, skipping",
+                dst_root,
+            );
+            continue;
+        }
+
+        mounter.plan(&src_root, dst_root, &mut plan)?;
+    }
+
+    let locale_dir = module_dir.join(LOCALE_DIR);
+    if locale_dir.is_dir() {
+        found = true;
+        match locale_tree(&locale_dir, language)? {
+            Some(tree) => mounter.plan(&tree, Path::new("/"), &mut plan)?,
+            None => info!(
+                "no locale tree for {} and no {LOCALE_FALLBACK} tree, skipping locale/",
+                language.unwrap_or("an unknown language")
+            ),
+        }
+    }
+
+    if !found {
+        let dirs: Vec<_> = PAYLOAD_ROOTS.iter().map(|(dir, _)| *dir).collect();
+        bail!("module has no payload, expected one of {dirs:?}");
+    }
+
+    Ok(plan)
+}
+
+pub fn mount_module(
+    module_dir: &Path,
+    mounter: &dyn Mounter,
+    language: Option<&str>,
+    critical_paths: &[String],
+) -> Result<()> {
+    let plan = plan_module(module_dir, mounter, language)?;
+
+    let module_id = module_dir
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unknown");
+    let mut log = ModuleLog::open(module_id);
+    plan.warnings.report(module_id, &mut log);
+    if strict::enabled() && !plan.warnings.entries.is_empty() {
+        bail!(
+            "module {module_id}: {} path(s) would be skipped, not mounting in strict mode",
+            plan.warnings.entries.len()
+        );
+    }
+
+    critical::protect(module_id, &plan, mounter.name(), critical_paths)
+        .context("failed to back up critical paths, not mounting")?;
+
+    info!("mounting module {module_id} ({})", mounter.name());
+    let before = mount_table().unwrap_or_default();
+    let heartbeat = Heartbeat::background(format!(
+        "mounting module {module_id}, {} path(s)",
+        plan.targets.len() + plan.additions.len()
+    ));
+    let applied = mounter.apply(&plan);
+    drop(heartbeat);
+    // partial mounts are recorded too, so `module unmount` can clean up
+    if let Err(e) = record_mounts(module_id, &before) {
+        warn!("failed to record mounts of {module_id}: {e}");
+    }
+    let Err(err) = applied else {
+        record_outcome(module_id, None);
+        return Ok(());
+    };
+    let kind = classify(&err, mounter);
+    record_outcome(module_id, Some((kind, &err)));
+    // a missing path is retried later, the kernel has nothing to say on it
+    if is_missing_path(&err) {
+        return Err(err);
+    }
+
+    // errnos alone rarely explain vendor kernel restrictions
+    log.write(Level::ERROR, &format!("{err:#}"));
+    let Some(kernel_log) = kernel_log_tail(KERNEL_LOG_LINES) else {
+        return Err(err);
+    };
+    for line in kernel_log.lines() {
+        log.write(Level::ERROR, &format!("kernel: {line}"));
+    }
+    Err(anyhow!("{err:#}\nrecent kernel log:\n{kernel_log}"))
+}
+
+/// Prepare the payload of an installed module and mount it with its
+/// configured backend.
+pub fn mount_installed(
+    module_dir: &Path,
+    module_config: &ModuleConfig,
+    guard: &GuardConfig,
+) -> Result<()> {
+    let mount_dir = storage::prepare(module_dir, module_config.storage)
+        .context("failed to prepare module storage")?;
+    let mounter = mounter(module_mode(module_config, module_dir));
+    let language = device_language(module_config.locale.as_deref());
+    mount_module(
+        &mount_dir,
+        mounter.as_ref(),
+        language.as_deref(),
+        &guard.critical_paths,
+    )
+    .context("failed to mount module")
+}
+
+/// Standalone sh script performing the mounts of an installed module,
+/// for recovery shells where scriba itself cannot run.
+pub fn emit_script(
+    module_id: &str,
+    module_dir: &Path,
+    mounter: &dyn Mounter,
+    language: Option<&str>,
+) -> Result<String> {
+    if !module_dir.join("system").is_dir() && module_dir.join(COMPRESSED_PAYLOAD).is_file() {
+        bail!(
+            "payload of {module_id} is compressed, set modules.{module_id}.storage to plain to emit a script"
+        );
+    }
+
+    let plan = plan_module(module_dir, mounter, language)?;
+
+    let mut script = String::new();
+    script.push_str("#!/bin/sh\n");
+    script.push_str(&format!(
+        "# mounts of module {module_id} ({}), generated by scriba\n",
+        mounter.name()
+    ));
+    script.push_str("set -e\n\n");
+
+    for (kind, path) in &plan.warnings.entries {
+        script.push_str(&format!("# skipped, {kind}: {}\n", path.display()));
+    }
+    for (_, dst) in &plan.additions {
+        script.push_str(&format!(
+            "# skipped, absent from / and only added at boot: {}\n",
+            dst.display()
+        ));
+    }
+    for path in &plan.hidden {
+        script.push_str(&format!(
+            "# skipped, hidden by a whiteout only at boot: {}\n",
+            path.display()
+        ));
+    }
+    if !plan.warnings.entries.is_empty() || !plan.additions.is_empty() || !plan.hidden.is_empty() {
+        script.push('\n');
+    }
+
+    for (src, dst) in &plan.targets {
+        script.push_str(&mounter.shell_command(src, dst));
+        script.push('\n');
+    }
+
+    Ok(script)
+}
+
+/// Single-quote a path for sh.
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/* =========================
+ * Mount records
+ * ========================= */
+
+/// A mount as listed in mountinfo; ids are not reused while it exists.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct MountEntry {
+    id: u64,
+    target: PathBuf,
+}
+
+fn mount_table() -> Result<Vec<MountEntry>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let id = fields.next()?.parse().ok()?;
+            let target = unescape_mountinfo(fields.nth(3)?);
+            Some(MountEntry {
+                id,
+                target: PathBuf::from(target),
+            })
+        })
+        .collect())
+}
+
+/// Mounts made for each module, kept in the state directory for `mount
+/// list`. Mount ids only mean something within one boot, so records of an
+/// earlier boot are dropped on load.
+#[derive(Default, Serialize, Deserialize)]
+struct MountRecords {
+    boot_id: String,
+    modules: BTreeMap<String, Vec<MountEntry>>,
+}
+
+fn load_records() -> Result<MountRecords> {
+    let boot_id = SystemClock.boot_id();
+    let records = match fs::read_to_string(MOUNTS_FILE) {
+        Ok(json) => {
+            serde_json::from_str(&json).with_context(|| format!("failed to parse {MOUNTS_FILE}"))?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => MountRecords::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    if records.boot_id != boot_id {
+        return Ok(MountRecords {
+            boot_id,
+            modules: BTreeMap::new(),
+        });
+    }
+    Ok(records)
+}
+
+fn save_records(records: &MountRecords) -> Result<()> {
+    if let Some(dir) = Path::new(MOUNTS_FILE).parent() {
+        fs::create_dir_all(dir)?;
+    }
+    state::write_atomic(Path::new(MOUNTS_FILE), serde_json::to_vec_pretty(records)?)?;
+    Ok(())
+}
+
+/// Add the mounts that appeared since `before` to the module's record.
+fn record_mounts(module_id: &str, before: &[MountEntry]) -> Result<()> {
+    let mut records = load_records()?;
+    records
+        .modules
+        .entry(module_id.to_string())
+        .or_default()
+        .extend(
+            mount_table()?
+                .into_iter()
+                .filter(|entry| !before.contains(entry)),
+        );
+    save_records(&records)
+}
+
+/// Undo the mounts made for a module, newest first: those recorded this
+/// boot, or else those mountinfo attributes to it. Returns how many were
+/// undone.
+pub fn unmount_module(module_id: &str) -> Result<usize> {
+    let mut records = load_records()?;
+    let before = mount_table()?;
+    let targets: Vec<_> = match records.modules.remove(module_id) {
+        Some(recorded) => recorded,
+        None => {
+            let attributed = mounts_of(module_id)?;
+            before
+                .iter()
+                .filter(|entry| attributed.contains(&entry.target))
+                .cloned()
+                .collect()
+        }
+    };
+
+    let mut undone = 0;
+    for entry in targets.iter().rev() {
+        // detaching a replica takes the mounts inside it along
+        if !mount_table()?.contains(entry) {
+            continue;
+        }
+        info!("unmounting {:?}", entry.target);
+        unmount(&entry.target)?;
+        undone += 1;
+    }
+    save_records(&records)?;
+
+    // replicas and stacked overlays can be shared between modules
+    let after = mount_table()?;
+    for (other, entries) in &records.modules {
+        let lost = entries
+            .iter()
+            .any(|entry| before.contains(entry) && !after.contains(entry));
+        if lost {
+            warn!(
+                "module {other} lost mounts shared with {module_id}, run `module remount {other}`"
+            );
+        }
+    }
+    Ok(undone)
+}
+
+/// A mount point of `mount list`.
+#[derive(Serialize)]
+pub struct ModuleMount {
+    pub target: PathBuf,
+    /// `None` for scriba mounts made before records were kept
+    pub module_id: Option<String>,
+    /// Recorded, but no longer in the mount table
+    pub gone: bool,
+}
+
+/// Recorded module mounts checked against the mount table, followed by
+/// scriba mounts nothing recorded.
+pub fn list_mounts() -> Result<Vec<ModuleMount>> {
+    let records = load_records()?;
+    let table = mount_table()?;
+
+    let mut mounts = Vec::new();
+    for (module_id, entries) in &records.modules {
+        for entry in entries {
+            mounts.push(ModuleMount {
+                target: entry.target.clone(),
+                module_id: Some(module_id.clone()),
+                gone: !table.contains(entry),
+            });
+        }
+    }
+
+    let recorded: Vec<_> = records.modules.values().flatten().collect();
+    for target in module_mounts()? {
+        if !recorded.iter().any(|entry| entry.target == target) {
+            mounts.push(ModuleMount {
+                target,
+                module_id: None,
+                gone: false,
+            });
+        }
+    }
+    Ok(mounts)
+}
+
+/* =========================
+ * Magic mount
+ * ========================= */
+
+/// Source name of the tmpfs replicas, to tell them apart in mountinfo.
+const MAGIC_TMPFS: &str = "scriba-magic";
+
+/// Make entries absent from the read-only root appear and whiteouts hide
+/// existing ones, Magisk style: their parent directory is covered by a
+/// tmpfs replica in which the original entries are bound back, except the
+/// hidden ones, next to placeholders for the new ones.
+fn magic_mount(plan: &MountPlan) -> Result<()> {
+    type Changes<'a> = (Vec<(&'a Path, &'a Path)>, Vec<&'a Path>);
+    let mut by_parent: BTreeMap<&Path, Changes> = BTreeMap::new();
+    for (src, dst) in &plan.additions {
+        let parent = dst.parent().context("addition without parent")?;
+        by_parent.entry(parent).or_default().0.push((src, dst));
+    }
+    for path in &plan.hidden {
+        let parent = path.parent().context("whiteout without parent")?;
+        by_parent.entry(parent).or_default().1.push(path);
+    }
+
+    for (parent, (additions, hidden)) in by_parent {
+        if is_magic_replica(parent)? {
+            // a replica made for another module only needs the changes
+            for path in hidden {
+                info!("hiding {path:?}");
+                // the original was bound onto a placeholder, detach it first
+                let _ = unmount(path);
+                remove_entry(path)?;
+            }
+        } else {
+            replicate(parent, &hidden)?;
+        }
+        for (src, dst) in additions {
+            info!("adding {src:?} as {dst:?}");
+            placeholder(src.is_dir(), dst)?;
+            bind_mount(src, dst)?;
+        }
+    }
+    Ok(())
+}
+
+/// Cover `dir` with a writable tmpfs showing the same entries, but `hidden`.
+fn replicate(dir: &Path, hidden: &[&Path]) -> Result<()> {
+    info!("replicating {dir:?} on tmpfs");
+    let meta = fs::metadata(dir)?;
+    let staging = Path::new(RUN_STATE_DIR)
+        .join("magic")
+        .join(&integrity::sha256(&mut dir.as_os_str().as_bytes())?[..16]);
+    fs::create_dir_all(&staging)?;
+
+    let options = format!(
+        "mode={:o},uid={},gid={}",
+        meta.mode() & 0o7777,
+        meta.uid(),
+        meta.gid()
+    );
+    sys_mount(
+        Path::new(MAGIC_TMPFS),
+        &staging,
+        Some("tmpfs"),
+        0,
+        Some(&options),
+    )?;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let original = entry.path();
+        let copy = staging.join(entry.file_name());
+        let meta = fs::symlink_metadata(&original)?;
+
+        if hidden.contains(&original.as_path()) {
+            info!("hiding {original:?}");
+            continue;
+        }
+
+        if meta.file_type().is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&original)?, &copy)?;
+        } else if meta.is_dir() || meta.is_file() {
+            placeholder(meta.is_dir(), &copy)?;
+            // recursive, so mounts below the original stay visible
+            sys_mount(&original, &copy, None, MS_BIND | MS_REC, None)?;
+        } else {
+            warn!("cannot replicate special file {original:?}, leaving it out");
+        }
+    }
+
+    sys_mount(&staging, dir, None, MS_BIND | MS_REC, None)?;
+    // the replica keeps the tmpfs alive, the staging mount is not needed
+    unmount(&staging)
+}
+
+/// Whether `dir` is covered by a replica from `replicate`.
+fn is_magic_replica(dir: &Path) -> Result<bool> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let dir = dir.to_string_lossy();
+
+    // the topmost mount on `dir` decides
+    let mut replica = false;
+    for line in mountinfo.lines() {
+        let Some((fields, super_fields)) = line.split_once(" - ") else {
+            continue;
+        };
+        if fields.split(' ').nth(4).map(unescape_mountinfo).as_deref() == Some(&*dir) {
+            replica = super_fields.split(' ').nth(1) == Some(MAGIC_TMPFS);
+        }
+    }
+    Ok(replica)
+}
+
+/// Remove a file, symlink or directory tree.
+fn remove_entry(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| format!("failed to remove {path:?}"))
+}
+
+/// Empty file or directory for a bind mount to land on.
+fn placeholder(dir: bool, path: &Path) -> Result<()> {
+    if dir {
+        fs::create_dir(path)
+    } else {
+        fs::File::create(path).map(drop)
+    }
+    .with_context(|| format!("failed to create placeholder {path:?}"))
+}
+
+/* =========================
+ * Syscalls
+ * ========================= */
+
+/// Last `lines` lines of the kernel ring buffer, from `dmesg` or, where that
+/// is missing or restricted, straight from `/dev/kmsg`.
+fn kernel_log_tail(lines: usize) -> Option<String> {
+    let from_dmesg = Command::new("dmesg")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+
+    let text = from_dmesg.or_else(read_kmsg)?;
+    let all: Vec<_> = text.lines().collect();
+    let tail = all[all.len().saturating_sub(lines)..].join("\n");
+    (!tail.is_empty()).then_some(tail)
+}
+
+/// Drain `/dev/kmsg` without blocking; each read returns one record.
+fn read_kmsg() -> Option<String> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/kmsg")
+        .ok()?;
+
+    let mut text = String::new();
+    let mut record = [0u8; 8192];
+    loop {
+        match file.read(&mut record) {
+            Ok(0) => break,
+            Ok(n) => {
+                // "<prio>,<seq>,<time>,<flags>;<message>"
+                let line = String::from_utf8_lossy(&record[..n]);
+                let message = line.split_once(';').map_or(&*line, |(_, m)| m);
+                text.push_str(message.lines().next().unwrap_or_default());
+                text.push('\n');
+            }
+            // EPIPE marks overwritten records, keep reading past them
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(_) => break,
+        }
+    }
+
+    Some(text)
+}
+
+/// Whether `/proc/filesystems` lists `fstype`.
+pub fn kernel_supports(fstype: &str) -> bool {
+    fs::read_to_string("/proc/filesystems").is_ok_and(|list| {
+        list.lines()
+            .any(|line| line.split_whitespace().last() == Some(fstype))
+    })
+}
+
+pub fn sys_mount(
+    src: &Path,
+    dst: &Path,
+    fstype: Option<&str>,
+    flags: c_ulong,
+    data: Option<&str>,
+) -> Result<()> {
+    let src_c = CString::new(src.as_os_str().as_bytes()).context("invalid src path")?;
+    let dst_c = CString::new(dst.as_os_str().as_bytes()).context("invalid dst path")?;
+    let fstype_c = fstype.map(CString::new).transpose()?;
+    let data_c = data.map(CString::new).transpose()?;
+
+    let ret = unsafe {
+        libc::mount(
+            src_c.as_ptr(),
+            dst_c.as_ptr(),
+            fstype_c.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            flags,
+            data_c
+                .as_ref()
+                .map_or(std::ptr::null(), |s| s.as_ptr().cast()),
+        )
+    };
+
+    if ret != 0 {
+        // keep the io::Error in the chain, see `is_missing_path`
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("mount failed: {} -> {}", src.display(), dst.display()));
+    }
+
+    Ok(())
+}
+
+/// Category of a mount failure, to tell recurring device-specific problems
+/// apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FailureKind {
+    /// EPERM or EACCES, often a vendor kernel or LSM restriction
+    PermissionDenied,
+    /// EROFS, the target filesystem is mounted read-only
+    ReadOnly,
+    /// ENOENT, the target does not exist (yet)
+    MissingPath,
+    /// EBUSY, the target is in use
+    Busy,
+    /// The kernel lacks overlayfs, or the fuse helper is missing or failed
+    OverlayUnsupported,
+    Other,
+}
+
+impl FailureKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureKind::PermissionDenied => "permission-denied",
+            FailureKind::ReadOnly => "read-only",
+            FailureKind::MissingPath => "missing-path",
+            FailureKind::Busy => "busy",
+            FailureKind::OverlayUnsupported => "overlay-unsupported",
+            FailureKind::Other => "other",
+        }
+    }
+
+    pub const ALL: [FailureKind; 6] = [
+        FailureKind::PermissionDenied,
+        FailureKind::ReadOnly,
+        FailureKind::MissingPath,
+        FailureKind::Busy,
+        FailureKind::OverlayUnsupported,
+        FailureKind::Other,
+    ];
+}
+
+/// Classify a failure of `mounter`, from the first errno in the chain.
+fn classify(err: &anyhow::Error, mounter: &dyn Mounter) -> FailureKind {
+    let overlay = matches!(mounter.name(), "overlay" | "fuse overlay");
+    let errno = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<io::Error>())
+        .and_then(io::Error::raw_os_error);
+    match errno {
+        Some(libc::EPERM | libc::EACCES) => FailureKind::PermissionDenied,
+        Some(libc::EROFS) => FailureKind::ReadOnly,
+        Some(libc::ENOENT) => FailureKind::MissingPath,
+        Some(libc::EBUSY) => FailureKind::Busy,
+        Some(libc::ENODEV | libc::EINVAL) | None if overlay => FailureKind::OverlayUnsupported,
+        _ => FailureKind::Other,
+    }
+}
+
+/// The last mount failure of a module, until it mounts again.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MountFailure {
+    pub kind: FailureKind,
+    pub error: String,
+    pub at: Timestamp,
+    /// Failed attempts in a row
+    pub count: u32,
+}
+
+/// Last mount failure by module id, of modules still installed.
+pub fn mount_failures() -> BTreeMap<String, MountFailure> {
+    let mut failures: BTreeMap<String, MountFailure> = fs::read_to_string(MOUNT_FAILURES_FILE)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    failures.retain(|id, _| Path::new(MODULES_DIR).join(id).is_dir());
+    failures
+}
+
+/// Remember the outcome of mounting a module: the failure, or that it
+/// mounted, which clears an earlier one.
+fn record_outcome(module_id: &str, failure: Option<(FailureKind, &anyhow::Error)>) {
+    let mut failures = mount_failures();
+    let changed = match failure {
+        Some((kind, err)) => {
+            let count = failures.get(module_id).map_or(0, |last| last.count);
+            failures.insert(
+                module_id.to_string(),
+                MountFailure {
+                    kind,
+                    error: format!("{err:#}"),
+                    at: Timestamp::now(),
+                    count: count + 1,
+                },
+            );
+            true
+        }
+        None => failures.remove(module_id).is_some(),
+    };
+    if !changed {
+        return;
+    }
+
+    let written = serde_json::to_vec_pretty(&failures)
+        .map_err(io::Error::from)
+        .and_then(|json| state::write_atomic(Path::new(MOUNT_FAILURES_FILE), json));
+    if let Err(e) = written {
+        warn!("failed to record mount outcome of {module_id} in {MOUNT_FAILURES_FILE}: {e}");
+    }
+}
+
+/// Whether `err` comes from a path that does not exist (ENOENT), e.g. a
+/// target directory a service creates only after boot-complete ran.
+pub fn is_missing_path(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+    })
+}
+
+/// Undo a mount, lazily so busy targets detach once released.
+pub fn unmount(target: &Path) -> Result<()> {
+    let target_c = CString::new(target.as_os_str().as_bytes()).context("invalid target path")?;
+    let ret = unsafe { libc::umount2(target_c.as_ptr(), libc::MNT_DETACH) };
+    if ret != 0 {
+        return Err(anyhow!(
+            "unmount of {} failed ({})",
+            target.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Decode the octal escapes (`\040` for space, ...) used in mountinfo.
+fn unescape_mountinfo(field: &str) -> String {
+    let mut out = Vec::new();
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 4 <= bytes.len()
+            && let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 4])
+                .ok()
+                .and_then(|octal| u8::from_str_radix(octal, 8).ok())
+        {
+            out.push(byte);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Mount points that come from module payloads, in mount order.
+///
+/// Bind mounts are recognized by their source path inside the modules or
+/// staging directory, overlays by a `lowerdir` pointing there and magic
+/// mount replicas by their tmpfs source name.
+pub fn module_mounts() -> Result<Vec<PathBuf>> {
+    mounts_from(|path| {
+        path.contains("/scriba/modules/")
+            || path.contains("/scriba/staging/")
+            || path == MAGIC_TMPFS
+    })
+}
+
+/// Mount points that come from the payload of one module, in mount order.
+pub fn mounts_of(module_id: &str) -> Result<Vec<PathBuf>> {
+    let installed = format!("/scriba/modules/{module_id}/");
+    let staged = format!("/scriba/staging/{module_id}/");
+    mounts_from(|path| path.contains(&installed) || path.contains(&staged))
+}
+
+/// Layers of the topmost overlay mounted on `target` by scriba, if any.
+fn scriba_overlay_lowerdir(target: &Path) -> Result<Option<String>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    let mut lower = None;
+    for line in mountinfo.lines() {
+        let Some((fields, super_fields)) = line.split_once(" - ") else {
+            continue;
+        };
+        let mount_point = fields.split(' ').nth(4).map(unescape_mountinfo);
+        let mut super_fields = super_fields.split(' ');
+        if mount_point.as_deref() != Some(&*target.to_string_lossy())
+            || super_fields.next() != Some("overlay")
+        {
+            continue;
+        }
+
+        lower = super_fields
+            .nth(1)
+            .and_then(|options| {
+                options
+                    .split(',')
+                    .find_map(|option| option.strip_prefix("lowerdir="))
+            })
+            .map(unescape_mountinfo)
+            .filter(|lower| lower.contains("/scriba/"));
+    }
+
+    Ok(lower)
+}
+
+fn mounts_from(from_scriba: impl Fn(&str) -> bool) -> Result<Vec<PathBuf>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    let mut mounts = Vec::new();
+    for line in mountinfo.lines() {
+        let Some((fields, super_fields)) = line.split_once(" - ") else {
+            continue;
+        };
+        let fields: Vec<_> = fields.split(' ').collect();
+        let (Some(root), Some(mount_point)) = (fields.get(3), fields.get(4)) else {
+            continue;
+        };
+        let mut super_fields = super_fields.split(' ').skip(1);
+        let source = super_fields.next().unwrap_or_default();
+        let super_options = super_fields.next().unwrap_or_default();
+
+        if from_scriba(&unescape_mountinfo(root))
+            || from_scriba(&unescape_mountinfo(source))
+            || from_scriba(&unescape_mountinfo(super_options))
+        {
+            mounts.push(PathBuf::from(unescape_mountinfo(mount_point)));
+        }
+    }
+
+    Ok(mounts)
+}
+
+pub fn bind_mount(src: &Path, dst: &Path) -> Result<()> {
+    info!("mounting {src:?} on {dst:?}");
+    sys_mount(src, dst, None, MS_BIND, None)
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+use crate::boot::{self, ModuleStep};
+use crate::defs::{MODULES_DIR, PATH_PROFILE_FILE};
+use crate::module;
+use crate::state;
+
+/// Directories a `path_dirs` value names, relative to the module root.
+pub fn parse(value: &str) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for dir in value
+        .split(',')
+        .map(str::trim)
+        .filter(|dir| !dir.is_empty())
+    {
+        if dir.contains(['\'', ':']) {
+            bail!("path_dirs entry `{dir}` contains a quote or colon");
+        }
+        let path = PathBuf::from(dir);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!("path_dirs entry `{dir}` must be a plain path inside the module");
+        }
+        dirs.push(path);
+    }
+    Ok(dirs)
+}
+
+/// Absolute `path_dirs` of the module in `module_dir`, skipping any that
+/// do not exist.
+fn module_path_dirs(module_dir: &Path, props: &HashMap<String, String>) -> Vec<PathBuf> {
+    let Some(value) = props.get("path_dirs") else {
+        return Vec::new();
+    };
+    let Ok(dirs) = parse(value) else {
+        return Vec::new();
+    };
+    dirs.into_iter()
+        .map(|dir| module_dir.join(dir))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+/// `path_dirs` of the modules `include` picks among those boot-complete
+/// initializes, with the id of each module, in reverse mount order so the
+/// module whose mounts stay visible wins lookups too.
+pub fn dirs(include: impl Fn(&Path) -> bool) -> Vec<(String, PathBuf)> {
+    let mut modules: Vec<PathBuf> = module::installed_ids()
+        .into_iter()
+        .map(|id| Path::new(MODULES_DIR).join(id))
+        .filter(|dir| include(dir))
+        .collect();
+    modules.sort_by_cached_key(|dir| module::mount_order_key(dir));
+    modules.reverse();
+
+    let mut dirs = Vec::new();
+    for module_dir in modules {
+        if let ModuleStep::Init { props, .. } = boot::module_step(&module_dir) {
+            let id = props["id"].clone();
+            dirs.extend(
+                module_path_dirs(&module_dir, &props)
+                    .into_iter()
+                    .map(|dir| (id.clone(), dir)),
+            );
+        }
+    }
+    dirs
+}
+
+fn join(dirs: &[(String, PathBuf)]) -> String {
+    dirs.iter()
+        .map(|(_, dir)| dir.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The module dirs sourcing `PATH_PROFILE_FILE` adds, joined with `:`;
+/// those of every enabled module until boot-complete has written it.
+pub fn profile_path() -> String {
+    match fs::read_to_string(PATH_PROFILE_FILE) {
+        Ok(profile) => rendered_path(&profile).to_string(),
+        Err(_) => join(&dirs(|_| true)),
+    }
+}
+
+/// The dirs a script made by `render` adds.
+fn rendered_path(profile: &str) -> &str {
+    profile
+        .lines()
+        .find_map(|line| line.strip_prefix("SCRIBA_PATH='")?.strip_suffix('\''))
+        .unwrap_or_default()
+}
+
+/// `PATH` with the module dirs `path` lists in front of `current`.
+pub fn prepend(path: &str, current: &str) -> String {
+    match (path.is_empty(), current.is_empty()) {
+        (true, _) => current.to_string(),
+        (false, true) => path.to_string(),
+        (false, false) => format!("{path}:{current}"),
+    }
+}
+
+/// Shell script putting `dirs` in front of `PATH`, once however often it
+/// is sourced.
+fn render(dirs: &[(String, PathBuf)]) -> String {
+    let mut script = String::from(
+        "# Generated by scriba at boot-complete from the path_dirs of the\n\
+         # modules it initialized, in reverse mount order. Changes are\n\
+         # overwritten at the next boot.\n",
+    );
+    if dirs.is_empty() {
+        script.push_str("# no initialized module declares path_dirs\n");
+        return script;
+    }
+
+    for (id, dir) in dirs {
+        script.push_str(&format!("# {id}: {}\n", dir.display()));
+    }
+    script.push_str(&format!(
+        "SCRIBA_PATH='{}'\n\
+         case \":$PATH:\" in\n    \
+         *\":$SCRIBA_PATH:\"*) ;;\n    \
+         *) PATH=\"$SCRIBA_PATH${{PATH:+:$PATH}}\" ;;\n\
+         esac\n\
+         export PATH\n",
+        join(dirs)
+    ));
+    script
+}
+
+/// Write `PATH_PROFILE_FILE` putting `dirs` on `PATH`.
+pub fn write_profile(dirs: &[(String, PathBuf)]) -> Result<()> {
+    state::write_atomic(Path::new(PATH_PROFILE_FILE), render(dirs))?;
+    info!(
+        "{} module PATH dir(s) written to {PATH_PROFILE_FILE}",
+        dirs.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendered_path_reads_back_what_render_wrote() {
+        let dirs = [
+            (
+                "high".to_string(),
+                PathBuf::from("/userdisk/scriba/modules/high/bin"),
+            ),
+            (
+                "low".to_string(),
+                PathBuf::from("/userdisk/scriba/modules/low/sbin"),
+            ),
+        ];
+        assert_eq!(
+            rendered_path(&render(&dirs)),
+            "/userdisk/scriba/modules/high/bin:/userdisk/scriba/modules/low/sbin"
+        );
+        assert_eq!(rendered_path(&render(&[])), "");
+    }
+
+    #[test]
+    fn prepend_skips_empty_parts() {
+        assert_eq!(prepend("/m/bin", "/usr/bin:/bin"), "/m/bin:/usr/bin:/bin");
+        assert_eq!(prepend("/m/bin", ""), "/m/bin");
+        assert_eq!(prepend("", "/usr/bin"), "/usr/bin");
+    }
+}
@@ -1,11 +1,28 @@
 use std::io::{self, Write};
-use std::process::{Command, ExitStatus, Stdio};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::bail;
 
 pub fn run_with_output(cmd: &str, args: &[&str]) -> anyhow::Result<ExitStatus> {
+    run_with_output_env(cmd, args, &[])
+}
+
+/// Like `run_with_output`, with extra environment variables for the child.
+pub fn run_with_output_env(
+    cmd: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+) -> anyhow::Result<ExitStatus> {
+    Ok(run_echoed(cmd, args, envs)?.status)
+}
+
+/// Run a command, echo its output once it exits and hand the output back.
+pub fn run_echoed(cmd: &str, args: &[&str], envs: &[(String, String)]) -> anyhow::Result<Output> {
     let child = Command::new(cmd)
         .args(args)
+        .envs(envs.iter().map(|(k, v)| (k, v)))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()?;
@@ -14,8 +31,37 @@ pub fn run_with_output(cmd: &str, args: &[&str]) -> anyhow::Result<ExitStatus> {
         let _ = io::stdout().write_all(&output.stdout);
         let _ = io::stderr().write_all(&output.stderr);
 
-        return Ok(output.status);
+        return Ok(output);
     }
 
     bail!("failed to run process");
 }
+
+/// Run a command with output passed straight through, killing it once
+/// `timeout` has elapsed.
+pub fn run_with_timeout(
+    cmd: &str,
+    args: &[&str],
+    envs: &[(String, String)],
+    timeout: Duration,
+) -> anyhow::Result<ExitStatus> {
+    let mut child = Command::new(cmd)
+        .args(args)
+        .envs(envs.iter().map(|(k, v)| (k, v)))
+        .spawn()?;
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{cmd} timed out after {}s", timeout.as_secs());
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::defs::PROFILES_DIR;
+use crate::module;
+use crate::state;
+
+/// A named set of enabled and disabled modules.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Profile {
+    pub enabled: Vec<String>,
+    pub disabled: Vec<String>,
+}
+
+fn profile_path(name: &str) -> PathBuf {
+    Path::new(PROFILES_DIR).join(format!("{name}.json"))
+}
+
+/// Record which installed modules are currently enabled.
+pub fn save(name: &str) -> anyhow::Result<Profile> {
+    let mut profile = Profile::default();
+    for id in module::installed_ids() {
+        if module::is_enabled(&id) {
+            profile.enabled.push(id);
+        } else {
+            profile.disabled.push(id);
+        }
+    }
+
+    fs::create_dir_all(PROFILES_DIR)?;
+    state::write_atomic(&profile_path(name), serde_json::to_string_pretty(&profile)?)?;
+    Ok(profile)
+}
+
+pub fn load(name: &str) -> anyhow::Result<Profile> {
+    let path = profile_path(name);
+    if !path.exists() {
+        bail!("profile {name} does not exist");
+    }
+
+    let content = fs::read_to_string(&path)?;
+    serde_json::from_str(&content).with_context(|| format!("invalid profile {path:?}"))
+}
+
+/// Enable and disable installed modules to match a profile.
+///
+/// Modules installed after the profile was saved are left as they are.
+pub fn apply(name: &str) -> anyhow::Result<()> {
+    let profile = load(name)?;
+    let installed = module::installed_ids();
+
+    let wanted = profile
+        .enabled
+        .iter()
+        .map(|id| (id, true))
+        .chain(profile.disabled.iter().map(|id| (id, false)));
+
+    let mut changed = 0;
+    for (id, enabled) in wanted {
+        if !installed.contains(id) {
+            warn!("module {id} is in profile {name} but not installed");
+            continue;
+        }
+
+        if module::set_enabled(id, enabled)? {
+            info!("{} {id}", if enabled { "enabled" } else { "disabled" });
+            changed += 1;
+        }
+    }
+
+    for id in &installed {
+        if !profile.enabled.contains(id) && !profile.disabled.contains(id) {
+            info!("module {id} is not in profile {name}, left unchanged");
+        }
+    }
+
+    info!("profile {name} applied, {changed} module(s) changed, effective after reboot");
+    Ok(())
+}
+
+pub fn list() -> anyhow::Result<()> {
+    let mut names: Vec<String> = match fs::read_dir(PROFILES_DIR) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                (path.extension()? == "json")
+                    .then(|| path.file_stem()?.to_str().map(str::to_string))?
+            })
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    names.sort();
+
+    info!("profiles:");
+    if names.is_empty() {
+        info!("  (no profiles saved)");
+    }
+    for name in names {
+        match load(&name) {
+            Ok(profile) => info!(
+                "  {name}: {} enabled, {} disabled",
+                profile.enabled.len(),
+                profile.disabled.len()
+            ),
+            Err(e) => warn!("  {name}: {e}"),
+        }
+    }
+
+    Ok(())
+}
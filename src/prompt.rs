@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use dialoguer::Input;
+use tracing::{info, warn};
+
+use crate::module;
+use crate::strict;
+
+/// Marker of a question in an install script, a comment to the shell:
+/// `#!prompt <key> "<text>" [default]`.
+pub const PROMPT_MARKER: &str = "#!prompt";
+/// Install scripts scanned for prompts, in the order they are asked.
+const PROMPT_SCRIPTS: &[&str] = &["preinstall.sh", "postinstall.sh", "install.sh"];
+/// Optional `key=text` file; prompt texts of the form `@key` are looked up here.
+const STRINGS_FILE: &str = "strings.prop";
+
+/// A question a module asks at install time.
+#[derive(Debug)]
+pub struct Prompt {
+    pub key: String,
+    pub text: String,
+    pub default: String,
+}
+
+impl Prompt {
+    /// Variable the answer is passed to install scripts in.
+    pub fn env_name(&self) -> String {
+        format!("SCRIBA_ANSWER_{}", self.key.to_ascii_uppercase())
+    }
+}
+
+fn valid_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse what follows the marker: a key, a double-quoted text and an
+/// optional default, quoted or not.
+fn parse_prompt(rest: &str) -> anyhow::Result<Prompt> {
+    let rest = rest.trim();
+    let (key, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if !valid_key(key) {
+        bail!("invalid prompt key `{key}`, expected letters, numbers or underscore");
+    }
+
+    let rest = rest
+        .trim_start()
+        .strip_prefix('"')
+        .with_context(|| format!("prompt {key} needs a double-quoted text"))?;
+    let (text, default) = rest
+        .split_once('"')
+        .with_context(|| format!("prompt {key} has an unterminated text"))?;
+
+    let default = default.trim();
+    let default = default
+        .strip_prefix('"')
+        .and_then(|d| d.strip_suffix('"'))
+        .unwrap_or(default);
+
+    Ok(Prompt {
+        key: key.to_string(),
+        text: text.to_string(),
+        default: default.to_string(),
+    })
+}
+
+/// Prompts declared by a module's install scripts, each key once.
+pub fn module_prompts(module_dir: &Path) -> anyhow::Result<Vec<Prompt>> {
+    let mut prompts: Vec<Prompt> = Vec::new();
+    for script in PROMPT_SCRIPTS {
+        let Ok(content) = fs::read_to_string(module_dir.join(script)) else {
+            continue;
+        };
+
+        for (number, line) in content.lines().enumerate() {
+            let Some(rest) = line.trim_start().strip_prefix(PROMPT_MARKER) else {
+                continue;
+            };
+            match parse_prompt(rest) {
+                Ok(prompt) if prompts.iter().any(|p| p.key == prompt.key) => {}
+                Ok(prompt) => prompts.push(prompt),
+                Err(e) => {
+                    strict::warn_or_bail!("{script}:{}: {e:#}", number + 1);
+                }
+            }
+        }
+    }
+    Ok(prompts)
+}
+
+/// Prompt text with `@key` references resolved from the module's strings.
+fn resolve_text(text: &str, strings: &HashMap<String, String>) -> String {
+    text.strip_prefix('@')
+        .and_then(|key| strings.get(key))
+        .cloned()
+        .unwrap_or_else(|| text.to_string())
+}
+
+/// Answer every prompt of a module, as environment variables for its install
+/// scripts. Answers given up front win; the rest are asked when running in a
+/// terminal and fall back to their defaults otherwise.
+pub fn answers(
+    module_dir: &Path,
+    given: &[(String, String)],
+) -> anyhow::Result<Vec<(String, String)>> {
+    let prompts = module_prompts(module_dir)?;
+    for (key, _) in given {
+        if !prompts.iter().any(|p| p.key == *key) {
+            warn!("module does not ask for `{key}`, ignoring the answer");
+        }
+    }
+    if prompts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let strings_path = module_dir.join(STRINGS_FILE);
+    let strings = if strings_path.is_file() {
+        module::parse_prop_file(&strings_path)?
+    } else {
+        HashMap::new()
+    };
+    let interactive = io::stdin().is_terminal();
+
+    let mut envs = Vec::new();
+    for prompt in &prompts {
+        let answer = match given.iter().find(|(key, _)| *key == prompt.key) {
+            Some((_, value)) => value.clone(),
+            None if interactive => Input::new()
+                .with_prompt(resolve_text(&prompt.text, &strings))
+                .default(prompt.default.clone())
+                .allow_empty(true)
+                .interact_text()?,
+            None => {
+                info!(
+                    "not interactive, answering `{}` with its default `{}`",
+                    prompt.key, prompt.default
+                );
+                prompt.default.clone()
+            }
+        };
+        envs.push((prompt.env_name(), answer));
+    }
+    Ok(envs)
+}
@@ -0,0 +1,197 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, params};
+use serde_json::{Map, Value};
+
+use crate::app;
+use crate::audit;
+use crate::clock;
+use crate::config::AppConfig;
+use crate::defs::{MODULES_DIR, MODULES_UPDATE_DIR};
+use crate::module;
+use crate::mount;
+use crate::trust::{self, TrustStatus};
+
+/// Tables `query` exposes; kept in sync with the `Query` help text.
+const SCHEMA: &str = "
+    CREATE TABLE modules (
+        id TEXT PRIMARY KEY,
+        name TEXT,
+        version INTEGER,
+        author TEXT,
+        description TEXT,
+        enabled INTEGER NOT NULL,
+        update_pending INTEGER NOT NULL,
+        uninstall_pending INTEGER NOT NULL,
+        mounted INTEGER NOT NULL,
+        trust TEXT
+    );
+    CREATE TABLE module_props (module_id TEXT NOT NULL, key TEXT NOT NULL, value TEXT);
+    CREATE TABLE scripts (
+        module_id TEXT NOT NULL,
+        script TEXT NOT NULL,
+        exit_code INTEGER,
+        duration_ms INTEGER,
+        finished TEXT
+    );
+    CREATE TABLE mounts (mount_point TEXT NOT NULL, module_id TEXT);
+    CREATE TABLE audit (
+        time TEXT,
+        peer TEXT,
+        token_id TEXT,
+        command TEXT,
+        args TEXT,
+        result TEXT
+    );
+    CREATE TABLE app_crashes (app_id INTEGER NOT NULL, path TEXT NOT NULL);
+";
+
+/// Snapshot the current state into an in-memory database.
+fn snapshot(config: &AppConfig) -> anyhow::Result<Connection> {
+    let db = Connection::open_in_memory()?;
+    db.execute_batch(SCHEMA)?;
+
+    for id in module::installed_ids() {
+        let module_dir = Path::new(MODULES_DIR).join(&id);
+        let props = module::parse_prop_file(&module_dir.join("module.prop")).unwrap_or_default();
+        let trust = match trust::status(&props) {
+            Ok(TrustStatus::Trusted) => Some("trusted"),
+            Ok(TrustStatus::Untrusted) => Some("untrusted"),
+            Ok(TrustStatus::Unsigned) => Some("unsigned"),
+            Err(_) => None,
+        };
+        db.execute(
+            "INSERT INTO modules VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                id,
+                props.get("name"),
+                props.get("version").and_then(|v| v.parse::<i64>().ok()),
+                props.get("author"),
+                props.get("description"),
+                module::is_enabled(&id),
+                Path::new(MODULES_UPDATE_DIR).join(&id).is_dir(),
+                module_dir.join("uninstall.flag").exists(),
+                module::is_mounted(&id),
+                trust,
+            ],
+        )?;
+
+        for (key, value) in &props {
+            db.execute(
+                "INSERT INTO module_props VALUES (?1, ?2, ?3)",
+                params![id, key, value],
+            )?;
+        }
+
+        for result in module::script_results(&id) {
+            db.execute(
+                "INSERT INTO scripts VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id,
+                    result.script,
+                    result.exit_code,
+                    result.duration_ms as i64,
+                    clock::rfc3339(result.finished.timestamp),
+                ],
+            )?;
+        }
+    }
+
+    // /proc/self/mountinfo only exists on Linux, leave the table empty elsewhere
+    for entry in mount::list_mounts().unwrap_or_default() {
+        if entry.gone {
+            continue;
+        }
+        db.execute(
+            "INSERT INTO mounts VALUES (?1, ?2)",
+            params![entry.target.to_string_lossy(), entry.module_id],
+        )?;
+    }
+
+    for entry in audit::read_entries()? {
+        db.execute(
+            "INSERT INTO audit VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                clock::rfc3339(entry.time.timestamp),
+                entry.client.peer,
+                entry.client.token_id,
+                entry.command,
+                serde_json::to_string(&entry.args)?,
+                entry.result,
+            ],
+        )?;
+    }
+
+    for (app_id, files) in app::find_crashes(&config.app.crash_dirs, None) {
+        for file in files {
+            db.execute(
+                "INSERT INTO app_crashes VALUES (?1, ?2)",
+                params![app_id as i64, file.to_string_lossy()],
+            )?;
+        }
+    }
+
+    db.pragma_update(None, "query_only", true)?;
+    Ok(db)
+}
+
+fn to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into(),
+        ValueRef::Blob(blob) => hex::encode(blob).into(),
+    }
+}
+
+fn to_text(value: ValueRef) -> String {
+    match to_json(value) {
+        Value::Null => "NULL".to_string(),
+        Value::String(text) => text,
+        other => other.to_string(),
+    }
+}
+
+/// Run a read-only SQL statement against a snapshot of the state and print
+/// the rows, tab separated with a header line, or as a JSON array.
+pub fn run(config: &AppConfig, sql: &str, json: bool) -> anyhow::Result<()> {
+    let db = snapshot(config).context("failed to collect state")?;
+    let mut statement = db.prepare(sql)?;
+    if !statement.readonly() {
+        bail!("query must not modify anything");
+    }
+
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    let mut rows = statement.query([])?;
+    let mut out = io::stdout().lock();
+
+    if json {
+        let mut objects = Vec::new();
+        while let Some(row) = rows.next()? {
+            let mut object = Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                object.insert(column.clone(), to_json(row.get_ref(i)?));
+            }
+            objects.push(Value::Object(object));
+        }
+        writeln!(out, "{}", serde_json::to_string_pretty(&objects)?)?;
+        return Ok(());
+    }
+
+    writeln!(out, "{}", columns.join("\t"))?;
+    while let Some(row) = rows.next()? {
+        let fields = (0..columns.len())
+            .map(|i| Ok(to_text(row.get_ref(i)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        writeln!(out, "{}", fields.join("\t"))?;
+    }
+    Ok(())
+}
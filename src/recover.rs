@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::Path;
+
+use tracing::{info, warn};
+
+use crate::defs::{MODULES_DIR, MODULES_UPDATE_DIR, RUN_STATE_DIR, SAFE_MODE_FLAG};
+use crate::module;
+use crate::mount;
+
+/// Outcome of one recovery step, for the final report.
+struct Step {
+    name: &'static str,
+    done: usize,
+    failed: Vec<String>,
+}
+
+impl Step {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            done: 0,
+            failed: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, what: &Path, result: anyhow::Result<()>) {
+        match result {
+            Ok(()) => self.done += 1,
+            Err(e) => {
+                warn!("{}: {what:?}: {e}", self.name);
+                self.failed.push(format!("{what:?}: {e}"));
+            }
+        }
+    }
+}
+
+fn dirs(dir: &str) -> Vec<std::path::PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_dir())
+        .collect()
+}
+
+/// Put the device back into a known good state: every module disabled and
+/// unmounted, pending updates dropped, and safe mode set for the next boot.
+///
+/// Each step carries on past failures so as much as possible is undone.
+pub fn recover() -> anyhow::Result<()> {
+    warn!("entering recovery mode");
+
+    let mut disable = Step::new("disable modules");
+    for dir in dirs(MODULES_DIR) {
+        disable.record(
+            &dir,
+            fs::write(dir.join("disable.flag"), "").map_err(Into::into),
+        );
+    }
+    disable.record(Path::new(RUN_STATE_DIR), module::clear_until_reboot());
+
+    let mut unmount = Step::new("unmount module files");
+    match mount::module_mounts() {
+        // undo in reverse so stacked mounts come off top first
+        Ok(mounts) => {
+            for target in mounts.iter().rev() {
+                unmount.record(target, mount::unmount(target));
+            }
+        }
+        Err(e) => unmount.failed.push(format!("cannot read mount table: {e}")),
+    }
+
+    let mut updates = Step::new("clear pending updates");
+    for dir in dirs(MODULES_UPDATE_DIR) {
+        updates.record(&dir, module::delete_dir(&dir));
+    }
+
+    let mut safe_mode = Step::new("set safe mode");
+    safe_mode.record(
+        Path::new(SAFE_MODE_FLAG),
+        fs::write(SAFE_MODE_FLAG, "").map_err(Into::into),
+    );
+
+    let steps = [disable, unmount, updates, safe_mode];
+    info!("recovery report:");
+    for step in &steps {
+        if step.failed.is_empty() {
+            info!("  {}: ok ({} done)", step.name, step.done);
+        } else {
+            warn!(
+                "  {}: {} done, {} failed",
+                step.name,
+                step.done,
+                step.failed.len()
+            );
+            for failure in &step.failed {
+                warn!("    {failure}");
+            }
+        }
+    }
+
+    if steps.iter().any(|step| !step.failed.is_empty()) {
+        anyhow::bail!("recovery finished with errors, see the report above");
+    }
+
+    info!("modules stay disabled and safe mode is set; reboot, then re-enable modules one by one");
+    Ok(())
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::cache;
+use crate::channel;
+use crate::clock;
+use crate::defs::{MODULES_DIR, REPOS_FILE};
+use crate::module;
+use crate::state;
+
+/// A module offered by a repository index.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepoModule {
+    pub id: String,
+    pub name: String,
+    pub version: i64,
+    /// Download URL, absolute or relative to the index
+    pub url: String,
+    pub sha256: String,
+    /// Release channel this entry belongs to; an index may list a module
+    /// once per channel
+    #[serde(default = "default_channel")]
+    pub channel: String,
+    /// Patches from older versions, preferred over `url` when one starts
+    /// at the installed version
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deltas: Vec<RepoDelta>,
+}
+
+/// A delta package turning `from_version` into the version it is listed
+/// under.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepoDelta {
+    pub from_version: i64,
+    /// Delta package URL, absolute or relative to the index
+    pub url: String,
+    pub sha256: String,
+}
+
+impl RepoModule {
+    /// The delta that updates `installed` to this version, if offered.
+    pub fn delta_from(&self, installed: Option<i64>) -> Option<&RepoDelta> {
+        let installed = installed?;
+        self.deltas
+            .iter()
+            .find(|delta| delta.from_version == installed)
+    }
+}
+
+/// Run `install` on the delta when one is offered, and on the full package
+/// at `url` when there is none or it cannot be applied.
+pub fn install_preferring_delta(
+    delta: Option<RepoDelta>,
+    url: String,
+    sha256: Option<String>,
+    install: impl Fn(String, Option<String>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if let Some(delta) = delta {
+        info!(
+            "using the delta from v{} at {}",
+            delta.from_version, delta.url
+        );
+        match install(delta.url, Some(delta.sha256)) {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("{e:#}, downloading the full package instead"),
+        }
+    }
+    install(url, sha256)
+}
+
+/// Whether `value` is a sha256 digest in hex.
+pub fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Check the `deltas` listed in the index at `index_url` and make their
+/// URLs absolute, dropping malformed ones.
+pub fn resolve_deltas(index_url: &str, id: &str, deltas: &mut Vec<RepoDelta>) {
+    deltas.retain_mut(|delta| {
+        if !is_sha256(&delta.sha256) {
+            warn!(
+                "skipping delta of {id} from v{} in {index_url}: sha256 must be 64 hex digits",
+                delta.from_version
+            );
+            return false;
+        }
+        delta.sha256.make_ascii_lowercase();
+        delta.url = resolve_url(index_url, &delta.url);
+        true
+    });
+}
+
+fn default_channel() -> String {
+    channel::DEFAULT_CHANNEL.to_string()
+}
+
+/// The JSON document a repository serves.
+#[derive(Debug, Deserialize)]
+struct Index {
+    modules: Vec<RepoModule>,
+}
+
+/// A repository added with `repo add` and its last fetched index.
+#[derive(Debug, Serialize, Deserialize)]
+struct Repo {
+    #[serde(with = "clock::rfc3339_or_unix")]
+    updated: u64,
+    modules: Vec<RepoModule>,
+}
+
+/// A search hit, with the repository offering it.
+#[derive(Debug, Serialize)]
+pub struct RepoMatch {
+    pub repo: String,
+    #[serde(flatten)]
+    pub module: RepoModule,
+    /// Version installed on the device, if any
+    pub installed: Option<i64>,
+}
+
+fn load() -> anyhow::Result<BTreeMap<String, Repo>> {
+    match fs::read_to_string(REPOS_FILE) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save(repos: &BTreeMap<String, Repo>) -> anyhow::Result<()> {
+    state::write_atomic(Path::new(REPOS_FILE), serde_json::to_string_pretty(repos)?)?;
+    Ok(())
+}
+
+/// `url` made absolute against the index it was listed in.
+pub fn resolve_url(index_url: &str, url: &str) -> String {
+    if cache::is_url(url) {
+        return url.to_string();
+    }
+    let base = index_url.split(['?', '#']).next().unwrap_or(index_url);
+    let base = base.rsplit_once('/').map_or(base, |(dir, _)| dir);
+    format!("{base}/{}", url.trim_start_matches("./"))
+}
+
+/// Download and check the index served at `url`.
+fn fetch_index(url: &str) -> anyhow::Result<Vec<RepoModule>> {
+    let content = ureq::get(url)
+        .call()
+        .and_then(|response| response.into_body().read_to_string())
+        .with_context(|| format!("failed to download repository index {url}"))?;
+    let index: Index = serde_json::from_str(&content)
+        .with_context(|| format!("{url} is not a repository index"))?;
+
+    let mut modules = Vec::new();
+    for mut entry in index.modules {
+        if let Err(e) = module::validate_module_id(&entry.id) {
+            warn!("skipping entry of {url}: {e:#}");
+            continue;
+        }
+        if let Err(e) = channel::validate(&entry.channel) {
+            warn!("skipping {} of {url}: {e:#}", entry.id);
+            continue;
+        }
+        if !is_sha256(&entry.sha256) {
+            warn!(
+                "skipping {} of {url}: sha256 must be 64 hex digits",
+                entry.id
+            );
+            continue;
+        }
+        entry.sha256.make_ascii_lowercase();
+        entry.url = resolve_url(url, &entry.url);
+        resolve_deltas(url, &entry.id, &mut entry.deltas);
+        modules.push(entry);
+    }
+    Ok(modules)
+}
+
+/// Add the repository at `url` and fetch its index. Returns whether it
+/// was new.
+pub fn add(url: &str) -> anyhow::Result<bool> {
+    if !cache::is_url(url) {
+        bail!("repository `{url}` must be an http(s) URL");
+    }
+    let mut repos = load()?;
+    let new = !repos.contains_key(url);
+
+    let modules = fetch_index(url)?;
+    info!("{url} offers {} module(s)", modules.len());
+    repos.insert(
+        url.to_string(),
+        Repo {
+            updated: clock::unix_now(),
+            modules,
+        },
+    );
+    save(&repos)?;
+    Ok(new)
+}
+
+/// Returns whether the repository was added before.
+pub fn remove(url: &str) -> anyhow::Result<bool> {
+    let mut repos = load()?;
+    if repos.remove(url).is_none() {
+        return Ok(false);
+    }
+    save(&repos)?;
+    Ok(true)
+}
+
+/// Fetch the index of every repository again. A repository that cannot be
+/// reached keeps its previous index.
+pub fn update() -> anyhow::Result<()> {
+    let mut repos = load()?;
+    if repos.is_empty() {
+        bail!("no repositories, add one with `repo add <url>`");
+    }
+
+    let mut failed = 0;
+    for (url, repo) in repos.iter_mut() {
+        match fetch_index(url) {
+            Ok(modules) => {
+                info!("{url} offers {} module(s)", modules.len());
+                repo.modules = modules;
+                repo.updated = clock::unix_now();
+            }
+            Err(e) => {
+                warn!(
+                    "{e:#}, keeping the index from {}",
+                    clock::local(repo.updated)
+                );
+                failed += 1;
+            }
+        }
+    }
+    save(&repos)?;
+
+    if failed == repos.len() {
+        bail!("no repository could be updated");
+    }
+    Ok(())
+}
+
+/// URLs of the added repositories, none when they cannot be read.
+pub fn urls() -> Vec<String> {
+    load()
+        .map(|repos| repos.into_keys().collect())
+        .unwrap_or_default()
+}
+
+pub fn list() -> anyhow::Result<()> {
+    let repos = load()?;
+
+    info!("repositories:");
+    if repos.is_empty() {
+        info!("  (none)");
+    }
+    for (url, repo) in &repos {
+        info!(
+            "  {url} - {} module(s), updated {}",
+            repo.modules.len(),
+            clock::local(repo.updated)
+        );
+    }
+
+    Ok(())
+}
+
+/// Modules whose id or name contains `term`, ignoring case.
+pub fn search(term: &str) -> anyhow::Result<Vec<RepoMatch>> {
+    let term = term.to_lowercase();
+    let mut matches = Vec::new();
+    for (url, repo) in load()? {
+        for module in repo.modules {
+            if !module.id.to_lowercase().contains(&term)
+                && !module.name.to_lowercase().contains(&term)
+            {
+                continue;
+            }
+            matches.push(RepoMatch {
+                installed: module::module_version(&Path::new(MODULES_DIR).join(&module.id)),
+                repo: url.clone(),
+                module,
+            });
+        }
+    }
+    matches.sort_by(|a, b| a.module.id.cmp(&b.module.id));
+    Ok(matches)
+}
+
+pub fn print_search(matches: &[RepoMatch]) {
+    if matches.is_empty() {
+        info!("no matching modules");
+    }
+    for hit in matches {
+        let installed = match hit.installed {
+            Some(version) if version < hit.module.version => format!(", v{version} installed"),
+            Some(_) => ", installed".to_string(),
+            None => String::new(),
+        };
+        let channel = if hit.module.channel == channel::DEFAULT_CHANNEL {
+            String::new()
+        } else {
+            format!(", {} channel", hit.module.channel)
+        };
+        info!(
+            "{} - {} v{} ({}{channel}{installed})",
+            hit.module.id, hit.module.name, hit.module.version, hit.repo
+        );
+    }
+}
+
+/// The newest version of `module_id` on `channel` across all repositories.
+pub fn offered(module_id: &str, channel: &str) -> anyhow::Result<Option<RepoModule>> {
+    Ok(load()?
+        .into_values()
+        .flat_map(|repo| repo.modules)
+        .filter(|module| module.id == module_id && module.channel == channel)
+        .max_by_key(|module| module.version))
+}
+
+/// The newest version of `module_id` across all repositories, on the
+/// channel chosen for it.
+pub fn find(module_id: &str) -> anyhow::Result<RepoModule> {
+    if load()?.is_empty() {
+        bail!("no repositories, add one with `repo add <url>`");
+    }
+    let channel = channel::of(module_id);
+    offered(module_id, &channel)?.with_context(|| {
+        if channel == channel::DEFAULT_CHANNEL {
+            format!("no repository offers {module_id}, try `repo update` or `repo search`")
+        } else {
+            format!(
+                "no repository offers {module_id} on the {channel} channel, try `repo update` or `module channel set {module_id} {}`",
+                channel::DEFAULT_CHANNEL
+            )
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    const SHA: &str = "AB00000000000000000000000000000000000000000000000000000000000000";
+
+    fn delta(from_version: i64, url: &str, sha256: &str) -> RepoDelta {
+        RepoDelta {
+            from_version,
+            url: url.to_string(),
+            sha256: sha256.to_string(),
+        }
+    }
+
+    #[test]
+    fn resolve_deltas_drops_malformed_and_resolves_urls() {
+        let mut deltas = vec![
+            delta(1, "./demo-1-3.zip", SHA),
+            delta(2, "https://cdn.example/demo-2-3.zip", SHA),
+            delta(3, "demo-bad.zip", "abc"),
+        ];
+        resolve_deltas("https://example.com/repo/index.json", "demo", &mut deltas);
+
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(deltas[0].url, "https://example.com/repo/demo-1-3.zip");
+        assert_eq!(deltas[0].sha256, SHA.to_ascii_lowercase());
+        assert_eq!(deltas[1].url, "https://cdn.example/demo-2-3.zip");
+    }
+
+    #[test]
+    fn delta_from_matches_installed_version_only() {
+        let module: RepoModule = serde_json::from_value(serde_json::json!({
+            "id": "demo",
+            "name": "Demo",
+            "version": 3,
+            "url": "demo-3.zip",
+            "sha256": SHA,
+            "deltas": [{ "from_version": 2, "url": "demo-2-3.zip", "sha256": SHA }],
+        }))
+        .unwrap();
+
+        assert_eq!(module.delta_from(Some(2)).unwrap().url, "demo-2-3.zip");
+        assert!(module.delta_from(Some(1)).is_none());
+        assert!(module.delta_from(None).is_none());
+    }
+
+    #[test]
+    fn install_preferring_delta_falls_back_to_full_package() {
+        let tried = RefCell::new(Vec::new());
+        let result = install_preferring_delta(
+            Some(delta(2, "delta.zip", "d")),
+            "full.zip".to_string(),
+            Some("f".to_string()),
+            |path, sha256| {
+                tried.borrow_mut().push((path.clone(), sha256));
+                if path == "delta.zip" {
+                    bail!("installed copy is missing system/bin/demo");
+                }
+                Ok(())
+            },
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            tried.into_inner(),
+            [
+                ("delta.zip".to_string(), Some("d".to_string())),
+                ("full.zip".to_string(), Some("f".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn install_preferring_delta_stops_after_delta_succeeds() {
+        let tried = RefCell::new(Vec::new());
+        install_preferring_delta(
+            Some(delta(2, "delta.zip", "d")),
+            "full.zip".to_string(),
+            None,
+            |path, _| {
+                tried.borrow_mut().push(path);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!(tried.into_inner(), ["delta.zip"]);
+    }
+}
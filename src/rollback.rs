@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+use crate::defs::{MODULES_BACKUP_DIR, MODULES_DIR, MODULES_UPDATE_DIR};
+use crate::module;
+use crate::status;
+
+/// Previous versions kept per module; older ones are deleted when another
+/// is archived.
+const KEPT_VERSIONS: usize = 2;
+
+fn backup_dir(module_id: &str) -> PathBuf {
+    Path::new(MODULES_BACKUP_DIR).join(module_id)
+}
+
+/// Archived versions of a module, oldest first.
+pub fn versions(module_id: &str) -> Vec<i64> {
+    let mut versions: Vec<i64> = fs::read_dir(backup_dir(module_id))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect();
+    versions.sort();
+    versions
+}
+
+/// Move the installed `module_dir` at `version` into the backup directory
+/// before an update replaces it, pruning the oldest archived versions.
+pub fn archive(module_id: &str, module_dir: &Path, version: i64) -> Result<()> {
+    let dir = backup_dir(module_id);
+    fs::create_dir_all(&dir)?;
+    let target = dir.join(version.to_string());
+    module::delete_dir(&target)?;
+    fs::rename(module_dir, &target)?;
+    info!("kept v{version} of {module_id} in {target:?}");
+
+    let versions = versions(module_id);
+    let excess = versions.len().saturating_sub(KEPT_VERSIONS);
+    for old in &versions[..excess] {
+        module::delete_dir(&dir.join(old.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Drop the oldest archived version of every module that has one, leaving
+/// the critical path backups in `<id>/critical/` alone. Returns the bytes
+/// freed, or `None` when no version is archived.
+pub fn evict_oldest() -> Result<Option<u64>> {
+    let mut ids: Vec<String> = fs::read_dir(MODULES_BACKUP_DIR)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    ids.sort();
+
+    let mut freed = None;
+    for id in ids {
+        let Some(oldest) = versions(&id).first().copied() else {
+            continue;
+        };
+        let dir = backup_dir(&id).join(oldest.to_string());
+        let size = status::dir_size(&dir);
+        module::delete_dir(&dir)?;
+        info!("dropped archived v{oldest} of {id} ({} KiB)", size / 1024);
+        *freed.get_or_insert(0) += size;
+    }
+    Ok(freed)
+}
+
+/// Drop every archived version of a module, when it is uninstalled.
+pub fn remove(module_id: &str) -> Result<()> {
+    module::delete_dir(&backup_dir(module_id))
+}
+
+/// Stage an archived version of `module_id` as its pending update: `to`, or
+/// else the newest one older than the installed version. Returns the
+/// version staged.
+pub fn rollback(module_id: &str, to: Option<i64>) -> Result<i64> {
+    let module_dir = Path::new(MODULES_DIR).join(module_id);
+    if !module_dir.is_dir() {
+        bail!("module {module_id} is not installed");
+    }
+    let update_dir = Path::new(MODULES_UPDATE_DIR).join(module_id);
+    if update_dir.exists() {
+        bail!(
+            "module {module_id} has a pending update, cancel it with `module cancel-update` first"
+        );
+    }
+
+    let versions = versions(module_id);
+    if versions.is_empty() {
+        bail!("no previous version of {module_id} is kept");
+    }
+    let installed = module::module_version(&module_dir);
+    let version = match to {
+        Some(version) if versions.contains(&version) => version,
+        Some(version) => bail!(
+            "v{version} of {module_id} is not kept, available: {}",
+            list(&versions)
+        ),
+        None => *versions
+            .iter()
+            .rev()
+            .find(|version| installed.is_none_or(|installed| **version < installed))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "no kept version of {module_id} is older than the installed one, available: {}",
+                    list(&versions)
+                )
+            })?,
+    };
+
+    module::move_dir(
+        &backup_dir(module_id).join(version.to_string()),
+        &update_dir,
+    )?;
+    Ok(version)
+}
+
+fn list(versions: &[i64]) -> String {
+    versions
+        .iter()
+        .map(|version| format!("v{version}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use dialoguer::Input;
+use tempfile::tempdir;
+
+use crate::module;
+
+/// What goes into the `module.prop` of a new module.
+pub struct Skeleton {
+    pub id: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub version: Option<i64>,
+    pub author: Option<String>,
+}
+
+const INSTALL_SH: &str = r#"#!/bin/sh
+# Runs on the device after the module is staged, with MODDIR,
+# SCRIBA_MODULE_ID, SCRIBA_MODULE_VERSION, SCRIBA_VER and SCRIBA_VER_CODE
+# set. A non-zero exit aborts the install.
+#
+# To ask the user something at install time, answered in
+# $SCRIBA_ANSWER_<KEY>, uncomment:
+# #!prompt example "Example question" default
+"#;
+
+const BOOT_COMPLETE_SH: &str = r#"#!/bin/sh
+# Runs at every boot, once the module's system/ tree is mounted over /.
+# MODDIR is the module's directory, MODDATA a directory for its data that
+# survives updates, SCRIBA_VER_CODE the version of scriba.
+"#;
+
+/// Fill in what was not given, asking when running in a terminal.
+fn ask(given: Option<String>, prompt: &str, default: &str) -> anyhow::Result<String> {
+    if let Some(value) = given {
+        return Ok(value);
+    }
+    if !io::stdin().is_terminal() {
+        return Ok(default.to_string());
+    }
+    Ok(Input::new()
+        .with_prompt(prompt)
+        .default(default.to_string())
+        .interact_text()?)
+}
+
+fn write_script(path: &Path, content: &str) -> anyhow::Result<()> {
+    fs::write(path, content)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+/// Write the skeleton into `dir`, which must be named after the module id.
+fn write_skeleton(skeleton: &Skeleton, dir: &Path) -> anyhow::Result<()> {
+    let name = ask(skeleton.name.clone(), "Name", &skeleton.id)?;
+    let description = ask(
+        skeleton.description.clone(),
+        "Description",
+        "A scriba module",
+    )?;
+    let version = match skeleton.version {
+        Some(version) => version,
+        None => ask(None, "Version", "1")?
+            .parse()
+            .context("version must be an integer")?,
+    };
+    let author = ask(skeleton.author.clone(), "Author", "unknown")?;
+
+    fs::create_dir(dir).with_context(|| format!("failed to create {dir:?}"))?;
+    let prop = format!(
+        "id={}\nname={name}\ndescription={description}\nversion={version}\nauthor={author}\nskip_mount=false\n",
+        skeleton.id
+    );
+    fs::write(dir.join("module.prop"), prop)?;
+    write_script(&dir.join("install.sh"), INSTALL_SH)?;
+    write_script(&dir.join("boot-complete.sh"), BOOT_COMPLETE_SH)?;
+    fs::create_dir(dir.join("system"))?;
+
+    // catch anything the answers broke, e.g. a version out of range
+    module::read_module_prop(&dir.join("module.prop"))
+        .context("the generated module.prop is invalid")?;
+    Ok(())
+}
+
+/// Generate a new module source directory at `output`, or a zip of one when
+/// `zip` is set.
+pub fn create(skeleton: &Skeleton, output: &Path, zip: bool) -> anyhow::Result<()> {
+    module::validate_module_id(&skeleton.id)?;
+    if output.exists() {
+        bail!("{output:?} already exists");
+    }
+
+    // built under its id, which module.prop validation expects
+    let scratch = tempdir()?;
+    let dir = scratch.path().join(&skeleton.id);
+    write_skeleton(skeleton, &dir)?;
+    if zip {
+        module::pack_module(&dir, output, false, None)
+    } else {
+        module::copy_dir(&dir, output)
+    }
+}
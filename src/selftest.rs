@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use libc::{MS_PRIVATE, MS_REC};
+use serde::Serialize;
+use tempfile::tempdir;
+use tracing::{info, warn};
+use zip::CompressionMethod;
+
+use crate::archive::{self, Limits};
+use crate::defs::STATE_DIR;
+use crate::module;
+use crate::mount;
+use crate::state;
+
+/// Outcome of one selftest check.
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    /// The error, for failed checks
+    pub detail: Option<String>,
+}
+
+const PROP: &str =
+    "id=selftest\nname=Selftest\ndescription=selftest module\nversion=1\nskip_mount=false\n";
+
+/// Write a minimal module into `dir` and parse its module.prop back.
+fn prop_parsing(dir: &Path) -> Result<()> {
+    let module_dir = dir.join("selftest");
+    fs::create_dir_all(module_dir.join("system/bin"))?;
+    fs::write(module_dir.join("module.prop"), PROP)?;
+    fs::write(module_dir.join("system/bin/selftest"), "selftest\n")?;
+
+    let props = module::read_module_prop(&module_dir.join("module.prop"))?;
+    if props.get("version").map(String::as_str) != Some("1") {
+        bail!("module.prop parsed to {props:?}");
+    }
+    Ok(())
+}
+
+/// Zip the module `prop_parsing` wrote and unpack it again.
+fn extraction(dir: &Path) -> Result<()> {
+    let archive_path = dir.join("selftest.zip");
+    module::zip_dir(
+        &dir.join("selftest"),
+        &archive_path,
+        CompressionMethod::Deflated,
+        true,
+    )?;
+    let unpacked = dir.join("unpacked");
+    archive::extract(&archive_path, &unpacked, Limits::default())?;
+    if fs::read_to_string(unpacked.join("system/bin/selftest"))? != "selftest\n" {
+        bail!("unpacked file differs from the packed one");
+    }
+    Ok(())
+}
+
+/// Replace a file in the real state directory and read it back.
+fn state_io() -> Result<()> {
+    let path = Path::new(STATE_DIR).join(".selftest");
+    let result = (|| -> Result<()> {
+        state::write_atomic(&path, "first")?;
+        state::write_atomic(&path, "second")?;
+        if fs::read_to_string(&path)? != "second" {
+            bail!("{path:?} does not hold what was written last");
+        }
+        Ok(())
+    })();
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// Bind a file over another inside a private mount namespace, which leaves
+/// the rest of the system alone and goes away with this process.
+fn private_mount(dir: &Path) -> Result<()> {
+    // SAFETY: unshare only changes this process's namespaces
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("unshare of the mount namespace");
+    }
+    mount::sys_mount(
+        Path::new("none"),
+        Path::new("/"),
+        None,
+        MS_REC | MS_PRIVATE,
+        None,
+    )
+    .context("making / private")?;
+
+    let (src, dst) = (dir.join("mount-src"), dir.join("mount-dst"));
+    fs::write(&src, "module")?;
+    fs::write(&dst, "original")?;
+    mount::bind_mount(&src, &dst)?;
+    let mounted = fs::read_to_string(&dst)?;
+    mount::unmount(&dst)?;
+    if mounted != "module" {
+        bail!("bind mount did not show the module file");
+    }
+    if fs::read_to_string(&dst)? != "original" {
+        bail!("unmount did not bring the original file back");
+    }
+    Ok(())
+}
+
+/// Exercise prop parsing, extraction, state IO and mounting in scratch
+/// space. A binary failing these should not run the next boot.
+pub fn run() -> Result<Vec<Check>> {
+    let scratch = tempdir()?;
+    let dir = scratch.path();
+    let checks: [(&'static str, &dyn Fn() -> Result<()>); 4] = [
+        ("prop parsing", &|| prop_parsing(dir)),
+        ("extraction", &|| extraction(dir)),
+        ("state io", &state_io),
+        ("private mount", &|| private_mount(dir)),
+    ];
+
+    Ok(checks
+        .into_iter()
+        .map(|(name, check)| {
+            let result = check();
+            Check {
+                name,
+                passed: result.is_ok(),
+                detail: result.err().map(|e| format!("{e:#}")),
+            }
+        })
+        .collect())
+}
+
+pub fn print(checks: &[Check]) {
+    for check in checks {
+        match &check.detail {
+            None => info!("{}: pass", check.name),
+            Some(detail) => warn!("{}: FAIL, {detail}", check.name),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+use anyhow::{Context, bail};
+use tracing::{info, warn};
+
+use crate::integrity;
+
+/// Longest request line or header accepted.
+const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Address other machines on the LAN reach this host at: the source
+/// address of the default route. Connecting a UDP socket sends nothing.
+fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_unspecified()).then_some(ip)
+}
+
+/// `path` with every byte outside the URL-safe set percent-encoded.
+fn url_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn url_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// File below `root` a request path names, refusing anything that could
+/// leave it.
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next()?;
+    let rel = PathBuf::from(url_decode(path)?.trim_start_matches('/'));
+    if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let path = root.join(rel).canonicalize().ok()?;
+    (path.starts_with(root) && path.is_file()).then_some(path)
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE_LEN as u64)
+        .read_line(&mut line)?;
+    Ok(line.trim_end().to_string())
+}
+
+fn respond(stream: &mut TcpStream, status: &str, length: u64) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/octet-stream\r\nContent-Length: {length}\r\nConnection: close\r\n\r\n"
+    )
+}
+
+fn handle(root: &Path, mut stream: TcpStream) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = read_line(&mut reader)?;
+    while !read_line(&mut reader)?.is_empty() {}
+
+    let mut parts = request.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, "400 Bad Request", 0);
+    };
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", 0);
+    }
+    let Some(path) = resolve(root, target) else {
+        warn!("{peer}: {method} {target} -> 404");
+        return respond(&mut stream, "404 Not Found", 0);
+    };
+
+    let mut file = fs::File::open(&path)?;
+    respond(&mut stream, "200 OK", file.metadata()?.len())?;
+    if method == "GET" {
+        io::copy(&mut file, &mut stream)?;
+    }
+    info!("{peer}: {method} {target} -> 200");
+    Ok(())
+}
+
+/// Serve the files below `dir` over HTTP until interrupted, printing a
+/// `module install` command for each.
+pub fn serve_dir(dir: &Path, bind: IpAddr, port: u16) -> anyhow::Result<()> {
+    let root = dir
+        .canonicalize()
+        .with_context(|| format!("cannot serve {dir:?}"))?;
+    if !root.is_dir() {
+        bail!("{dir:?} is not a directory");
+    }
+
+    let listener = TcpListener::bind((bind, port))
+        .with_context(|| format!("failed to listen on {bind}:{port}"))?;
+    let host = if bind.is_unspecified() {
+        lan_address().unwrap_or(bind)
+    } else {
+        bind
+    };
+    let address = SocketAddr::new(host, listener.local_addr()?.port());
+    info!("serving {root:?} on http://{address}/");
+
+    for (rel, digest) in integrity::tree_digests(&root)? {
+        if digest.starts_with("->") {
+            continue;
+        }
+        let url = format!("http://{address}/{}", url_encode(&rel.to_string_lossy()));
+        println!("scriba module install {url} --sha256 {digest}");
+    }
+    info!("press Ctrl-C to stop");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        let root = root.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(&root, stream) {
+                warn!("request failed: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Canonical served root with `module.zip` and `sub dir/other.zip`,
+    /// next to a file outside it.
+    fn served() -> (tempfile::TempDir, PathBuf) {
+        let scratch = tempdir().unwrap();
+        let root = scratch.path().join("root");
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::write(root.join("module.zip"), "zip").unwrap();
+        fs::write(root.join("sub dir/other.zip"), "zip").unwrap();
+        fs::write(scratch.path().join("secret"), "secret").unwrap();
+        let root = root.canonicalize().unwrap();
+        (scratch, root)
+    }
+
+    #[test]
+    fn resolve_finds_files_below_the_root() {
+        let (_scratch, root) = served();
+
+        assert_eq!(resolve(&root, "/module.zip"), Some(root.join("module.zip")));
+        assert_eq!(
+            resolve(&root, "/sub%20dir/other.zip?v=1#x"),
+            Some(root.join("sub dir/other.zip"))
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_escapes_and_malformed_paths() {
+        let (_scratch, root) = served();
+        for target in [
+            "/../secret",
+            "/%2e%2e/secret",
+            "/sub%20dir/../../secret",
+            "//etc/passwd",
+            "/./module.zip",
+            "/",
+            "/sub%20dir",
+            "/missing.zip",
+            "/module.zip%",
+            "/module.zip%2",
+            "/module.zip%zz",
+            "/%ff%fe",
+            "/%00",
+        ] {
+            assert_eq!(resolve(&root, target), None, "{target:?} resolved");
+        }
+    }
+
+    #[test]
+    fn resolve_rejects_symlinks_out_of_the_root() {
+        let (scratch, root) = served();
+        std::os::unix::fs::symlink(scratch.path().join("secret"), root.join("leak")).unwrap();
+        std::os::unix::fs::symlink(scratch.path(), root.join("up")).unwrap();
+
+        assert_eq!(resolve(&root, "/leak"), None);
+        assert_eq!(resolve(&root, "/up/secret"), None);
+    }
+
+    #[test]
+    fn url_decode_needs_two_hex_digits() {
+        assert_eq!(url_decode("a%20b").as_deref(), Some("a b"));
+        for malformed in ["%", "%2", "%zz", "%+1", "%-1", "%ff"] {
+            assert_eq!(url_decode(malformed), None, "{malformed:?} decoded");
+        }
+    }
+}
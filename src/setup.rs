@@ -0,0 +1,147 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use clap::crate_name;
+use tracing::{info, warn};
+
+use crate::defs::{
+    BIN_DIR, INIT_HOOK, LOGS_DIR, MODULES_DIR, MODULES_UPDATE_DIR, PATH_PROFILE_FILE, PROFILE_HOOK,
+    SCRIBA_DIR, STATE_DIR,
+};
+use crate::process;
+
+/// Where `install-self` puts the binary.
+pub fn installed_binary() -> PathBuf {
+    Path::new(BIN_DIR).join(crate_name!())
+}
+
+fn init_hook_script() -> String {
+    format!(
+        "#!/bin/sh\n\
+         # installed by {name} install-self\n\
+         case \"$1\" in\n\
+         \x20 start) {bin} internal boot-complete >/dev/null 2>&1 & ;;\n\
+         esac\n",
+        name = crate_name!(),
+        bin = installed_binary().display()
+    )
+}
+
+/// Sources the generated profile, which does not exist before the first
+/// boot-complete.
+fn profile_hook_script() -> String {
+    format!(
+        "# installed by {name} install-self\n\
+         [ -r {profile} ] && . {profile}\n",
+        name = crate_name!(),
+        profile = PATH_PROFILE_FILE
+    )
+}
+
+/// Run `internal selftest` of the binary at `binary`, as it runs on the
+/// device, before it is trusted with the init hook.
+fn selftest(binary: &Path) -> anyhow::Result<()> {
+    let binary = binary.to_string_lossy();
+    let status = process::run_with_output(&binary, &["internal", "selftest"])
+        .with_context(|| format!("failed to run the selftest of {binary}"))?;
+    if !status.success() {
+        bail!("{binary} failed its selftest, not installing it");
+    }
+    Ok(())
+}
+
+/// Copy the running binary into `BIN_DIR`, create the directory layout and
+/// install the init hook that runs boot-complete.
+pub fn install_self() -> anyhow::Result<()> {
+    for dir in [
+        BIN_DIR,
+        MODULES_DIR,
+        MODULES_UPDATE_DIR,
+        STATE_DIR,
+        LOGS_DIR,
+    ] {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {dir}"))?;
+    }
+
+    let current = std::env::current_exe()?.canonicalize()?;
+    let target = installed_binary();
+    if target.canonicalize().ok().as_ref() == Some(&current) {
+        info!("already running from {target:?}, not copying");
+        selftest(&target)?;
+    } else {
+        // copy next to the target and rename, so a running copy is never
+        // overwritten in place
+        let tmp = target.with_extension("new");
+        fs::copy(&current, &tmp)?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+        if let Err(e) = selftest(&tmp) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+        fs::rename(&tmp, &target)?;
+        info!("installed {current:?} to {target:?}");
+    }
+
+    fs::write(INIT_HOOK, init_hook_script())
+        .and_then(|_| fs::set_permissions(INIT_HOOK, fs::Permissions::from_mode(0o755)))
+        .with_context(|| {
+            format!("failed to install init hook {INIT_HOOK}, is the root filesystem writable?")
+        })?;
+    info!("installed init hook {INIT_HOOK}");
+
+    // modules still work without it, only their path_dirs are not on PATH
+    // in login shells
+    let profile_hook = Path::new(PROFILE_HOOK);
+    match profile_hook
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(profile_hook, profile_hook_script()))
+    {
+        Ok(()) => info!("installed profile hook {PROFILE_HOOK}"),
+        Err(e) => warn!("failed to install profile hook {PROFILE_HOOK}: {e}"),
+    }
+
+    info!("next steps:");
+    info!(
+        "  1. install modules with `{} module install <zip>`",
+        target.display()
+    );
+    info!("  2. reboot, boot-complete runs from {INIT_HOOK}");
+    info!(
+        "  3. check `{} simulate-boot` before later reboots",
+        target.display()
+    );
+    Ok(())
+}
+
+/// Undo `install-self`. Modules, config and logs stay unless `purge` is set.
+pub fn uninstall_self(purge: bool) -> anyhow::Result<()> {
+    for path in [
+        Path::new(INIT_HOOK),
+        Path::new(PROFILE_HOOK),
+        &installed_binary(),
+    ] {
+        match fs::remove_file(path) {
+            Ok(()) => info!("removed {path:?}"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("failed to remove {path:?}: {e}"),
+        }
+    }
+
+    if purge {
+        fs::remove_dir_all(SCRIBA_DIR)
+            .or_else(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e),
+            })
+            .with_context(|| format!("failed to remove {SCRIBA_DIR}"))?;
+        info!("removed {SCRIBA_DIR} with all modules, config and logs");
+    } else {
+        info!("modules, config and logs are kept in {SCRIBA_DIR}, pass --purge to remove them");
+    }
+
+    info!("reboot to unload mounted modules");
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use anyhow::{Context, anyhow, bail};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use tracing::info;
+
+use crate::defs::{KEYS_DIR, SIGNATURE_FILE};
+use crate::integrity;
+
+/// Outcome of checking a module's `signature` file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// Signed by the key stored as `<name>.pub` in the keys directory
+    Valid(String),
+    /// No `signature` file
+    Unsigned,
+    /// The signature is malformed, does not match or names an unknown key
+    Invalid(String),
+}
+
+/// Canonical listing of a module tree that signatures cover: one
+/// `<sha256>  <path>` line per file and `-> <target>  <path>` per symlink,
+/// sorted by path, leaving out the signature itself and the state flags and
+/// hash manifest scriba adds once the module is installed.
+fn manifest(module_dir: &Path) -> anyhow::Result<Vec<u8>> {
+    let mut manifest = Vec::new();
+    for (rel, digest) in integrity::tree_digests(module_dir)? {
+        if rel != Path::new(SIGNATURE_FILE) && !integrity::is_bookkeeping(&rel) {
+            manifest.extend_from_slice(format!("{digest}  {}\n", rel.to_string_lossy()).as_bytes());
+        }
+    }
+    Ok(manifest)
+}
+
+fn decode_key<const N: usize>(hex_value: &str, what: &str) -> anyhow::Result<[u8; N]> {
+    hex::decode(hex_value.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("{what} must be {} hex digits", N * 2))
+}
+
+/// Trusted public keys, `<name>.pub` files holding the key in hex.
+fn trusted_keys() -> anyhow::Result<Vec<(String, [u8; 32])>> {
+    let entries = match fs::read_dir(KEYS_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut keys = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "pub") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let key = decode_key(&fs::read_to_string(&path)?, &format!("key {path:?}"))?;
+        keys.push((name, key));
+    }
+    Ok(keys)
+}
+
+/// Check the `signature` file of an extracted module against the keys in
+/// the keys directory.
+pub fn verify(module_dir: &Path) -> anyhow::Result<Verification> {
+    let content = match fs::read_to_string(module_dir.join(SIGNATURE_FILE)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Verification::Unsigned),
+        Err(e) => return Err(e.into()),
+    };
+
+    // first line the signer's public key, second line the signature
+    let mut lines = content.lines();
+    let (Some(public), Some(signature)) = (lines.next(), lines.next()) else {
+        return Ok(Verification::Invalid(
+            "signature file must hold a public key and a signature line".to_string(),
+        ));
+    };
+    let public: [u8; 32] = match decode_key(public, "public key") {
+        Ok(public) => public,
+        Err(e) => return Ok(Verification::Invalid(e.to_string())),
+    };
+    let signature: [u8; 64] = match decode_key(signature, "signature") {
+        Ok(signature) => signature,
+        Err(e) => return Ok(Verification::Invalid(e.to_string())),
+    };
+
+    let Some((name, _)) = trusted_keys()?.into_iter().find(|(_, key)| *key == public) else {
+        return Ok(Verification::Invalid(format!(
+            "signed by {} which is not in {KEYS_DIR}",
+            hex::encode(public)
+        )));
+    };
+    let key = match VerifyingKey::from_bytes(&public) {
+        Ok(key) => key,
+        Err(e) => return Ok(Verification::Invalid(format!("public key is invalid: {e}"))),
+    };
+    match key.verify_strict(&manifest(module_dir)?, &Signature::from_bytes(&signature)) {
+        Ok(()) => Ok(Verification::Valid(name)),
+        Err(_) => Ok(Verification::Invalid(format!(
+            "signature by {name} does not match the module contents"
+        ))),
+    }
+}
+
+/// Load the secret key at `key_path`, creating a new one when missing.
+fn load_or_create_key(key_path: &Path) -> anyhow::Result<SigningKey> {
+    match fs::read_to_string(key_path) {
+        Ok(content) => {
+            let seed = decode_key(&content, &format!("secret key {key_path:?}"))?;
+            return Ok(SigningKey::from_bytes(&seed));
+        }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        Err(_) => {}
+    }
+
+    let mut seed = [0u8; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut seed)?;
+    fs::write(key_path, hex::encode(seed))
+        .with_context(|| format!("failed to write secret key {key_path:?}"))?;
+    fs::set_permissions(key_path, fs::Permissions::from_mode(0o600))?;
+    info!("created secret key {key_path:?}");
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Sign a module source directory with the secret key at `key_path`,
+/// writing its `signature` file. Returns the public key in hex.
+pub fn sign(module_dir: &Path, key_path: &Path) -> anyhow::Result<String> {
+    if !module_dir.join("module.prop").is_file() {
+        bail!("{module_dir:?} does not contain a module.prop");
+    }
+
+    let key = load_or_create_key(key_path)?;
+    let public = hex::encode(key.verifying_key().to_bytes());
+    let signature = key.sign(&manifest(module_dir)?);
+    fs::write(
+        module_dir.join(SIGNATURE_FILE),
+        format!("{public}\n{}\n", hex::encode(signature.to_bytes())),
+    )?;
+    Ok(public)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::defs::HASHES_FILE;
+
+    #[test]
+    fn manifest_ignores_install_bookkeeping() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("system/bin")).unwrap();
+        fs::write(dir.path().join("module.prop"), "id=example\n").unwrap();
+        fs::write(dir.path().join("system/bin/tool"), "tool").unwrap();
+        let signed = manifest(dir.path()).unwrap();
+
+        fs::write(dir.path().join(SIGNATURE_FILE), "key\nsignature\n").unwrap();
+        fs::write(dir.path().join(HASHES_FILE), "digests").unwrap();
+        fs::write(dir.path().join("disable.flag"), "").unwrap();
+        assert_eq!(manifest(dir.path()).unwrap(), signed);
+
+        // a nested file of the same name is module content
+        fs::write(dir.path().join("system/bin/disable.flag"), "").unwrap();
+        assert_ne!(manifest(dir.path()).unwrap(), signed);
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use tempfile::tempdir;
+use tracing::{info, warn};
+
+use crate::boot;
+use crate::config::AppConfig;
+use crate::defs::{MODULES_DIR, MODULES_UPDATE_DIR, SCRIPT_RESULTS_DIR};
+use crate::module;
+use crate::mount;
+use crate::selftest::Check;
+
+/// A module shipped inside scriba, written out as `(path, content, mode)`.
+struct Example {
+    id: &'static str,
+    files: &'static [(&'static str, &'static str, u32)],
+}
+
+const OVERRIDE_ID: &str = "scriba_example_override";
+const SCRIPT_ID: &str = "scriba_example_script";
+
+/// File the override example adds to `/`, and what it holds.
+const OVERRIDE_TARGET: &str = "/etc/scriba-example";
+const OVERRIDE_CONTENT: &str = "mounted by scriba\n";
+
+/// What the boot script example leaves in its data directory.
+const SCRIPT_MARKER: &str = "boot-complete-ran";
+
+const EXAMPLES: &[Example] = &[
+    Example {
+        id: OVERRIDE_ID,
+        files: &[
+            (
+                "module.prop",
+                "id=scriba_example_override\nname=Example: file override\ndescription=Adds /etc/scriba-example\nversion=1\nauthor=scriba\nskip_mount=false\n",
+                0o644,
+            ),
+            ("system/etc/scriba-example", OVERRIDE_CONTENT, 0o644),
+            (
+                "boot-complete.sh",
+                "#!/bin/sh\n# nothing to do at boot, the payload is the example\n",
+                0o755,
+            ),
+        ],
+    },
+    Example {
+        id: SCRIPT_ID,
+        files: &[
+            (
+                "module.prop",
+                "id=scriba_example_script\nname=Example: boot script\ndescription=Records each boot in its data directory\nversion=1\nauthor=scriba\nskip_mount=true\n",
+                0o644,
+            ),
+            (
+                "boot-complete.sh",
+                "#!/bin/sh\necho \"scriba $SCRIBA_VER, boot mode $BOOTMODE\" > \"$MODDATA/boot-complete-ran\"\n",
+                0o755,
+            ),
+        ],
+    },
+];
+
+/// Write an example's files into `<dir>/<id>` and pack them as a zip.
+fn pack_example(example: &Example, dir: &Path) -> Result<PathBuf> {
+    let source = dir.join(example.id);
+    for (path, content, mode) in example.files {
+        let path = source.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(*mode))?;
+    }
+    let archive = dir.join(format!("{}.zip", example.id));
+    module::pack_module(&source, &archive, true, None)?;
+    Ok(archive)
+}
+
+/// Move the staged examples into place and mount and run them the way
+/// boot-complete would, without a reboot.
+fn activate(config: &AppConfig) -> Result<()> {
+    for example in EXAMPLES {
+        let staged = Path::new(MODULES_UPDATE_DIR).join(example.id);
+        let target = Path::new(MODULES_DIR).join(example.id);
+        module::move_dir(&staged, &target)?;
+        boot::init_module(&target, config)
+            .with_context(|| format!("failed to initialize {}", example.id))?;
+    }
+    Ok(())
+}
+
+fn verify_override() -> Result<()> {
+    let content = fs::read_to_string(OVERRIDE_TARGET)
+        .with_context(|| format!("{OVERRIDE_TARGET} did not appear"))?;
+    if content != OVERRIDE_CONTENT {
+        bail!("{OVERRIDE_TARGET} does not hold the module's file");
+    }
+    Ok(())
+}
+
+fn verify_script() -> Result<()> {
+    let marker = module::data_dir(SCRIPT_ID).join(SCRIPT_MARKER);
+    let content = fs::read_to_string(&marker)
+        .with_context(|| format!("boot-complete.sh did not write {marker:?}"))?;
+    info!("boot-complete.sh wrote: {}", content.trim());
+    Ok(())
+}
+
+/// Unmount and delete the examples with everything they left behind.
+fn remove() -> Result<()> {
+    for example in EXAMPLES {
+        mount::unmount_module(example.id)?;
+        module::delete_dir(&Path::new(MODULES_DIR).join(example.id))?;
+        module::delete_dir(&Path::new(MODULES_UPDATE_DIR).join(example.id))?;
+        module::delete_dir(&Path::new(SCRIPT_RESULTS_DIR).join(example.id))?;
+        module::wipe_data(example.id)?;
+    }
+    if fs::symlink_metadata(OVERRIDE_TARGET).is_ok() {
+        bail!("{OVERRIDE_TARGET} is still there after unmounting");
+    }
+    Ok(())
+}
+
+/// Install the bundled example modules through `install`, mount them, check
+/// their file and boot script took effect and remove them again. Stops at
+/// the first failing step, but always removes the examples.
+pub fn run(config: &AppConfig, install: &dyn Fn(&Path) -> Result<()>) -> Result<Vec<Check>> {
+    for example in EXAMPLES {
+        if Path::new(MODULES_DIR).join(example.id).exists()
+            || Path::new(MODULES_UPDATE_DIR).join(example.id).exists()
+        {
+            bail!(
+                "module {} is already installed, uninstall it before the smoke test",
+                example.id
+            );
+        }
+    }
+
+    let scratch = tempdir()?;
+    let install_all = || -> Result<()> {
+        for example in EXAMPLES {
+            install(&pack_example(example, scratch.path())?)?;
+        }
+        Ok(())
+    };
+    let steps: [(&'static str, &dyn Fn() -> Result<()>); 4] = [
+        ("install", &install_all),
+        ("mount and boot scripts", &|| activate(config)),
+        ("file override", &verify_override),
+        ("boot script", &verify_script),
+    ];
+
+    let mut checks = Vec::new();
+    for (name, step) in steps {
+        info!("smoke test: {name}");
+        let result = step();
+        let passed = result.is_ok();
+        checks.push(Check {
+            name,
+            passed,
+            detail: result.err().map(|e| format!("{e:#}")),
+        });
+        if !passed {
+            warn!("smoke test: {name} failed, removing the examples");
+            break;
+        }
+    }
+
+    info!("smoke test: remove");
+    let result = remove();
+    checks.push(Check {
+        name: "remove",
+        passed: result.is_ok(),
+        detail: result.err().map(|e| format!("{e:#}")),
+    });
+    Ok(checks)
+}
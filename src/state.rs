@@ -0,0 +1,284 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::ids::{IdSource, ProcessIds};
+
+/// `<path>.<suffix>`, next to `path` so renames stay on one filesystem.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{suffix}"));
+    path.with_file_name(name)
+}
+
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+/// Replace `path` with `contents` so that after a crash or power loss it
+/// holds either the old or the new contents, never a mix.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp = sibling(path, &format!("tmp-{}", ProcessIds.next_id()));
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        sync_parent(path)
+    };
+
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&tmp);
+    })
+}
+
+/// Exclusive lock on a journal, held until dropped.
+struct JournalLock {
+    _file: File,
+}
+
+impl JournalLock {
+    fn acquire(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(sibling(path, "lock"))?;
+        // SAFETY: flock only operates on the descriptor, which outlives the call
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// Cut a record left half-written by a crash, so the next append starts on
+/// a fresh line.
+fn repair_torn_tail(file: &mut File) -> io::Result<()> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    let mut last = [0u8];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    if last[0] == b'\n' {
+        return Ok(());
+    }
+
+    let mut content = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut content)?;
+
+    let keep = content
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    file.set_len(keep as u64)?;
+    file.sync_all()
+}
+
+/// Append one record to a newline-delimited journal and flush it to disk.
+///
+/// A record only counts once its trailing newline is written; a torn record
+/// from an earlier crash is dropped first. Appends from concurrent scriba
+/// processes are serialized through a lock file.
+pub fn append_record(path: &Path, record: &str) -> io::Result<()> {
+    let _lock = JournalLock::acquire(path)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    repair_torn_tail(&mut file)?;
+
+    let mut line = record.replace('\n', " ");
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.sync_all()
+}
+
+/// Complete records of a journal, ignoring a torn last record.
+pub fn read_records(path: &Path) -> io::Result<Vec<String>> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let complete = match content.iter().rposition(|&b| b == b'\n') {
+        Some(i) => &content[..=i],
+        None => &[][..],
+    };
+    Ok(String::from_utf8_lossy(complete)
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
+
+/// Read the records of a journal, let `update` change them and replace the
+/// journal atomically if it reports a change. Appends from other processes
+/// wait for the lock, so none lands between the read and the rewrite.
+pub fn update_journal(
+    path: &Path,
+    update: impl FnOnce(&mut Vec<String>) -> bool,
+) -> io::Result<()> {
+    let _lock = JournalLock::acquire(path)?;
+    let mut records = read_records(path)?;
+    if !update(&mut records) {
+        return Ok(());
+    }
+
+    let mut content = String::new();
+    for record in &records {
+        content.push_str(record);
+        content.push('\n');
+    }
+    write_atomic(path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Set for the child processes the kill tests start and kill.
+    const CHILD_TARGET: &str = "SCRIBA_STATE_TEST_TARGET";
+
+    /// Run the ignored test `name` of this binary in a child process
+    /// writing to `target`, let it work for `run_for` and kill it.
+    fn kill_child(name: &str, target: &Path, run_for: Duration) {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", name, "--ignored", "--test-threads=1"])
+            .env(CHILD_TARGET, target)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        thread::sleep(run_for);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    fn contents(fill: u8) -> Vec<u8> {
+        vec![fill; 4 << 20]
+    }
+
+    #[test]
+    #[ignore = "child of write_atomic_survives_kill"]
+    fn write_atomic_child() {
+        let Some(target) = std::env::var_os(CHILD_TARGET) else {
+            return;
+        };
+        let (old, new) = (contents(b'o'), contents(b'n'));
+        loop {
+            write_atomic(Path::new(&target), &new).unwrap();
+            write_atomic(Path::new(&target), &old).unwrap();
+        }
+    }
+
+    #[test]
+    fn write_atomic_survives_kill() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("state.json");
+        let (old, new) = (contents(b'o'), contents(b'n'));
+        write_atomic(&target, &old).unwrap();
+
+        for round in 0..8 {
+            kill_child(
+                "state::tests::write_atomic_child",
+                &target,
+                Duration::from_millis(40 + 15 * round),
+            );
+            let content = fs::read(&target).unwrap();
+            assert!(
+                content == old || content == new,
+                "round {round}: file holds neither the old nor the new contents"
+            );
+        }
+    }
+
+    #[test]
+    fn write_atomic_replaces_and_cleans_up() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("state.json");
+        write_atomic(&target, "old").unwrap();
+        write_atomic(&target, "new").unwrap();
+
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn append_record_repairs_torn_tail() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        fs::write(&journal, "first\nhalf-writ").unwrap();
+
+        assert_eq!(read_records(&journal).unwrap(), ["first"]);
+        append_record(&journal, "second").unwrap();
+        assert_eq!(fs::read_to_string(&journal).unwrap(), "first\nsecond\n");
+    }
+
+    #[test]
+    fn append_record_keeps_records_on_one_line() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        append_record(&journal, "one\ntwo").unwrap();
+
+        assert_eq!(read_records(&journal).unwrap(), ["one two"]);
+    }
+
+    #[test]
+    fn update_journal_racing_append_keeps_every_record() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        append_record(&journal, "seed").unwrap();
+
+        let appender = {
+            let journal = journal.clone();
+            thread::spawn(move || {
+                for n in 0..200 {
+                    append_record(&journal, &format!("appended-{n}")).unwrap();
+                }
+            })
+        };
+        for _ in 0..200 {
+            update_journal(&journal, |records| {
+                records[0] = "seed".to_string();
+                true
+            })
+            .unwrap();
+        }
+        appender.join().unwrap();
+
+        let records = read_records(&journal).unwrap();
+        assert_eq!(records[0], "seed");
+        for n in 0..200 {
+            assert!(
+                records.contains(&format!("appended-{n}")),
+                "appended-{n} was lost"
+            );
+        }
+    }
+
+    #[test]
+    fn update_journal_without_change_leaves_file_alone() {
+        let dir = tempdir().unwrap();
+        let journal = dir.path().join("journal.jsonl");
+        fs::write(&journal, "kept\ntorn").unwrap();
+
+        update_journal(&journal, |_| false).unwrap();
+        assert_eq!(fs::read_to_string(&journal).unwrap(), "kept\ntorn");
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::boot::{self, BootRecord, DeferredStage};
+use crate::defs::{
+    MODULES_BACKUP_DIR, MODULES_DIR, MODULES_UPDATE_DIR, SAFE_MODE_FLAG, SCRIBA_DIR,
+};
+use crate::module;
+use crate::mount::{self, MountFailure};
+
+/// One-screen overview of scriba on the device.
+#[derive(Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub last_boot: Option<BootRecord>,
+    pub modules: ModuleCounts,
+    /// Modules that failed to initialize last boot or have a broken module.prop
+    pub unhealthy: Vec<String>,
+    /// Module ids that differ only in case and may collide
+    pub id_collisions: Vec<Vec<String>>,
+    /// Last classified mount failure of modules that have not mounted since
+    pub mount_failures: BTreeMap<String, MountFailure>,
+    pub disk: Option<DiskUsage>,
+    pub safe_mode: bool,
+}
+
+#[derive(Default, Serialize)]
+pub struct ModuleCounts {
+    pub enabled: usize,
+    pub disabled: usize,
+    /// Staged modules that are not installed yet
+    pub pending_install: usize,
+    /// Staged updates of installed modules
+    pub pending_update: usize,
+    /// Installed modules flagged for uninstall
+    pub pending_removal: usize,
+}
+
+#[derive(Serialize)]
+pub struct DiskUsage {
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    /// Space taken by installed and staged modules
+    pub modules_bytes: u64,
+}
+
+/// Total size of the files under `path`, not following symlinks.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Total and available bytes of the filesystem holding `path`.
+pub fn filesystem_space(path: &str) -> Option<(u64, u64)> {
+    let path_c = CString::new(path).ok()?;
+    // SAFETY: statvfs only writes into the zeroed struct we pass it
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::statvfs(path_c.as_ptr(), &mut stat) };
+    if ret != 0 {
+        warn!(
+            "failed to stat filesystem of {path}: {}",
+            std::io::Error::last_os_error()
+        );
+        return None;
+    }
+
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+fn disk_usage(path: &str) -> Option<DiskUsage> {
+    let (total_bytes, free_bytes) = filesystem_space(path)?;
+    Some(DiskUsage {
+        total_bytes,
+        free_bytes,
+        modules_bytes: [MODULES_DIR, MODULES_UPDATE_DIR, MODULES_BACKUP_DIR]
+            .iter()
+            .map(|dir| dir_size(Path::new(dir)))
+            .sum(),
+    })
+}
+
+pub fn collect() -> Status {
+    let installed = module::installed_ids();
+    let last_boot = boot::last_boot();
+
+    let mut modules = ModuleCounts::default();
+    let mut unhealthy = Vec::new();
+    for id in &installed {
+        let module_dir = Path::new(MODULES_DIR).join(id);
+        if module::is_enabled(id) {
+            modules.enabled += 1;
+        } else {
+            modules.disabled += 1;
+        }
+        if module_dir.join("uninstall.flag").exists() {
+            modules.pending_removal += 1;
+        }
+
+        let failed_last_boot = last_boot
+            .as_ref()
+            .is_some_and(|boot| boot.failed_modules.contains(id));
+        if failed_last_boot || module::read_module_prop(&module_dir.join("module.prop")).is_err() {
+            unhealthy.push(id.clone());
+        }
+    }
+
+    for id in module::module_ids() {
+        if !Path::new(MODULES_UPDATE_DIR).join(&id).is_dir() {
+            continue;
+        }
+        if installed.contains(&id) {
+            modules.pending_update += 1;
+        } else {
+            modules.pending_install += 1;
+        }
+    }
+
+    Status {
+        version: env!("CARGO_PKG_VERSION"),
+        last_boot,
+        modules,
+        unhealthy,
+        id_collisions: module::all_case_collisions(),
+        mount_failures: mount::mount_failures(),
+        disk: disk_usage(SCRIBA_DIR),
+        safe_mode: Path::new(SAFE_MODE_FLAG).exists(),
+    }
+}
+
+fn mib(bytes: u64) -> u64 {
+    bytes / (1024 * 1024)
+}
+
+pub fn print(status: &Status) {
+    info!("scriba {}", status.version);
+
+    match &status.last_boot {
+        Some(boot) => info!(
+            "last boot: {} at {} (took {} ms)",
+            boot.result, boot.started, boot.duration_ms
+        ),
+        None => info!("last boot: no record"),
+    }
+
+    if let Some(boot) = status
+        .last_boot
+        .as_ref()
+        .filter(|boot| !boot.deferred.is_empty())
+    {
+        let deferred: Vec<_> = boot
+            .deferred
+            .iter()
+            .map(|module| {
+                let stage = match module.stage {
+                    DeferredStage::Mount => "mount",
+                    DeferredStage::Script => "script",
+                };
+                format!("{} ({stage})", module.id)
+            })
+            .collect();
+        warn!(
+            "deferred by boot budgets: {}; late pass: {}",
+            deferred.join(", "),
+            boot.late_pass.as_deref().unwrap_or("not finished")
+        );
+    }
+
+    if let Some(boot) = status
+        .last_boot
+        .as_ref()
+        .filter(|boot| !boot.waiting_mounts.is_empty())
+    {
+        warn!(
+            "mounts waiting for their targets: {}; late mount: {}",
+            boot.waiting_mounts.join(", "),
+            boot.late_mount.as_deref().unwrap_or("not finished")
+        );
+    }
+
+    let modules = &status.modules;
+    info!(
+        "modules: {} enabled, {} disabled",
+        modules.enabled, modules.disabled
+    );
+    info!(
+        "pending: {} install(s), {} update(s), {} removal(s) on next boot",
+        modules.pending_install, modules.pending_update, modules.pending_removal
+    );
+
+    if status.unhealthy.is_empty() {
+        info!("unhealthy modules: none");
+    } else {
+        warn!("unhealthy modules: {}", status.unhealthy.join(", "));
+    }
+
+    for (id, failure) in &status.mount_failures {
+        warn!(
+            "module {id} failed to mount ({}, {} time(s) in a row, last at {}): {}",
+            failure.kind.as_str(),
+            failure.count,
+            failure.at,
+            failure.error
+        );
+    }
+
+    for ids in &status.id_collisions {
+        warn!(
+            "module ids differ only in case: {}, uninstall all but one",
+            ids.join(", ")
+        );
+    }
+
+    if let Some(disk) = &status.disk {
+        info!(
+            "disk: {} MiB free of {} MiB, modules use {} MiB",
+            mib(disk.free_bytes),
+            mib(disk.total_bytes),
+            mib(disk.modules_bytes)
+        );
+    }
+
+    if status.safe_mode {
+        warn!("safe mode: on ({SAFE_MODE_FLAG}), modules are not initialized at boot");
+    } else {
+        info!("safe mode: off");
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use tracing::info;
+use zip::CompressionMethod;
+
+use crate::archive::{self, Limits, Zip};
+use crate::config::StorageMode;
+use crate::defs::{LOCALE_DIR, PAYLOAD_ROOTS, STAGING_DIR};
+use crate::module;
+
+/// Compressed form of a module's `system/` tree.
+pub const COMPRESSED_PAYLOAD: &str = "system.zip";
+
+/// Bring the module payload in line with the configured storage mode and
+/// return the directory to mount from: the module itself, or its staged copy.
+pub fn prepare(module_dir: &Path, mode: StorageMode) -> Result<PathBuf> {
+    let system_dir = module_dir.join("system");
+    let payload = module_dir.join(COMPRESSED_PAYLOAD);
+
+    match mode {
+        StorageMode::Compressed if system_dir.is_dir() => compress(module_dir)?,
+        StorageMode::Plain if payload.is_file() => decompress(module_dir)?,
+        _ => {}
+    }
+
+    if payload.is_file() {
+        stage(module_dir)
+    } else {
+        Ok(module_dir.to_path_buf())
+    }
+}
+
+/// Replace `system/` with a zstd-compressed archive of it.
+pub fn compress(module_dir: &Path) -> Result<()> {
+    info!("compressing payload of {module_dir:?}");
+
+    let system_dir = module_dir.join("system");
+    let tmp = module_dir.join(format!("{COMPRESSED_PAYLOAD}.tmp"));
+    module::zip_dir(&system_dir, &tmp, CompressionMethod::Zstd, false)?;
+    fs::rename(&tmp, module_dir.join(COMPRESSED_PAYLOAD))?;
+    module::delete_dir(&system_dir)
+}
+
+/// Restore `system/` from the compressed archive.
+pub fn decompress(module_dir: &Path) -> Result<()> {
+    info!("decompressing payload of {module_dir:?}");
+
+    let payload = module_dir.join(COMPRESSED_PAYLOAD);
+    let tmp = module_dir.join("system.tmp");
+    module::delete_dir(&tmp)?;
+    archive::extract_with(&Zip, &payload, &tmp, Limits::default())?;
+    fs::rename(&tmp, module_dir.join("system"))?;
+    fs::remove_file(payload)?;
+    Ok(())
+}
+
+/// Extract the compressed payload into the tmpfs staging area.
+fn stage(module_dir: &Path) -> Result<PathBuf> {
+    let module_id = module_dir
+        .file_name()
+        .ok_or_else(|| anyhow!("cannot get module directory name"))?;
+    let staging_dir = Path::new(STAGING_DIR).join(module_id);
+    info!("staging compressed payload of {module_dir:?} into {staging_dir:?}");
+
+    module::delete_dir(&staging_dir)?;
+    archive::extract_with(
+        &Zip,
+        &module_dir.join(COMPRESSED_PAYLOAD),
+        &staging_dir.join("system"),
+        Limits::default(),
+    )?;
+    link_other_roots(module_dir, &staging_dir)?;
+    Ok(staging_dir)
+}
+
+/// Only `system/` is compressed; make the other payload roots and the
+/// locale trees reachable from a staged copy through symlinks to the module.
+fn link_other_roots(module_dir: &Path, staged_dir: &Path) -> Result<()> {
+    let others = PAYLOAD_ROOTS.iter().map(|(dir, _)| *dir);
+    for dir in others.filter(|dir| *dir != "system").chain([LOCALE_DIR]) {
+        let root = module_dir.join(dir);
+        if root.is_dir() {
+            std::os::unix::fs::symlink(&root, staged_dir.join(dir))?;
+        }
+    }
+    Ok(())
+}
+
+/// What `prepare` would change about the payload, without touching it.
+pub fn planned_change(module_dir: &Path, mode: StorageMode) -> Option<&'static str> {
+    match mode {
+        StorageMode::Compressed if module_dir.join("system").is_dir() => {
+            Some("compress payload, then stage it")
+        }
+        StorageMode::Plain if module_dir.join(COMPRESSED_PAYLOAD).is_file() => {
+            Some("decompress payload")
+        }
+        _ if module_dir.join(COMPRESSED_PAYLOAD).is_file() => Some("stage compressed payload"),
+        _ => None,
+    }
+}
+
+/// A directory with the module's `system/` tree for read-only inspection,
+/// extracting a compressed payload into `scratch` when there is no plain one.
+pub fn preview(module_dir: &Path, scratch: &Path) -> Result<PathBuf> {
+    let payload = module_dir.join(COMPRESSED_PAYLOAD);
+    if module_dir.join("system").is_dir() || !payload.is_file() {
+        return Ok(module_dir.to_path_buf());
+    }
+
+    archive::extract_with(&Zip, &payload, &scratch.join("system"), Limits::default())?;
+    link_other_roots(module_dir, scratch)?;
+    Ok(scratch.to_path_buf())
+}
@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once from `--strict` or the `strict` config key, before any command runs.
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    STRICT.store(true, Ordering::Relaxed);
+}
+
+/// Whether questionable input should fail instead of being warned about.
+pub fn enabled() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+/// `warn!` that returns the message as an error from the enclosing
+/// function instead when strict mode is on.
+macro_rules! warn_or_bail {
+    ($($arg:tt)+) => {
+        if $crate::strict::enabled() {
+            ::anyhow::bail!($($arg)+);
+        }
+        ::tracing::warn!($($arg)+);
+    };
+}
+
+pub(crate) use warn_or_bail;
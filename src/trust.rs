@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::clock;
+use crate::defs::TRUSTED_AUTHORS_FILE;
+use crate::state;
+
+/// Accepted fingerprint lengths in hex digits (64 to 512 bit keys).
+const FINGERPRINT_LEN: std::ops::RangeInclusive<usize> = 16..=128;
+
+/// An author key the user chose to trust.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrustedAuthor {
+    /// Name given when trusting the key, if any
+    pub name: Option<String>,
+    #[serde(with = "clock::rfc3339_or_unix")]
+    pub added: u64,
+}
+
+/// How far a module's declared author is trusted.
+///
+/// `author_key` is a claim made by module.prop, not a signature: trusting
+/// a key means trusting every module that names it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrustStatus {
+    Trusted,
+    Untrusted,
+    /// The module declares no `author_key`
+    Unsigned,
+}
+
+/// Canonical form of a fingerprint: lowercase hex without separators.
+pub fn normalize_fingerprint(fingerprint: &str) -> anyhow::Result<String> {
+    let hex: String = fingerprint
+        .chars()
+        .filter(|c| !matches!(c, ':' | ' ' | '-'))
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("fingerprint `{fingerprint}` must be hexadecimal");
+    }
+    if !FINGERPRINT_LEN.contains(&hex.len()) {
+        bail!(
+            "fingerprint `{fingerprint}` must have {} to {} hex digits",
+            FINGERPRINT_LEN.start(),
+            FINGERPRINT_LEN.end()
+        );
+    }
+    Ok(hex)
+}
+
+fn load() -> anyhow::Result<BTreeMap<String, TrustedAuthor>> {
+    match fs::read_to_string(TRUSTED_AUTHORS_FILE) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save(authors: &BTreeMap<String, TrustedAuthor>) -> anyhow::Result<()> {
+    state::write_atomic(
+        Path::new(TRUSTED_AUTHORS_FILE),
+        serde_json::to_string_pretty(authors)?,
+    )?;
+    Ok(())
+}
+
+/// Returns whether the key was newly trusted.
+pub fn add(fingerprint: &str, name: Option<String>) -> anyhow::Result<bool> {
+    let fingerprint = normalize_fingerprint(fingerprint)?;
+    let mut authors = load()?;
+    if authors.contains_key(&fingerprint) {
+        return Ok(false);
+    }
+
+    authors.insert(
+        fingerprint,
+        TrustedAuthor {
+            name,
+            added: clock::unix_now(),
+        },
+    );
+    save(&authors)?;
+    Ok(true)
+}
+
+/// Returns whether the key was trusted before.
+pub fn remove(fingerprint: &str) -> anyhow::Result<bool> {
+    let fingerprint = normalize_fingerprint(fingerprint)?;
+    let mut authors = load()?;
+    if authors.remove(&fingerprint).is_none() {
+        return Ok(false);
+    }
+    save(&authors)?;
+    Ok(true)
+}
+
+pub fn list() -> anyhow::Result<()> {
+    let authors = load()?;
+
+    info!("trusted authors:");
+    if authors.is_empty() {
+        info!("  (none)");
+    }
+    for (fingerprint, author) in &authors {
+        info!(
+            "  {fingerprint} {} (added {})",
+            author.name.as_deref().unwrap_or("-"),
+            clock::local(author.added)
+        );
+    }
+
+    Ok(())
+}
+
+/// Trust status of a module from its props.
+pub fn status(props: &HashMap<String, String>) -> anyhow::Result<TrustStatus> {
+    let Some(key) = props.get("author_key").filter(|key| !key.is_empty()) else {
+        return Ok(TrustStatus::Unsigned);
+    };
+
+    let fingerprint = normalize_fingerprint(key)?;
+    if load()?.contains_key(&fingerprint) {
+        Ok(TrustStatus::Trusted)
+    } else {
+        Ok(TrustStatus::Untrusted)
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::channel;
+use crate::defs::{MODULES_DIR, MODULES_UPDATE_DIR};
+use crate::module;
+use crate::repo;
+
+/// The JSON document a module's `updateJson` points to. The top level is
+/// the stable release; other channels are listed under `channels`.
+#[derive(Debug, Deserialize)]
+struct UpdateManifest {
+    #[serde(flatten)]
+    release: Release,
+    #[serde(default)]
+    channels: BTreeMap<String, Release>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    version: i64,
+    /// Archive URL, absolute or relative to the manifest
+    #[serde(rename = "zipUrl")]
+    zip_url: String,
+    sha256: Option<String>,
+    /// Patches from older versions, as in a repository index
+    #[serde(default)]
+    deltas: Vec<repo::RepoDelta>,
+}
+
+/// Result of checking one module against its `updateJson`.
+#[derive(Debug, Serialize)]
+pub struct UpdateCheck {
+    pub id: String,
+    /// Release channel the module follows
+    pub channel: String,
+    /// Installed version, or the pending one when an update is staged
+    pub version: i64,
+    pub latest: Option<i64>,
+    pub url: Option<String>,
+    pub sha256: Option<String>,
+    /// Delta package from the installed version, tried before `url`
+    pub delta: Option<repo::RepoDelta>,
+    /// Why the manifest could not be used
+    pub error: Option<String>,
+}
+
+impl UpdateCheck {
+    pub fn available(&self) -> bool {
+        self.latest.is_some_and(|latest| latest > self.version)
+    }
+
+    /// The channel's release is older than what is installed, as after
+    /// switching from beta back to stable.
+    pub fn behind(&self) -> bool {
+        self.latest.is_some_and(|latest| latest < self.version)
+    }
+}
+
+/// Download the manifest `id` declares at `url` and pick the release of
+/// `channel`.
+fn fetch_release(url: &str, id: &str, channel: &str) -> anyhow::Result<Release> {
+    let content = ureq::get(url)
+        .call()
+        .and_then(|response| response.into_body().read_to_string())
+        .with_context(|| format!("failed to download update manifest {url}"))?;
+    let mut manifest: UpdateManifest = serde_json::from_str(&content)
+        .with_context(|| format!("{url} is not an update manifest"))?;
+
+    let mut release = if channel == channel::DEFAULT_CHANNEL {
+        manifest.release
+    } else {
+        manifest
+            .channels
+            .remove(channel)
+            .with_context(|| format!("{url} has no {channel} channel"))?
+    };
+    if let Some(sha256) = &mut release.sha256 {
+        if !repo::is_sha256(sha256) {
+            bail!("sha256 of {url} must be 64 hex digits");
+        }
+        sha256.make_ascii_lowercase();
+    }
+    release.zip_url = repo::resolve_url(url, &release.zip_url);
+    repo::resolve_deltas(url, id, &mut release.deltas);
+    Ok(release)
+}
+
+/// Check `module_id`, or every installed module, against its `updateJson`
+/// on the channel chosen for it.
+/// Modules without one are skipped; a manifest that cannot be fetched is
+/// reported in the result instead of failing the whole check.
+pub fn check(module_id: Option<&str>) -> anyhow::Result<Vec<UpdateCheck>> {
+    let ids = match module_id {
+        Some(id) => {
+            if !Path::new(MODULES_DIR).join(id).is_dir() {
+                bail!("module {id} is not installed");
+            }
+            vec![id.to_string()]
+        }
+        None => module::installed_ids(),
+    };
+
+    let mut checks = Vec::new();
+    for id in ids {
+        // a staged update is what the next boot installs, compare against it
+        let update_dir = Path::new(MODULES_UPDATE_DIR).join(&id);
+        let dir = if update_dir.is_dir() {
+            update_dir
+        } else {
+            Path::new(MODULES_DIR).join(&id)
+        };
+        let Ok(props) = module::parse_prop_file(&dir.join("module.prop")) else {
+            warn!("skipping {id}: cannot read module.prop");
+            continue;
+        };
+        let Some(url) = props.get("updateJson").filter(|url| !url.is_empty()) else {
+            if module_id.is_some() {
+                bail!("module {id} does not declare updateJson");
+            }
+            continue;
+        };
+        let Some(version) = props.get("version").and_then(|v| v.parse::<i64>().ok()) else {
+            warn!("skipping {id}: version is not an integer");
+            continue;
+        };
+
+        // deltas patch the installed copy, whatever update is staged
+        let installed = module::module_version(&Path::new(MODULES_DIR).join(&id));
+
+        let channel = channel::of(&id);
+        let check = match fetch_release(url, &id, &channel) {
+            Ok(release) => UpdateCheck {
+                delta: release
+                    .deltas
+                    .into_iter()
+                    .find(|delta| Some(delta.from_version) == installed),
+                id,
+                channel,
+                version,
+                latest: Some(release.version),
+                url: Some(release.zip_url),
+                sha256: release.sha256,
+                error: None,
+            },
+            Err(e) => UpdateCheck {
+                id,
+                channel,
+                version,
+                latest: None,
+                url: None,
+                sha256: None,
+                delta: None,
+                error: Some(format!("{e:#}")),
+            },
+        };
+        checks.push(check);
+    }
+    Ok(checks)
+}
+
+pub fn print_checks(checks: &[UpdateCheck]) {
+    if checks.is_empty() {
+        info!("no installed module declares updateJson");
+    }
+    for check in checks {
+        match (&check.error, check.latest) {
+            (Some(error), _) => warn!("{}: {error}", check.id),
+            (None, Some(latest)) if check.available() => {
+                info!("{}: v{} -> v{latest}", check.id, check.version)
+            }
+            (None, Some(latest)) if check.behind() => warn!(
+                "{}: v{} is newer than v{latest} of the {} channel, not downgrading",
+                check.id, check.version, check.channel
+            ),
+            _ => info!("{}: v{} is up to date", check.id, check.version),
+        }
+    }
+}